@@ -0,0 +1,79 @@
+//
+//  GET throughput benchmark.
+//
+//  Serves a large file from a LocalFs with different read buffer sizes
+//  and prints the throughput of draining the response body.
+//
+//  cargo run --release --example get-throughput -- --size-mb 512
+//
+
+use std::error::Error;
+use std::io::Write;
+use std::time::Instant;
+
+use clap::Parser;
+use http_body::Body as _;
+
+use dav_server::{localfs::LocalFs, DavHandler};
+
+#[derive(Debug, clap::Parser)]
+#[command(about, version)]
+struct Cli {
+    /// size of the test file in MiB
+    #[arg(short, long, default_value_t = 256)]
+    size_mb: usize,
+    /// buffer sizes to compare, in KiB
+    #[arg(short, long, value_delimiter = ',', default_value = "8,16,64,256,1024")]
+    buffers: Vec<usize>,
+    /// number of runs per buffer size, the best one is reported
+    #[arg(short, long, default_value_t = 3)]
+    runs: usize,
+}
+
+async fn get(dav: &DavHandler) -> Result<u64, Box<dyn Error>> {
+    let req = http::Request::builder()
+        .uri("/file")
+        .body(hyper::Body::empty())?;
+    let mut body = dav.handle(req).await.into_body();
+    let mut total = 0;
+    while let Some(chunk) = body.data().await {
+        total += chunk?.len() as u64;
+    }
+    Ok(total)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Cli::parse();
+
+    let dir = std::env::temp_dir().join(format!("dav-throughput-{}", std::process::id()));
+    std::fs::create_dir(&dir)?;
+    let mut file = std::fs::File::create(dir.join("file"))?;
+    let block = vec![0x5a; 1024 * 1024];
+    for _ in 0..args.size_mb {
+        file.write_all(&block)?;
+    }
+    drop(file);
+
+    for kb in &args.buffers {
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .read_buf_size(kb * 1024)
+            .build_handler();
+        let mut best = f64::MAX;
+        for _ in 0..args.runs {
+            let start = Instant::now();
+            let total = get(&dav).await?;
+            assert_eq!(total, (args.size_mb * 1024 * 1024) as u64);
+            best = best.min(start.elapsed().as_secs_f64());
+        }
+        println!(
+            "{:>6} KiB buffer: {:>8.1} MiB/s",
+            kb,
+            args.size_mb as f64 / best
+        );
+    }
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
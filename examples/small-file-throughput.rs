@@ -0,0 +1,86 @@
+//
+//  Small file GET throughput benchmark.
+//
+//  Serves a set of small files from a LocalFs, once reading them with
+//  the normal read loop and once from memory (LocalFs::set_file_cache),
+//  and prints the number of requests per second for each.
+//
+//  cargo run --release --example small-file-throughput -- --size-kb 4
+//
+
+use std::error::Error;
+use std::time::Instant;
+
+use clap::Parser;
+use http_body::Body as _;
+
+use dav_server::{localfs::LocalFs, DavHandler};
+
+#[derive(Debug, clap::Parser)]
+#[command(about, version)]
+struct Cli {
+    /// size of the test files in KiB
+    #[arg(short, long, default_value_t = 4)]
+    size_kb: usize,
+    /// number of test files
+    #[arg(short, long, default_value_t = 64)]
+    files: usize,
+    /// number of GET requests per run
+    #[arg(short, long, default_value_t = 100000)]
+    requests: usize,
+    /// only GET this range, like "bytes=100-199"
+    #[arg(long)]
+    range: Option<String>,
+}
+
+async fn get(dav: &DavHandler, uri: &str, range: Option<&str>) -> Result<u64, Box<dyn Error>> {
+    let mut req = http::Request::builder().uri(uri);
+    if let Some(range) = range {
+        req = req.header("Range", range);
+    }
+    let mut body = dav
+        .handle(req.body(hyper::Body::empty())?)
+        .await
+        .into_body();
+    let mut total = 0;
+    while let Some(chunk) = body.data().await {
+        total += chunk?.len() as u64;
+    }
+    Ok(total)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Cli::parse();
+
+    let dir = std::env::temp_dir().join(format!("dav-small-files-{}", std::process::id()));
+    std::fs::create_dir(&dir)?;
+    let data = vec![0x5a; args.size_kb * 1024];
+    for n in 0..args.files {
+        std::fs::write(dir.join(format!("file{}", n)), &data)?;
+    }
+    let uris: Vec<_> = (0..args.files).map(|n| format!("/file{}", n)).collect();
+
+    for &cached in &[false, true] {
+        let mut fs = LocalFs::new(&dir, false, false, false);
+        if cached {
+            fs.set_file_cache(data.len() as u64, args.files);
+        }
+        let dav = DavHandler::builder().filesystem(fs).build_handler();
+        let start = Instant::now();
+        let mut total = 0;
+        for i in 0..args.requests {
+            total += get(&dav, &uris[i % uris.len()], args.range.as_deref()).await?;
+        }
+        let secs = start.elapsed().as_secs_f64();
+        println!(
+            "{:>9}: {:>9.0} requests/s, {:>8.1} MiB/s",
+            if cached { "cache" } else { "read loop" },
+            args.requests as f64 / secs,
+            total as f64 / (1024.0 * 1024.0) / secs
+        );
+    }
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
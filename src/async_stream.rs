@@ -140,10 +140,14 @@ impl<I, E: Unpin> Stream for AsyncStream<I, E> {
     type Item = Result<I, E>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<I, E>>> {
-        let pollres = {
-            let fut = self.fut.as_mut().unwrap();
-            fut.as_mut().poll(cx)
+        let pollres = match self.fut.as_mut() {
+            Some(fut) => fut.as_mut().poll(cx),
+            // already done, or ended with an error.
+            None => return Poll::Ready(None),
         };
+        if pollres.is_ready() {
+            self.fut = None;
+        }
         match pollres {
             // If the future returned Poll::Ready, that signals the end of the stream.
             Poll::Ready(Ok(_)) => Poll::Ready(None),
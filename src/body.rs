@@ -5,10 +5,10 @@ use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-use bytes::{Buf, Bytes};
-use futures_util::stream::Stream;
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::stream::{Stream, StreamExt};
 use http::header::HeaderMap;
-use http_body::Body as HttpBody;
+use http_body::{Body as HttpBody, SizeHint};
 
 use crate::async_stream::AsyncStream;
 
@@ -33,6 +33,37 @@ impl Body {
     }
 }
 
+impl Body {
+    // Is this a stream of unknown length.
+    pub(crate) fn is_stream(&self) -> bool {
+        matches!(self.inner, BodyType::AsyncStream(_))
+    }
+
+    // Read the body into memory, up to `max` bytes. If it is longer,
+    // this returns a body that produces all of it, from the start.
+    pub(crate) async fn into_bytes(mut self, max: usize) -> io::Result<Result<Bytes, Body>> {
+        if let BodyType::Bytes(ref mut b) = self.inner {
+            return Ok(Ok(b.take().unwrap_or_default()));
+        }
+        let mut buf = BytesMut::new();
+        while let Some(data) = self.next().await {
+            buf.extend_from_slice(&data?);
+            if buf.len() > max {
+                let head = buf.freeze();
+                let strm = AsyncStream::new(|mut tx| async move {
+                    tx.send(head).await;
+                    while let Some(data) = self.next().await {
+                        tx.send(data?).await;
+                    }
+                    Ok(())
+                });
+                return Ok(Err(Body::from(strm)));
+            }
+        }
+        Ok(Ok(buf.freeze()))
+    }
+}
+
 impl Stream for Body {
     type Item = io::Result<Bytes>;
 
@@ -65,6 +96,20 @@ impl HttpBody for Body {
     ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
         Poll::Ready(Ok(None))
     }
+
+    fn is_end_stream(&self) -> bool {
+        matches!(self.inner, BodyType::Bytes(None) | BodyType::Empty)
+    }
+
+    // An exact size lets the server send a Content-Length instead
+    // of using chunked encoding.
+    fn size_hint(&self) -> SizeHint {
+        match self.inner {
+            BodyType::Bytes(Some(ref b)) => SizeHint::with_exact(b.len() as u64),
+            BodyType::Bytes(None) | BodyType::Empty => SizeHint::with_exact(0),
+            BodyType::AsyncStream(_) => SizeHint::default(),
+        }
+    }
 }
 
 impl From<String> for Body {
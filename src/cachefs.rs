@@ -0,0 +1,477 @@
+//! Filesystem adapter that caches metadata.
+//!
+//! `CachingFs` wraps another filesystem and remembers the result of
+//! `metadata()` (and so of `exists()`) for a while. That way a `GET`
+//! followed by a `PROPFIND` of the same file does not ask a slow or
+//! remote backend the same question twice. "Not found" is remembered
+//! as well.
+//!
+//! A change made through the adapter removes the cached entries of the
+//! path, of everything below it and of its parent directory. Changes
+//! made behind its back show up when the entry expires.
+//!
+//! Example:
+//!
+//! ```
+//! use std::time::Duration;
+//! use dav_server::{cachefs::CachingFs, memfs::MemFs, DavHandler};
+//!
+//! let dav = DavHandler::builder()
+//!     .filesystem(CachingFs::new(MemFs::new(), Duration::from_secs(5), 10000))
+//!     .build_handler();
+//! ```
+//!
+use std::io::SeekFrom;
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use futures_util::{Future, FutureExt};
+use http::StatusCode;
+use lru::LruCache;
+use parking_lot::Mutex;
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+
+/// Filesystem adapter that caches metadata.
+#[derive(Clone)]
+pub struct CachingFs<F> {
+    fs: Box<F>,
+    cache: Arc<Cache>,
+}
+
+#[derive(Debug)]
+struct Cache {
+    ttl: Duration,
+    entries: Mutex<LruCache<Vec<u8>, Entry>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    time: Instant,
+    meta: FsResult<Box<dyn DavMetaData>>,
+}
+
+// "/dir/" and "/dir" are the same node.
+fn cache_key(path: &DavPath) -> Vec<u8> {
+    let path = path.as_bytes();
+    match path.len() > 1 && path.ends_with(b"/") {
+        true => path[..path.len() - 1].to_vec(),
+        false => path.to_vec(),
+    }
+}
+
+impl Cache {
+    fn get(&self, key: &[u8]) -> Option<FsResult<Box<dyn DavMetaData>>> {
+        let mut entries = self.entries.lock();
+        match entries.get(key) {
+            Some(e) if e.time.elapsed() < self.ttl => Some(e.meta.clone()),
+            Some(_) => {
+                entries.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, key: Vec<u8>, meta: FsResult<Box<dyn DavMetaData>>) {
+        let time = Instant::now();
+        self.entries.lock().put(key, Entry { time, meta });
+    }
+
+    // Forget the node, its parent, and everything below it.
+    fn invalidate(&self, path: &DavPath) {
+        let key = cache_key(path);
+        let mut entries = self.entries.lock();
+        if key == b"/" {
+            entries.clear();
+            return;
+        }
+        let parent = match key.iter().rposition(|&b| b == b'/') {
+            Some(0) | None => b"/".to_vec(),
+            Some(n) => key[..n].to_vec(),
+        };
+        let below: Vec<_> = entries
+            .iter()
+            .map(|(k, _)| k)
+            .filter(|k| k.starts_with(&key) && k.get(key.len()) == Some(&b'/'))
+            .cloned()
+            .collect();
+        for k in below.iter().chain([key, parent].iter()) {
+            entries.pop(k);
+        }
+    }
+}
+
+impl<F: DavFileSystem + Clone> CachingFs<F> {
+    /// Cache the metadata of `fs` for `ttl`, for at most `max_entries`
+    /// paths. When the cache is full, the least recently used entry is
+    /// dropped.
+    pub fn new(fs: Box<F>, ttl: Duration, max_entries: usize) -> Box<CachingFs<F>> {
+        let size = NonZeroUsize::new(max_entries.max(1)).unwrap();
+        let cache = Cache {
+            ttl,
+            entries: Mutex::new(LruCache::new(size)),
+        };
+        Box::new(CachingFs {
+            fs,
+            cache: Arc::new(cache),
+        })
+    }
+
+    // Run a future that changes `path`, then drop what we know about it.
+    fn changes<'a, T: Send + 'a>(
+        &'a self,
+        path: &'a DavPath,
+        fut: FsFuture<'a, T>,
+    ) -> FsFuture<'a, T> {
+        async move {
+            let res = fut.await;
+            self.cache.invalidate(path);
+            res
+        }
+        .boxed()
+    }
+}
+
+impl<F: DavFileSystem + Clone + 'static> DavFileSystem for CachingFs<F> {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        self.open_mode(path, options, None)
+    }
+
+    fn open_mode<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+        mode: Option<u32>,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        async move {
+            let write = options.write || options.append || options.truncate || options.create;
+            let file = self.fs.open_mode(path, options, mode).await?;
+            if !write {
+                return Ok(file);
+            }
+            self.cache.invalidate(path);
+            Ok(Box::new(CachingFile {
+                file,
+                path: path.clone(),
+                cache: self.cache.clone(),
+            }) as Box<dyn DavFile>)
+        }
+        .boxed()
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        self.fs.read_dir(path, meta)
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            let key = cache_key(path);
+            if let Some(meta) = self.cache.get(&key) {
+                return meta;
+            }
+            let meta = self.fs.metadata(path).await;
+            if matches!(meta, Ok(_) | Err(FsError::NotFound)) {
+                self.cache.insert(key, meta.clone());
+            }
+            meta
+        }
+        .boxed()
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.fs.symlink_metadata(path)
+    }
+
+    // `exists` is not forwarded, the default calls the cached `metadata`.
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.changes(path, self.fs.create_dir(path))
+    }
+
+    fn create_dir_mode<'a>(&'a self, path: &'a DavPath, mode: Option<u32>) -> FsFuture<'a, ()> {
+        let fut = self.fs.create_dir_mode(path, mode);
+        self.changes(path, fut)
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.changes(path, self.fs.remove_dir(path))
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.changes(path, self.fs.remove_file(path))
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let res = self.fs.rename(from, to).await;
+            self.cache.invalidate(from);
+            self.cache.invalidate(to);
+            res
+        }
+        .boxed()
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.changes(to, self.fs.copy(from, to))
+    }
+
+    fn set_accessed<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        let fut = self.fs.set_accessed(path, tm);
+        self.changes(path, fut)
+    }
+
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        let fut = self.fs.set_modified(path, tm);
+        self.changes(path, fut)
+    }
+
+    fn have_bind<'a>(
+        &'a self,
+        path: &'a DavPath,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        self.fs.have_bind(path)
+    }
+
+    fn link<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.changes(to, self.fs.link(from, to))
+    }
+
+    fn relink<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            let res = self.fs.relink(from, to).await;
+            self.cache.invalidate(from);
+            self.cache.invalidate(to);
+            res
+        }
+        .boxed()
+    }
+
+    fn unlink<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.changes(path, self.fs.unlink(path))
+    }
+
+    fn have_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        self.fs.have_props(path)
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(StatusCode, DavProp)>> {
+        self.fs.patch_props(path, patch)
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        self.fs.get_props(path, do_content)
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        self.fs.get_prop(path, prop)
+    }
+
+    fn get_quota(&self) -> FsFuture<'_, (u64, Option<u64>)> {
+        self.fs.get_quota()
+    }
+
+    fn user_principal_url<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Vec<u8>> {
+        self.fs.user_principal_url(path)
+    }
+
+    fn patch_path<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, DavPath> {
+        self.fs.patch_path(path)
+    }
+
+    fn sync_token<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        self.fs.sync_token(path)
+    }
+
+    fn content_hash<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Vec<u8>> {
+        self.fs.content_hash(path)
+    }
+
+    fn write_if_etag<'a>(
+        &'a self,
+        path: &'a DavPath,
+        etag: &'a str,
+        data: bytes::Bytes,
+    ) -> FsFuture<'a, String> {
+        self.changes(path, self.fs.write_if_etag(path, etag, data))
+    }
+
+    fn get_acl<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, DavAccessControl> {
+        self.fs.get_acl(path)
+    }
+}
+
+// A file opened for writing. Every write changes the length and the
+// modification time, so it invalidates the cached metadata.
+#[derive(Debug)]
+struct CachingFile {
+    file: Box<dyn DavFile>,
+    path: DavPath,
+    cache: Arc<Cache>,
+}
+
+impl DavFile for CachingFile {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        self.file.metadata()
+    }
+
+    fn write_buf(&mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<'_, ()> {
+        async move {
+            let res = self.file.write_buf(buf).await;
+            self.cache.invalidate(&self.path);
+            res
+        }
+        .boxed()
+    }
+
+    fn write_bytes(&mut self, buf: bytes::Bytes) -> FsFuture<'_, ()> {
+        async move {
+            let res = self.file.write_bytes(buf).await;
+            self.cache.invalidate(&self.path);
+            res
+        }
+        .boxed()
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, bytes::Bytes> {
+        self.file.read_bytes(count)
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        self.file.seek(pos)
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        async move {
+            let res = self.file.flush().await;
+            self.cache.invalidate(&self.path);
+            res
+        }
+        .boxed()
+    }
+
+    fn sync_all(&mut self) -> FsFuture<'_, ()> {
+        self.file.sync_all()
+    }
+
+    fn redirect_url(&mut self) -> FsFuture<'_, Option<String>> {
+        self.file.redirect_url()
+    }
+
+    fn read_range(&mut self, offset: u64, count: u64) -> FsFuture<'_, bytes::Bytes> {
+        self.file.read_range(offset, count)
+    }
+}
+
+#[cfg(all(test, feature = "memfs"))]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::testfs::{FsHooks, HookFs};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // MemFs that counts the metadata() calls.
+    #[derive(Clone)]
+    struct CountMeta(Arc<AtomicUsize>);
+
+    impl FsHooks for CountMeta {
+        fn metadata<'a>(
+            &'a self,
+            fs: &'a MemFs,
+            path: &'a DavPath,
+        ) -> FsFuture<'a, Box<dyn DavMetaData>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            fs.metadata(path)
+        }
+    }
+
+    type CountFs = HookFs<CountMeta>;
+
+    fn caching_fs(ttl: Duration) -> (Box<CachingFs<CountFs>>, Box<CountFs>) {
+        let inner = HookFs::new(CountMeta(Arc::new(AtomicUsize::new(0))));
+        (CachingFs::new(inner.clone(), ttl, 100), inner)
+    }
+
+    async fn write(fs: &dyn DavFileSystem, path: &DavPath, data: &'static [u8]) {
+        let options = OpenOptions {
+            write: true,
+            create: true,
+            truncate: true,
+            ..OpenOptions::default()
+        };
+        let mut file = fs.open(path, options).await.unwrap();
+        file.write_bytes(bytes::Bytes::from(data)).await.unwrap();
+        file.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ttl() {
+        let (fs, inner) = caching_fs(Duration::from_millis(50));
+        let path = DavPath::new("/file").unwrap();
+        write(&*fs, &path, b"hello").await;
+        let count = || inner.hooks.0.load(Ordering::SeqCst);
+
+        assert_eq!(fs.metadata(&path).await.unwrap().len(), 5);
+        assert!(fs.exists(&path).await);
+        assert_eq!(count(), 1);
+
+        // a change behind the back of the cache shows up after the ttl.
+        write(&*inner, &path, b"hello, world").await;
+        assert_eq!(fs.metadata(&path).await.unwrap().len(), 5);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(fs.metadata(&path).await.unwrap().len(), 12);
+        assert_eq!(count(), 2);
+
+        // so does "not found".
+        let gone = DavPath::new("/gone").unwrap();
+        assert!(!fs.exists(&gone).await);
+        write(&*inner, &gone, b"").await;
+        assert!(!fs.exists(&gone).await);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(fs.exists(&gone).await);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate() {
+        let (fs, _) = caching_fs(Duration::from_secs(3600));
+        let dir = DavPath::new("/dir/").unwrap();
+        let file = DavPath::new("/dir/file").unwrap();
+        fs.create_dir(&dir).await.unwrap();
+        assert!(!fs.exists(&file).await);
+
+        // writes through the adapter are seen at once.
+        write(&*fs, &file, b"hello").await;
+        assert_eq!(fs.metadata(&file).await.unwrap().len(), 5);
+        write(&*fs, &file, b"hello, world").await;
+        assert_eq!(fs.metadata(&file).await.unwrap().len(), 12);
+
+        let moved = DavPath::new("/moved/").unwrap();
+        let moved_file = DavPath::new("/moved/file").unwrap();
+        assert!(!fs.exists(&moved).await);
+        fs.rename(&dir, &moved).await.unwrap();
+        assert!(!fs.exists(&dir).await);
+        assert!(!fs.exists(&file).await);
+        assert!(fs.exists(&moved_file).await);
+
+        fs.remove_file(&moved_file).await.unwrap();
+        assert!(!fs.exists(&moved_file).await);
+    }
+}
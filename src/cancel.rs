@@ -0,0 +1,72 @@
+//! Cancellation of a single request.
+//!
+//! Response bodies of `PROPFIND`, `REPORT`, `SEARCH` and `GET` are
+//! produced while they are being sent, so when the server drops the body
+//! because the client went away, the work stops with it. A server only
+//! notices that the client is gone when it writes to the connection
+//! though, and an enumeration that skips most of what it reads can take
+//! a long time before it has something to write.
+//!
+//! A server that knows earlier, for example because it watches the
+//! connection, can pass a `CancelToken` with the request. The handlers
+//! check it between filesystem calls, and once it is cancelled they stop
+//! and end the response body with an error.
+//!
+//! Example:
+//!
+//! ```no_run
+//! # async fn example(req: http::Request<hyper::Body>) {
+//! use dav_server::{cancel::CancelToken, memfs::MemFs, DavConfig, DavHandler};
+//!
+//! let dav = DavHandler::builder()
+//!     .filesystem(MemFs::new())
+//!     .build_handler();
+//!
+//! let token = CancelToken::new();
+//! let config = DavConfig::new().cancel(token.clone());
+//! let resp = dav.handle_with(config, req).await;
+//!
+//! // ... and when the connection is closed:
+//! token.cancel();
+//! # }
+//! ```
+//!
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Cancels the work for a request.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// Create a new `CancelToken`.
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Stop the work for the request.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Has `cancel` been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    // Err if cancelled.
+    pub(crate) fn check(&self) -> io::Result<()> {
+        if self.is_cancelled() {
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "request cancelled",
+            ));
+        }
+        Ok(())
+    }
+}
@@ -5,7 +5,7 @@ use http::{Method, StatusCode};
 
 use crate::davheaders::{self, ETag};
 use crate::davpath::DavPath;
-use crate::fs::{DavFileSystem, DavMetaData};
+use crate::fs::{with_content_hash, DavFileSystem, DavMetaData};
 use crate::ls::DavLockSystem;
 
 type Request = http::Request<()>;
@@ -20,6 +20,10 @@ fn round_time(tm: impl Into<SystemTime>) -> SystemTime {
     }
 }
 
+// If-Range only matches a strong validator (RFC 7233, 3.2). An etag
+// is compared with the strong comparison, so a weak etag never matches.
+// A modification time is only strong if it is at least a second in the
+// past, otherwise the file might have changed twice within that second.
 pub(crate) fn ifrange_match(
     hdr: &davheaders::IfRange,
     tag: Option<&davheaders::ETag>,
@@ -27,7 +31,11 @@ pub(crate) fn ifrange_match(
 ) -> bool {
     match *hdr {
         davheaders::IfRange::Date(ref d) => match date {
-            Some(date) => round_time(date) == round_time(*d),
+            Some(date) => {
+                let age = SystemTime::now().duration_since(date);
+                let strong = matches!(age, Ok(age) if age >= Duration::from_secs(1));
+                strong && round_time(date) == round_time(*d)
+            }
             None => false,
         },
         davheaders::IfRange::ETag(ref t) => match tag {
@@ -37,66 +45,109 @@ pub(crate) fn ifrange_match(
     }
 }
 
+// Match an If-Match (strong comparison) or If-None-Match (weak
+// comparison) list against the etag of the resource.
 pub(crate) fn etaglist_match(
     tags: &davheaders::ETagList,
     exists: bool,
     tag: Option<&davheaders::ETag>,
+    weak: bool,
 ) -> bool {
     match *tags {
         davheaders::ETagList::Star => exists,
         davheaders::ETagList::Tags(ref t) => match tag {
+            Some(tag) if weak => t.iter().any(|x| x.weak_eq(tag)),
             Some(tag) => t.iter().any(|x| x == tag),
             None => false,
         },
     }
 }
 
+/// Outcome of evaluate_preconditions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Proceed,
+    NotModified,
+    PreconditionFailed,
+}
+
+impl Outcome {
+    pub(crate) fn status(self) -> Option<StatusCode> {
+        match self {
+            Outcome::Proceed => None,
+            Outcome::NotModified => Some(StatusCode::NOT_MODIFIED),
+            Outcome::PreconditionFailed => Some(StatusCode::PRECONDITION_FAILED),
+        }
+    }
+}
+
+fn is_copymove(req: &Request) -> bool {
+    matches!(req.method().as_str(), "COPY" | "MOVE")
+}
+
 // Handle the if-headers: RFC 7232, HTTP/1.1 Conditional Requests.
-pub(crate) fn http_if_match(
+//
+// The steps of RFC 7232, 6 are:
+//
+// 1. If-Match, strong comparison. false: 412.
+// 2. If there is no If-Match, If-Unmodified-Since. false: 412. It is
+//    ignored if the resource has no modification date.
+// 3. If-None-Match, weak comparison. A match is 304 for GET and HEAD,
+//    412 for other methods.
+// 4. If there is no If-None-Match, and the method is GET or HEAD,
+//    If-Modified-Since. Not modified: 304.
+// 5. If-Range. This only decides whether a Range is served, so it is
+//    left to handle_get (see ifrange_match).
+//
+// So an etag condition always wins over the date condition of the
+// same step.
+pub(crate) fn evaluate_preconditions(
     req: &Request,
     meta: Option<&Box<dyn DavMetaData>>,
-) -> Option<StatusCode> {
+) -> Outcome {
     let file_modified = meta.and_then(|m| m.modified().ok());
+    let etag = meta.and_then(ETag::from_meta);
+    let get_or_head = req.method() == Method::GET || req.method() == Method::HEAD;
 
     if let Some(r) = req.headers().typed_get::<davheaders::IfMatch>() {
-        let etag = meta.and_then(ETag::from_meta);
-        if !etaglist_match(&r.0, meta.is_some(), etag.as_ref()) {
+        if !etaglist_match(&r.0, meta.is_some(), etag.as_ref(), false) {
             trace!("precondition fail: If-Match {:?}", r);
-            return Some(StatusCode::PRECONDITION_FAILED);
+            return Outcome::PreconditionFailed;
         }
     } else if let Some(r) = req.headers().typed_get::<headers::IfUnmodifiedSince>() {
-        match file_modified {
-            None => return Some(StatusCode::PRECONDITION_FAILED),
-            Some(file_modified) => {
-                if round_time(file_modified) > round_time(r) {
-                    trace!("precondition fail: If-Unmodified-Since {:?}", r);
-                    return Some(StatusCode::PRECONDITION_FAILED);
-                }
+        if let Some(file_modified) = file_modified {
+            if round_time(file_modified) > round_time(r) {
+                trace!("precondition fail: If-Unmodified-Since {:?}", r);
+                return Outcome::PreconditionFailed;
             }
         }
     }
 
-    if let Some(r) = req.headers().typed_get::<davheaders::IfNoneMatch>() {
-        let etag = meta.and_then(ETag::from_meta);
-        if etaglist_match(&r.0, meta.is_some(), etag.as_ref()) {
+    // "If-None-Match: *" on COPY and MOVE is about the destination: do not
+    // overwrite it. handle_copymove checks that.
+    let inm = req.headers().typed_get::<davheaders::IfNoneMatch>();
+    let inm = inm.filter(|r| !(r.0 == davheaders::ETagList::Star && is_copymove(req)));
+
+    if let Some(r) = inm {
+        if etaglist_match(&r.0, meta.is_some(), etag.as_ref(), true) {
             trace!("precondition fail: If-None-Match {:?}", r);
-            if req.method() == Method::GET || req.method() == Method::HEAD {
-                return Some(StatusCode::NOT_MODIFIED);
+            if get_or_head {
+                return Outcome::NotModified;
             } else {
-                return Some(StatusCode::PRECONDITION_FAILED);
+                return Outcome::PreconditionFailed;
             }
         }
     } else if let Some(r) = req.headers().typed_get::<headers::IfModifiedSince>() {
-        if req.method() == Method::GET || req.method() == Method::HEAD {
+        if get_or_head {
             if let Some(file_modified) = file_modified {
                 if round_time(file_modified) <= round_time(r) {
                     trace!("not-modified If-Modified-Since {:?}", r);
-                    return Some(StatusCode::NOT_MODIFIED);
+                    return Outcome::NotModified;
                 }
             }
         }
     }
-    None
+    Outcome::Proceed
 }
 
 // handle the If header: RFC4918, 10.4.  If Header
@@ -159,8 +210,9 @@ pub(crate) async fn dav_if_match<'a>(
                     if !valid || s.starts_with("DAV:") {
                         false
                     } else {
+                        // true if it is the token of a lock on the resource.
                         match *ls {
-                            Some(ref ls) => ls.check(p, None, true, false, vec![s]).is_ok(),
+                            Some(ref ls) => ls.discover(p).iter().any(|l| &l.token == s),
                             None => false,
                         }
                     }
@@ -173,6 +225,7 @@ pub(crate) async fn dav_if_match<'a>(
                         match fs.metadata(p).await {
                             Ok(meta) => {
                                 // exists and may have metadata ..
+                                let meta = with_content_hash(&**fs, p, meta).await;
                                 if let Some(mtag) = ETag::from_meta(meta) {
                                     tag == &mtag
                                 } else {
@@ -203,6 +256,22 @@ pub(crate) async fn dav_if_match<'a>(
     (any_list_ok, tokens)
 }
 
+// The metadata to compare If-Match and If-None-Match with. If the
+// filesystem has a content hash, that is the etag.
+async fn precondition_meta(
+    req: &Request,
+    meta: Option<&dyn DavMetaData>,
+    fs: &dyn DavFileSystem,
+    path: &DavPath,
+) -> Option<Box<dyn DavMetaData>> {
+    let meta = meta?.box_clone();
+    let headers = req.headers();
+    if !headers.contains_key("if-match") && !headers.contains_key("if-none-match") {
+        return Some(meta);
+    }
+    Some(with_content_hash(fs, path, meta).await)
+}
+
 // Handle both the HTTP conditional If: headers, and the webdav If: header.
 pub(crate) async fn if_match<'a>(
     req: &'a Request,
@@ -215,7 +284,8 @@ pub(crate) async fn if_match<'a>(
         (true, _) => {}
         (false, _) => return Some(StatusCode::PRECONDITION_FAILED),
     }
-    http_if_match(req, meta)
+    let meta = precondition_meta(req, meta.map(|m| &**m), &**fs, path).await;
+    evaluate_preconditions(req, meta.as_ref()).status()
 }
 
 // Like if_match, but also returns all "associated state-tokens"
@@ -226,7 +296,8 @@ pub(crate) async fn if_match_get_tokens<'a>(
     ls: &'a Option<Box<dyn DavLockSystem + 'static>>,
     path: &'a DavPath,
 ) -> Result<Vec<String>, StatusCode> {
-    if let Some(code) = http_if_match(req, meta) {
+    let meta = precondition_meta(req, meta.map(|m| &**m), &**fs, path).await;
+    if let Some(code) = evaluate_preconditions(req, meta.as_ref()).status() {
         return Err(code);
     }
     match dav_if_match(req, fs, ls, path).await {
@@ -234,3 +305,141 @@ pub(crate) async fn if_match_get_tokens<'a>(
         (false, _) => Err(StatusCode::PRECONDITION_FAILED),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::FsResult;
+
+    #[derive(Debug, Clone)]
+    struct Meta;
+
+    impl DavMetaData for Meta {
+        fn len(&self) -> u64 {
+            4
+        }
+        fn modified(&self) -> FsResult<SystemTime> {
+            Ok(UNIX_EPOCH + Duration::from_secs(1_000_000))
+        }
+        fn is_dir(&self) -> bool {
+            false
+        }
+    }
+
+    fn eval(method: Method, exists: bool, headers: &[(&str, &str)]) -> Outcome {
+        let meta: Box<dyn DavMetaData> = Box::new(Meta);
+        let mut req = http::Request::builder().method(method);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        evaluate_preconditions(&req.body(()).unwrap(), Some(&meta).filter(|_| exists))
+    }
+
+    fn etag(weak: bool) -> String {
+        let meta: Box<dyn DavMetaData> = Box::new(Meta);
+        let tag = meta.etag().unwrap();
+        format!("{}\"{}\"", if weak { "W/" } else { "" }, tag)
+    }
+
+    // Meta was modified at 1_000_000 seconds past the epoch.
+    const BEFORE: &str = "Mon, 12 Jan 1970 13:46:39 GMT";
+    const AT: &str = "Mon, 12 Jan 1970 13:46:40 GMT";
+
+    #[test]
+    fn test_if_match_star() {
+        use Outcome::*;
+        let star = &[("If-Match", "*")];
+        assert_eq!(eval(Method::GET, true, star), Proceed);
+        assert_eq!(eval(Method::PUT, true, star), Proceed);
+        assert_eq!(eval(Method::GET, false, star), PreconditionFailed);
+        assert_eq!(eval(Method::PUT, false, star), PreconditionFailed);
+    }
+
+    #[test]
+    fn test_preconditions() {
+        use Outcome::*;
+        let (strong, weak) = (etag(false), etag(true));
+
+        // If-Match uses the strong comparison, If-None-Match the weak one.
+        assert_eq!(eval(Method::PUT, true, &[("If-Match", &strong)]), Proceed);
+        assert_eq!(
+            eval(Method::PUT, true, &[("If-Match", &weak)]),
+            PreconditionFailed
+        );
+        assert_eq!(
+            eval(Method::PUT, true, &[("If-Match", "\"xyzzy\"")]),
+            PreconditionFailed
+        );
+        assert_eq!(
+            eval(Method::GET, true, &[("If-None-Match", &weak)]),
+            NotModified
+        );
+        assert_eq!(
+            eval(Method::GET, true, &[("If-None-Match", &strong)]),
+            NotModified
+        );
+        assert_eq!(
+            eval(Method::PUT, true, &[("If-None-Match", "*")]),
+            PreconditionFailed
+        );
+        assert_eq!(eval(Method::PUT, false, &[("If-None-Match", "*")]), Proceed);
+
+        // dates.
+        let ius = |date| eval(Method::PUT, true, &[("If-Unmodified-Since", date)]);
+        assert_eq!(ius(AT), Proceed);
+        assert_eq!(ius(BEFORE), PreconditionFailed);
+        assert_eq!(
+            eval(Method::PUT, false, &[("If-Unmodified-Since", BEFORE)]),
+            Proceed
+        );
+        let ims = |method, date| eval(method, true, &[("If-Modified-Since", date)]);
+        assert_eq!(ims(Method::GET, AT), NotModified);
+        assert_eq!(ims(Method::HEAD, AT), NotModified);
+        assert_eq!(ims(Method::GET, BEFORE), Proceed);
+        assert_eq!(ims(Method::PUT, AT), Proceed);
+
+        // an etag condition takes precedence over the date condition.
+        let h = &[
+            ("If-Match", strong.as_str()),
+            ("If-Unmodified-Since", BEFORE),
+        ];
+        assert_eq!(eval(Method::PUT, true, h), Proceed);
+        let h = &[("If-None-Match", "\"xyzzy\""), ("If-Modified-Since", AT)];
+        assert_eq!(eval(Method::GET, true, h), Proceed);
+
+        // If-Match / If-Unmodified-Since are evaluated first.
+        let h = &[
+            ("If-Match", "\"xyzzy\""),
+            ("If-None-Match", strong.as_str()),
+        ];
+        assert_eq!(eval(Method::GET, true, h), PreconditionFailed);
+        let h = &[("If-Unmodified-Since", BEFORE), ("If-Modified-Since", AT)];
+        assert_eq!(eval(Method::GET, true, h), PreconditionFailed);
+    }
+
+    #[test]
+    fn test_if_range() {
+        let meta: Box<dyn DavMetaData> = Box::new(Meta);
+        let tag = ETag::from_meta(&meta);
+        let modified = meta.modified().ok();
+        let if_range = |value: &str| {
+            let mut headers = http::HeaderMap::new();
+            headers.insert("if-range", value.parse().unwrap());
+            let hdr = headers.typed_get::<davheaders::IfRange>().unwrap();
+            ifrange_match(&hdr, tag.as_ref(), modified)
+        };
+        assert!(if_range(&etag(false)));
+        assert!(!if_range(&etag(true)));
+        assert!(!if_range("\"xyzzy\""));
+        assert!(if_range(AT));
+        assert!(!if_range(BEFORE));
+
+        // a modification time in the last second is a weak validator.
+        let now = SystemTime::now();
+        let date = crate::util::systemtime_to_httpdate(now);
+        let mut headers = http::HeaderMap::new();
+        headers.insert("if-range", date.parse().unwrap());
+        let hdr = headers.typed_get::<davheaders::IfRange>().unwrap();
+        assert!(!ifrange_match(&hdr, tag.as_ref(), Some(now)));
+    }
+}
@@ -2,27 +2,41 @@
 // This module contains the main entry point of the library,
 // DavHandler.
 //
+use std::collections::HashMap;
 use std::error::Error as StdError;
 use std::io;
 use std::sync::Arc;
+use std::time::Duration;
 
-use bytes::{self, buf::Buf};
+use bytes::{self, buf::Buf, Bytes};
 use futures_util::stream::Stream;
 use headers::HeaderMapExt;
 use http::{Request, Response, StatusCode};
 use http_body::Body as HttpBody;
+use regex::Regex;
+use tokio::sync::Semaphore;
+use xmltree::Element;
 
 use crate::body::{Body, StreamBody};
+use crate::cancel::CancelToken;
 use crate::davheaders;
 use crate::davpath::DavPath;
-use crate::util::{dav_method, DavMethod, DavMethodSet};
+use crate::util::{
+    conflicting_headers, dav_method, dav_xml_error, forwarded_origin, prefers_text_xml, DavMethod,
+    DavMethodSet,
+};
 
 use crate::errors::DavError;
 use crate::fs::*;
 use crate::ls::*;
+use crate::metrics::{DavMetrics, Served, ServedBytes};
+use crate::shutdown::Shutdown;
 use crate::voidfs::{is_voidfs, VoidFs};
 use crate::DavResult;
 
+// Default maximum size of a body that is buffered for an HTTP/1.0 client.
+const HTTP10_BUFFER_SIZE: usize = 1024 * 1024;
+
 /// The webdav handler struct.
 ///
 /// The `new` and `build` etc methods are used to instantiate a handler.
@@ -33,6 +47,14 @@ pub struct DavHandler {
     pub(crate) config: Arc<DavConfig>,
 }
 
+// Callback that decides if a member of a collection is listed.
+pub(crate) type CanRead = dyn Fn(Option<&str>, &DavPath) -> bool + Send + Sync;
+pub(crate) type PrincipalSearch = dyn Fn(&[PropertySearch], bool) -> Vec<DavPath> + Send + Sync;
+pub(crate) type AddressDataValidator = dyn Fn(&[u8]) -> bool + Send + Sync;
+pub(crate) type PrincipalQuota = dyn Fn(Option<&str>) -> Option<(u64, Option<u64>)> + Send + Sync;
+pub(crate) type ResourceTypes = dyn Fn(&DavPath) -> Vec<Element> + Send + Sync;
+pub(crate) type RedirectRules = dyn Fn(&DavPath) -> Option<(StatusCode, String)> + Send + Sync;
+
 /// Configuration of the handler.
 #[derive(Default)]
 pub struct DavConfig {
@@ -57,6 +79,108 @@ pub struct DavConfig {
     pub(crate) read_buf_size: Option<usize>,
     // Does GET on a file return 302 redirect.
     pub(crate) redirect: Option<bool>,
+    // Max bytes to read-and-discard to serve a range from a non-seekable file.
+    pub(crate) range_discard_max: Option<u64>,
+    // Check the privileges returned by DavFileSystem::get_acl.
+    pub(crate) enforce_acl: Option<bool>,
+    // Product token for the Server header.
+    pub(crate) server: Option<String>,
+    // Metrics collector.
+    pub(crate) metrics: Option<Box<dyn DavMetrics>>,
+    // Extra extension to mime type mappings.
+    pub(crate) mime_types: Option<Arc<HashMap<String, String>>>,
+    // Workarounds for Microsoft clients.
+    pub(crate) microsoft_compat: Option<bool>,
+    // Max time to wait for the next chunk of the request body.
+    pub(crate) read_timeout: Option<Duration>,
+    // Max time for the client to take a chunk of a GET response.
+    pub(crate) write_timeout: Option<Duration>,
+    // Error pages to serve to browsers, by status code.
+    pub(crate) error_pages: Option<Arc<HashMap<StatusCode, DavPath>>>,
+    // Reject paths with duplicate slashes instead of collapsing them.
+    pub(crate) reject_duplicate_slashes: Option<bool>,
+    // Show symlinks that point to nothing in listings.
+    pub(crate) show_broken_symlinks: Option<bool>,
+    // Accept Add-Member POST on collections.
+    pub(crate) add_member: Option<bool>,
+    // Max number of ranges in a Range request.
+    pub(crate) max_ranges: Option<usize>,
+    // Return 416 instead of 200 when over max_ranges.
+    pub(crate) max_ranges_reject: Option<bool>,
+    // Show file-type icons in the autoindex.
+    pub(crate) autoindex_icons: Option<bool>,
+    // Permissions for newly created files.
+    pub(crate) file_mode: Option<u32>,
+    // Permissions for newly created directories.
+    pub(crate) dir_mode: Option<u32>,
+    // Default number of entries per autoindex page.
+    pub(crate) autoindex_page_size: Option<usize>,
+    // Redirect GET of "/file/" to "/file" instead of 404.
+    pub(crate) file_slash_redirect: Option<bool>,
+    // Trust the X-Forwarded-*/Forwarded headers for generated URLs.
+    pub(crate) trust_forwarded: Option<bool>,
+    // Which members of a collection are listed.
+    pub(crate) can_read: Option<Arc<CanRead>>,
+    // Follow symlinks in the autoindex.
+    pub(crate) autoindex_follow_symlinks: Option<bool>,
+    // Media type for XML response bodies.
+    pub(crate) xml_content_type: Option<String>,
+    // Sync uploaded files to stable storage.
+    pub(crate) fsync_on_put: Option<bool>,
+    // Principals provider for principal-property-search.
+    pub(crate) principal_search: Option<Arc<PrincipalSearch>>,
+    // Serve a whole-file range as 200.
+    pub(crate) full_range_as_ok: Option<bool>,
+    // Read the autoindex order from a .davsort file.
+    pub(crate) autoindex_sort_file: Option<bool>,
+    // Methods that are always refused.
+    pub(crate) disabled_methods: Option<DavMethodSet>,
+    // Callback for the bytes served per GET.
+    pub(crate) served_bytes: Option<Arc<ServedBytes>>,
+    // Callback that checks vCards stored in an address book.
+    pub(crate) address_data_validator: Option<Arc<AddressDataValidator>>,
+    // Responses for well-known paths, like /favicon.ico.
+    pub(crate) fixed_responses: Option<Arc<HashMap<String, (StatusCode, Bytes)>>>,
+    // Limits the number of file bodies served at the same time.
+    pub(crate) get_limit: Option<Arc<Semaphore>>,
+    // Callback for the quota of a principal.
+    pub(crate) principal_quota: Option<Arc<PrincipalQuota>>,
+    // Reject requests with conflicting headers.
+    pub(crate) strict: Option<bool>,
+    // Format of the times in the autoindex.
+    pub(crate) autoindex_time_format: Option<String>,
+    // Offset from UTC of the times in the autoindex.
+    pub(crate) autoindex_utc_offset: Option<time::UtcOffset>,
+    // Redirect GET on the root to this path.
+    pub(crate) root_redirect: Option<(String, StatusCode)>,
+    // Files that never change.
+    pub(crate) immutable_paths: Option<Regex>,
+    // Redirects for /.well-known/ service discovery.
+    pub(crate) well_known: Option<Arc<HashMap<String, String>>>,
+    // Graceful shutdown.
+    pub(crate) shutdown: Option<Shutdown>,
+    // Advertised in CARDDAV:supported-address-data.
+    pub(crate) address_data_types: Option<Arc<Vec<(String, String)>>>,
+    // Extra DAV:resourcetype elements of collections.
+    pub(crate) resourcetypes: Option<Arc<ResourceTypes>>,
+    // Cancellation of this request.
+    pub(crate) cancel: Option<CancelToken>,
+    // Maximum depth of a COPY.
+    pub(crate) max_copy_depth: Option<usize>,
+    // Maximum number of resources a COPY copies.
+    pub(crate) max_copy_nodes: Option<usize>,
+    // Files smaller than this are not served in ranges.
+    pub(crate) accept_ranges_min_size: Option<u64>,
+    // Take Depth: 2 and up as infinity.
+    pub(crate) lenient_depth: Option<bool>,
+    // Redirects before the filesystem is consulted.
+    pub(crate) redirect_rules: Option<Arc<RedirectRules>>,
+    // Maximum number of resources a SEARCH looks at.
+    pub(crate) max_search_nodes: Option<usize>,
+    // Bodies buffered for HTTP/1.0 clients up to this size.
+    pub(crate) http10_buffer_size: Option<usize>,
+    // gzip or deflate the generated responses.
+    pub(crate) compression: Option<bool>,
 }
 
 impl DavConfig {
@@ -123,13 +247,21 @@ impl DavConfig {
     }
 
     /// Indexfile to show (index.html, usually).
+    ///
+    /// If the directory has no such file and `autoindex` is set to `true`,
+    /// a directory index is generated instead.
     pub fn indexfile(self, indexfile: impl Into<String>) -> Self {
         let mut this = self;
         this.indexfile = Some(indexfile.into());
         this
     }
 
-    /// Read buffer size in bytes
+    /// Read buffer size in bytes (default 16 KiB).
+    ///
+    /// This is the size of the chunks a `GET` reads from the file and
+    /// hands to the response body. Larger buffers mean fewer reads and
+    /// wakeups for big downloads, at the cost of memory per request.
+    /// `examples/get-throughput.rs` compares a few sizes.
     pub fn read_buf_size(self, size: usize) -> Self {
         let mut this = self;
         this.read_buf_size = Some(size);
@@ -142,6 +274,657 @@ impl DavConfig {
         this
     }
 
+    /// Serve ranges from files that cannot seek by reading and discarding data.
+    ///
+    /// Some `DavFile` implementations are forward-only streams. By default a
+    /// range request on such a file fails with `416 Range Not Satisfiable`.
+    /// With this option set, the data before the start of the range(s) is read
+    /// and thrown away instead, up to at most `max` bytes in total. That is
+    /// wasteful, so keep `max` small. If more than `max` bytes would have
+    /// to be discarded, or for a suffix range (`bytes=-n`), the entire
+    /// file is sent with a `200 OK` status.
+    pub fn range_discard_max(self, max: u64) -> Self {
+        let mut this = self;
+        this.range_discard_max = Some(max);
+        this
+    }
+
+    /// Check the privileges returned by [`DavFileSystem::get_acl`] before
+    /// handling a request (default is false).
+    ///
+    /// If the privilege needed for the request method is missing, the
+    /// request is denied with a `403 Forbidden` and a `DAV:need-privileges`
+    /// error body (RFC3744, 7.1.1). This is checked on the request path,
+    /// the `Destination` of COPY and MOVE, the source of a REBIND and
+    /// the scopes of a SEARCH.
+    ///
+    /// Note that the default `get_acl` grants no privileges at all, so
+    /// with a filesystem that does not implement it (like `MemFs` and
+    /// `LocalFs`) every request but OPTIONS is denied.
+    ///
+    /// [`DavFileSystem::get_acl`]: fs/trait.DavFileSystem.html#method.get_acl
+    pub fn enforce_acl(self, enforce: bool) -> Self {
+        let mut this = self;
+        this.enforce_acl = Some(enforce);
+        this
+    }
+
+    /// Set the product token sent in the `Server` response header.
+    ///
+    /// The default is `dav-server-rs/<version>`. An empty string means
+    /// that no `Server` header is sent at all.
+    pub fn server(self, server: impl Into<String>) -> Self {
+        let mut this = self;
+        this.server = Some(server.into());
+        this
+    }
+
+    /// Set the metrics collector to use.
+    pub fn metrics(self, metrics: Box<dyn DavMetrics>) -> Self {
+        let mut this = self;
+        this.metrics = Some(metrics);
+        this
+    }
+
+    /// Set the mime type for files with extension `ext`.
+    ///
+    /// This is consulted before the builtin table, so it can be used
+    /// to add new types or to override the builtin ones. Call it
+    /// multiple times to add more than one mapping.
+    ///
+    /// Text types get `; charset=utf-8` appended in responses. To use a
+    /// different charset, include it in `mime_type`, as in
+    /// `text/plain; charset=iso-8859-1`. A type that has parameters
+    /// is sent unchanged.
+    pub fn mime_type(self, ext: impl Into<String>, mime_type: impl Into<String>) -> Self {
+        let mut this = self;
+        let ext = ext.into().trim_start_matches('.').to_lowercase();
+        let types = this.mime_types.get_or_insert_with(Default::default);
+        Arc::make_mut(types).insert(ext, mime_type.into());
+        this
+    }
+
+    /// Enable workarounds for Microsoft clients (default is false).
+    ///
+    /// What is covered:
+    ///
+    /// - `Brief: t` on `PROPFIND`: properties that were not found are left
+    ///   out of the response instead of being listed with a `404` status.
+    ///   The Windows WebDAV redirector and Office send this to cut down on
+    ///   the size of the response.
+    ///
+    /// Some other quirks are handled for all clients, flag or not:
+    /// `OPTIONS` always advertises `MS-Author-Via: DAV`, `GET` always
+    /// serves the file itself (what `Translate: f` asks for), `PROPPATCH`
+    /// of the `Win32*` properties always succeeds, and a `404` to a client
+    /// with `Microsoft` in its user-agent is sent with headers that
+    /// keep Windows from caching it.
+    pub fn microsoft_compat(self, enable: bool) -> Self {
+        let mut this = self;
+        this.microsoft_compat = Some(enable);
+        this
+    }
+
+    /// Abort a request if no data arrives on the request body for
+    /// `timeout` (default is no timeout).
+    ///
+    /// The request fails with a `408 Request Timeout` and the connection
+    /// is closed. This needs a tokio runtime with the time driver enabled.
+    pub fn read_timeout(self, timeout: Duration) -> Self {
+        let mut this = self;
+        this.read_timeout = Some(timeout);
+        this
+    }
+
+    /// Abort sending a file if the client did not take the previous
+    /// chunk of data within `timeout` (default is no timeout).
+    ///
+    /// The file is then sent from a task of its own, so this also works
+    /// for a client that stops reading entirely. This needs a tokio
+    /// runtime with the time driver enabled.
+    pub fn write_timeout(self, timeout: Duration) -> Self {
+        let mut this = self;
+        this.write_timeout = Some(timeout);
+        this
+    }
+
+    /// Serve the file at `path` as the body of an error response with
+    /// status `status` (default is an empty body).
+    ///
+    /// Only used for `GET` requests that accept `text/html`, i.e. web
+    /// browsers. The page is read through the filesystem on every error,
+    /// so it can be changed without a restart. If it cannot be read, the
+    /// empty body is sent as usual.
+    pub fn error_page(self, status: StatusCode, path: DavPath) -> Self {
+        let mut this = self;
+        let pages = this.error_pages.get_or_insert_with(Default::default);
+        Arc::make_mut(pages).insert(status, path);
+        this
+    }
+
+    /// Reject request paths with duplicate slashes, like `/a//b`, with a
+    /// `400 Bad Request` (default is false).
+    ///
+    /// By default duplicate slashes are collapsed, so `/a//b` is the same
+    /// resource as `/a/b`.
+    pub fn reject_duplicate_slashes(self, reject: bool) -> Self {
+        let mut this = self;
+        this.reject_duplicate_slashes = Some(reject);
+        this
+    }
+
+    /// Show symbolic links that point to nothing in directory listings
+    /// (default is false, they are left out).
+    ///
+    /// In the HTML index they are shown without a link, and `PROPFIND`
+    /// reports them with a `404 Not Found` status. For `PROPFIND` this only
+    /// matters if symbolic links are not hidden, see `hide_symlinks`.
+    pub fn show_broken_symlinks(self, show: bool) -> Self {
+        let mut this = self;
+        this.show_broken_symlinks = Some(show);
+        this
+    }
+
+    /// Accept RFC 5995 Add-Member `POST` requests on collections (default false).
+    ///
+    /// The server picks a unique name for the new member, and collections
+    /// advertise the `DAV:add-member` property.
+    pub fn add_member(self, enable: bool) -> Self {
+        let mut this = self;
+        this.add_member = Some(enable);
+        this
+    }
+
+    /// Maximum number of ranges served for a single `Range` request
+    /// (default is no limit).
+    ///
+    /// If a request asks for more ranges, overlapping and adjacent ranges
+    /// are merged first. If that still leaves more than `max` ranges, the
+    /// entire file is sent with a `200 OK` status, or the request is
+    /// refused with `416 Range Not Satisfiable` if `max_ranges_reject` is set.
+    pub fn max_ranges(self, max: usize) -> Self {
+        let mut this = self;
+        this.max_ranges = Some(max);
+        this
+    }
+
+    /// Refuse requests with too many ranges with `416 Range Not Satisfiable`,
+    /// instead of sending the entire file (default false). See `max_ranges`.
+    pub fn max_ranges_reject(self, reject: bool) -> Self {
+        let mut this = self;
+        this.max_ranges_reject = Some(reject);
+        this
+    }
+
+    /// Show a small file-type icon in front of each entry of the
+    /// HTML index (default false).
+    ///
+    /// The icons are plain emoji, so nothing extra is loaded by the browser.
+    pub fn autoindex_icons(self, icons: bool) -> Self {
+        let mut this = self;
+        this.autoindex_icons = Some(icons);
+        this
+    }
+
+    /// Permissions (unix mode bits, like `0o644`) for files created by
+    /// `PUT` or `POST` (default: up to the filesystem).
+    ///
+    /// This is passed to the filesystem as a hint; backends without
+    /// unix permissions ignore it. The process umask still applies.
+    pub fn file_mode(self, mode: u32) -> Self {
+        let mut this = self;
+        this.file_mode = Some(mode);
+        this
+    }
+
+    /// Permissions (unix mode bits, like `0o755`) for directories created
+    /// by `MKCOL` and `COPY` (default: up to the filesystem). See `file_mode`.
+    pub fn dir_mode(self, mode: u32) -> Self {
+        let mut this = self;
+        this.dir_mode = Some(mode);
+        this
+    }
+
+    /// Number of entries per page of the HTML index (default is
+    /// everything on one page).
+    ///
+    /// A client can always pick a page and page size with the
+    /// `page` and `per` query parameters, for example `?page=2&per=500`.
+    pub fn autoindex_page_size(self, size: usize) -> Self {
+        let mut this = self;
+        this.autoindex_page_size = Some(size);
+        this
+    }
+
+    /// What to do with a `GET` or `HEAD` of a file with a trailing slash in
+    /// the URL, like `/file.txt/`. If set to true, redirect to the URL
+    /// without the slash, otherwise return `404 Not Found` (the default).
+    pub fn file_slash_redirect(self, redirect: bool) -> Self {
+        let mut this = self;
+        this.file_slash_redirect = Some(redirect);
+        this
+    }
+
+    /// Use the `Forwarded`, `X-Forwarded-Proto` and `X-Forwarded-Host`
+    /// headers to build absolute URLs for `Location` headers (default false).
+    ///
+    /// Only enable this behind a reverse proxy that sets (or removes) these
+    /// headers, since a client can send anything it likes.
+    pub fn trust_forwarded(self, trust: bool) -> Self {
+        let mut this = self;
+        this.trust_forwarded = Some(trust);
+        this
+    }
+
+    /// Leave members of a collection out of listings unless `can_read` allows them.
+    ///
+    /// The callback gets the principal and the path of each member. Members
+    /// for which it returns `false` are not shown in the autoindex and are not
+    /// enumerated by `PROPFIND` on the collection. The collection itself is
+    /// not checked. The default is to list everything.
+    pub fn can_read<F>(self, can_read: F) -> Self
+    where
+        F: Fn(Option<&str>, &DavPath) -> bool + Send + Sync + 'static,
+    {
+        let mut this = self;
+        this.can_read = Some(Arc::new(can_read));
+        this
+    }
+
+    /// Follow symbolic links in the autoindex (default is true).
+    ///
+    /// When true, a link to a directory is listed as a directory, with a
+    /// trailing slash. When false, links are listed as plain `[LINK]`
+    /// entries, whatever they point to.
+    pub fn autoindex_follow_symlinks(self, follow: bool) -> Self {
+        let mut this = self;
+        this.autoindex_follow_symlinks = Some(follow);
+        this
+    }
+
+    /// Set the media type of XML response bodies, like multistatus and
+    /// error bodies.
+    ///
+    /// By default this is `application/xml; charset=utf-8`, or
+    /// `text/xml; charset=utf-8` if the request's `Accept` header prefers
+    /// that. Setting it here uses the given type for every request, for
+    /// clients that misbehave with one or the other.
+    pub fn xml_content_type(self, content_type: impl Into<String>) -> Self {
+        let mut this = self;
+        this.xml_content_type = Some(content_type.into());
+        this
+    }
+
+    /// Sync uploaded files to stable storage before answering a `PUT`
+    /// or `POST` (default is false).
+    ///
+    /// The data is always flushed to the backend, so a following `GET`
+    /// sees it. This also makes it survive a crash, at the cost of an
+    /// `fsync` per upload on backends that implement `DavFile::sync_all`.
+    pub fn fsync_on_put(self, fsync: bool) -> Self {
+        let mut this = self;
+        this.fsync_on_put = Some(fsync);
+        this
+    }
+
+    /// Answer `principal-property-search` REPORTs with `principal_search`.
+    ///
+    /// The callback gets the search terms and whether any of them (`true`)
+    /// or all of them (`false`) must match, and returns the paths of the
+    /// matching principal resources. Their properties are then read from
+    /// the filesystem. Without a callback the REPORT fails with
+    /// `403 DAV:supported-report`.
+    pub fn principal_search<F>(self, principal_search: F) -> Self
+    where
+        F: Fn(&[PropertySearch], bool) -> Vec<DavPath> + Send + Sync + 'static,
+    {
+        let mut this = self;
+        this.principal_search = Some(Arc::new(principal_search));
+        this
+    }
+
+    /// Serve a single range that covers the whole file as a plain
+    /// `200 OK` without `Content-Range`, instead of `206 Partial Content`
+    /// (default false).
+    ///
+    /// `Range: bytes=0-` is the usual example. Some caches handle the
+    /// `200` better.
+    pub fn full_range_as_ok(self, ok: bool) -> Self {
+        let mut this = self;
+        this.full_range_as_ok = Some(ok);
+        this
+    }
+
+    /// Read the default order of the HTML index from a `.davsort` file
+    /// in the directory (default is false).
+    ///
+    /// The file contains for example `sort=time order=desc`. `sort` is
+    /// one of `name`, `time` or `size`, `order` is `asc` or `desc`. The
+    /// `sort` and `order` query parameters override it, and anything that
+    /// does not parse is ignored. Directories are always listed first.
+    pub fn autoindex_sort_file(self, enable: bool) -> Self {
+        let mut this = self;
+        this.autoindex_sort_file = Some(enable);
+        this
+    }
+
+    /// Methods to refuse, whatever the filesystem supports (default none).
+    ///
+    /// This is applied on top of `methods`. A disabled method is answered
+    /// with `405 Method Not Allowed`, and it is left out of the `Allow`
+    /// header of `OPTIONS` and of that response.
+    pub fn disabled_methods(self, methods: DavMethodSet) -> Self {
+        let mut this = self;
+        this.disabled_methods = Some(methods);
+        this
+    }
+
+    /// Call `served_bytes` for every file body that a `GET` sends.
+    ///
+    /// The callback gets a `metrics::Served` record with the path, the
+    /// status, whether it was a range request, and the number of bytes
+    /// that were actually sent. It is called when the body is done, and
+    /// also when the client disconnects halfway, with the partial count.
+    pub fn served_bytes<F>(self, served_bytes: F) -> Self
+    where
+        F: Fn(&Served) + Send + Sync + 'static,
+    {
+        let mut this = self;
+        this.served_bytes = Some(Arc::new(served_bytes));
+        this
+    }
+
+    /// Check vCards that are `PUT` into an address book with
+    /// `validator` before they are stored.
+    ///
+    /// The validator gets the request body and returns whether it is a
+    /// valid vCard, e.g. by parsing it with `vcard4::parse`. Invalid data
+    /// is refused with `403 CARDDAV:valid-address-data`, a `Content-Type`
+    /// other than `text/vcard` with `415 Unsupported Media Type`
+    /// (RFC 6352, 6.3.2.1). Uploads to other collections are not checked.
+    pub fn address_data_validator<F>(self, validator: F) -> Self
+    where
+        F: Fn(&[u8]) -> bool + Send + Sync + 'static,
+    {
+        let mut this = self;
+        this.address_data_validator = Some(Arc::new(validator));
+        this
+    }
+
+    /// Answer `GET` and `HEAD` requests for `path` with `status` and
+    /// `body`, without touching the filesystem.
+    ///
+    /// Meant for the requests that browsers and crawlers make on their
+    /// own, like `/favicon.ico` or `/robots.txt`. `path` is compared with
+    /// the path of the request URI as is, before the prefix is stripped
+    /// and before any access checks. The `Content-Type` follows from the
+    /// extension of `path`.
+    pub fn fixed_response(self, path: &str, status: StatusCode, body: impl Into<Bytes>) -> Self {
+        let mut this = self;
+        let responses = this.fixed_responses.get_or_insert_with(Default::default);
+        Arc::make_mut(responses).insert(path.to_string(), (status, body.into()));
+        this
+    }
+
+    /// Serve at most `limit` file bodies at the same time (default no
+    /// limit).
+    ///
+    /// Each `GET` of a file holds an open file and a read buffer until the
+    /// body is done, or until the client goes away. A `GET` over the limit
+    /// is answered with `503 Service Unavailable` and `Retry-After: 1`.
+    pub fn max_concurrent_gets(self, limit: usize) -> Self {
+        let mut this = self;
+        this.get_limit = Some(Arc::new(Semaphore::new(limit)));
+        this
+    }
+
+    /// Report the quota of the principal, instead of that of the
+    /// filesystem, in `DAV:quota-available-bytes` and
+    /// `DAV:quota-used-bytes`.
+    ///
+    /// The callback gets the principal and returns the space used and
+    /// the total space (used + available) allotted to it, like
+    /// `DavFileSystem::get_quota`. If it returns `None`, the quota of the
+    /// filesystem is used.
+    pub fn principal_quota<F>(self, quota: F) -> Self
+    where
+        F: Fn(Option<&str>) -> Option<(u64, Option<u64>)> + Send + Sync + 'static,
+    {
+        let mut this = self;
+        this.principal_quota = Some(Arc::new(quota));
+        this
+    }
+
+    /// Reject requests with headers that contradict each other with
+    /// `400 Bad Request` (default false).
+    ///
+    /// Meant for finding client bugs. The conflicts that are detected:
+    ///
+    /// - `If-Match` together with `If-None-Match: *`, which can never
+    ///   both be true.
+    /// - `Content-Range` on a `PUT` or `PATCH` with a chunked body, so
+    ///   the length of the range is not known.
+    /// - `Range` on a `GET` or `HEAD` that sends a body (a non-zero
+    ///   `Content-Length`).
+    /// - More than one `Range` header on a `GET` or `HEAD`. Without
+    ///   strict mode, only the first one is used.
+    pub fn strict(self, strict: bool) -> Self {
+        let mut this = self;
+        this.strict = Some(strict);
+        this
+    }
+
+    /// Format of the modification times in the HTML index.
+    ///
+    /// This is a version 2 format description of the `time` crate. The
+    /// default is `[year]-[month]-[day] [hour]:[minute]`,
+    /// followed by `Z` for UTC or by the offset otherwise, like
+    /// `2024-01-31 12:00Z`. A format that does not parse is ignored.
+    pub fn autoindex_time_format(self, format: impl Into<String>) -> Self {
+        let mut this = self;
+        this.autoindex_time_format = Some(format.into());
+        this
+    }
+
+    /// Show the modification times in the HTML index at this offset from
+    /// UTC (default is UTC).
+    ///
+    /// Pass the local offset of the server to show local times.
+    pub fn autoindex_utc_offset(self, offset: time::UtcOffset) -> Self {
+        let mut this = self;
+        this.autoindex_utc_offset = Some(offset);
+        this
+    }
+
+    /// Redirect `GET` and `HEAD` on the root collection to `path`, with
+    /// `301 Moved Permanently` if `permanent` is set, `302 Found`
+    /// otherwise (default no redirect).
+    ///
+    /// `path` is relative to the prefix, like `/files/`. Other methods on
+    /// the root, like `PROPFIND` and `OPTIONS`, are not redirected.
+    pub fn root_redirect(self, path: impl Into<String>, permanent: bool) -> Self {
+        let mut this = self;
+        let status = match permanent {
+            true => StatusCode::MOVED_PERMANENTLY,
+            false => StatusCode::FOUND,
+        };
+        this.root_redirect = Some((path.into(), status));
+        this
+    }
+
+    /// Send `Cache-Control: public, max-age=31536000, immutable` with
+    /// files whose path matches the regular expression `pattern`
+    /// (default none).
+    ///
+    /// Meant for content-addressed files, like `app.3f2a9c.js`, that
+    /// never change. The path is URL encoded and does not include the
+    /// prefix, for example `^/assets/.*\.[0-9a-f]{6,}\.(js|css)$`.
+    ///
+    /// # Panics
+    ///
+    /// If `pattern` is not a valid regular expression.
+    pub fn immutable_paths(self, pattern: &str) -> Self {
+        let mut this = self;
+        this.immutable_paths = Some(Regex::new(pattern).expect("immutable_paths: invalid regex"));
+        this
+    }
+
+    /// Redirect `/.well-known/<service>` to `context_path` with
+    /// `301 Moved Permanently`, for service discovery as in RFC 6764.
+    ///
+    /// For example `well_known("carddav", "/dav/addressbooks/")`. The
+    /// request path is compared as is, before the prefix is stripped and
+    /// before any access checks, and for any method: clients probe with
+    /// `GET` as well as `PROPFIND`. `context_path` is sent as is, so it
+    /// must include the prefix.
+    pub fn well_known(self, service: &str, context_path: impl Into<String>) -> Self {
+        let mut this = self;
+        let paths = this.well_known.get_or_insert_with(Default::default);
+        let path = format!("/.well-known/{}", service);
+        Arc::make_mut(paths).insert(path, context_path.into());
+        this
+    }
+
+    /// Take part in the graceful shutdown coordinated by `shutdown`
+    /// (default none), see the `shutdown` module.
+    pub fn shutdown(self, shutdown: Shutdown) -> Self {
+        let mut this = self;
+        this.shutdown = Some(shutdown);
+        this
+    }
+
+    /// Add a media type and version to `CARDDAV:supported-address-data`
+    /// of address books, like `("text/vcard", "4.0")`.
+    ///
+    /// Without any, `text/vcard` versions 3.0 and 4.0 are advertised.
+    pub fn address_data_type(self, content_type: &str, version: &str) -> Self {
+        let mut this = self;
+        let types = this.address_data_types.get_or_insert_with(Default::default);
+        Arc::make_mut(types).push((content_type.to_string(), version.to_string()));
+        this
+    }
+
+    /// Add elements to the `DAV:resourcetype` of collections, next to
+    /// `<D:collection/>`, like a marker that a vendor's own clients look
+    /// for. The callback gets the path, as the filesystem sees it, and
+    /// returns the elements. Give them a namespace; prefixes are
+    /// assigned when the response is written.
+    ///
+    /// Where the type comes from is up to the callback. The filesystem
+    /// can remember it from an extended `MKCOL` or a `PROPPATCH`, for
+    /// example.
+    pub fn resourcetypes<F>(self, types: F) -> Self
+    where
+        F: Fn(&DavPath) -> Vec<Element> + Send + Sync + 'static,
+    {
+        let mut this = self;
+        this.resourcetypes = Some(Arc::new(types));
+        this
+    }
+
+    /// Stop working on the request when `token` is cancelled (default
+    /// none). Usually set per request with `DavHandler::handle_with`,
+    /// see the `cancel` module.
+    pub fn cancel(self, token: CancelToken) -> Self {
+        let mut this = self;
+        this.cancel = Some(token);
+        this
+    }
+
+    /// Maximum depth of a `COPY` (default unlimited). A collection with
+    /// members deeper than `depth` levels is not copied: nothing is
+    /// created or removed at the destination, and that member and the
+    /// request URI get a `507 Insufficient Storage` status in the
+    /// multistatus response.
+    pub fn max_copy_depth(self, depth: usize) -> Self {
+        let mut this = self;
+        this.max_copy_depth = Some(depth);
+        this
+    }
+
+    /// Maximum number of resources a `COPY` copies (default unlimited).
+    /// A larger collection is not copied, like for `max_copy_depth`.
+    pub fn max_copy_nodes(self, nodes: usize) -> Self {
+        let mut this = self;
+        this.max_copy_nodes = Some(nodes);
+        this
+    }
+
+    /// Leave out `Accept-Ranges: bytes` for files smaller than `size`
+    /// bytes, and send them whole, ignoring `Range` (default 0: always
+    /// advertise ranges).
+    ///
+    /// Ranges of tiny files are rarely worth it, and some clients
+    /// behave better with a plain `200 OK`.
+    pub fn accept_ranges_min_size(self, size: u64) -> Self {
+        let mut this = self;
+        this.accept_ranges_min_size = Some(size);
+        this
+    }
+
+    /// Take a `Depth` header with an integer larger than 1, like
+    /// `Depth: 2`, as `Depth: infinity` (default false: `400 Bad Request`).
+    ///
+    /// RFC 4918 only allows 0, 1 and infinity, but a few clients send
+    /// the number of levels they want. Note that they then get all
+    /// levels, and that `PROPFIND` with infinite depth is refused.
+    pub fn lenient_depth(self, lenient: bool) -> Self {
+        let mut this = self;
+        this.lenient_depth = Some(lenient);
+        this
+    }
+
+    /// Redirect requests before the filesystem is consulted, for
+    /// example from legacy paths to their new location.
+    ///
+    /// The callback gets the path, as the filesystem sees it, and
+    /// returns the status, like `301 Moved Permanently`, and the
+    /// location, or `None` to handle the request as usual. It runs for
+    /// any method, after the `well_known` redirects. Like there, the
+    /// location is sent as is, so it must include the prefix.
+    pub fn redirect_rules<F>(self, rules: F) -> Self
+    where
+        F: Fn(&DavPath) -> Option<(StatusCode, String)> + Send + Sync + 'static,
+    {
+        let mut this = self;
+        this.redirect_rules = Some(Arc::new(rules));
+        this
+    }
+
+    /// Maximum number of resources a `SEARCH` looks at (default
+    /// unlimited). When it is reached the search stops, and the request
+    /// URI gets a `507 Insufficient Storage` status in the multistatus
+    /// response, to tell the client that the results are incomplete.
+    pub fn max_search_nodes(self, nodes: usize) -> Self {
+        let mut this = self;
+        this.max_search_nodes = Some(nodes);
+        this
+    }
+
+    /// Maximum size of a response body of unknown length that is
+    /// buffered for an HTTP/1.0 client, to send it with a
+    /// `Content-Length` (default 1 MiB).
+    ///
+    /// HTTP/1.0 has no chunked encoding, so a longer body is sent as
+    /// is, and the end of it is marked by closing the connection.
+    pub fn http10_buffer_size(self, size: usize) -> Self {
+        let mut this = self;
+        this.http10_buffer_size = Some(size);
+        this
+    }
+
+    /// Compress responses that the server generates itself, like the
+    /// HTML index, with gzip or deflate if the client accepts that
+    /// (default false).
+    ///
+    /// The responses get a `Vary: Accept-Encoding` header, and a weak
+    /// ETag when compressed. File contents are sent as they are.
+    pub fn compression(self, compress: bool) -> Self {
+        let mut this = self;
+        this.compression = Some(compress);
+        this
+    }
+
     fn merge(&self, new: DavConfig) -> DavConfig {
         DavConfig {
             prefix: new.prefix.or_else(|| self.prefix.clone()),
@@ -154,6 +937,71 @@ impl DavConfig {
             indexfile: new.indexfile.or_else(|| self.indexfile.clone()),
             read_buf_size: new.read_buf_size.or(self.read_buf_size),
             redirect: new.redirect.or(self.redirect),
+            range_discard_max: new.range_discard_max.or(self.range_discard_max),
+            enforce_acl: new.enforce_acl.or(self.enforce_acl),
+            server: new.server.or_else(|| self.server.clone()),
+            metrics: new.metrics.or_else(|| self.metrics.clone()),
+            mime_types: new.mime_types.or_else(|| self.mime_types.clone()),
+            microsoft_compat: new.microsoft_compat.or(self.microsoft_compat),
+            read_timeout: new.read_timeout.or(self.read_timeout),
+            write_timeout: new.write_timeout.or(self.write_timeout),
+            error_pages: new.error_pages.or_else(|| self.error_pages.clone()),
+            reject_duplicate_slashes: new
+                .reject_duplicate_slashes
+                .or(self.reject_duplicate_slashes),
+            show_broken_symlinks: new.show_broken_symlinks.or(self.show_broken_symlinks),
+            add_member: new.add_member.or(self.add_member),
+            max_ranges: new.max_ranges.or(self.max_ranges),
+            max_ranges_reject: new.max_ranges_reject.or(self.max_ranges_reject),
+            autoindex_icons: new.autoindex_icons.or(self.autoindex_icons),
+            file_mode: new.file_mode.or(self.file_mode),
+            dir_mode: new.dir_mode.or(self.dir_mode),
+            autoindex_page_size: new.autoindex_page_size.or(self.autoindex_page_size),
+            file_slash_redirect: new.file_slash_redirect.or(self.file_slash_redirect),
+            trust_forwarded: new.trust_forwarded.or(self.trust_forwarded),
+            can_read: new.can_read.or_else(|| self.can_read.clone()),
+            autoindex_follow_symlinks: new
+                .autoindex_follow_symlinks
+                .or(self.autoindex_follow_symlinks),
+            xml_content_type: new
+                .xml_content_type
+                .or_else(|| self.xml_content_type.clone()),
+            fsync_on_put: new.fsync_on_put.or(self.fsync_on_put),
+            principal_search: new
+                .principal_search
+                .or_else(|| self.principal_search.clone()),
+            full_range_as_ok: new.full_range_as_ok.or(self.full_range_as_ok),
+            autoindex_sort_file: new.autoindex_sort_file.or(self.autoindex_sort_file),
+            disabled_methods: new.disabled_methods.or(self.disabled_methods),
+            served_bytes: new.served_bytes.or_else(|| self.served_bytes.clone()),
+            address_data_validator: new
+                .address_data_validator
+                .or_else(|| self.address_data_validator.clone()),
+            fixed_responses: new.fixed_responses.or_else(|| self.fixed_responses.clone()),
+            get_limit: new.get_limit.or_else(|| self.get_limit.clone()),
+            principal_quota: new.principal_quota.or_else(|| self.principal_quota.clone()),
+            strict: new.strict.or(self.strict),
+            autoindex_time_format: new
+                .autoindex_time_format
+                .or_else(|| self.autoindex_time_format.clone()),
+            autoindex_utc_offset: new.autoindex_utc_offset.or(self.autoindex_utc_offset),
+            root_redirect: new.root_redirect.or_else(|| self.root_redirect.clone()),
+            immutable_paths: new.immutable_paths.or_else(|| self.immutable_paths.clone()),
+            well_known: new.well_known.or_else(|| self.well_known.clone()),
+            shutdown: new.shutdown.or_else(|| self.shutdown.clone()),
+            address_data_types: new
+                .address_data_types
+                .or_else(|| self.address_data_types.clone()),
+            resourcetypes: new.resourcetypes.or_else(|| self.resourcetypes.clone()),
+            cancel: new.cancel.or_else(|| self.cancel.clone()),
+            max_copy_depth: new.max_copy_depth.or(self.max_copy_depth),
+            max_copy_nodes: new.max_copy_nodes.or(self.max_copy_nodes),
+            accept_ranges_min_size: new.accept_ranges_min_size.or(self.accept_ranges_min_size),
+            lenient_depth: new.lenient_depth.or(self.lenient_depth),
+            redirect_rules: new.redirect_rules.or_else(|| self.redirect_rules.clone()),
+            max_search_nodes: new.max_search_nodes.or(self.max_search_nodes),
+            http10_buffer_size: new.http10_buffer_size.or(self.http10_buffer_size),
+            compression: new.compression.or(self.compression),
         }
     }
 }
@@ -173,6 +1021,57 @@ pub(crate) struct DavInner {
     pub indexfile: Option<String>,
     pub read_buf_size: Option<usize>,
     pub redirect: Option<bool>,
+    pub range_discard_max: Option<u64>,
+    pub enforce_acl: Option<bool>,
+    pub server: Option<String>,
+    pub metrics: Option<Box<dyn DavMetrics>>,
+    pub mime_types: Option<Arc<HashMap<String, String>>>,
+    pub microsoft_compat: Option<bool>,
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    pub error_pages: Option<Arc<HashMap<StatusCode, DavPath>>>,
+    pub reject_duplicate_slashes: Option<bool>,
+    pub show_broken_symlinks: Option<bool>,
+    pub add_member: Option<bool>,
+    pub max_ranges: Option<usize>,
+    pub max_ranges_reject: Option<bool>,
+    pub autoindex_icons: Option<bool>,
+    pub file_mode: Option<u32>,
+    pub dir_mode: Option<u32>,
+    pub autoindex_page_size: Option<usize>,
+    pub file_slash_redirect: Option<bool>,
+    pub trust_forwarded: Option<bool>,
+    pub can_read: Option<Arc<CanRead>>,
+    pub autoindex_follow_symlinks: Option<bool>,
+    pub xml_content_type: Option<String>,
+    pub fsync_on_put: Option<bool>,
+    pub principal_search: Option<Arc<PrincipalSearch>>,
+    pub full_range_as_ok: Option<bool>,
+    pub autoindex_sort_file: Option<bool>,
+    pub disabled_methods: Option<DavMethodSet>,
+    pub served_bytes: Option<Arc<ServedBytes>>,
+    pub address_data_validator: Option<Arc<AddressDataValidator>>,
+    pub fixed_responses: Option<Arc<HashMap<String, (StatusCode, Bytes)>>>,
+    pub get_limit: Option<Arc<Semaphore>>,
+    pub principal_quota: Option<Arc<PrincipalQuota>>,
+    pub strict: Option<bool>,
+    pub autoindex_time_format: Option<String>,
+    pub autoindex_utc_offset: Option<time::UtcOffset>,
+    pub root_redirect: Option<(String, StatusCode)>,
+    pub immutable_paths: Option<Regex>,
+    pub well_known: Option<Arc<HashMap<String, String>>>,
+    pub shutdown: Option<Shutdown>,
+    pub address_data_types: Option<Arc<Vec<(String, String)>>>,
+    pub resourcetypes: Option<Arc<ResourceTypes>>,
+    pub cancel: Option<CancelToken>,
+    pub max_copy_depth: Option<usize>,
+    pub max_copy_nodes: Option<usize>,
+    pub accept_ranges_min_size: Option<u64>,
+    pub lenient_depth: Option<bool>,
+    pub redirect_rules: Option<Arc<RedirectRules>>,
+    pub max_search_nodes: Option<usize>,
+    pub http10_buffer_size: Option<usize>,
+    pub compression: Option<bool>,
 }
 
 impl From<DavConfig> for DavInner {
@@ -188,6 +1087,57 @@ impl From<DavConfig> for DavInner {
             indexfile: cfg.indexfile,
             read_buf_size: cfg.read_buf_size,
             redirect: cfg.redirect,
+            range_discard_max: cfg.range_discard_max,
+            enforce_acl: cfg.enforce_acl,
+            server: cfg.server,
+            metrics: cfg.metrics,
+            mime_types: cfg.mime_types,
+            microsoft_compat: cfg.microsoft_compat,
+            read_timeout: cfg.read_timeout,
+            write_timeout: cfg.write_timeout,
+            error_pages: cfg.error_pages,
+            reject_duplicate_slashes: cfg.reject_duplicate_slashes,
+            show_broken_symlinks: cfg.show_broken_symlinks,
+            add_member: cfg.add_member,
+            max_ranges: cfg.max_ranges,
+            max_ranges_reject: cfg.max_ranges_reject,
+            autoindex_icons: cfg.autoindex_icons,
+            file_mode: cfg.file_mode,
+            dir_mode: cfg.dir_mode,
+            autoindex_page_size: cfg.autoindex_page_size,
+            file_slash_redirect: cfg.file_slash_redirect,
+            trust_forwarded: cfg.trust_forwarded,
+            can_read: cfg.can_read,
+            autoindex_follow_symlinks: cfg.autoindex_follow_symlinks,
+            xml_content_type: cfg.xml_content_type,
+            fsync_on_put: cfg.fsync_on_put,
+            principal_search: cfg.principal_search,
+            full_range_as_ok: cfg.full_range_as_ok,
+            autoindex_sort_file: cfg.autoindex_sort_file,
+            disabled_methods: cfg.disabled_methods,
+            served_bytes: cfg.served_bytes,
+            address_data_validator: cfg.address_data_validator,
+            fixed_responses: cfg.fixed_responses,
+            get_limit: cfg.get_limit,
+            principal_quota: cfg.principal_quota,
+            strict: cfg.strict,
+            autoindex_time_format: cfg.autoindex_time_format,
+            autoindex_utc_offset: cfg.autoindex_utc_offset,
+            root_redirect: cfg.root_redirect,
+            immutable_paths: cfg.immutable_paths,
+            well_known: cfg.well_known,
+            shutdown: cfg.shutdown,
+            address_data_types: cfg.address_data_types,
+            resourcetypes: cfg.resourcetypes,
+            cancel: cfg.cancel,
+            max_copy_depth: cfg.max_copy_depth,
+            max_copy_nodes: cfg.max_copy_nodes,
+            accept_ranges_min_size: cfg.accept_ranges_min_size,
+            lenient_depth: cfg.lenient_depth,
+            redirect_rules: cfg.redirect_rules,
+            max_search_nodes: cfg.max_search_nodes,
+            http10_buffer_size: cfg.http10_buffer_size,
+            compression: cfg.compression,
         }
     }
 }
@@ -209,6 +1159,57 @@ impl From<&DavConfig> for DavInner {
             indexfile: cfg.indexfile.clone(),
             read_buf_size: cfg.read_buf_size,
             redirect: cfg.redirect,
+            range_discard_max: cfg.range_discard_max,
+            enforce_acl: cfg.enforce_acl,
+            server: cfg.server.clone(),
+            metrics: cfg.metrics.clone(),
+            mime_types: cfg.mime_types.clone(),
+            microsoft_compat: cfg.microsoft_compat,
+            read_timeout: cfg.read_timeout,
+            write_timeout: cfg.write_timeout,
+            error_pages: cfg.error_pages.clone(),
+            reject_duplicate_slashes: cfg.reject_duplicate_slashes,
+            show_broken_symlinks: cfg.show_broken_symlinks,
+            add_member: cfg.add_member,
+            max_ranges: cfg.max_ranges,
+            max_ranges_reject: cfg.max_ranges_reject,
+            autoindex_icons: cfg.autoindex_icons,
+            file_mode: cfg.file_mode,
+            dir_mode: cfg.dir_mode,
+            autoindex_page_size: cfg.autoindex_page_size,
+            file_slash_redirect: cfg.file_slash_redirect,
+            trust_forwarded: cfg.trust_forwarded,
+            can_read: cfg.can_read.clone(),
+            autoindex_follow_symlinks: cfg.autoindex_follow_symlinks,
+            xml_content_type: cfg.xml_content_type.clone(),
+            fsync_on_put: cfg.fsync_on_put,
+            principal_search: cfg.principal_search.clone(),
+            full_range_as_ok: cfg.full_range_as_ok,
+            autoindex_sort_file: cfg.autoindex_sort_file,
+            disabled_methods: cfg.disabled_methods,
+            served_bytes: cfg.served_bytes.clone(),
+            address_data_validator: cfg.address_data_validator.clone(),
+            fixed_responses: cfg.fixed_responses.clone(),
+            get_limit: cfg.get_limit.clone(),
+            principal_quota: cfg.principal_quota.clone(),
+            strict: cfg.strict,
+            autoindex_time_format: cfg.autoindex_time_format.clone(),
+            autoindex_utc_offset: cfg.autoindex_utc_offset,
+            root_redirect: cfg.root_redirect.clone(),
+            immutable_paths: cfg.immutable_paths.clone(),
+            well_known: cfg.well_known.clone(),
+            shutdown: cfg.shutdown.clone(),
+            address_data_types: cfg.address_data_types.clone(),
+            resourcetypes: cfg.resourcetypes.clone(),
+            cancel: cfg.cancel.clone(),
+            max_copy_depth: cfg.max_copy_depth,
+            max_copy_nodes: cfg.max_copy_nodes,
+            accept_ranges_min_size: cfg.accept_ranges_min_size,
+            lenient_depth: cfg.lenient_depth,
+            redirect_rules: cfg.redirect_rules.clone(),
+            max_search_nodes: cfg.max_search_nodes,
+            http10_buffer_size: cfg.http10_buffer_size,
+            compression: cfg.compression,
         }
     }
 }
@@ -226,6 +1227,57 @@ impl Clone for DavInner {
             indexfile: self.indexfile.clone(),
             read_buf_size: self.read_buf_size,
             redirect: self.redirect,
+            range_discard_max: self.range_discard_max,
+            enforce_acl: self.enforce_acl,
+            server: self.server.clone(),
+            metrics: self.metrics.clone(),
+            mime_types: self.mime_types.clone(),
+            microsoft_compat: self.microsoft_compat,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            error_pages: self.error_pages.clone(),
+            reject_duplicate_slashes: self.reject_duplicate_slashes,
+            show_broken_symlinks: self.show_broken_symlinks,
+            add_member: self.add_member,
+            max_ranges: self.max_ranges,
+            max_ranges_reject: self.max_ranges_reject,
+            autoindex_icons: self.autoindex_icons,
+            file_mode: self.file_mode,
+            dir_mode: self.dir_mode,
+            autoindex_page_size: self.autoindex_page_size,
+            file_slash_redirect: self.file_slash_redirect,
+            trust_forwarded: self.trust_forwarded,
+            can_read: self.can_read.clone(),
+            autoindex_follow_symlinks: self.autoindex_follow_symlinks,
+            xml_content_type: self.xml_content_type.clone(),
+            fsync_on_put: self.fsync_on_put,
+            principal_search: self.principal_search.clone(),
+            full_range_as_ok: self.full_range_as_ok,
+            autoindex_sort_file: self.autoindex_sort_file,
+            disabled_methods: self.disabled_methods,
+            served_bytes: self.served_bytes.clone(),
+            address_data_validator: self.address_data_validator.clone(),
+            fixed_responses: self.fixed_responses.clone(),
+            get_limit: self.get_limit.clone(),
+            principal_quota: self.principal_quota.clone(),
+            strict: self.strict,
+            autoindex_time_format: self.autoindex_time_format.clone(),
+            autoindex_utc_offset: self.autoindex_utc_offset,
+            root_redirect: self.root_redirect.clone(),
+            immutable_paths: self.immutable_paths.clone(),
+            well_known: self.well_known.clone(),
+            shutdown: self.shutdown.clone(),
+            address_data_types: self.address_data_types.clone(),
+            resourcetypes: self.resourcetypes.clone(),
+            cancel: self.cancel.clone(),
+            max_copy_depth: self.max_copy_depth,
+            max_copy_nodes: self.max_copy_nodes,
+            accept_ranges_min_size: self.accept_ranges_min_size,
+            lenient_depth: self.lenient_depth,
+            redirect_rules: self.redirect_rules.clone(),
+            max_search_nodes: self.max_search_nodes,
+            http10_buffer_size: self.http10_buffer_size,
+            compression: self.compression,
         }
     }
 }
@@ -340,6 +1392,13 @@ impl DavInner {
     }
 
     // helper.
+    // Is the method in the `methods` set, and not disabled.
+    pub(crate) fn method_allowed(&self, method: DavMethod) -> bool {
+        let allowed = self.allow.map(|a| a.contains(method)).unwrap_or(true);
+        let disabled = matches!(self.disabled_methods, Some(d) if d.contains(method));
+        allowed && !disabled
+    }
+
     pub(crate) fn path(&self, req: &Request<()>) -> DavPath {
         // This never fails (has been checked before)
         DavPath::from_uri_and_prefix(req.uri(), &self.prefix).unwrap()
@@ -362,6 +1421,107 @@ impl DavInner {
         meta
     }
 
+    // URL for a Location header. Just the path, unless we trust the
+    // proxy headers, then it is an absolute URL.
+    pub(crate) fn location(&self, req: &Request<()>, path: &DavPath) -> String {
+        let path = path.with_prefix().as_url_string();
+        if self.trust_forwarded.unwrap_or(false) {
+            if let Some(origin) = forwarded_origin(req.headers()) {
+                return format!("{}{}", origin, path);
+            }
+        }
+        path
+    }
+
+    // Content-Type for multistatus and other XML response bodies.
+    pub(crate) fn xml_type(&self, req: &Request<()>) -> String {
+        self.xml_type_headers(req.headers())
+    }
+
+    fn xml_type_headers(&self, headers: &http::HeaderMap) -> String {
+        if let Some(ref ct) = self.xml_content_type {
+            return ct.clone();
+        }
+        match prefers_text_xml(headers) {
+            true => "text/xml; charset=utf-8".to_string(),
+            false => "application/xml; charset=utf-8".to_string(),
+        }
+    }
+
+    // Should this member of a collection be listed for the principal.
+    pub(crate) fn can_list(&self, path: &DavPath) -> bool {
+        match self.can_read {
+            Some(ref can_read) => can_read(self.principal.as_deref(), path),
+            None => true,
+        }
+    }
+
+    // Has the request been cancelled. Checked between filesystem calls
+    // in enumerations, which can take a while before they send anything.
+    pub(crate) fn check_cancel(&self) -> DavResult<()> {
+        match self.cancel {
+            Some(ref token) => Ok(token.check()?),
+            None => Ok(()),
+        }
+    }
+
+    // Check if the principal has `privilege` on `path`. A resource that
+    // does not exist gets the privileges of its parent. If the privilege
+    // is missing, this returns a 403 with a DAV:need-privileges body.
+    pub(crate) async fn check_privilege(
+        &self,
+        privilege: &'static str,
+        path: &DavPath,
+    ) -> DavResult<()> {
+        if !self.enforce_acl.unwrap_or(false) {
+            return Ok(());
+        }
+        let mut path = path.clone();
+        let acl = match self.fs.get_acl(&path).await {
+            Ok(acl) => acl,
+            Err(FsError::NotFound) => {
+                path = path.parent();
+                self.fs.get_acl(&path).await?
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let allowed = match privilege {
+            "read" => acl.read,
+            "unbind" => acl.unbind,
+            _ => acl.write,
+        };
+        if allowed {
+            return Ok(());
+        }
+        debug!("need privilege {} on {}", privilege, path);
+        let body = format!(
+            "<D:need-privileges><D:resource><D:href>{}</D:href><D:privilege><D:{}/></D:privilege></D:resource></D:need-privileges>",
+            path.with_prefix().as_url_string(),
+            privilege
+        );
+        Err(DavError::Condition(StatusCode::FORBIDDEN, body))
+    }
+
+    // read the next chunk of the request body, within the read timeout.
+    pub(crate) async fn read_chunk<B>(
+        &self,
+        body: &mut B,
+    ) -> DavResult<Option<Result<B::Data, B::Error>>>
+    where
+        B: HttpBody + Unpin,
+    {
+        match self.read_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, body.data()).await {
+                Ok(data) => Ok(data),
+                Err(_) => {
+                    debug!("timeout reading request body");
+                    Err(DavError::StatusClose(StatusCode::REQUEST_TIMEOUT))
+                }
+            },
+            None => Ok(body.data().await),
+        }
+    }
+
     // drain request body and return length.
     pub(crate) async fn read_request<ReqBody, ReqData, ReqError>(
         &self,
@@ -375,7 +1535,7 @@ impl DavInner {
     {
         let mut data = Vec::new();
         pin_utils::pin_mut!(body);
-        while let Some(res) = body.data().await {
+        while let Some(res) = self.read_chunk(&mut body).await? {
             let mut buf = res.map_err(|_| {
                 DavError::IoError(io::Error::new(
                     io::ErrorKind::UnexpectedEof,
@@ -409,8 +1569,46 @@ impl DavInner {
             .map(|s| s.contains("Microsoft"))
             .unwrap_or(false);
 
+        let method = req.method().clone();
+        let version = req.version();
+        let http10_buffer_size = self.http10_buffer_size.unwrap_or(HTTP10_BUFFER_SIZE);
+        let metrics = self.metrics.clone();
+        let xml_type = self.xml_type_headers(req.headers());
+
+        // Browsers get the custom error pages, if any.
+        let accepts_html = req
+            .headers()
+            .get("accept")
+            .and_then(|s| s.to_str().ok())
+            .map(|s| s.contains("text/html"))
+            .unwrap_or(false);
+        let error_pages = match self.error_pages {
+            Some(ref pages) if method == http::Method::GET && accepts_html => {
+                Some((pages.clone(), self.fs.clone(), self.mime_types.clone()))
+            }
+            _ => None,
+        };
+        if let Some(ref m) = metrics {
+            m.request(&method);
+        }
+
+        let server = match self.server {
+            Some(ref s) => s.clone(),
+            None => concat!("dav-server-rs/", env!("CARGO_PKG_VERSION")).to_string(),
+        };
+
+        // Once the shutdown has started, refuse new requests.
+        let guard = self.shutdown.as_ref().and_then(|s| s.request());
+        let result = match (&self.shutdown, &guard) {
+            (Some(_), None) => {
+                debug!("shutting down: refusing {} {}", method, req.uri());
+                Err(DavError::StatusClose(StatusCode::SERVICE_UNAVAILABLE))
+            }
+            _ => self.handle2(req).await,
+        };
+
         // Turn any DavError results into a HTTP error response.
-        match self.handle2(req).await {
+        let mut resp = match result {
             Ok(resp) => {
                 debug!("== END REQUEST result OK");
                 resp
@@ -434,13 +1632,152 @@ impl DavInner {
                         .header("Expires", "0")
                         .header("Vary", "*");
                 }
-                resp = resp.header("Content-Length", "0").status(err.statuscode());
                 if err.must_close() {
                     resp = resp.header("connection", "close");
                 }
-                resp.body(Body::empty()).unwrap()
+                resp = resp.status(err.statuscode());
+                // a DAV:error body is for the client, not for a person.
+                let condition = matches!(err, DavError::Condition(..));
+                let page = match error_pages {
+                    Some((ref pages, ref fs, ref types)) if !condition => {
+                        match pages.get(&err.statuscode()) {
+                            Some(path) => match read_error_page(&**fs, path).await {
+                                Ok(data) => Some((path.get_content_type(types.as_deref()), data)),
+                                Err(e) => {
+                                    debug!("error page {}: {:?}", path, e);
+                                    None
+                                }
+                            },
+                            None => None,
+                        }
+                    }
+                    _ => None,
+                };
+                match (page, err) {
+                    (Some((ctype, data)), _) => resp
+                        .header("Content-Type", ctype)
+                        .header("Content-Length", data.len().to_string())
+                        .body(Body::from(bytes::Bytes::from(data)))
+                        .unwrap(),
+                    (None, DavError::Condition(_, body)) => resp
+                        .header("Content-Type", xml_type)
+                        .body(dav_xml_error(&body))
+                        .unwrap(),
+                    (None, _) => resp
+                        .header("Content-Length", "0")
+                        .body(Body::empty())
+                        .unwrap(),
+                }
+            }
+        };
+
+        // HTTP/1.0 clients do not know chunked encoding. Without a length
+        // the server can only close the connection after the body, which
+        // some of them do not handle well either, so buffer the body.
+        // Unless it is too big, then closing the connection it is.
+        if version == http::Version::HTTP_10
+            && resp.body().is_stream()
+            && !resp.headers().contains_key("content-length")
+        {
+            let (mut parts, body) = resp.into_parts();
+            resp = match body.into_bytes(http10_buffer_size).await {
+                Ok(Ok(data)) => {
+                    let len = data.len().to_string();
+                    parts.headers.insert("content-length", len.parse().unwrap());
+                    Response::from_parts(parts, Body::from(data))
+                }
+                Ok(Err(body)) => {
+                    parts.headers.insert("connection", "close".parse().unwrap());
+                    Response::from_parts(parts, body)
+                }
+                Err(e) => {
+                    debug!("HTTP/1.0 response body: {}", e);
+                    Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .header("connection", "close")
+                        .header("Content-Length", "0")
+                        .body(Body::empty())
+                        .unwrap()
+                }
+            };
+        }
+
+        // the request is in flight until the body has been sent.
+        if let Some(guard) = guard {
+            resp = resp.map(|body| guard.body(body));
+        }
+
+        // An empty product token means: no Server header.
+        if !server.is_empty() {
+            if let Ok(value) = server.parse() {
+                resp.headers_mut().insert("server", value);
             }
         }
+        if let Some(ref m) = metrics {
+            m.response(&method, resp.status());
+        }
+        resp
+    }
+
+    // The response configured with `fixed_response` for this request, if any.
+    fn fixed_response(&self, req: &Request<()>) -> Option<Response<Body>> {
+        if req.method() != http::Method::GET && req.method() != http::Method::HEAD {
+            return None;
+        }
+        let (status, body) = self.fixed_responses.as_ref()?.get(req.uri().path())?;
+        debug!("fixed response {} for {}", status, req.uri().path());
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = *status;
+        res.headers_mut()
+            .typed_insert(headers::ContentLength(body.len() as u64));
+        if !body.is_empty() {
+            if let Ok(path) = DavPath::new(req.uri().path()) {
+                let ct = path.get_content_type(self.mime_types.as_deref());
+                res.headers_mut()
+                    .insert("content-type", ct.parse().unwrap());
+            }
+            if req.method() == http::Method::GET {
+                *res.body_mut() = Body::from(body.clone());
+            }
+        }
+        Some(res)
+    }
+
+    // The redirect configured with `well_known` for this request, if any.
+    fn well_known_redirect(&self, req: &Request<()>) -> Option<Response<Body>> {
+        let target = self.well_known.as_ref()?.get(req.uri().path())?;
+        debug!("well-known redirect {} to {}", req.uri().path(), target);
+        self.redirect(req, StatusCode::MOVED_PERMANENTLY, target)
+    }
+
+    // The redirect that the `redirect_rules` callback has for this
+    // request, if any.
+    fn rule_redirect(&self, req: &Request<()>) -> Option<Response<Body>> {
+        let rules = self.redirect_rules.as_ref()?;
+        let path = DavPath::from_uri_and_prefix(req.uri(), &self.prefix).ok()?;
+        let (status, target) = rules(&path)?;
+        debug!("redirect {} to {} ({})", req.uri().path(), target, status);
+        self.redirect(req, status, &target)
+    }
+
+    fn redirect(
+        &self,
+        req: &Request<()>,
+        status: StatusCode,
+        target: &str,
+    ) -> Option<Response<Body>> {
+        let location = match self.trust_forwarded.unwrap_or(false) {
+            true => match forwarded_origin(req.headers()) {
+                Some(origin) => format!("{}{}", origin, target),
+                None => target.to_string(),
+            },
+            false => target.to_string(),
+        };
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = status;
+        res.headers_mut().insert("Location", location.parse().ok()?);
+        res.headers_mut().typed_insert(headers::ContentLength(0));
+        Some(res)
     }
 
     // internal dispatcher part 2.
@@ -465,6 +1802,17 @@ impl DavInner {
             }
         }
 
+        // well-known paths with a fixed response.
+        if let Some(res) = self.fixed_response(&req) {
+            return Ok(res);
+        }
+        if let Some(res) = self.well_known_redirect(&req) {
+            return Ok(res);
+        }
+        if let Some(res) = self.rule_redirect(&req) {
+            return Ok(res);
+        }
+
         // translate HTTP method to Webdav method.
         let method = match dav_method(req.method()) {
             Ok(m) => m,
@@ -496,25 +1844,77 @@ impl DavInner {
             }
         }
 
+        // make sure the request path is valid.
+        let path = DavPath::from_uri_and_prefix(req.uri(), &self.prefix)?;
+        if path.is_star() && method != DavMethod::Options {
+            debug!(
+                "{} on *: only OPTIONS has an asterisk-form target",
+                req.method()
+            );
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+        if self.reject_duplicate_slashes.unwrap_or(false) && req.uri().path().contains("//") {
+            debug!("duplicate slashes in path {}", req.uri().path());
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+
+        // in strict mode, refuse requests that contradict themselves.
+        if self.strict.unwrap_or(false) {
+            if let Some(conflict) = conflicting_headers(method, req.headers()) {
+                debug!("conflicting headers on request {}: {}", req.uri(), conflict);
+                return Err(StatusCode::BAD_REQUEST.into());
+            }
+        }
+
         // see if method is allowed.
-        if let Some(ref a) = self.allow {
-            if !a.contains(method) {
-                debug!(
-                    "method {} not allowed on request {}",
-                    req.method(),
-                    req.uri()
-                );
-                return Err(DavError::StatusClose(StatusCode::METHOD_NOT_ALLOWED));
+        if !self.method_allowed(method) {
+            debug!(
+                "method {} not allowed on request {}",
+                req.method(),
+                req.uri()
+            );
+            let mut res = Response::new(Body::empty());
+            res.headers_mut().typed_insert(headers::Connection::close());
+            res.headers_mut().typed_insert(headers::ContentLength(0));
+            let options = self.handle_options(&req).await?;
+            if let Some(allow) = options.headers().get("allow") {
+                res.headers_mut().insert("allow", allow.clone());
             }
+            *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            return Ok(res);
         }
 
-        // make sure the request path is valid.
-        let path = DavPath::from_uri_and_prefix(req.uri(), &self.prefix)?;
+        // see if the principal has the privileges needed for this method.
+        // BIND and REBIND sources and SEARCH scopes are checked by
+        // their handlers.
+        if self.enforce_acl.unwrap_or(false) {
+            let privilege = match method {
+                DavMethod::Options => None,
+                DavMethod::Head | DavMethod::Get | DavMethod::PropFind | DavMethod::Search => {
+                    Some("read")
+                }
+                #[cfg(feature = "carddav")]
+                DavMethod::Report => Some("read"),
+                DavMethod::Copy => Some("read"),
+                DavMethod::Delete | DavMethod::Move | DavMethod::Unbind => Some("unbind"),
+                _ => Some("write"),
+            };
+            if let Some(privilege) = privilege {
+                self.check_privilege(privilege, &path).await?;
+            }
+            // COPY and MOVE also create the destination.
+            if matches!(method, DavMethod::Copy | DavMethod::Move) {
+                if let Some(dest) = req.headers().typed_get::<davheaders::Destination>() {
+                    let dest = DavPath::from_str_and_prefix(&dest.0, &self.prefix)?;
+                    self.check_privilege("write", &dest).await?;
+                }
+            }
+        }
 
-        // PUT is the only handler that reads the body itself. All the
-        // other handlers either expected no body, or a pre-read Vec<u8>.
+        // PUT and POST are the only handlers that read the body themselves. All
+        // the other handlers either expected no body, or a pre-read Vec<u8>.
         let (body_strm, body_data) = match method {
-            DavMethod::Put | DavMethod::Patch => (Some(body), Vec::new()),
+            DavMethod::Put | DavMethod::Patch | DavMethod::Post => (Some(body), Vec::new()),
             _ => (None, self.read_request(body, 65536).await?),
         };
 
@@ -522,10 +1922,15 @@ impl DavInner {
         match method {
             DavMethod::Put
             | DavMethod::Patch
+            | DavMethod::Post
             | DavMethod::PropFind
             | DavMethod::PropPatch
             | DavMethod::Lock
-            | DavMethod::Report => {}
+            | DavMethod::Report
+            | DavMethod::Bind
+            | DavMethod::Unbind
+            | DavMethod::Rebind
+            | DavMethod::Search => {}
             _ => {
                 if !body_data.is_empty() {
                     return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE.into());
@@ -546,9 +1951,28 @@ impl DavInner {
             DavMethod::Head | DavMethod::Get => self.handle_get(&req).await,
             DavMethod::Copy | DavMethod::Move => self.handle_copymove(&req, method).await,
             DavMethod::Put | DavMethod::Patch => self.handle_put(&req, body_strm.unwrap()).await,
+            DavMethod::Post => self.handle_post(&req, body_strm.unwrap()).await,
             #[cfg(feature = "carddav")]
-            DavMethod::Report => self.handle_report(&req, &body_data).await
+            DavMethod::Report => self.handle_report(&req, &body_data).await,
+            DavMethod::Bind | DavMethod::Unbind | DavMethod::Rebind => {
+                self.handle_bind(&req, method, &body_data).await
+            }
+            DavMethod::Search => self.handle_search(&req, &body_data).await,
         };
         res
     }
 }
+
+// read a custom error page from the filesystem.
+async fn read_error_page(fs: &dyn DavFileSystem, path: &DavPath) -> FsResult<Vec<u8>> {
+    let mut file = fs.open(path, OpenOptions::read()).await?;
+    let mut data = Vec::new();
+    loop {
+        let buf = file.read_bytes(8192).await?;
+        if buf.is_empty() {
+            break;
+        }
+        data.extend_from_slice(&buf);
+    }
+    Ok(data)
+}
@@ -17,6 +17,7 @@ lazy_static! {
     pub static ref DESTINATION: HeaderName = HeaderName::from_static("destination");
     pub static ref ETAG: HeaderName = HeaderName::from_static("etag");
     pub static ref IF_RANGE: HeaderName = HeaderName::from_static("if-range");
+    pub static ref RANGE: HeaderName = HeaderName::from_static("range");
     pub static ref IF_MATCH: HeaderName = HeaderName::from_static("if-match");
     pub static ref IF_NONE_MATCH: HeaderName = HeaderName::from_static("if-none-match");
     pub static ref X_UPDATE_RANGE: HeaderName = HeaderName::from_static("x-update-range");
@@ -92,6 +93,8 @@ header!(XLitmus, X_LITMUS, "x-litmus");
 pub enum Depth {
     Zero,
     One,
+    /// `1,noroot`: just the members of a collection, not the collection itself.
+    OneNoRoot,
     Infinity,
 }
 
@@ -105,10 +108,19 @@ impl Header for Depth {
         I: Iterator<Item = &'i HeaderValue>,
     {
         let value = one(values)?;
-        match value.as_bytes() {
-            b"0" => Ok(Depth::Zero),
-            b"1" => Ok(Depth::One),
-            b"infinity" | b"Infinity" => Ok(Depth::Infinity),
+        let value = value.to_str().map_err(|_| invalid())?.to_ascii_lowercase();
+        let (depth, noroot) = match value.split_once(',') {
+            Some((depth, ext)) if ext.trim() == "noroot" => (depth.trim(), true),
+            Some(_) => return Err(invalid()),
+            None => (value.trim(), false),
+        };
+        // some clients send "1.0".
+        let depth = depth.strip_suffix(".0").unwrap_or(depth);
+        match (depth, noroot) {
+            ("0", false) => Ok(Depth::Zero),
+            ("1", false) => Ok(Depth::One),
+            ("1", true) => Ok(Depth::OneNoRoot),
+            ("infinity", false) => Ok(Depth::Infinity),
             _ => Err(invalid()),
         }
     }
@@ -120,6 +132,7 @@ impl Header for Depth {
         let value = match *self {
             Depth::Zero => "0",
             Depth::One => "1",
+            Depth::OneNoRoot => "1,noroot",
             Depth::Infinity => "Infinity",
         };
         values.extend(std::iter::once(HeaderValue::from_static(value)));
@@ -308,7 +321,6 @@ pub struct ETag {
 }
 
 impl ETag {
-    #[allow(dead_code)]
     pub fn new(weak: bool, t: impl Into<String>) -> Result<ETag, headers::Error> {
         let t = t.into();
         if t.contains('\"') {
@@ -334,6 +346,18 @@ impl ETag {
     pub fn is_weak(&self) -> bool {
         self.weak
     }
+
+    /// The opaque tag, without `W/` and the quotes.
+    pub fn value(&self) -> &str {
+        let t = self.tag.trim_start_matches("W/");
+        &t[1..t.len() - 1]
+    }
+
+    /// Weak comparison (RFC 7232, 2.3.2), ignores the `W/` prefix.
+    /// `==` is the strong comparison.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag.trim_start_matches("W/") == other.tag.trim_start_matches("W/")
+    }
 }
 
 impl FromStr for ETag {
@@ -439,6 +463,89 @@ impl Header for IfRange {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteRange {
+    FromTo(u64, u64),
+    AllFrom(u64),
+    Last(u64),
+}
+
+// The Range header, RFC 9110, 14.2. The parser in the headers crate is
+// too lenient in some places ("+1-2", "bytes=-", invalid ranges are
+// silently dropped) and too strict in others (the unit is
+// case-insensitive). A header that does not follow the grammar
+// is invalid as a whole, and should be ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Range(pub Vec<ByteRange>);
+
+fn parse_pos(s: &str) -> Result<u64, headers::Error> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    s.parse::<u64>().map_err(map_invalid)
+}
+
+impl Header for Range {
+    fn name() -> &'static HeaderName {
+        &RANGE
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let s = one(values)?.to_str().map_err(map_invalid)?;
+        let (unit, set) = s.split_once('=').ok_or_else(invalid)?;
+        if !unit.eq_ignore_ascii_case("bytes") {
+            return Err(invalid());
+        }
+
+        let mut ranges = Vec::new();
+        for spec in set.split(',') {
+            // empty list elements are allowed, see RFC 9110, 5.6.1.2.
+            let spec = spec.trim_matches(|c| c == ' ' || c == '\t');
+            if spec.is_empty() {
+                continue;
+            }
+            let (first, last) = spec.split_once('-').ok_or_else(invalid)?;
+            let range = match (first.is_empty(), last.is_empty()) {
+                (false, false) => {
+                    let (first, last) = (parse_pos(first)?, parse_pos(last)?);
+                    if last < first {
+                        return Err(invalid());
+                    }
+                    ByteRange::FromTo(first, last)
+                }
+                (false, true) => ByteRange::AllFrom(parse_pos(first)?),
+                (true, false) => ByteRange::Last(parse_pos(last)?),
+                (true, true) => return Err(invalid()),
+            };
+            ranges.push(range);
+        }
+        if ranges.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Range(ranges))
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        let ranges = self
+            .0
+            .iter()
+            .map(|r| match *r {
+                ByteRange::FromTo(b, e) => format!("{}-{}", b, e),
+                ByteRange::AllFrom(b) => format!("{}-", b),
+                ByteRange::Last(e) => format!("-{}", e),
+            })
+            .collect::<Vec<_>>();
+        let value = format!("bytes={}", ranges.join(","));
+        values.extend(std::iter::once(HeaderValue::from_str(&value).unwrap()));
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ETagList {
     Tags(Vec<ETag>),
@@ -634,6 +741,16 @@ pub enum IfItem {
     ETag(ETag),
 }
 
+// A Resource-Tag is an absolute URI, or an absolute path (RFC 4918, 10.4).
+// Only the path is used, so a path gets a dummy scheme and host.
+fn resource_tag(url: &str) -> Result<url::Url, headers::Error> {
+    if url.starts_with('/') && !url.starts_with("//") {
+        url::Url::parse(&format!("http://localhost{}", url)).map_err(map_invalid)
+    } else {
+        url::Url::parse(url).map_err(map_invalid)
+    }
+}
+
 // Below stuff is for the parser state.
 #[derive(Debug, Clone, PartialEq)]
 enum IfToken {
@@ -674,8 +791,8 @@ fn trim_left(mut out: &'_ [u8]) -> &'_ [u8] {
 fn scan_until(buf: &[u8], c: u8) -> Result<(&[u8], &[u8]), headers::Error> {
     let mut i = 1;
     let mut quote = false;
-    while quote || buf[i] != c {
-        if buf.is_empty() || is_whitespace(buf[i]) {
+    while quote || buf.get(i) != Some(&c) {
+        if i >= buf.len() || is_whitespace(buf[i]) {
             return Err(invalid());
         }
         if buf[i] == b'"' {
@@ -754,8 +871,7 @@ impl Header for If {
                 IfState::Start => match tok {
                     IfToken::ListOpen => IfState::List,
                     IfToken::Pointy(url) => {
-                        let u = url::Url::parse(&url).map_err(map_invalid)?;
-                        cur_list.resource_tag = Some(u);
+                        cur_list.resource_tag = Some(resource_tag(&url)?);
                         IfState::RTag
                     }
                     IfToken::End => {
@@ -798,8 +914,12 @@ impl Header for If {
                             if cur_list.conditions.is_empty() {
                                 IfState::Bad
                             } else {
+                                // in a tagged list, the next lists are about
+                                // the same resource until a new tag shows up.
+                                let tag = cur_list.resource_tag.clone();
                                 if_lists.0.push(cur_list);
                                 cur_list = IfList::new();
+                                cur_list.resource_tag = tag;
                                 IfState::Start
                             }
                         }
@@ -840,6 +960,94 @@ mod tests {
         assert!(hdr.is_ok());
     }
 
+    fn parse_depth(val: &'static str) -> Result<Depth, headers::Error> {
+        let hdrval = HeaderValue::from_static(val);
+        let mut iter = std::iter::once(&hdrval);
+        Depth::decode(&mut iter)
+    }
+
+    #[test]
+    fn depth_header() {
+        assert_eq!(parse_depth("0").unwrap(), Depth::Zero);
+        assert_eq!(parse_depth("1").unwrap(), Depth::One);
+        assert_eq!(parse_depth("infinity").unwrap(), Depth::Infinity);
+        assert_eq!(parse_depth("1,noroot").unwrap(), Depth::OneNoRoot);
+        assert_eq!(parse_depth("1, noroot").unwrap(), Depth::OneNoRoot);
+        assert_eq!(parse_depth("Infinity").unwrap(), Depth::Infinity);
+        assert_eq!(parse_depth("INFINITY").unwrap(), Depth::Infinity);
+        assert_eq!(parse_depth(" 1 ").unwrap(), Depth::One);
+        assert_eq!(parse_depth("1.0").unwrap(), Depth::One);
+        assert_eq!(parse_depth("0.0").unwrap(), Depth::Zero);
+        assert!(parse_depth("0,noroot").is_err());
+        assert!(parse_depth("1,other").is_err());
+        assert!(parse_depth("2").is_err());
+        assert!(parse_depth("1.5").is_err());
+        assert!(parse_depth("-1").is_err());
+        assert!(parse_depth("one").is_err());
+        assert!(parse_depth("").is_err());
+    }
+
+    fn parse_if(val: &'static str) -> Result<If, headers::Error> {
+        let hdrval = HeaderValue::from_static(val);
+        let mut iter = std::iter::once(&hdrval);
+        If::decode(&mut iter)
+    }
+
+    #[test]
+    fn if_header_untagged() {
+        let hdr = parse_if(r#"(<urn:uuid:1234> ["etag"])"#).unwrap();
+        assert_eq!(hdr.0.len(), 1);
+        let list = &hdr.0[0];
+        assert!(list.resource_tag.is_none());
+        assert_eq!(list.conditions.len(), 2);
+        assert_eq!(
+            list.conditions[0].item,
+            IfItem::StateToken("urn:uuid:1234".to_string())
+        );
+        assert_eq!(
+            list.conditions[1].item,
+            IfItem::ETag(ETag::from_str(r#""etag""#).unwrap())
+        );
+    }
+
+    #[test]
+    fn if_header_tagged() {
+        let val = r#"<http://x.yz/a> (<urn:a>) (<urn:b>) <http://x.yz/b> (<urn:c>)"#;
+        let hdr = parse_if(val).unwrap();
+        let tags = hdr
+            .0
+            .iter()
+            .map(|l| l.resource_tag.as_ref().unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(tags, vec!["/a", "/a", "/b"]);
+
+        // a tag must be followed by a list.
+        assert!(parse_if("<http://x.yz/a>").is_err());
+        assert!(parse_if("<http://x.yz/a> <http://x.yz/b> (<urn:a>)").is_err());
+    }
+
+    #[test]
+    fn if_header_not() {
+        let hdr = parse_if(r#"(Not <urn:a> <urn:b>) (Not ["x"])"#).unwrap();
+        assert_eq!(hdr.0.len(), 2);
+        assert!(hdr.0[0].conditions[0].not);
+        assert!(!hdr.0[0].conditions[1].not);
+        assert!(hdr.0[1].conditions[0].not);
+
+        assert!(parse_if("(Not Not <urn:a>)").is_err());
+        assert!(parse_if("(Not)").is_err());
+    }
+
+    #[test]
+    fn if_header_invalid() {
+        assert!(parse_if("").is_err());
+        assert!(parse_if("()").is_err());
+        assert!(parse_if("(<urn:a>").is_err());
+        assert!(parse_if("(<urn:a").is_err());
+        assert!(parse_if(r#"(["etag)"#).is_err());
+        assert!(parse_if("(nocolon)").is_err());
+    }
+
     #[test]
     fn etag_header() {
         let t1 = ETag::from_str(r#"W/"12345""#).unwrap();
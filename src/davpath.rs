@@ -1,5 +1,6 @@
 //! Utility module to handle the path part of an URL as a filesytem path.
 //!
+use std::collections::HashMap;
 use std::error::Error;
 use std::ffi::OsStr;
 #[cfg(target_os = "windows")]
@@ -242,6 +243,13 @@ impl DavPath {
         }
     }
 
+    // remove the slash at the end of the path, unless the path is "/".
+    pub(crate) fn remove_slash(&mut self) {
+        if self.is_collection() && self.as_bytes() != b"/" {
+            self.fullpath.pop();
+        }
+    }
+
     // add a slash
     pub(crate) fn add_slash_if(&mut self, b: bool) {
         if b && !self.is_collection() {
@@ -419,20 +427,87 @@ impl DavPathRef {
         }
     }
 
-    pub(crate) fn get_mime_type_str(&self) -> &'static str {
+    // The extension of the filename, if any.
+    pub(crate) fn file_extension(&self) -> Option<&str> {
         let name = self.file_name_bytes();
         let d = name.rsplitn(2, |&c| c == b'.').collect::<Vec<&[u8]>>();
         if d.len() > 1 {
-            if let Ok(ext) = std::str::from_utf8(d[0]) {
-                if ext == "vcf" {
-                    return "text/vcard"
-                }
-                if let Some(t) = mime_guess::from_ext(ext).first_raw() {
-                    // let t =     t.replace("x-", "").as_str();
-                    return t;
-                }
+            std::str::from_utf8(d[0]).ok()
+        } else {
+            None
+        }
+    }
+
+    // The mime type, from the `types` table if it has this extension,
+    // otherwise from the builtin table.
+    pub(crate) fn get_mime_type_str<'a>(
+        &self,
+        types: Option<&'a HashMap<String, String>>,
+    ) -> &'a str {
+        if let Some(ext) = self.file_extension() {
+            let ext_lc = ext.to_lowercase();
+            if let Some(t) = types.and_then(|types| types.get(&ext_lc)) {
+                return t;
+            }
+            match ext_lc.as_str() {
+                "vcf" | "vcard" => return "text/vcard",
+                "ics" | "ifb" => return "text/calendar",
+                "wasm" => return "application/wasm",
+                _ => {}
+            }
+            if let Some(t) = mime_guess::from_ext(ext).first_raw() {
+                return t;
             }
         }
         "application/octet-stream"
     }
+
+    // The Content-Type for this path: the mime type, with a utf-8
+    // charset added for text types. A type that already has
+    // parameters (set through DavConfig::mime_type) is used as-is.
+    pub(crate) fn get_content_type(&self, types: Option<&HashMap<String, String>>) -> String {
+        let mime = self.get_mime_type_str(types);
+        if !mime.contains(';') && is_text_mime(mime) {
+            format!("{}; charset=utf-8", mime)
+        } else {
+            mime.to_string()
+        }
+    }
+}
+
+fn is_text_mime(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || mime.ends_with("+xml")
+        || mime.ends_with("+json")
+        || matches!(
+            mime,
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/x-subrip"
+                | "application/x-sh"
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(src: &str, prefix: &str) -> (String, bool) {
+        let p = DavPath::from_str_and_prefix(src, prefix).unwrap();
+        (p.as_url_string(), p.is_collection())
+    }
+
+    #[test]
+    fn test_duplicate_slashes() {
+        assert_eq!(path("//a/b", ""), ("/a/b".to_string(), false));
+        assert_eq!(path("/a//b///c", ""), ("/a/b/c".to_string(), false));
+        assert_eq!(path("/a/b//", ""), ("/a/b/".to_string(), true));
+        assert_eq!(path("///", ""), ("/".to_string(), true));
+
+        // the prefix is matched against the collapsed path.
+        assert_eq!(path("/pfx//a//b", "/pfx"), ("/a/b".to_string(), false));
+        assert_eq!(path("/pfx//", "/pfx"), ("/".to_string(), true));
+        assert!(DavPath::from_str_and_prefix("/pf//x/a", "/pfx").is_err());
+    }
 }
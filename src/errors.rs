@@ -19,6 +19,7 @@ pub(crate) enum DavError {
     Utf8Error,
     Status(StatusCode),
     StatusClose(StatusCode),
+    Condition(StatusCode, String), // status with a DAV:error body
     FsError(FsError),
     IoError(io::Error),
     XmlReaderError(xml::reader::Error),
@@ -128,6 +129,7 @@ fn fserror_to_ioerror(e: FsError) -> io::Error {
         FsError::PathTooLong => io::Error::new(io::ErrorKind::Other, "PathTooLong"),
         FsError::TooLarge => io::Error::new(io::ErrorKind::Other, "TooLarge"),
         FsError::IsRemote => io::Error::new(io::ErrorKind::Other, "IsRemote"),
+        FsError::PreconditionFailed => io::Error::new(io::ErrorKind::Other, "PreconditionFailed"),
     }
 }
 
@@ -153,6 +155,7 @@ fn fserror_to_status(e: &FsError) -> StatusCode {
         FsError::PathTooLong => StatusCode::URI_TOO_LONG,
         FsError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
         FsError::IsRemote => StatusCode::BAD_GATEWAY,
+        FsError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
     }
 }
 
@@ -171,6 +174,7 @@ impl DavError {
             DavError::FsError(ref e) => fserror_to_status(e),
             DavError::Status(e) => e,
             DavError::StatusClose(e) => e,
+            DavError::Condition(e, _) => e,
             DavError::XmlReaderError(ref _e) => StatusCode::BAD_REQUEST,
             DavError::XmlWriterError(ref _e) => StatusCode::INTERNAL_SERVER_ERROR,
         }
@@ -180,6 +184,7 @@ impl DavError {
         !matches!(
             self,
             &DavError::Status(_)
+                | &DavError::Condition(..)
                 | &DavError::FsError(FsError::NotFound)
                 | &DavError::FsError(FsError::Forbidden)
                 | &DavError::FsError(FsError::Exists)
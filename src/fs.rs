@@ -52,6 +52,8 @@ pub enum FsError {
     TooLarge,
     /// Trying to MOVE over a mount boundary (EXDEV) (502)
     IsRemote,
+    /// A precondition, like the etag of `write_if_etag`, does not hold (412)
+    PreconditionFailed,
 }
 /// The Result type.
 pub type FsResult<T> = std::result::Result<T, FsError>;
@@ -114,6 +116,16 @@ pub struct DavProp {
     pub xml: Option<Vec<u8>>,
 }
 
+/// One `DAV:property-search` term of a `principal-property-search` REPORT.
+#[derive(Debug, Clone)]
+pub struct PropertySearch {
+    /// Properties to search, `xml` is always `None`.
+    pub props: Vec<DavProp>,
+    /// Text to match. RFC3744 leaves the kind of match to the server,
+    /// usually it is a caseless substring match.
+    pub text: String,
+}
+
 /// Webdav access control list according to RFC3744
 #[derive(Debug, Clone, Default)]
 pub struct DavAccessControl {
@@ -159,7 +171,28 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
     /// Open a file.
     fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<Box<dyn DavFile>>;
 
+    /// Open a file, and if it is created, give it the given permissions
+    /// (unix mode bits).
+    ///
+    /// `mode` is a hint, filesystems that have no permission bits ignore it.
+    ///
+    /// The default implementation calls `open`.
+    #[allow(unused_variables)]
+    fn open_mode<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+        mode: Option<u32>,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        self.open(path, options)
+    }
+
     /// Perform read_dir.
+    ///
+    /// The entries are returned as a stream, which is polled as the
+    /// PROPFIND response or the directory index is generated. An async
+    /// backend can fetch entries lazily. A backend that already has all
+    /// entries in a `Vec` can return `futures_util::stream::iter(v)`.
     fn read_dir<'a>(
         &'a self,
         path: &'a DavPath,
@@ -181,6 +214,15 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
         self.metadata(path)
     }
 
+    /// Check if a node exists.
+    ///
+    /// Used where only existence matters. The default implementation
+    /// calls `metadata()`. Backends that can answer this cheaper than
+    /// a full stat (object stores, for example) can override it.
+    fn exists<'a>(&'a self, path: &'a DavPath) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        Box::pin(async move { self.metadata(path).await.is_ok() })
+    }
+
     /// Create a directory.
     ///
     /// The default implementation returns FsError::NotImplemented.
@@ -189,6 +231,16 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
         notimplemented_fut!("create_dir")
     }
 
+    /// Create a directory with the given permissions (unix mode bits).
+    ///
+    /// `mode` is a hint, filesystems that have no permission bits ignore it.
+    ///
+    /// The default implementation calls `create_dir`.
+    #[allow(unused_variables)]
+    fn create_dir_mode<'a>(&'a self, path: &'a DavPath, mode: Option<u32>) -> FsFuture<'a, ()> {
+        self.create_dir(path)
+    }
+
     /// Remove a directory.
     ///
     /// The default implementation returns FsError::NotImplemented.
@@ -247,6 +299,46 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
         notimplemented_fut!("set_mofified")
     }
 
+    /// Indicator that tells if this filesystem driver supports the
+    /// BIND, UNBIND and REBIND methods (RFC5842).
+    ///
+    /// The default implementation returns `false`.
+    #[allow(unused_variables)]
+    fn have_bind<'a>(
+        &'a self,
+        path: &'a DavPath,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        Box::pin(future::ready(false))
+    }
+
+    /// Add a binding `to` for the resource at `from` (like a hard link).
+    /// An existing binding at `to` is replaced, in one step: if this
+    /// fails, the old binding must still be there.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    fn link<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        notimplemented_fut!("link")
+    }
+
+    /// Move the binding `from` to `to`. Like `link`, an existing
+    /// binding at `to` is replaced in one step.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    fn relink<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        notimplemented_fut!("relink")
+    }
+
+    /// Remove the binding at `path`. Other bindings to the same
+    /// resource are left alone.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    fn unlink<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        notimplemented_fut!("unlink")
+    }
+
     /// Indicator that tells if this filesystem driver supports DAV properties.
     ///
     /// The default implementation returns `false`.
@@ -310,7 +402,57 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
         notimplemented_fut!("patch_path`")
     }
 
+    /// Get the current sync token of a collection (RFC 6578).
+    ///
+    /// This is returned as the `DAV:sync-token` property. It must be
+    /// an absolute URI, and a client will send it back unchanged in a
+    /// `sync-collection` REPORT.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    fn sync_token<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        notimplemented_fut!("sync_token")
+    }
+
+    /// Get a precomputed hash of the content of a file, like the MD5
+    /// that object stores keep.
+    ///
+    /// If there is one, it is used as a strong ETag for `GET`, in
+    /// conditional requests and as `DAV:getetag`, instead of
+    /// `DavMetaData::etag`. It is asked for on every such request, so it
+    /// should be cheap: do not hash the file here.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    fn content_hash<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Vec<u8>> {
+        notimplemented_fut!("content_hash")
+    }
+
+    /// Replace the content of an existing file with `data`, but only if
+    /// its etag still is `etag`, and return the new etag.
+    ///
+    /// Used by `PUT` with an `If-Match` header, so that a lost update is
+    /// impossible even without locks: the check and the write have to be
+    /// one atomic operation, like a compare-and-swap in a database. The
+    /// `etag` is the one from the header without the quotes, so it is the
+    /// `content_hash` if there is one and `DavMetaData::etag` otherwise.
+    /// If it does not match, return `FsError::PreconditionFailed`.
+    ///
+    /// The default implementation returns FsError::NotImplemented, and
+    /// the handler does a normal, non-atomic write instead.
+    #[allow(unused_variables)]
+    fn write_if_etag<'a>(
+        &'a self,
+        path: &'a DavPath,
+        etag: &'a str,
+        data: bytes::Bytes,
+    ) -> FsFuture<'a, String> {
+        notimplemented_fut!("write_if_etag")
+    }
+
     /// Access control list can read current user privilege set
+    ///
+    /// The default implementation grants nothing.
     fn get_acl<'a>(&'a self, path: &'a DavPath) -> FsFuture<DavAccessControl> {
         Box::pin(future::ok(DavAccessControl::default()))
     }
@@ -376,9 +518,24 @@ pub trait DavFile: Debug + Send + Sync {
     fn read_bytes(&mut self, count: usize) -> FsFuture<bytes::Bytes>;
     fn seek(&mut self, pos: SeekFrom) -> FsFuture<u64>;
     fn flush(&mut self) -> FsFuture<()>;
+    /// Make sure the data written so far is on stable storage, like
+    /// `std::fs::File::sync_all`. The default is to do nothing.
+    fn sync_all(&mut self) -> FsFuture<'_, ()> {
+        future::ready(Ok(())).boxed()
+    }
     fn redirect_url(&mut self) -> FsFuture<Option<String>> {
         future::ready(Ok(None)).boxed()
     }
+    /// Read `count` bytes at `offset` in one go, without moving the file
+    /// position. For backends that have the data at hand, like a cache
+    /// in memory. `GET` tries this for every range, and if it fails, reads
+    /// the range with `seek` and `read_bytes`.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    fn read_range(&mut self, offset: u64, count: u64) -> FsFuture<'_, bytes::Bytes> {
+        notimplemented_fut!("read_range")
+    }
 }
 
 /// File metadata. Basically type, length, and some timestamps.
@@ -390,11 +547,32 @@ pub trait DavMetaData: Debug + BoxCloneMd + Send + Sync {
     /// File or directory (aka collection).
     fn is_dir(&self) -> bool;
 
+    /// Revision of the resource, if the backend keeps one, like the row
+    /// version of a database. Default: `None`.
+    ///
+    /// If there is one, the default `etag()` is based on it instead of
+    /// on the length and modification time. `GET` uses the metadata of
+    /// the opened file (`DavFile::metadata`), which should return the
+    /// same version.
+    fn version(&self) -> Option<String> {
+        None
+    }
+
     /// Simplistic implementation of `etag()`
     ///
-    /// Returns a simple etag that basically is `\<length\>-\<timestamp_in_ms\>`
+    /// Returns the `version()` if there is one. Otherwise returns a simple
+    /// etag that basically is `\<length\>-\<timestamp_in_ms\>`
     /// with the numbers in hex. Enough for most implementations.
+    ///
+    /// A collection's etag is used for `DAV:getetag`, the `ETag` of
+    /// the HTML index and the `If-Match` checks, so it should change
+    /// when a member is added, removed or modified. `MemFs` and
+    /// `LocalFs` update the mtime of the directory when a file in it
+    /// is written.
     fn etag(&self) -> Option<String> {
+        if let Some(version) = self.version() {
+            return Some(version_etag(&version));
+        }
         if let Ok(t) = self.modified() {
             if let Ok(t) = t.duration_since(UNIX_EPOCH) {
                 let t = t.as_secs() * 1000000 + t.subsec_nanos() as u64 / 1000;
@@ -424,7 +602,10 @@ pub trait DavMetaData: Debug + BoxCloneMd + Send + Sync {
         notimplemented!("access time")
     }
 
-    /// Creation time. Default: `FsError::NotImplemented`.
+    /// Creation (birth) time. Default: `FsError::NotImplemented`.
+    ///
+    /// Used for the `DAV:creationdate` property. If this is not
+    /// implemented, the earliest of ctime and mtime is used instead.
     fn created(&self) -> FsResult<SystemTime> {
         notimplemented!("creation time")
     }
@@ -439,6 +620,21 @@ pub trait DavMetaData: Debug + BoxCloneMd + Send + Sync {
         notimplemented!("executable")
     }
 
+    /// Numeric id of the owner (unix: `st_uid`). Default: `None`.
+    fn uid(&self) -> Option<u32> {
+        None
+    }
+
+    /// Numeric id of the group (unix: `st_gid`). Default: `None`.
+    fn gid(&self) -> Option<u32> {
+        None
+    }
+
+    /// Permission bits (unix: `st_mode & 0o7777`). Default: `None`.
+    fn mode(&self) -> Option<u32> {
+        None
+    }
+
     // Is empty file
     fn is_empty(&self) -> bool {
         self.len() == 0
@@ -461,6 +657,108 @@ pub trait DavMetaData: Debug + BoxCloneMd + Send + Sync {
     }
 }
 
+// A version string as an etag. It is used as is if it can go between the
+// quotes of an entity-tag (RFC 7232, 2.3), otherwise it is hex encoded.
+fn version_etag(version: &str) -> String {
+    let etagc = |b: u8| b == 0x21 || (0x23..=0x7e).contains(&b);
+    if !version.is_empty() && version.bytes().all(etagc) {
+        return version.to_string();
+    }
+    version.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+// A FIFO, socket or device: not a directory, regular file or symlink.
+// These are listed like files, but they have no content: there is no
+// DAV:getcontentlength or DAV:getcontenttype, and GET is refused.
+pub(crate) fn is_special(meta: &dyn DavMetaData) -> bool {
+    !meta.is_dir() && !meta.is_file() && !meta.is_symlink()
+}
+
+// Metadata of a file with the etag replaced by its content hash, if the
+// filesystem has one (see DavFileSystem::content_hash).
+pub(crate) async fn with_content_hash(
+    fs: &dyn DavFileSystem,
+    path: &DavPath,
+    meta: Box<dyn DavMetaData>,
+) -> Box<dyn DavMetaData> {
+    if meta.is_dir() {
+        return meta;
+    }
+    match fs.content_hash(path).await {
+        Ok(hash) if !hash.is_empty() => {
+            let etag = hash.iter().map(|b| format!("{:02x}", b)).collect();
+            Box::new(ContentHashMeta { meta, etag })
+        }
+        _ => meta,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ContentHashMeta {
+    meta: Box<dyn DavMetaData>,
+    etag: String,
+}
+
+impl DavMetaData for ContentHashMeta {
+    fn len(&self) -> u64 {
+        self.meta.len()
+    }
+    fn modified(&self) -> FsResult<SystemTime> {
+        self.meta.modified()
+    }
+    fn is_dir(&self) -> bool {
+        self.meta.is_dir()
+    }
+    fn version(&self) -> Option<String> {
+        self.meta.version()
+    }
+    fn etag(&self) -> Option<String> {
+        Some(self.etag.clone())
+    }
+    fn is_file(&self) -> bool {
+        self.meta.is_file()
+    }
+    fn is_symlink(&self) -> bool {
+        self.meta.is_symlink()
+    }
+    fn accessed(&self) -> FsResult<SystemTime> {
+        self.meta.accessed()
+    }
+    fn created(&self) -> FsResult<SystemTime> {
+        self.meta.created()
+    }
+    fn status_changed(&self) -> FsResult<SystemTime> {
+        self.meta.status_changed()
+    }
+    fn executable(&self) -> FsResult<bool> {
+        self.meta.executable()
+    }
+    fn uid(&self) -> Option<u32> {
+        self.meta.uid()
+    }
+    fn gid(&self) -> Option<u32> {
+        self.meta.gid()
+    }
+    fn mode(&self) -> Option<u32> {
+        self.meta.mode()
+    }
+    fn is_empty(&self) -> bool {
+        self.meta.is_empty()
+    }
+    #[cfg(feature = "carddav")]
+    fn is_addrbook(&self) -> FsResult<bool> {
+        self.meta.is_addrbook()
+    }
+    #[cfg(feature = "carddav")]
+    fn displayname(&self) -> FsResult<String> {
+        self.meta.displayname()
+    }
+    #[cfg(feature = "carddav")]
+    fn vcard_data(&self) -> FsResult<Vcard> {
+        self.meta.vcard_data()
+    }
+}
+
 // generic Clone, calls implementation-specific box_clone().
 impl Clone for Box<dyn DavMetaData> {
     fn clone(&self) -> Box<dyn DavMetaData> {
@@ -0,0 +1,334 @@
+use std::io::Cursor;
+
+use headers::HeaderMapExt;
+use http::StatusCode as SC;
+use http::{Request, Response};
+use xmltree::Element;
+
+use crate::body::Body;
+use crate::conditional::if_match_get_tokens;
+use crate::davheaders;
+use crate::davpath::DavPath;
+use crate::errors::*;
+use crate::fs::FsError;
+use crate::util::{locked, DavMethod};
+use crate::xmltree_ext::ElementExt;
+use crate::DavResult;
+
+impl crate::DavInner {
+    // BIND, UNBIND and REBIND (RFC5842).
+    //
+    // The request-URI is the collection, the request body names the
+    // binding (segment) in that collection and, for BIND and REBIND,
+    // the resource to bind to (href).
+    pub(crate) async fn handle_bind(
+        &self,
+        req: &Request<()>,
+        method: DavMethod,
+        xmldata: &[u8],
+    ) -> DavResult<Response<Body>> {
+        let mut path = self.path(req);
+        if !self.fs.have_bind(&path).await {
+            return Err(SC::NOT_IMPLEMENTED.into());
+        }
+
+        // bindings are always made in a collection.
+        let meta = self.fs.metadata(&path).await?;
+        if !meta.is_dir() {
+            return Err(SC::METHOD_NOT_ALLOWED.into());
+        }
+        path.add_slash();
+
+        // parse xml
+        let tree = Element::parse2(Cursor::new(xmldata))?;
+        let name = match method {
+            DavMethod::Bind => "bind",
+            DavMethod::Unbind => "unbind",
+            _ => "rebind",
+        };
+        if tree.name != name {
+            return Err(DavError::XmlParseError);
+        }
+        let mut segment = None;
+        let mut href = None;
+        for elem in tree.child_elems_iter() {
+            match elem.name.as_str() {
+                "segment" => segment = elem.get_text().map(|t| t.to_string()),
+                "href" => href = elem.get_text().map(|t| t.trim().to_string()),
+                _ => {}
+            }
+        }
+
+        // the segment must be a single path segment.
+        let segment = segment.ok_or(DavError::XmlParseError)?;
+        if segment.is_empty() || segment == "." || segment == ".." || segment.contains('/') {
+            return Err(SC::BAD_REQUEST.into());
+        }
+        let mut dest = path.clone();
+        dest.push_segment(segment.as_bytes());
+
+        // the source of a BIND or REBIND.
+        let source = match href {
+            Some(href) if method != DavMethod::Unbind => {
+                let uri = href
+                    .parse::<http::Uri>()
+                    .map_err(|_| DavError::InvalidPath)?;
+                let mut source = DavPath::from_uri_and_prefix(&uri, &self.prefix)?;
+                match self.fs.symlink_metadata(&source).await {
+                    Ok(m) => source.add_slash_if(m.is_dir()),
+                    Err(FsError::NotFound) => return Err(SC::CONFLICT.into()),
+                    Err(e) => return Err(e.into()),
+                }
+                Some(source)
+            }
+            None if method != DavMethod::Unbind => return Err(DavError::XmlParseError),
+            _ => None,
+        };
+        // a BIND makes the source reachable at another path, so it must
+        // be readable. A REBIND removes it at the source, like a MOVE.
+        if let Some(ref source) = source {
+            let privilege = match method {
+                DavMethod::Rebind => "unbind",
+                _ => "read",
+            };
+            self.check_privilege(privilege, source).await?;
+        }
+
+        // does the binding exist already.
+        let exists = match self.fs.symlink_metadata(&dest).await {
+            Ok(m) => {
+                dest.add_slash_if(m.is_dir());
+                true
+            }
+            Err(_) => false,
+        };
+        if method == DavMethod::Unbind && !exists {
+            return Err(SC::NOT_FOUND.into());
+        }
+        if let Some(ref source) = source {
+            dest.add_slash_if(source.is_collection());
+            if *source == dest {
+                return Err(SC::FORBIDDEN.into());
+            }
+            let overwrite = req
+                .headers()
+                .typed_get::<davheaders::Overwrite>()
+                .map(|o| o.0)
+                .unwrap_or(true);
+            if exists && !overwrite {
+                return Err(SC::PRECONDITION_FAILED.into());
+            }
+        }
+
+        // check If and If-* headers for the collection.
+        let tokens = match if_match_get_tokens(req, Some(&meta), &self.fs, &self.ls, &path).await {
+            Ok(t) => t,
+            Err(s) => return Err(s.into()),
+        };
+
+        // check locks on the binding, and for REBIND on the source.
+        if let Some(ref locksystem) = self.ls {
+            let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+            let principal = self.principal.as_deref();
+            if method == DavMethod::Rebind {
+                let source = source.as_ref().unwrap();
+                if let Err(l) = locksystem.check(source, principal, false, true, t.clone()) {
+                    return Err(locked(&l));
+                }
+            }
+            if let Err(l) = locksystem.check(&dest, principal, false, true, t) {
+                return Err(locked(&l));
+            }
+        }
+
+        // an existing binding is replaced by the filesystem in one step,
+        // its locks are only dropped once that succeeded.
+        match source {
+            Some(ref source) if method == DavMethod::Bind => {
+                self.fs.link(source, &dest).await?;
+            }
+            Some(ref source) => {
+                self.fs.relink(source, &dest).await?;
+                if let Some(ref locksystem) = self.ls {
+                    let _ = locksystem.delete(source);
+                }
+            }
+            None => self.fs.unlink(&dest).await?,
+        }
+        if exists {
+            if let Some(ref locksystem) = self.ls {
+                let _ = locksystem.delete(&dest);
+            }
+        }
+
+        // UNBIND returns 200 (RFC5842, 5).
+        let status = if exists { SC::OK } else { SC::CREATED };
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = status;
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::localfs::LocalFs;
+    use crate::memls::MemLs;
+    use crate::testfs::{body_text, memfs_handler, request, TempDir};
+    use crate::DavHandler;
+
+    fn bind_body(method: &str, segment: &str, href: Option<&str>) -> String {
+        let href = href.map(|h| format!("<D:href>{}</D:href>", h));
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:{0} xmlns:D="DAV:"><D:segment>{1}</D:segment>{2}</D:{0}>"#,
+            method.to_lowercase(),
+            segment,
+            href.unwrap_or_default()
+        )
+    }
+
+    #[tokio::test]
+    async fn test_bind() {
+        let dir = TempDir::new("bind");
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&*dir, false, false, false))
+            .locksystem(MemLs::new())
+            .build_handler();
+        let dav = &dav;
+        let request = |method, uri, hdrs: &'static [(&str, &str)], body: String| async move {
+            let resp = request(dav, method, uri, hdrs, body).await;
+            (resp.status().as_u16(), body_text(resp).await)
+        };
+        let bind = |method, segment, href, hdrs| {
+            request(method, "/", hdrs, bind_body(method, segment, href))
+        };
+        let get = |uri| request("GET", uri, &[], String::new());
+
+        request("PUT", "/a", &[], "a".to_string()).await;
+        request("PUT", "/x", &[], "x".to_string()).await;
+
+        // a new binding, then replacing it.
+        assert_eq!(bind("BIND", "b", Some("/a"), &[]).await.0, 201);
+        assert_eq!(get("/b").await, (200, "a".to_string()));
+        let no_overwrite = &[("Overwrite", "F")];
+        assert_eq!(bind("BIND", "b", Some("/x"), no_overwrite).await.0, 412);
+        assert_eq!(get("/b").await, (200, "a".to_string()));
+        assert_eq!(bind("BIND", "b", Some("/x"), &[]).await.0, 200);
+        assert_eq!(get("/b").await, (200, "x".to_string()));
+        assert_eq!(get("/a").await, (200, "a".to_string()));
+
+        // missing source or binding.
+        assert_eq!(bind("BIND", "c", Some("/nope"), &[]).await.0, 409);
+        assert_eq!(bind("UNBIND", "nope", None, &[]).await.0, 404);
+
+        // a locked binding is not replaced or removed.
+        let lockinfo = r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:lockinfo xmlns:D="DAV:">
+              <D:lockscope><D:exclusive/></D:lockscope>
+              <D:locktype><D:write/></D:locktype>
+            </D:lockinfo>"#;
+        let (status, _) = request("LOCK", "/b", &[], lockinfo.to_string()).await;
+        assert_eq!(status, 200);
+        assert_eq!(bind("BIND", "b", Some("/a"), &[]).await.0, 423);
+        assert_eq!(bind("REBIND", "b", Some("/a"), &[]).await.0, 423);
+        assert_eq!(bind("UNBIND", "b", None, &[]).await.0, 423);
+        assert_eq!(get("/b").await, (200, "x".to_string()));
+
+        // after a REBIND the source is gone.
+        assert_eq!(bind("REBIND", "r", Some("/a"), &[]).await.0, 201);
+        assert_eq!(get("/a").await.0, 404);
+        assert_eq!(get("/r").await, (200, "a".to_string()));
+        assert_eq!(bind("REBIND", "r", Some("/x"), &[]).await.0, 200);
+        assert_eq!(get("/x").await.0, 404);
+        assert_eq!(get("/r").await, (200, "x".to_string()));
+
+        // UNBIND returns 200.
+        assert_eq!(bind("UNBIND", "r", None, &[]).await.0, 200);
+        assert_eq!(get("/r").await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn test_bind_acl() {
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+
+        // LocalFs where "/secret" cannot be read.
+        #[derive(Clone)]
+        struct Acl(Box<LocalFs>);
+
+        impl DavFileSystem for Acl {
+            fn open<'a>(
+                &'a self,
+                path: &'a DavPath,
+                options: OpenOptions,
+            ) -> FsFuture<'a, Box<dyn DavFile>> {
+                self.0.open(path, options)
+            }
+            fn read_dir<'a>(
+                &'a self,
+                path: &'a DavPath,
+                meta: ReadDirMeta,
+            ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+                self.0.read_dir(path, meta)
+            }
+            fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+                self.0.metadata(path)
+            }
+            fn symlink_metadata<'a>(
+                &'a self,
+                path: &'a DavPath,
+            ) -> FsFuture<'a, Box<dyn DavMetaData>> {
+                self.0.symlink_metadata(path)
+            }
+            fn have_bind<'a>(
+                &'a self,
+                path: &'a DavPath,
+            ) -> futures_util::future::BoxFuture<'a, bool> {
+                self.0.have_bind(path)
+            }
+            fn link<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+                self.0.link(from, to)
+            }
+            fn get_acl<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, DavAccessControl> {
+                let read = path.as_url_string() != "/secret";
+                let acl = DavAccessControl {
+                    read,
+                    write: true,
+                    unbind: true,
+                    ..DavAccessControl::default()
+                };
+                Box::pin(futures_util::future::ok(acl))
+            }
+        }
+
+        let dir = TempDir::new("bind-acl");
+        let fs = LocalFs::new(&*dir, false, false, false);
+        let setup = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        request(&setup, "PUT", "/a", &[], "a").await;
+        request(&setup, "PUT", "/secret", &[], "s").await;
+
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(Acl(fs)))
+            .enforce_acl(true)
+            .build_handler();
+        let bind = |segment, href| {
+            let body = bind_body("BIND", segment, Some(href));
+            request(&dav, "BIND", "/", &[], body)
+        };
+        assert_eq!(bind("b", "/a").await.status(), 201);
+        let resp = bind("c", "/secret").await;
+        assert_eq!(resp.status(), 403);
+        assert!(body_text(resp).await.contains("<D:read/>"));
+        assert!(!dir.join("c").exists());
+    }
+
+    #[tokio::test]
+    async fn test_bind_not_implemented() {
+        let dav = memfs_handler();
+        for method in &["BIND", "UNBIND", "REBIND"] {
+            let body = bind_body(method, "b", Some("/a"));
+            let resp = request(&dav, method, "/", &[], body).await;
+            assert_eq!(resp.status(), 501, "{}", method);
+        }
+    }
+}
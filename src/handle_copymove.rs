@@ -1,4 +1,6 @@
-use futures_util::{future::BoxFuture, FutureExt, StreamExt};
+use std::collections::VecDeque;
+
+use futures_util::StreamExt;
 use headers::HeaderMapExt;
 use http::{Request, Response, StatusCode};
 
@@ -10,7 +12,8 @@ use crate::davpath::DavPath;
 use crate::errors::*;
 use crate::fs::*;
 use crate::multierror::{multi_error, MultiError};
-use crate::{util::DavMethod, DavResult};
+use crate::util::{get_depth, locked, write_status, DavMethod};
+use crate::DavResult;
 
 // map_err helper.
 async fn add_status<'a>(
@@ -25,93 +28,160 @@ async fn add_status<'a>(
     Err(daverror)
 }
 
+// What a COPY creates, found before anything is created: the source
+// and destination of each resource, and if it is a collection, in
+// the order they are copied. `result` is the last error reading the
+// source.
+pub(crate) struct CopyPlan {
+    items: Vec<(DavPath, DavPath, bool)>,
+    result: DavResult<()>,
+}
+
 impl crate::DavInner {
-    pub(crate) fn do_copy<'a>(
-        &'a self,
-        source: &'a DavPath,
-        topdest: &'a DavPath,
-        dest: &'a DavPath,
+    // Find what to copy: `source`, and if it is a collection and depth
+    // allows, its members. This walks the tree with a work queue, so that
+    // a very deep tree cannot overflow the stack. When the configured
+    // maximum depth or number of resources is reached, the copy is
+    // refused as a whole, and nothing has been created yet.
+    pub(crate) async fn plan_copy(
+        &self,
+        source: &DavPath,
+        dest: &DavPath,
         depth: Depth,
-        multierror: &'a mut MultiError,
-    ) -> BoxFuture<'a, DavResult<()>> {
-        async move {
+        multierror: &mut MultiError,
+    ) -> DavResult<CopyPlan> {
+        let max_depth = self.max_copy_depth.unwrap_or(usize::MAX);
+        let max_nodes = self.max_copy_nodes.unwrap_or(usize::MAX);
+        let mut nodes = 0;
+
+        // (source, destination, level below the top).
+        let mut queue = VecDeque::new();
+        queue.push_back((source.clone(), dest.clone(), 0));
+
+        // If we encounter errors, just print them, and keep going.
+        let mut plan = CopyPlan {
+            items: Vec::new(),
+            result: Ok(()),
+        };
+        while let Some((nsrc, ndest, level)) = queue.pop_front() {
             // when doing "COPY /a/b /a/b/c make sure we don't recursively
             // copy /a/b/c/ into /a/b/c.
-            if source == topdest {
-                return Ok(());
+            if nsrc == *dest {
+                continue;
+            }
+            nodes += 1;
+            if level > max_depth || nodes > max_nodes {
+                debug!("plan_copy: limit reached at {}", nsrc);
+                let e = StatusCode::INSUFFICIENT_STORAGE;
+                if nsrc != *source {
+                    let _ = add_status(multierror, &nsrc, e).await;
+                }
+                return add_status(multierror, source, e).await.map(|_| plan);
+            }
+            let meta = match self.fs.metadata(&nsrc).await {
+                Ok(m) => m,
+                Err(e) => {
+                    plan.result = add_status(multierror, &nsrc, e).await;
+                    continue;
+                }
+            };
+            // only recurse into a collection when Depth > 0.
+            if meta.is_dir() && depth != Depth::Zero {
+                let res = self
+                    .add_members(&nsrc, &ndest, level, &mut queue, multierror)
+                    .await;
+                if let Err(e) = res {
+                    plan.result = Err(e);
+                }
+            }
+            plan.items.push((nsrc, ndest, meta.is_dir()));
+        }
+
+        Ok(plan)
+    }
+
+    // Add the members of a collection to `queue`.
+    async fn add_members(
+        &self,
+        source: &DavPath,
+        dest: &DavPath,
+        level: usize,
+        queue: &mut VecDeque<(DavPath, DavPath, usize)>,
+        multierror: &mut MultiError,
+    ) -> DavResult<()> {
+        let mut entries = match self.fs.read_dir(source, ReadDirMeta::DataSymlink).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                debug!("plan_copy: self.fs.read_dir error: {:?}", e);
+                return add_status(multierror, source, e).await;
             }
+        };
 
-            // source must exist.
-            let meta = match self.fs.metadata(source).await {
+        while let Some(dirent) = entries.next().await {
+            // NOTE: dirent.metadata() behaves like symlink_metadata()
+            let meta = match dirent.metadata().await {
+                Ok(meta) => meta,
                 Err(e) => return add_status(multierror, source, e).await,
-                Ok(m) => m,
             };
+            let name = dirent.name();
+            let mut nsrc = source.clone();
+            let mut ndest = dest.clone();
+            nsrc.push_segment(&name);
+            ndest.push_segment(&name);
+
+            if meta.is_dir() {
+                nsrc.add_slash();
+                ndest.add_slash();
+            }
+            queue.push_back((nsrc, ndest, level + 1));
+        }
+        Ok(())
+    }
+
+    // Copy what plan_copy() found. A collection is created before its
+    // members, and when that fails, they are skipped.
+    pub(crate) async fn do_copy(
+        &self,
+        plan: CopyPlan,
+        depth: Depth,
+        multierror: &mut MultiError,
+    ) -> DavResult<()> {
+        // If we encounter errors, just print them, and keep going.
+        // Last seen error is returned from function.
+        let mut retval = plan.result;
+        let mut failed_dirs: Vec<DavPath> = Vec::new();
+        for (source, dest, is_dir) in plan.items {
+            if failed_dirs
+                .iter()
+                .any(|d| dest.as_bytes().starts_with(d.as_bytes()))
+            {
+                continue;
+            }
 
             // if it's a file we can overwrite it.
-            if !meta.is_dir() {
-                return match self.fs.copy(source, dest).await {
-                    Ok(_) => Ok(()),
-                    Err(e) => {
-                        debug!("do_copy: self.fs.copy error: {:?}", e);
-                        add_status(multierror, source, e).await
-                    }
-                };
+            if !is_dir {
+                if let Err(e) = self.fs.copy(&source, &dest).await {
+                    debug!("do_copy: self.fs.copy error: {:?}", e);
+                    retval = add_status(multierror, &source, e).await;
+                }
+                continue;
             }
 
             // Copying a directory onto an existing directory with Depth 0
             // is not an error. It means "only copy properties" (which
             // we do not do yet).
-            if let Err(e) = self.fs.create_dir(dest).await {
+            if let Err(e) = self.fs.create_dir_mode(&dest, self.dir_mode).await {
                 if depth != Depth::Zero || e != FsError::Exists {
                     debug!("do_copy: self.fs.create_dir({}) error: {:?}", dest, e);
-                    return add_status(multierror, dest, e).await;
-                }
-            }
-
-            // only recurse when Depth > 0.
-            if depth == Depth::Zero {
-                return Ok(());
-            }
-
-            let mut entries = match self.fs.read_dir(source, ReadDirMeta::DataSymlink).await {
-                Ok(entries) => entries,
-                Err(e) => {
-                    debug!("do_copy: self.fs.read_dir error: {:?}", e);
-                    return add_status(multierror, source, e).await;
-                }
-            };
-
-            // If we encounter errors, just print them, and keep going.
-            // Last seen error is returned from function.
-            let mut retval = Ok::<_, DavError>(());
-            while let Some(dirent) = entries.next().await {
-                // NOTE: dirent.metadata() behaves like symlink_metadata()
-                let meta = match dirent.metadata().await {
-                    Ok(meta) => meta,
-                    Err(e) => return add_status(multierror, source, e).await,
-                };
-                let name = dirent.name();
-                let mut nsrc = source.clone();
-                let mut ndest = dest.clone();
-                nsrc.push_segment(&name);
-                ndest.push_segment(&name);
-
-                if meta.is_dir() {
-                    nsrc.add_slash();
-                    ndest.add_slash();
-                }
-                // recurse.
-                if let Err(e) = self
-                    .do_copy(&nsrc, topdest, &ndest, depth, multierror)
-                    .await
-                {
-                    retval = Err(e);
+                    retval = add_status(multierror, &dest, e).await;
+                    let mut dir = dest.clone();
+                    dir.add_slash();
+                    failed_dirs.push(dir);
                 }
             }
-
-            retval
         }
-        .boxed()
+
+        retval
     }
 
     // Right now we handle MOVE with a simple RENAME. RFC4918 #9.9.2 talks
@@ -150,7 +220,7 @@ impl crate::DavInner {
             .headers()
             .typed_get::<davheaders::Overwrite>()
             .map_or(true, |o| o.0);
-        let depth = match req.headers().typed_get::<Depth>() {
+        let depth = match get_depth(req.headers(), self.lenient_depth.unwrap_or(false))? {
             Some(Depth::Infinity) | None => Depth::Infinity,
             Some(Depth::Zero) if method == DavMethod::Copy => Depth::Zero,
             _ => return Err(StatusCode::BAD_REQUEST.into()),
@@ -200,9 +270,12 @@ impl crate::DavInner {
             Err(e) => (false, Err(e)),
         };
 
-        // check if overwrite is "F"
+        // check if overwrite is "F", or if "If-None-Match: *" asks to
+        // only create the destination.
         let exists = dmeta.is_ok();
-        if !overwrite && exists {
+        let inm = req.headers().typed_get::<davheaders::IfNoneMatch>();
+        let create_only = matches!(inm, Some(h) if h.0 == davheaders::ETagList::Star);
+        if (!overwrite || create_only) && exists {
             return Err(StatusCode::PRECONDITION_FAILED.into());
         }
 
@@ -225,22 +298,33 @@ impl crate::DavInner {
             let principal = self.principal.as_deref();
             if method == DavMethod::Move {
                 // for MOVE check if source path is locked
-                if let Err(_l) = locksystem.check(&path, principal, false, true, t.clone()) {
-                    return Err(StatusCode::LOCKED.into());
+                if let Err(l) = locksystem.check(&path, principal, false, true, t.clone()) {
+                    return Err(locked(&l));
                 }
             }
             // for MOVE and COPY check if destination is locked
-            if let Err(_l) = locksystem.check(&dest, principal, false, true, t) {
-                return Err(StatusCode::LOCKED.into());
+            if let Err(l) = locksystem.check(&dest, principal, false, true, t) {
+                return Err(locked(&l));
             }
         }
 
         let req_path = path.clone();
+        let ct = self.xml_type(req);
 
         let items = AsyncStream::new(|tx| {
             async move {
                 let mut multierror = MultiError::new(tx);
 
+                // find what to copy first: when the copy is refused, the
+                // destination is left as it is.
+                let mut plan = None;
+                if method == DavMethod::Copy {
+                    match self.plan_copy(&path, &dest, depth, &mut multierror).await {
+                        Ok(p) => plan = Some(p),
+                        Err(_) => return Ok(()),
+                    }
+                }
+
                 // see if we need to delete the destination first.
                 if overwrite && exists && depth != Depth::Zero && !dest_is_file {
                     trace!("handle_copymove: deleting destination {}", dest);
@@ -258,18 +342,9 @@ impl crate::DavInner {
                 }
 
                 // COPY or MOVE.
-                if method == DavMethod::Copy {
-                    if self
-                        .do_copy(&path, &dest, &dest, depth, &mut multierror)
-                        .await
-                        .is_ok()
-                    {
-                        let s = if exists {
-                            StatusCode::NO_CONTENT
-                        } else {
-                            StatusCode::CREATED
-                        };
-                        let _ = multierror.add_status(&path, s).await;
+                if let Some(plan) = plan {
+                    if self.do_copy(plan, depth, &mut multierror).await.is_ok() {
+                        let _ = multierror.add_status(&path, write_status(exists)).await;
                     }
                 } else {
                     // move and if successful, remove locks at old location.
@@ -277,18 +352,339 @@ impl crate::DavInner {
                         if let Some(ref locksystem) = self.ls {
                             locksystem.delete(&path).ok();
                         }
-                        let s = if exists {
-                            StatusCode::NO_CONTENT
-                        } else {
-                            StatusCode::CREATED
-                        };
-                        let _ = multierror.add_status(&path, s).await;
+                        let _ = multierror.add_status(&path, write_status(exists)).await;
                     }
                 }
                 Ok::<_, DavError>(())
             }
         });
 
-        multi_error(req_path, items).await
+        multi_error(req_path, ct, items).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::davpath::DavPath;
+    use crate::fs::DavFileSystem;
+    use crate::memfs::MemFs;
+    use crate::testfs::{body_text, memfs_handler, request};
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn test_create_replace_status() {
+        let dav = &memfs_handler();
+        let request = |method, uri, dest: Option<&'static str>| async move {
+            let hdrs = [("Destination", dest.unwrap_or("")), ("Depth", "infinity")];
+            let n = if dest.is_some() { 2 } else { 0 };
+            let body = if method == "PUT" { "data" } else { "" };
+            let resp = request(dav, method, uri, &hdrs[..n], body).await;
+            resp.status().as_u16()
+        };
+
+        // files.
+        assert_eq!(request("PUT", "/f1", None).await, 201);
+        assert_eq!(request("PUT", "/f1", None).await, 204);
+        assert_eq!(request("COPY", "/f1", Some("/f2")).await, 201);
+        assert_eq!(request("COPY", "/f1", Some("/f2")).await, 204);
+        assert_eq!(request("MOVE", "/f2", Some("/f3")).await, 201);
+        assert_eq!(request("MOVE", "/f3", Some("/f1")).await, 204);
+
+        // collections.
+        let mkcol = |uri| request("MKCOL", uri, None);
+        assert_eq!(mkcol("/d1/").await, 201);
+        assert_eq!(mkcol("/d1/").await, 405);
+        assert_eq!(request("COPY", "/d1/", Some("/d2/")).await, 201);
+        assert_eq!(request("COPY", "/d1/", Some("/d2/")).await, 204);
+        assert_eq!(request("MOVE", "/d2/", Some("/d3/")).await, 201);
+        assert_eq!(request("MOVE", "/d3/", Some("/d1/")).await, 204);
+    }
+
+    #[tokio::test]
+    async fn test_if_none_match_star() {
+        let dav = &memfs_handler();
+        let request = |method, uri, dest: Option<&'static str>| async move {
+            let hdrs = [("If-None-Match", "*"), ("Destination", dest.unwrap_or(""))];
+            let n = if dest.is_some() { 2 } else { 1 };
+            request(dav, method, uri, &hdrs[..n], "")
+                .await
+                .status()
+                .as_u16()
+        };
+
+        assert_eq!(request("MKCOL", "/d1/", None).await, 201);
+        assert_eq!(request("MKCOL", "/d1/", None).await, 412);
+        assert_eq!(request("PUT", "/f1", None).await, 201);
+        assert_eq!(request("PUT", "/f1", None).await, 412);
+        assert_eq!(request("COPY", "/f1", Some("/f2")).await, 201);
+        assert_eq!(request("COPY", "/f1", Some("/f2")).await, 412);
+        assert_eq!(request("COPY", "/d1/", Some("/d2/")).await, 201);
+        assert_eq!(request("COPY", "/d1/", Some("/d2/")).await, 412);
+        assert_eq!(request("MOVE", "/f2", Some("/f3")).await, 201);
+        assert_eq!(request("MOVE", "/f3", Some("/f1")).await, 412);
+    }
+
+    #[tokio::test]
+    async fn test_if_destination_etag() {
+        let dav = memfs_handler();
+        request(&dav, "PUT", "/src", &[], "source").await;
+        let resp = request(&dav, "PUT", "/dst", &[], "destination").await;
+        let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+        let resp = request(&dav, "HEAD", "/src", &[], "").await;
+        let src_etag = resp.headers()["etag"].to_str().unwrap().to_string();
+
+        for method in &["COPY", "MOVE"] {
+            for tag in &["</dst>", "<http://localhost/dst>"] {
+                // the condition is about the destination, so the etag
+                // of the source does not match.
+                let cond = format!("{} ([{}])", tag, src_etag);
+                let hdrs = [("Destination", "/dst"), ("If", &cond)];
+                let resp = request(&dav, method, "/src", &hdrs, "").await;
+                assert_eq!(resp.status(), 412, "{} {}", method, tag);
+            }
+        }
+        let cond = format!("</dst> ([{}])", etag);
+        let hdrs = [("Destination", "/dst"), ("If", &cond)];
+        let resp = request(&dav, "COPY", "/src", &hdrs, "").await;
+        assert_eq!(resp.status(), 204);
+    }
+
+    #[tokio::test]
+    async fn test_lock_conditions() {
+        use crate::memls::MemLs;
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        let dav = &dav;
+        let request = |method, uri, hdrs: &'static [(&str, &str)], body| async move {
+            let resp = request(dav, method, uri, hdrs, body).await;
+            (resp.status().as_u16(), body_text(resp).await)
+        };
+        // the name of the condition in a DAV:error body, and its href.
+        let condition = |body: &str| {
+            let tree = xmltree::Element::parse(body.as_bytes()).unwrap();
+            assert_eq!(tree.name, "error");
+            let cond = tree.children[0].as_element().unwrap().clone();
+            let href = cond.get_child("href").and_then(|h| h.get_text());
+            (cond.name.clone(), href.map(|h| h.to_string()))
+        };
+        let lockinfo = r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:lockinfo xmlns:D="DAV:">
+              <D:lockscope><D:exclusive/></D:lockscope>
+              <D:locktype><D:write/></D:locktype>
+            </D:lockinfo>"#;
+
+        request("PUT", "/src", &[], "data").await;
+        request("MKCOL", "/locked", &[], "").await;
+        let (status, _) = request("LOCK", "/locked", &[("Depth", "infinity")], lockinfo).await;
+        assert_eq!(status, 200);
+        let locked = (
+            "lock-token-submitted".to_string(),
+            Some("/locked/".to_string()),
+        );
+
+        let dest = &[("Destination", "/locked/dst")];
+        for method in &["COPY", "MOVE"] {
+            let (status, body) = request(method, "/src", dest, "").await;
+            assert_eq!(status, 423, "{}", method);
+            assert_eq!(condition(&body), locked, "{}", method);
+        }
+        let dest = &[("Destination", "/dst")];
+        let (status, body) = request("MOVE", "/locked", dest, "").await;
+        assert_eq!(status, 423);
+        assert_eq!(condition(&body), locked);
+
+        let (status, body) = request("PUT", "/locked/file", &[], "data").await;
+        assert_eq!(status, 423);
+        assert_eq!(condition(&body), locked);
+
+        let (status, body) = request("LOCK", "/locked/file", &[], lockinfo).await;
+        assert_eq!(status, 423);
+        let expect = ("no-conflicting-lock".to_string(), locked.1.clone());
+        assert_eq!(condition(&body), expect);
+
+        let token = &[("Lock-Token", "<opaquelocktoken:nope>")];
+        let (status, body) = request("UNLOCK", "/locked", token, "").await;
+        assert_eq!(status, 409);
+        let expect = ("lock-token-matches-request-uri".to_string(), None);
+        assert_eq!(condition(&body), expect);
+    }
+
+    #[tokio::test]
+    async fn test_copy_limits() {
+        let memfs = MemFs::new();
+        let setup = DavHandler::builder()
+            .filesystem(memfs.clone())
+            .build_handler();
+        let request = |dav: DavHandler, method, uri: String, dest: Option<&'static str>| async move {
+            let hdrs = [("Destination", dest.unwrap_or(""))];
+            let n = if dest.is_some() { 1 } else { 0 };
+            let body = if method == "PUT" { "data" } else { "" };
+            let resp = request(&dav, method, &uri, &hdrs[..n], body).await;
+            (resp.status().as_u16(), body_text(resp).await)
+        };
+        // the href and status of each response in a multistatus.
+        let statuses = |body: &str| {
+            let tree = xmltree::Element::parse(body.as_bytes()).unwrap();
+            let mut statuses = tree
+                .children
+                .iter()
+                .filter_map(|n| n.as_element())
+                .map(|r| {
+                    let href = r.get_child("href").unwrap().get_text().unwrap();
+                    let status = r.get_child("status").unwrap().get_text().unwrap();
+                    (href.to_string(), status.to_string())
+                })
+                .collect::<Vec<_>>();
+            statuses.sort();
+            statuses
+        };
+        let full = |href: &str| {
+            (
+                href.to_string(),
+                "HTTP/1.1 507 Insufficient Storage".to_string(),
+            )
+        };
+
+        // /t/1/2/3/4/ and a file in every collection.
+        let mut path = "/t/".to_string();
+        for n in 1..=5 {
+            request(setup.clone(), "MKCOL", path.clone(), None).await;
+            request(setup.clone(), "PUT", format!("{}file", path), None).await;
+            path = format!("{}{}/", path, n);
+        }
+
+        // deeper than 2 levels.
+        let dav = DavHandler::builder()
+            .filesystem(memfs.clone())
+            .max_copy_depth(2)
+            .build_handler();
+        let (status, body) = request(dav.clone(), "COPY", "/t/".into(), Some("/u/")).await;
+        assert_eq!(status, 207);
+        let hrefs = statuses(&body);
+        assert_eq!(hrefs.len(), 2);
+        assert_eq!(hrefs[0], full("/t/"));
+        assert!(hrefs[1].0.starts_with("/t/1/2/"), "{:?}", hrefs);
+        // nothing was copied.
+        let (status, _) = request(dav.clone(), "GET", "/u/".into(), None).await;
+        assert_eq!(status, 404);
+
+        // and an existing destination is left as it is.
+        request(setup.clone(), "MKCOL", "/x/".into(), None).await;
+        request(setup.clone(), "PUT", "/x/file".into(), None).await;
+        let (status, _) = request(dav.clone(), "COPY", "/t/".into(), Some("/x/")).await;
+        assert_eq!(status, 207);
+        let (status, _) = request(dav.clone(), "GET", "/x/file".into(), None).await;
+        assert_eq!(status, 200);
+        let (status, _) = request(dav.clone(), "GET", "/x/1/".into(), None).await;
+        assert_eq!(status, 404);
+
+        // more than 3 resources.
+        let dav = DavHandler::builder()
+            .filesystem(memfs.clone())
+            .max_copy_nodes(3)
+            .build_handler();
+        let (status, body) = request(dav.clone(), "COPY", "/t/".into(), Some("/v/")).await;
+        assert_eq!(status, 207);
+        let hrefs = statuses(&body);
+        assert_eq!(hrefs.len(), 2);
+        assert_eq!(hrefs[0], full("/t/"));
+        assert!(hrefs[1].0.starts_with("/t/1/"), "{:?}", hrefs);
+        let (status, _) = request(dav.clone(), "GET", "/v/".into(), None).await;
+        assert_eq!(status, 404);
+
+        // within the limits.
+        let dav = DavHandler::builder()
+            .filesystem(memfs)
+            .max_copy_depth(5)
+            .max_copy_nodes(10)
+            .build_handler();
+        let (status, _) = request(dav.clone(), "COPY", "/t/".into(), Some("/w/")).await;
+        assert_eq!(status, 201);
+        let (status, _) = request(dav.clone(), "GET", "/w/1/2/3/4/file".into(), None).await;
+        assert_eq!(status, 200);
+    }
+
+    #[tokio::test]
+    async fn test_lenient_depth() {
+        let memfs = MemFs::new();
+        let copy = |dav: DavHandler, dest| async move {
+            let hdrs = [("Destination", dest), ("Depth", "2")];
+            request(&dav, "COPY", "/d1/", &hdrs, "")
+                .await
+                .status()
+                .as_u16()
+        };
+        let strict = DavHandler::builder()
+            .filesystem(memfs.clone())
+            .build_handler();
+        for uri in &["/d1/", "/d1/a/", "/d1/a/b/"] {
+            let resp = request(&strict, "MKCOL", uri, &[], "").await;
+            assert_eq!(resp.status(), 201);
+        }
+        assert_eq!(copy(strict, "/d2/").await, 400);
+
+        // taken as infinity: all levels are copied.
+        let lenient = DavHandler::builder()
+            .filesystem(memfs.clone())
+            .lenient_depth(true)
+            .build_handler();
+        assert_eq!(copy(lenient, "/d2/").await, 201);
+        let path = DavPath::new("/d2/a/b/").unwrap();
+        assert!(memfs.metadata(&path).await.unwrap().is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_acl_destination() {
+        use crate::fs::*;
+        use crate::testfs::{FsHooks, HookFs};
+
+        // MemFs where nothing can be written below "/ro/".
+        #[derive(Clone)]
+        struct ReadOnly;
+
+        impl FsHooks for ReadOnly {
+            fn get_acl<'a>(
+                &'a self,
+                _fs: &'a MemFs,
+                path: &'a DavPath,
+            ) -> FsFuture<'a, DavAccessControl> {
+                let ro = path.as_url_string().starts_with("/ro/") && path.as_bytes().len() > 4;
+                let acl = DavAccessControl {
+                    read: true,
+                    write: !ro,
+                    unbind: !ro,
+                    ..DavAccessControl::default()
+                };
+                Box::pin(futures_util::future::ok(acl))
+            }
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(HookFs::new(ReadOnly))
+            .enforce_acl(true)
+            .build_handler();
+        let dav = &dav;
+        let request = |method, uri, dest: Option<&'static str>| async move {
+            let hdrs = [("Destination", dest.unwrap_or(""))];
+            let n = if dest.is_some() { 1 } else { 0 };
+            let body = if method == "PUT" { "data" } else { "" };
+            let resp = request(dav, method, uri, &hdrs[..n], body).await;
+            (resp.status().as_u16(), body_text(resp).await)
+        };
+
+        assert_eq!(request("MKCOL", "/ro/", None).await.0, 201);
+        assert_eq!(request("PUT", "/f1", None).await.0, 201);
+        assert_eq!(request("PUT", "/ro/f1", None).await.0, 403);
+        for method in &["COPY", "MOVE"] {
+            let (status, body) = request(method, "/f1", Some("/ro/f2")).await;
+            assert_eq!(status, 403);
+            assert!(body.contains("<D:href>/ro/f2</D:href>"));
+            assert!(body.contains("<D:privilege><D:write/></D:privilege>"));
+        }
+        assert_eq!(request("COPY", "/f1", Some("/f2")).await.0, 201);
+        assert_eq!(request("MOVE", "/f2", Some("/f3")).await.0, 201);
     }
 }
@@ -10,6 +10,7 @@ use crate::davpath::DavPath;
 use crate::errors::*;
 use crate::fs::*;
 use crate::multierror::{multi_error, MultiError};
+use crate::util::locked;
 use crate::DavResult;
 
 // map_err helper.
@@ -136,12 +137,13 @@ impl crate::DavInner {
         if let Some(ref locksystem) = self.ls {
             let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
             let principal = self.principal.as_deref();
-            if let Err(_l) = locksystem.check(&path, principal, false, true, t) {
-                return Err(DavError::Status(StatusCode::LOCKED));
+            if let Err(l) = locksystem.check(&path, principal, false, true, t) {
+                return Err(locked(&l));
             }
         }
 
         let req_path = path.clone();
+        let ct = self.xml_type(req);
 
         let items = AsyncStream::new(|tx| {
             async move {
@@ -162,6 +164,6 @@ impl crate::DavInner {
             }
         });
 
-        multi_error(req_path, items).await
+        multi_error(req_path, ct, items).await
     }
 }
@@ -1,11 +1,16 @@
 use std::cmp;
 use std::io::Write;
+use std::time::{Duration, SystemTime};
 
 use futures_util::StreamExt;
 use headers::HeaderMapExt;
 use http::{status::StatusCode, Request, Response};
+use time::format_description::{self, OwnedFormatItem};
+use time::UtcOffset;
 
 use bytes::Bytes;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
 
 use crate::async_stream::AsyncStream;
 use crate::body::Body;
@@ -14,14 +19,12 @@ use crate::davheaders;
 use crate::davpath::DavPath;
 use crate::errors::*;
 use crate::fs::*;
+use crate::handle_props::stored_content_type;
+use crate::metrics::{ServedGuard, StreamGuard};
+use crate::ranges::{has_suffix_range, parse_ranges, Range, RangeResolution};
 use crate::util::systemtime_to_offsetdatetime;
 use crate::DavMethod;
 
-struct Range {
-    start: u64,
-    count: u64,
-}
-
 const BOUNDARY: &str = "BOUNDARY";
 const BOUNDARY_START: &str = "\n--BOUNDARY\n";
 const BOUNDARY_END: &str = "\n--BOUNDARY--\n";
@@ -32,9 +35,30 @@ impl crate::DavInner {
     pub(crate) async fn handle_get(&self, req: &Request<()>) -> DavResult<Response<Body>> {
         let head = req.method() == http::Method::HEAD;
         let mut path = self.path(req);
+        let mut is_index = false;
+
+        // a browser landing on the root can be sent somewhere else.
+        if let (b"/", Some((target, status))) = (path.as_bytes(), self.root_redirect.as_ref()) {
+            let target = format!("{}{}", self.prefix.trim_end_matches('/'), target);
+            let target = DavPath::from_str_and_prefix(&target, &self.prefix)?;
+            let mut res = Response::new(Body::empty());
+            let location = self.location(req, &target);
+            res.headers_mut()
+                .insert("Location", location.parse().unwrap());
+            res.headers_mut().typed_insert(headers::ContentLength(0));
+            *res.status_mut() = *status;
+            return Ok(res);
+        }
+
+        // "/file/" is not a file, and not a directory either.
+        let meta = self.fs.metadata(&path).await;
+        let is_dir = matches!(meta, Ok(ref m) if m.is_dir());
+        if path.is_collection() && path.as_bytes() != b"/" && !is_dir {
+            return self.file_with_slash(req, path).await;
+        }
 
         // check if it's a directory.
-        let meta = self.fs.metadata(&path).await?;
+        let meta = meta?;
         if meta.is_dir() {
             //
             // This is a directory. If the path doesn't end in "/", send a redir.
@@ -44,31 +68,77 @@ impl crate::DavInner {
             if !path.is_collection() {
                 let mut res = Response::new(Body::empty());
                 path.add_slash();
-                res.headers_mut().insert(
-                    "Location",
-                    path.with_prefix().as_url_string().parse().unwrap(),
-                );
+                let mut location = self.location(req, &path);
+                // keep the query, like the sort order of a listing.
+                if let Some(query) = req.uri().query() {
+                    location = format!("{}?{}", location, query);
+                }
+                res.headers_mut()
+                    .insert("Location", location.parse().unwrap());
                 res.headers_mut().typed_insert(headers::ContentLength(0));
                 *res.status_mut() = StatusCode::FOUND;
                 return Ok(res);
             }
 
-            // If indexfile was set, use it.
+            // If indexfile was set, use it. If it is not there and autoindex
+            // was explicitly enabled, fall back to a generated index.
             if let Some(indexfile) = self.indexfile.as_ref() {
-                path.push_segment(indexfile.as_bytes());
+                let mut index = path.clone();
+                index.push_segment(indexfile.as_bytes());
+                if self.autoindex == Some(true) && !self.fs.exists(&index).await {
+                    return self.handle_autoindex(req, head).await;
+                }
+                path = index;
+                is_index = true;
             } else {
                 // Otherwise see if we need to generate a directory index.
                 return self.handle_autoindex(req, head).await;
             }
         }
 
+        // a FIFO or device has no content to send. Do not even open
+        // it, that might block.
+        if is_special(&*meta) {
+            debug!("GET {}: not a regular file", path);
+            return Err(DavError::Status(StatusCode::METHOD_NOT_ALLOWED));
+        }
+
+        // HEAD only needs the metadata, unless it might have to
+        // send a redirect. Otherwise, open the file.
+        let mut permit = None;
+        let (mut file, meta) = if head && !self.redirect.unwrap_or(false) {
+            // if we're at a directory, path now points to the index file.
+            let meta = match meta.is_dir() {
+                true => self.fs.metadata(&path).await?,
+                false => meta,
+            };
+            (None, meta)
+        } else {
+            // a permit from max_concurrent_gets, held until the body is done.
+            if let Some(ref limit) = self.get_limit {
+                match limit.clone().try_acquire_owned() {
+                    Ok(p) => permit = Some(p),
+                    Err(_) => {
+                        debug!("GET {}: too many concurrent requests", path);
+                        let mut res = Response::new(Body::empty());
+                        *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                        res.headers_mut()
+                            .insert("retry-after", http::HeaderValue::from_static("1"));
+                        res.headers_mut().typed_insert(headers::ContentLength(0));
+                        return Ok(res);
+                    }
+                }
+            }
+            let mut file = self.fs.open(&path, OpenOptions::read()).await?;
+            let meta = file.metadata().await?;
+            (Some(file), meta)
+        };
+
         // double check, is it a regular file.
-        let mut file = self.fs.open(&path, OpenOptions::read()).await?;
-        #[allow(unused_mut)]
-        let mut meta = file.metadata().await?;
         if !meta.is_file() {
             return Err(DavError::Status(StatusCode::METHOD_NOT_ALLOWED));
         }
+        let meta = with_content_hash(&*self.fs, &path, meta).await;
 
         let len = meta.len();
         let mut curpos = 0u64;
@@ -93,27 +163,41 @@ impl crate::DavInner {
             res.headers_mut().typed_insert(etag);
         }
 
-        match self.redirect {
-            Some(redirect) => {
-                if redirect {
-                    match file.redirect_url().await? {
-                        Some(url) => {
-                            res.headers_mut().insert("Location", url.parse().unwrap());
-                            *res.status_mut() = StatusCode::FOUND;
-                            return Ok(res);
-                        }
-                        None => {}
-                    }
-                }
+        // content-addressed files can be cached forever.
+        let immutable = self.immutable_paths.as_ref();
+        if matches!(immutable, Some(re) if re.is_match(&path.as_url_string())) {
+            res.headers_mut().insert(
+                "Cache-Control",
+                "public, max-age=31536000, immutable".parse().unwrap(),
+            );
+        }
+
+        // the index file is a different resource than the collection,
+        // tell caches which one they got.
+        if is_index {
+            let location = self.location(req, &path);
+            res.headers_mut()
+                .typed_insert(davheaders::ContentLocation(location));
+        }
+
+        if let (Some(true), Some(file)) = (self.redirect, file.as_mut()) {
+            if let Some(url) = file.redirect_url().await? {
+                res.headers_mut().insert("Location", url.parse().unwrap());
+                *res.status_mut() = StatusCode::FOUND;
+                return Ok(res);
             }
-            None => {}
         }
 
         // Apache always adds an Accept-Ranges header, even with partial
         // responses where it should be pretty obvious. So something somewhere
-        // probably depends on that.
-        res.headers_mut()
-            .typed_insert(headers::AcceptRanges::bytes());
+        // probably depends on that. Small files can be configured to be
+        // sent whole though.
+        if len < self.accept_ranges_min_size.unwrap_or(0) {
+            do_range = false;
+        } else {
+            res.headers_mut()
+                .typed_insert(headers::AcceptRanges::bytes());
+        }
 
         // handle the if-headers.
         if let Some(s) = conditional::if_match(req, Some(&meta), &self.fs, &self.ls, &path).await {
@@ -123,52 +207,86 @@ impl crate::DavInner {
         }
 
         // see if we want to get one or more ranges.
+        let mut suffix_range = false;
         if do_range {
-            if let Some(r) = req.headers().typed_get::<headers::Range>() {
-                trace!("handle_gethead: range header {:?}", r);
-                use std::ops::Bound::*;
-                for range in r.iter() {
-                    let (start, mut count, valid) = match range {
-                        (Included(s), Included(e)) if e >= s => (s, e - s + 1, true),
-                        (Included(s), Unbounded) if s <= len => (s, len - s, true),
-                        (Unbounded, Included(n)) if n <= len => (len - n, n, true),
-                        _ => (0, 0, false),
-                    };
-                    if !valid || start >= len {
-                        let r = format!("bytes */{}", len);
-                        res.headers_mut()
-                            .insert("Content-Range", r.parse().unwrap());
-                        *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
-                        ranges.clear();
-                        no_body = true;
-                        break;
-                    }
-                    if start + count > len {
-                        count = len - start;
-                    }
-                    ranges.push(Range { start, count });
+            match parse_ranges(req.headers(), len) {
+                RangeResolution::Full => {}
+                RangeResolution::Ranges(r) => {
+                    ranges = r;
+                    suffix_range = has_suffix_range(req.headers(), len);
+                }
+                RangeResolution::Unsatisfiable => {
+                    let r = format!("bytes */{}", len);
+                    res.headers_mut()
+                        .insert("Content-Range", r.parse().unwrap());
+                    *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                    no_body = true;
                 }
             }
         }
 
-        if !ranges.is_empty() {
+        // too many ranges? merge them, and if that does not help, send
+        // the whole file or refuse the request.
+        if let Some(max) = self.max_ranges {
+            if ranges.len() > max {
+                ranges = coalesce_ranges(ranges);
+            }
+            if ranges.len() > max {
+                debug!("handle_gethead: too many ranges ({})", ranges.len());
+                if self.max_ranges_reject.unwrap_or(false) {
+                    let r = format!("bytes */{}", len);
+                    res.headers_mut()
+                        .insert("Content-Range", r.parse().unwrap());
+                    *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                    no_body = true;
+                }
+                ranges.clear();
+            }
+        }
+
+        // a single range for the entire file is just the file.
+        if self.full_range_as_ok.unwrap_or(false)
+            && ranges.len() == 1
+            && ranges[0].start == 0
+            && ranges[0].count == len
+        {
+            ranges.clear();
+        }
+
+        let mut discard = false;
+        if let (false, Some(file)) = (ranges.is_empty(), file.as_mut()) {
             // seek to beginning of the first range.
             if file
                 .seek(std::io::SeekFrom::Start(ranges[0].start))
                 .await
                 .is_err()
             {
-                let r = format!("bytes */{}", len);
-                res.headers_mut()
-                    .insert("Content-Range", r.parse().unwrap());
-                *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
-                ranges.clear();
-                no_body = true;
+                let discard_ok = |max| matches!(discard_len(&ranges), Some(n) if n <= max);
+                match self.range_discard_max {
+                    Some(max) if !suffix_range && discard_ok(max) => {
+                        // not seekable, read and discard data up to the start of each range.
+                        discard = true;
+                    }
+                    Some(_) => {
+                        // not worth it, send the entire file instead.
+                        ranges.clear();
+                    }
+                    None => {
+                        let r = format!("bytes */{}", len);
+                        res.headers_mut()
+                            .insert("Content-Range", r.parse().unwrap());
+                        *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                        ranges.clear();
+                        no_body = true;
+                    }
+                }
             }
         }
 
         if !ranges.is_empty() {
-            curpos = ranges[0].start;
+            if !discard {
+                curpos = ranges[0].start;
+            }
 
             *res.status_mut() = StatusCode::PARTIAL_CONTENT;
             if ranges.len() == 1 {
@@ -195,7 +313,10 @@ impl crate::DavInner {
         }
 
         // set content-length and start if we're not doing multipart.
-        let content_type = path.get_mime_type_str();
+        let content_type = match stored_content_type(&*self.fs, &path).await {
+            Some(ct) => ct,
+            None => path.get_content_type(self.mime_types.as_deref()),
+        };
         if ranges.len() <= 1 {
             res.headers_mut()
                 .typed_insert(davheaders::ContentType(content_type.to_owned()));
@@ -208,15 +329,21 @@ impl crate::DavInner {
             res.headers_mut().typed_insert(headers::ContentLength(len));
         }
 
-        if head || no_body {
-            return Ok(res);
-        }
+        let mut file = match file {
+            Some(file) if !head && !no_body => file,
+            _ => return Ok(res),
+        };
 
         // now just loop and send data.
         let read_buf_size = self.read_buf_size.unwrap_or(READ_BUF_SIZE);
-        *res.body_mut() = Body::from(AsyncStream::new(|mut tx| {
+        let metrics = self.metrics.clone();
+        let mut served = ServedGuard::new(self.served_bytes.clone(), path.clone(), res.status());
+        let cancel = self.cancel.clone();
+        let strm = AsyncStream::new(|mut tx| {
             async move {
+                let _permit = permit;
                 let zero = [0; 4096];
+                let guard = StreamGuard::new(metrics);
 
                 let multipart = ranges.len() > 1;
                 for range in ranges {
@@ -225,7 +352,27 @@ impl crate::DavInner {
                         range.start,
                         range.count
                     );
-                    if curpos != range.start {
+                    // the whole range at once, if the file can.
+                    let mut data = None;
+                    if !discard {
+                        data = file.read_range(range.start, range.count).await.ok();
+                    }
+
+                    if data.is_some() {
+                        // read_range does not move the file position.
+                    } else if curpos != range.start && discard {
+                        // cannot seek, so read up to the start of the range.
+                        let mut skip = range.start - curpos;
+                        while skip > 0 {
+                            let blen = cmp::min(skip, read_buf_size as u64) as usize;
+                            let buf = file.read_bytes(blen).await?;
+                            if buf.is_empty() {
+                                break;
+                            }
+                            skip = skip.saturating_sub(buf.len() as u64);
+                        }
+                        curpos = range.start;
+                    } else if curpos != range.start {
                         // this should never fail, but if it does, just skip this range
                         // and try the next one.
                         if let Err(_e) = file.seek(std::io::SeekFrom::Start(range.start)).await {
@@ -250,8 +397,19 @@ impl crate::DavInner {
                         tx.send(Bytes::from(hdrs)).await;
                     }
 
+                    if let Some(buf) = data {
+                        let len = buf.len() as u64;
+                        tx.send(buf).await;
+                        guard.bytes_sent(len);
+                        served.bytes_sent(len);
+                        continue;
+                    }
+
                     let mut count = range.count;
                     while count > 0 {
+                        if let Some(ref cancel) = cancel {
+                            cancel.check()?;
+                        }
                         let blen = cmp::min(count, read_buf_size as u64) as usize;
                         let mut buf = file.read_bytes(blen).await?;
                         if buf.is_empty() {
@@ -265,18 +423,52 @@ impl crate::DavInner {
                         curpos += len;
                         trace!("sending {} bytes", len);
                         tx.send(buf).await;
+                        guard.bytes_sent(len);
+                        served.bytes_sent(len);
                     }
                 }
                 if multipart {
                     tx.send(Bytes::from(BOUNDARY_END)).await;
                 }
+                served.complete();
                 Ok::<(), std::io::Error>(())
             }
-        }));
+        });
+        *res.body_mut() = match self.write_timeout {
+            Some(timeout) => Body::from(with_write_timeout(strm, timeout)),
+            None => Body::from(strm),
+        };
 
         Ok(res)
     }
 
+    // GET of a file with a trailing slash: redirect to the
+    // file if configured to do so, otherwise it does not exist.
+    async fn file_with_slash(
+        &self,
+        req: &Request<()>,
+        mut path: DavPath,
+    ) -> DavResult<Response<Body>> {
+        path.remove_slash();
+        if self.file_slash_redirect.unwrap_or(false) {
+            if let Ok(meta) = self.fs.metadata(&path).await {
+                if meta.is_file() {
+                    let mut res = Response::new(Body::empty());
+                    let mut location = self.location(req, &path);
+                    if let Some(query) = req.uri().query() {
+                        location = format!("{}?{}", location, query);
+                    }
+                    res.headers_mut()
+                        .insert("Location", location.parse().unwrap());
+                    res.headers_mut().typed_insert(headers::ContentLength(0));
+                    *res.status_mut() = StatusCode::FOUND;
+                    return Ok(res);
+                }
+            }
+        }
+        Err(DavError::Status(StatusCode::NOT_FOUND))
+    }
+
     pub(crate) async fn handle_autoindex(
         &self,
         req: &Request<()>,
@@ -302,67 +494,180 @@ impl crate::DavInner {
             return Err(DavError::StatusClose(StatusCode::METHOD_NOT_ALLOWED));
         }
 
-        // read directory or bail.
-        let mut entries = self.fs.read_dir(&path, ReadDirMeta::Data).await?;
+        // read directory or bail. Without following symlinks, the
+        // entries have the metadata of the links themselves.
+        let follow = self.autoindex_follow_symlinks.unwrap_or(true);
+        let readdir_meta = match follow {
+            true => ReadDirMeta::Data,
+            false => ReadDirMeta::DataSymlink,
+        };
+        let mut entries = self.fs.read_dir(&path, readdir_meta).await?;
+        let meta = self.fs.metadata(&path).await?;
+
+        let show_broken = self.show_broken_symlinks.unwrap_or(false);
+        let show_icons = self.autoindex_icons.unwrap_or(false);
+        let mime_types = self.mime_types.as_deref();
+
+        // transform all entries into a dirent struct.
+        let mut dirents: Vec<Dirent> = Vec::new();
+        while let Some(dirent) = entries.next().await {
+            let mut name = dirent.name();
+            if name.starts_with(b".") {
+                continue;
+            }
+            let mut npath = path.clone();
+            npath.push_segment(&name);
+            if !self.can_list(&npath) {
+                continue;
+            }
+            let (meta, broken) = match dirent.metadata().await {
+                Ok(meta) if meta.is_symlink() => match self.fs.metadata(&npath).await {
+                    Ok(_) => (meta, false),
+                    Err(_) if show_broken => (meta, true),
+                    Err(_) => continue,
+                },
+                Ok(meta) => (meta, false),
+                Err(_) if show_broken => match self.fs.symlink_metadata(&npath).await {
+                    Ok(meta) if meta.is_symlink() => (meta, true),
+                    _ => continue,
+                },
+                // removed after read_dir returned it. Leave it out, the
+                // same as PROPFIND does.
+                Err(e) => {
+                    trace!("metadata error on {}. Skipping {:?}", npath, e);
+                    continue;
+                }
+            };
+            let icon = if meta.is_dir() {
+                name.push(b'/');
+                npath.add_slash();
+                ICON_DIR
+            } else {
+                file_icon(npath.get_mime_type_str(mime_types))
+            };
+            dirents.push(Dirent {
+                path: npath.with_prefix().as_url_string(),
+                name: display_name(&name),
+                meta,
+                broken,
+                icon,
+            });
+        }
 
         // start output
+        let plain = prefers_plain_text(req);
+        let ct = if plain {
+            "text/plain; charset=utf-8"
+        } else {
+            "text/html; charset=utf-8"
+        };
         res.headers_mut()
-            .insert("Content-Type", "text/html; charset=utf-8".parse().unwrap());
+            .insert("Content-Type", ct.parse().unwrap());
+        let compression = self.compression.unwrap_or(false);
+        let coding = content_coding(req).filter(|_| compression);
+        if let Some(coding) = coding {
+            res.headers_mut()
+                .insert("Content-Encoding", coding.parse().unwrap());
+        }
+        let vary = match compression {
+            true => "Accept, Accept-Encoding",
+            false => "Accept",
+        };
+        res.headers_mut().insert("Vary", vary.parse().unwrap());
         *res.status_mut() = StatusCode::OK;
+
+        // Last-Modified, ETag and the if-headers are derived from the
+        // members of the directory, so that adding, removing or changing
+        // a file also changes the listing's validators. The etag is the
+        // one of the collection, the same as PROPFIND reports.
+        let meta = listing_meta(&*meta, &dirents);
+        if let Ok(modified) = meta.modified() {
+            res.headers_mut()
+                .typed_insert(headers::LastModified::from(modified));
+        }
+        // the compressed listing is another representation, so its
+        // etag is weak, the same as nginx does.
+        let etag = match coding {
+            Some(_) => meta
+                .etag()
+                .and_then(|t| davheaders::ETag::new(true, t).ok()),
+            None => davheaders::ETag::from_meta(&meta),
+        };
+        if let Some(etag) = etag {
+            res.headers_mut().typed_insert(etag);
+        }
+        if let Some(s) = conditional::if_match(req, Some(&meta), &self.fs, &self.ls, &path).await {
+            *res.status_mut() = s;
+            return Ok(res);
+        }
+
         if head {
             return Ok(res);
         }
 
+        let (page, per) = page_params(req.uri().query());
+        let per = per.or(self.autoindex_page_size).filter(|&n| n > 0);
+        // the page links keep the rest of the query, like the sort order.
+        let query: String = req
+            .uri()
+            .query()
+            .unwrap_or("")
+            .split('&')
+            .filter(|p| !p.is_empty() && !p.starts_with("page=") && !p.starts_with("per="))
+            .map(|p| format!("{}&amp;", html_text(p)))
+            .collect();
+
+        // listing order: the .davsort file, then the query string.
+        let mut sort = Sort::default();
+        if self.autoindex_sort_file.unwrap_or(false) {
+            let mut spath = path.clone();
+            spath.push_segment(b".davsort");
+            if let Ok(data) = read_sort_file(&*self.fs, &spath).await {
+                sort.parse(&String::from_utf8_lossy(&data));
+            }
+        }
+        sort.parse(req.uri().query().unwrap_or(""));
+
+        let utc_offset = self.autoindex_utc_offset.unwrap_or(UtcOffset::UTC);
+        let time_format = listing_time_format(self.autoindex_time_format.as_deref(), utc_offset);
+
         // now just loop and send data.
-        *res.body_mut() = Body::from(AsyncStream::new(|mut tx| {
+        let listing = AsyncStream::new(|mut tx| {
             async move {
-                // transform all entries into a dirent struct.
-                struct Dirent {
-                    path: String,
-                    name: String,
-                    meta: Box<dyn DavMetaData>,
-                }
+                // now we can sort the dirent struct.
+                dirents.sort_by(|a, b| sort.compare(a, b));
 
-                let mut dirents: Vec<Dirent> = Vec::new();
-                while let Some(dirent) = entries.next().await {
-                    let mut name = dirent.name();
-                    if name.starts_with(b".") {
-                        continue;
-                    }
-                    let mut npath = path.clone();
-                    npath.push_segment(&name);
-                    if let Ok(meta) = dirent.metadata().await {
-                        if meta.is_dir() {
-                            name.push(b'/');
-                            npath.add_slash();
-                        }
-                        dirents.push(Dirent {
-                            path: npath.with_prefix().as_url_string(),
-                            name: String::from_utf8_lossy(&name).to_string(),
-                            meta,
-                        });
+                // plain text is just one href per line.
+                if plain {
+                    let mut w = String::new();
+                    for dirent in dirents.iter().filter(|d| !d.broken) {
+                        w.push_str(&dirent.path);
+                        w.push('\n');
                     }
+                    tx.send(Bytes::from(w)).await;
+                    return Ok(());
                 }
 
-                // now we can sort the dirent struct.
-                dirents.sort_by(|a, b| {
-                    let adir = a.meta.is_dir();
-                    let bdir = b.meta.is_dir();
-                    if adir && !bdir {
-                        std::cmp::Ordering::Less
-                    } else if bdir && !adir {
-                        std::cmp::Ordering::Greater
-                    } else {
-                        (a.name).cmp(&b.name)
-                    }
-                });
+                // html output can be split into pages.
+                let mut pages = 1;
+                let mut page = page.unwrap_or(1);
+                if let Some(per) = per {
+                    pages = std::cmp::max(1, dirents.len().div_ceil(per));
+                    page = page.clamp(1, pages);
+                    let start = (page - 1) * per;
+                    dirents.truncate(start + per);
+                    dirents.drain(..start);
+                }
 
                 // and output html
-                let upath = htmlescape::encode_minimal(&path.with_prefix().as_url_string());
+                let upath = html_text(&path.with_prefix().as_url_string());
                 let mut w = String::new();
                 w.push_str(
                     "\
+                    <!DOCTYPE html>\n\
                     <html><head>\n\
+                    <meta charset=\"utf-8\" />\n\
+                    <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\" />\n\
                     <meta name=\"referrer\" content=\"no-referrer\" />\n\
                     <title>Index of ",
                 );
@@ -391,6 +696,9 @@ impl crate::DavInner {
                     .mw20 {\n\
                       min-width: 20em;\n\
                     }\n\
+                    .icon {\n\
+                      padding-right: 0.4em;\n\
+                    }\n\
                     </style>\n\
                     </head>\n\
                     <body>\n",
@@ -404,54 +712,440 @@ impl crate::DavInner {
                       <th class=\"left\">Last modified</th>\n\
                       <th>Size</th>\n\
                     </tr>\n\
-                    <tr><th colspan=\"3\"><hr></th></tr>\n\
+                    <tr><th colspan=\"3\"><hr></th></tr>\n",
+                );
+                let icon = |icon: &str| match show_icons {
+                    true => format!("<span class=\"icon\">{}</span>", icon),
+                    false => String::new(),
+                };
+                w.push_str(&format!(
+                    "\
                     <tr>\n\
-                      <td><a href=\"..\">Parent Directory</a></td>\n\
+                      <td>{}<a href=\"..\">Parent Directory</a></td>\n\
                       <td>&nbsp;</td>\n\
                       <td class=\"mono\" align=\"right\">[DIR]    </td>\n\
                     </tr>\n",
-                );
+                    icon(ICON_DIR)
+                ));
 
                 tx.send(Bytes::from(w)).await;
 
                 for dirent in &dirents {
                     let modified = match dirent.meta.modified() {
-                        Ok(t) => {
-                            let tm = systemtime_to_offsetdatetime(t);
-                            format!(
-                                "{:04}-{:02}-{:02} {:02}:{:02}",
-                                tm.year(),
-                                tm.month(),
-                                tm.day(),
-                                tm.hour(),
-                                tm.minute(),
-                            )
-                        }
+                        Ok(t) => systemtime_to_offsetdatetime(t)
+                            .to_offset(utc_offset)
+                            .format(&time_format)
+                            .unwrap_or_default(),
                         Err(_) => "".to_string(),
                     };
-                    let size = match dirent.meta.is_file() {
-                        true => display_size(dirent.meta.len()),
-                        false => "[DIR]    ".to_string(),
+                    let size = if dirent.meta.is_symlink() {
+                        "[LINK]   ".to_string()
+                    } else if dirent.meta.is_file() {
+                        display_size(dirent.meta.len())
+                    } else if is_special(&*dirent.meta) {
+                        "[SPECIAL]".to_string()
+                    } else {
+                        "[DIR]    ".to_string()
                     };
-                    let name = htmlescape::encode_minimal(&dirent.name);
-                    let s = format!("<tr><td><a href=\"{}\">{}</a></td><td class=\"mono\">{}</td><td class=\"mono\" align=\"right\">{}</td></tr>",
-                         dirent.path, name, modified, size);
+                    let name = html_text(&dirent.name);
+                    let modified = html_text(&modified);
+                    let size = html_text(&size);
+                    if dirent.broken {
+                        let s = format!("<tr><td>{}{}</td><td class=\"mono\">{}</td><td class=\"mono\" align=\"right\">[BROKEN LINK]</td></tr>",
+                            icon(ICON_FILE), name, modified);
+                        tx.send(Bytes::from(s)).await;
+                        continue;
+                    }
+                    let s = format!("<tr><td>{}<a href=\"{}\">{}</a></td><td class=\"mono\">{}</td><td class=\"mono\" align=\"right\">{}</td></tr>",
+                         icon(dirent.icon), html_text(&dirent.path), name, modified, size);
                     tx.send(Bytes::from(s)).await;
                 }
 
                 let mut w = String::new();
                 w.push_str("<tr><th colspan=\"3\"><hr></th></tr>");
-                w.push_str("</table></body></html>");
+                w.push_str("</table>");
+                if let (Some(per), true) = (per, pages > 1) {
+                    let link = |p: usize, text: &str| {
+                        if p == page {
+                            text.to_string()
+                        } else {
+                            format!(
+                                "<a href=\"?{}page={}&amp;per={}\">{}</a>",
+                                query, p, per, text
+                            )
+                        }
+                    };
+                    w.push_str(&format!(
+                        "<p>{} {} Page {} of {} {} {}</p>",
+                        link(1, "&laquo; first"),
+                        link(std::cmp::max(page - 1, 1), "&lsaquo; previous"),
+                        page,
+                        pages,
+                        link(std::cmp::min(page + 1, pages), "next &rsaquo;"),
+                        link(pages, "last &raquo;"),
+                    ));
+                }
+                w.push_str("</body></html>");
                 tx.send(Bytes::from(w)).await;
 
                 Ok::<_, std::io::Error>(())
             }
-        }));
+        });
+        *res.body_mut() = match coding {
+            Some(coding) => Body::from(compressed(listing, coding)),
+            None => Body::from(listing),
+        };
 
         Ok(res)
     }
 }
 
+// An entry in the html index.
+struct Dirent {
+    path: String,
+    name: String,
+    meta: Box<dyn DavMetaData>,
+    // a symbolic link that points to nothing.
+    broken: bool,
+    icon: &'static str,
+}
+
+// Metadata of a directory listing: the most recent modification time of
+// the directory and its members, and the etag of the directory.
+#[derive(Debug, Clone)]
+struct ListingMeta {
+    modified: Option<SystemTime>,
+    etag: Option<String>,
+}
+
+impl DavMetaData for ListingMeta {
+    fn len(&self) -> u64 {
+        0
+    }
+    fn modified(&self) -> FsResult<SystemTime> {
+        self.modified.ok_or(FsError::NotImplemented)
+    }
+    fn is_dir(&self) -> bool {
+        true
+    }
+    fn etag(&self) -> Option<String> {
+        self.etag.clone()
+    }
+}
+
+// Produce the items of `strm` in a task of its own, that gives up if
+// the client did not take an item within `timeout`. The body then ends
+// with an error. A client that stops reading entirely no longer keeps
+// the file open.
+fn with_write_timeout(
+    mut strm: AsyncStream<Bytes, std::io::Error>,
+    timeout: Duration,
+) -> AsyncStream<Bytes, std::io::Error> {
+    let (chan_tx, mut chan_rx) = tokio::sync::mpsc::channel(1);
+    let task = tokio::spawn(async move {
+        while let Some(item) = strm.next().await {
+            match tokio::time::timeout(timeout, chan_tx.send(item)).await {
+                Ok(Ok(())) => {}
+                // the body was dropped.
+                Ok(Err(_)) => break,
+                Err(_) => {
+                    debug!("handle_get: write timeout");
+                    return Err(std::io::ErrorKind::TimedOut.into());
+                }
+            }
+        }
+        Ok(())
+    });
+    AsyncStream::new(|mut tx| async move {
+        while let Some(item) = chan_rx.recv().await {
+            tx.send(item?).await;
+        }
+        task.await.map_err(std::io::Error::other)?
+    })
+}
+
+fn listing_meta(dir: &dyn DavMetaData, dirents: &[Dirent]) -> Box<dyn DavMetaData> {
+    let mut modified = dir.modified().ok();
+    for d in dirents {
+        if let Ok(t) = d.meta.modified() {
+            modified = cmp::max(modified, Some(t));
+        }
+    }
+    Box::new(ListingMeta {
+        modified,
+        etag: dir.etag(),
+    })
+}
+
+// Icons for the html index, as html character references so that
+// the page does not need to load anything.
+const ICON_DIR: &str = "&#x1F4C1;";
+const ICON_TEXT: &str = "&#x1F4C4;";
+const ICON_IMAGE: &str = "&#x1F5BC;&#xFE0F;";
+const ICON_ARCHIVE: &str = "&#x1F4E6;";
+const ICON_FILE: &str = "&#x1F4C3;";
+
+// Pick an icon for a file based on its mime type.
+fn file_icon(mime: &str) -> &'static str {
+    let mime = mime.split(';').next().unwrap_or("").trim();
+    match mime {
+        "application/zip"
+        | "application/gzip"
+        | "application/x-gzip"
+        | "application/x-tar"
+        | "application/x-bzip2"
+        | "application/x-xz"
+        | "application/x-7z-compressed"
+        | "application/x-rar-compressed"
+        | "application/vnd.rar"
+        | "application/zstd" => ICON_ARCHIVE,
+        "application/json" | "application/xml" | "application/javascript" => ICON_TEXT,
+        _ if mime.starts_with("text/") => ICON_TEXT,
+        _ if mime.starts_with("image/") => ICON_IMAGE,
+        _ => ICON_FILE,
+    }
+}
+
+// Number of bytes that have to be read and discarded to serve
+// these ranges from a file that cannot seek. Returns None if
+// the ranges are not in ascending order.
+fn discard_len(ranges: &[Range]) -> Option<u64> {
+    let mut pos = 0;
+    let mut total = 0;
+    for range in ranges {
+        if range.start < pos {
+            return None;
+        }
+        total += range.start - pos;
+        pos = range.start + range.count;
+    }
+    Some(total)
+}
+
+// Order of the directory listing.
+#[derive(Debug, Default, Clone, Copy)]
+struct Sort {
+    key: SortKey,
+    desc: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum SortKey {
+    #[default]
+    Name,
+    Time,
+    Size,
+}
+
+impl Sort {
+    // Apply the "sort" and "order" parameters of a query string or of a
+    // .davsort file. Unknown parameters and values are ignored.
+    fn parse(&mut self, params: &str) {
+        for param in params.split(|c: char| c == '&' || c.is_whitespace()) {
+            match param.split_once('=') {
+                Some(("sort", "name")) => self.key = SortKey::Name,
+                Some(("sort", "time")) => self.key = SortKey::Time,
+                Some(("sort", "size")) => self.key = SortKey::Size,
+                Some(("order", "asc")) => self.desc = false,
+                Some(("order", "desc")) => self.desc = true,
+                _ => {}
+            }
+        }
+    }
+
+    // Directories first, then by key, then by name.
+    fn compare(&self, a: &Dirent, b: &Dirent) -> cmp::Ordering {
+        let (adir, bdir) = (a.meta.is_dir(), b.meta.is_dir());
+        if adir != bdir {
+            return bdir.cmp(&adir);
+        }
+        let ord = match self.key {
+            SortKey::Name => cmp::Ordering::Equal,
+            SortKey::Time => a.meta.modified().ok().cmp(&b.meta.modified().ok()),
+            SortKey::Size => a.meta.len().cmp(&b.meta.len()),
+        };
+        let ord = ord.then_with(|| a.name.cmp(&b.name));
+        if self.desc {
+            ord.reverse()
+        } else {
+            ord
+        }
+    }
+}
+
+// Read a .davsort file. It is tiny, so a single read will do.
+async fn read_sort_file(fs: &dyn DavFileSystem, path: &DavPath) -> FsResult<Bytes> {
+    let mut file = fs.open(path, OpenOptions::read()).await?;
+    file.read_bytes(1024).await
+}
+
+// Get the "page" and "per" parameters from the query string.
+fn page_params(query: Option<&str>) -> (Option<usize>, Option<usize>) {
+    let mut page = None;
+    let mut per = None;
+    for param in query.unwrap_or("").split('&') {
+        let mut kv = param.splitn(2, '=');
+        match (kv.next(), kv.next().and_then(|v| v.parse().ok())) {
+            (Some("page"), Some(v)) => page = Some(v),
+            (Some("per"), Some(v)) => per = Some(v),
+            _ => {}
+        }
+    }
+    (page, per)
+}
+
+// Sort ranges and merge the ones that overlap or are adjacent.
+fn coalesce_ranges(mut ranges: Vec<Range>) -> Vec<Range> {
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.start + last.count => {
+                let end = std::cmp::max(last.start + last.count, range.start + range.count);
+                last.count = end - last.start;
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+// Format of the modification times in the autoindex. The default always
+// shows the offset, so that the time is not ambiguous.
+fn listing_time_format(format: Option<&str>, offset: UtcOffset) -> OwnedFormatItem {
+    if let Some(format) = format {
+        match format_description::parse_owned::<2>(format) {
+            Ok(f) => return f,
+            Err(e) => debug!("autoindex_time_format {:?}: {}", format, e),
+        }
+    }
+    let format = if offset.is_utc() {
+        "[year]-[month]-[day] [hour]:[minute]Z"
+    } else {
+        "[year]-[month]-[day] [hour]:[minute][offset_hour sign:mandatory]:[offset_minute]"
+    };
+    format_description::parse_owned::<2>(format).unwrap()
+}
+
+// See if the Accept header prefers text/plain over text/html.
+// Browsers and "Accept: */*" get html.
+fn prefers_plain_text(req: &Request<()>) -> bool {
+    let accept = match req.headers().get("accept").and_then(|v| v.to_str().ok()) {
+        Some(accept) => accept,
+        None => return false,
+    };
+
+    // quality values for text/plain, text/html, text/* and */*.
+    let mut q = [None; 4];
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let mtype = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let qval = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|v| v.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let idx = match mtype.as_str() {
+            "text/plain" => 0,
+            "text/html" => 1,
+            "text/*" => 2,
+            "*/*" => 3,
+            _ => continue,
+        };
+        q[idx] = Some(qval);
+    }
+    let wildcard = q[2].or(q[3]).unwrap_or(0.0);
+    let plain = q[0].unwrap_or(wildcard);
+    let html = q[1].unwrap_or(wildcard);
+    plain > html
+}
+
+// The content-coding to compress a generated response with: gzip or
+// deflate, whichever the client accepts with the higher quality.
+fn content_coding(req: &Request<()>) -> Option<&'static str> {
+    let accept = req
+        .headers()
+        .get("accept-encoding")
+        .and_then(|v| v.to_str().ok())?;
+
+    // quality values for gzip, deflate and *.
+    let mut q = [None; 3];
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let qval = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|v| v.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let idx = match coding.as_str() {
+            "gzip" | "x-gzip" => 0,
+            "deflate" => 1,
+            "*" => 2,
+            _ => continue,
+        };
+        q[idx] = Some(qval);
+    }
+    let gzip = q[0].or(q[2]).unwrap_or(0.0);
+    let deflate = q[1].or(q[2]).unwrap_or(0.0);
+    if gzip > 0.0 && gzip >= deflate {
+        Some("gzip")
+    } else if deflate > 0.0 {
+        Some("deflate")
+    } else {
+        None
+    }
+}
+
+// Compress `strm` with the content-coding from content_coding(). HTTP
+// "deflate" is the zlib format (RFC 9110, 8.4.1.2).
+fn compressed(
+    mut strm: AsyncStream<Bytes, std::io::Error>,
+    coding: &'static str,
+) -> AsyncStream<Bytes, std::io::Error> {
+    AsyncStream::new(|mut tx| async move {
+        let mut enc = match coding {
+            "gzip" => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::default())),
+            _ => Encoder::Deflate(ZlibEncoder::new(Vec::new(), Compression::default())),
+        };
+        while let Some(data) = strm.next().await {
+            let out = enc.write(&data?)?;
+            if !out.is_empty() {
+                tx.send(Bytes::from(out)).await;
+            }
+        }
+        tx.send(Bytes::from(enc.finish()?)).await;
+        Ok::<_, std::io::Error>(())
+    })
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    // compress `data`, and return what the encoder has output so far.
+    fn write(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let out = match self {
+            Encoder::Gzip(e) => {
+                e.write_all(data)?;
+                e.get_mut()
+            }
+            Encoder::Deflate(e) => {
+                e.write_all(data)?;
+                e.get_mut()
+            }
+        };
+        Ok(std::mem::take(out))
+    }
+
+    fn finish(self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(e) => e.finish(),
+            Encoder::Deflate(e) => e.finish(),
+        }
+    }
+}
+
 fn display_size(size: u64) -> String {
     let (formatted, unit) = ["KiB", "MiB", "GiB", "TiB", "PiB"]
         .iter()
@@ -462,8 +1156,42 @@ fn display_size(size: u64) -> String {
     format!("{} {}", (formatted * 100f64).round() / 100f64, unit)
 }
 
+// Filename for display. Bytes that are not valid UTF-8 are shown
+// percent-encoded, instead of as replacement characters, so that
+// different names do not end up looking the same.
+fn display_name(name: &[u8]) -> String {
+    let mut out = String::new();
+    let mut rest = name;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(s) => {
+                out.push_str(s);
+                break;
+            }
+            Err(e) => {
+                let (valid, invalid) = rest.split_at(e.valid_up_to());
+                out.push_str(std::str::from_utf8(valid).unwrap());
+                let n = e.error_len().unwrap_or(invalid.len());
+                for b in &invalid[..n] {
+                    out.push_str(&format!("%{:02X}", b));
+                }
+                rest = &invalid[n..];
+            }
+        }
+    }
+    out
+}
+
+// Escaping for the html index. Names come from the filesystem and can
+// contain anything, so every value that ends up in the page goes through
+// this, text and (double-quoted) attributes alike. Urls are
+// percent-encoded first, then escaped as well.
+fn html_text(s: &str) -> String {
+    htmlescape::encode_minimal(s)
+}
+
 fn display_path(path: &DavPath) -> String {
-    let path_dsp = String::from_utf8_lossy(path.with_prefix().as_bytes());
+    let path_dsp = display_name(path.with_prefix().as_bytes());
     let path_url = path.with_prefix().as_url_string();
     let dpath_segs = path_dsp
         .split('/')
@@ -476,22 +1204,1248 @@ fn display_path(path: &DavPath) -> String {
     let mut dpath = String::new();
     let mut upath = String::new();
 
-    if dpath_segs.is_empty() {
+    // the first link is to the root of the handler, the prefix.
+    let root = path.prefix().split('/').filter(|s| !s.is_empty()).count();
+    for idx in 0..root {
+        upath.push('/');
+        upath.push_str(upath_segs[idx]);
         dpath.push('/');
-    } else {
-        dpath.push_str("<a href = \"/\">/</a>");
+        dpath.push_str(&html_text(dpath_segs[idx]));
+    }
+    upath.push('/');
+    dpath.push('/');
+    if dpath_segs.len() > root {
+        dpath = format!("<a href = \"{}\">{}</a>", html_text(&upath), dpath);
     }
+    upath.pop();
 
-    for idx in 0..dpath_segs.len() {
+    for idx in root..dpath_segs.len() {
         upath.push('/');
         upath.push_str(upath_segs[idx]);
-        let dseg = htmlescape::encode_minimal(dpath_segs[idx]);
+        let dseg = html_text(dpath_segs[idx]);
         if idx == dpath_segs.len() - 1 {
             dpath.push_str(&dseg);
         } else {
-            dpath.push_str(&format!("<a href = \"{}\">{}</a>/", upath, dseg));
+            let href = html_text(&upath);
+            dpath.push_str(&format!("<a href = \"{}\">{}</a>/", href, dseg));
         }
     }
 
     dpath
 }
+
+#[cfg(all(test, feature = "memfs"))]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::testfs::{self, body_text, memfs_handler, FsHooks, HookFs};
+    use crate::DavHandler;
+
+    // testfs::request() with an Accept header, and the status and body
+    // of the response.
+    async fn request(
+        dav: &DavHandler,
+        method: &str,
+        uri: &str,
+        accept: &str,
+        body: &'static str,
+    ) -> (StatusCode, Bytes) {
+        let resp = testfs::request(dav, method, uri, &[("Accept", accept)], body).await;
+        let status = resp.status();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        (status, body)
+    }
+
+    #[test]
+    fn test_display_name() {
+        assert_eq!(display_name(b"caf\xc3\xa9"), "café");
+        assert_eq!(display_name(b"a\xffb\xfe"), "a%FFb%FE");
+    }
+
+    #[tokio::test]
+    async fn test_tricky_names() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .build_handler();
+
+        // name as url, name as displayed in the html listing.
+        let names = [
+            ("/a%20b%23c%3Fd%25e.txt", "a b#c?d%e.txt"),
+            ("/%C3%BCn%C3%AFc%C3%B6d%C3%A9", "ünïcödé"),
+            ("/%3Cb%3E%26amp%3B%22", "&lt;b&gt;&amp;amp;&quot;"),
+            ("/%FF%FEnot-utf8", "%FF%FEnot-utf8"),
+        ];
+        for (url, _) in &names {
+            let (status, _) = request(&dav, "PUT", url, "*/*", "hello").await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+
+        // every href in the listing must get us the file.
+        let (status, body) = request(&dav, "GET", "/", "text/plain", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let hrefs = body.lines().collect::<Vec<_>>();
+        assert_eq!(hrefs.len(), names.len());
+        for href in hrefs {
+            let (status, body) = request(&dav, "GET", href, "*/*", "").await;
+            assert_eq!(status, StatusCode::OK, "GET {}", href);
+            assert_eq!(&body[..], b"hello");
+        }
+
+        // and the html listing shows the escaped names.
+        let (status, body) = request(&dav, "GET", "/", "*/*", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        for (_, display) in &names {
+            assert!(body.contains(&format!(">{}</a>", display)), "{}", display);
+        }
+    }
+
+    #[cfg(all(unix, feature = "localfs"))]
+    #[tokio::test]
+    async fn test_special_files() {
+        use crate::localfs::LocalFs;
+        use crate::testfs::TempDir;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = TempDir::new("special");
+        let fifo = std::ffi::CString::new(dir.join("fifo").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&*dir, false, false, false))
+            .autoindex(true)
+            .build_handler();
+
+        // GET and HEAD are refused without opening (and blocking on) the FIFO.
+        for method in &["GET", "HEAD"] {
+            let (status, _) = request(&dav, method, "/fifo", "*/*", "").await;
+            assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        }
+
+        let (_, body) = request(&dav, "GET", "/", "text/html", "").await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let row = body.lines().find(|l| l.contains(">fifo</a>")).unwrap();
+        assert!(row.contains("[SPECIAL]"));
+
+        let resp = testfs::request(&dav, "PROPFIND", "/fifo", &[("Depth", "0")], "").await;
+        let body = body_text(resp).await;
+        assert!(body.contains("<D:resourcetype></D:resourcetype>"));
+        assert!(body.contains("<D:getlastmodified>"));
+        assert!(!body.contains("getcontentlength"));
+        assert!(!body.contains("getcontenttype"));
+    }
+
+    #[cfg(all(unix, feature = "localfs"))]
+    #[tokio::test]
+    async fn test_broken_symlinks() {
+        use crate::localfs::LocalFs;
+        use crate::testfs::TempDir;
+
+        let dir = TempDir::new("broken");
+        std::fs::write(dir.join("file"), b"hello").unwrap();
+        std::os::unix::fs::symlink(dir.join("nowhere"), dir.join("dangling")).unwrap();
+
+        for show in &[false, true] {
+            let dav = DavHandler::builder()
+                .filesystem(LocalFs::new(&*dir, false, false, false))
+                .autoindex(true)
+                .hide_symlinks(false)
+                .show_broken_symlinks(*show)
+                .build_handler();
+
+            let (status, body) = request(&dav, "GET", "/", "*/*", "").await;
+            assert_eq!(status, StatusCode::OK);
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            assert!(body.contains(">file</a>"));
+            assert_eq!(body.contains(">dangling</td>"), *show);
+            assert_eq!(body.contains("[BROKEN LINK]"), *show);
+
+            let resp = testfs::request(&dav, "PROPFIND", "/", &[("Depth", "1")], "").await;
+            let body = body_text(resp).await;
+            let broken = "<D:href>/dangling</D:href><D:status>HTTP/1.1 404 Not Found</D:status>";
+            assert!(body.contains("<D:href>/file</D:href>"));
+            assert_eq!(body.contains(broken), *show);
+        }
+    }
+
+    #[cfg(all(unix, feature = "localfs"))]
+    #[tokio::test]
+    async fn test_symlinked_dir() {
+        use crate::localfs::LocalFs;
+        use crate::testfs::TempDir;
+
+        let dir = TempDir::new("dirlink");
+        std::fs::create_dir(dir.join("real")).unwrap();
+        std::os::unix::fs::symlink(dir.join("real"), dir.join("link")).unwrap();
+
+        for follow in &[true, false] {
+            let dav = DavHandler::builder()
+                .filesystem(LocalFs::new(&*dir, false, false, false))
+                .autoindex(true)
+                .autoindex_follow_symlinks(*follow)
+                .build_handler();
+
+            let (status, body) = request(&dav, "GET", "/", "text/html", "").await;
+            assert_eq!(status, StatusCode::OK);
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            assert!(body.contains("<a href=\"/real/\">real/</a>"));
+            if *follow {
+                assert!(body.contains("<a href=\"/link/\">link/</a>"));
+            } else {
+                assert!(body.contains("<a href=\"/link\">link</a>"));
+                assert!(body.contains("[LINK]"));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_autoindex_icons() {
+        // icons are off by default.
+        for icons in &[None, Some(true), Some(false)] {
+            let mut cfg = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .autoindex(true);
+            if let Some(icons) = *icons {
+                cfg = cfg.autoindex_icons(icons);
+            }
+            let dav = cfg.build_handler();
+            request(&dav, "MKCOL", "/dir", "*/*", "").await;
+            request(&dav, "PUT", "/a.txt", "*/*", "a").await;
+            request(&dav, "PUT", "/b.png", "*/*", "b").await;
+            let (status, body) = request(&dav, "GET", "/", "text/html", "").await;
+            assert_eq!(status, StatusCode::OK);
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            for icon in &[ICON_DIR, ICON_TEXT, ICON_IMAGE] {
+                assert_eq!(body.contains(icon), *icons == Some(true));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_autoindex_breadcrumbs() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_prefix("/dav")
+            .autoindex(true)
+            .build_handler();
+        request(&dav, "MKCOL", "/dav/a%20b/", "*/*", "").await;
+        request(&dav, "MKCOL", "/dav/a%20b/c/", "*/*", "").await;
+        let h1 = |uri: &'static str| {
+            let dav = dav.clone();
+            async move {
+                let (_, body) = request(&dav, "GET", uri, "text/html", "").await;
+                let body = String::from_utf8(body.to_vec()).unwrap();
+                let start = body.find("<h1>").unwrap();
+                let end = body.find("</h1>").unwrap();
+                body[start + 4..end].to_string()
+            }
+        };
+        assert_eq!(h1("/dav/").await, "Index of /dav/");
+        assert_eq!(
+            h1("/dav/a%20b/c/").await,
+            "Index of <a href = \"/dav/\">/dav/</a><a href = \"/dav/a%20b\">a b</a>/c"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_autoindex_conditional() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .build_handler();
+        let dav = &dav;
+        let get = |etag| async move {
+            testfs::request(dav, "GET", "/", &[("If-None-Match", etag)], "").await
+        };
+        request(dav, "PUT", "/a", "*/*", "a").await;
+        let resp = get("\"nomatch\"").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+        assert!(resp.headers().contains_key("last-modified"));
+
+        assert_eq!(get(&etag).await.status(), StatusCode::NOT_MODIFIED);
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        request(dav, "PUT", "/b", "*/*", "b").await;
+        assert_eq!(get(&etag).await.status(), StatusCode::OK);
+
+        // an unchanged directory lists with the same etag, and
+        // overwriting a member changes it.
+        let etag = get("\"nomatch\"").await.headers()["etag"].clone();
+        assert_eq!(get("\"nomatch\"").await.headers()["etag"], etag);
+        let etag = etag.to_str().unwrap().to_string();
+        assert_eq!(get(&etag).await.status(), StatusCode::NOT_MODIFIED);
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        request(dav, "PUT", "/a", "*/*", "aa").await;
+        assert_eq!(get(&etag).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_autoindex_compression() {
+        use std::io::Read;
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .compression(true)
+            .build_handler();
+        let dav = &dav;
+        let get = |method, coding, etag| async move {
+            let hdrs = [("Accept-Encoding", coding), ("If-None-Match", etag)];
+            testfs::request(dav, method, "/", &hdrs, "").await
+        };
+        request(dav, "PUT", "/a", "*/*", "a").await;
+        let (_, plain) = request(dav, "GET", "/", "text/html", "").await;
+
+        let resp = get("GET", "gzip, deflate", "\"nomatch\"").await;
+        assert_eq!(resp.headers()["content-encoding"], "gzip");
+        assert_eq!(resp.headers()["vary"], "Accept, Accept-Encoding");
+        let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+        assert!(etag.starts_with("W/"));
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let mut data = Vec::new();
+        flate2::read::GzDecoder::new(&body[..])
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, plain);
+        assert_eq!(get("GET", "gzip", &etag).await.status(), 304);
+
+        let resp = get("GET", "gzip;q=0.5, deflate", "\"nomatch\"").await;
+        assert_eq!(resp.headers()["content-encoding"], "deflate");
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let mut data = Vec::new();
+        flate2::read::ZlibDecoder::new(&body[..])
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, plain);
+
+        let resp = get("HEAD", "gzip", "\"nomatch\"").await;
+        assert_eq!(resp.headers()["content-encoding"], "gzip");
+        assert!(!resp.headers().contains_key("content-length"));
+
+        let resp = get("GET", "gzip;q=0, identity", "\"nomatch\"").await;
+        assert!(!resp.headers().contains_key("content-encoding"));
+        assert!(!resp.headers()["etag"].to_str().unwrap().starts_with("W/"));
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body, plain);
+    }
+
+    #[tokio::test]
+    async fn test_autoindex_pages() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .autoindex_page_size(4)
+            .build_handler();
+        for name in &["a", "b", "c", "d", "e"] {
+            request(&dav, "PUT", &format!("/{}", name), "*/*", "").await;
+        }
+        let names = |body: Bytes| {
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            let names = ["a", "b", "c", "d", "e"].iter();
+            names
+                .filter(|n| body.contains(&format!(">{}</a>", n)))
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        let (_, body) = request(&dav, "GET", "/", "text/html", "").await;
+        assert_eq!(names(body), ["a", "b", "c", "d"]);
+        let (_, body) = request(&dav, "GET", "/?page=2", "text/html", "").await;
+        assert_eq!(names(body), ["e"]);
+        let (_, body) = request(&dav, "GET", "/?page=2&per=2", "text/html", "").await;
+        assert_eq!(names(body.clone()), ["c", "d"]);
+        assert!(String::from_utf8_lossy(&body).contains("Page 2 of 3"));
+        let (_, body) = request(&dav, "GET", "/?sort=size&page=2&per=2", "text/html", "").await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("Page 2 of 3"));
+        assert!(body.contains("<a href=\"?sort=size&amp;page=3&amp;per=2\">"));
+        let (_, body) = request(&dav, "GET", "/?page=9&per=2", "text/html", "").await;
+        assert_eq!(names(body), ["e"]);
+    }
+
+    #[tokio::test]
+    async fn test_autoindex_sort_file() {
+        for &enable in &[false, true] {
+            let dav = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .autoindex(true)
+                .autoindex_sort_file(enable)
+                .build_handler();
+            for (name, body) in &[("a", "aaa"), ("b", "b"), ("c", "cc")] {
+                request(&dav, "PUT", &format!("/{}", name), "*/*", body).await;
+            }
+            request(&dav, "PUT", "/.davsort", "*/*", "sort=size order=desc").await;
+            let names = |body: Bytes| {
+                let body = String::from_utf8(body.to_vec()).unwrap();
+                let names = body.lines().filter(|l| !l.contains(".davsort"));
+                names.map(|l| l.to_string()).collect::<Vec<_>>()
+            };
+            let (_, body) = request(&dav, "GET", "/", "text/plain", "").await;
+            if enable {
+                assert_eq!(names(body), ["/a", "/c", "/b"]);
+            } else {
+                assert_eq!(names(body), ["/a", "/b", "/c"]);
+            }
+            let (_, body) = request(&dav, "GET", "/?order=asc", "text/plain", "").await;
+            if enable {
+                assert_eq!(names(body), ["/b", "/c", "/a"]);
+            } else {
+                assert_eq!(names(body), ["/a", "/b", "/c"]);
+            }
+            let (_, body) = request(&dav, "GET", "/?sort=name", "text/plain", "").await;
+            if enable {
+                assert_eq!(names(body), ["/c", "/b", "/a"]);
+            } else {
+                assert_eq!(names(body), ["/a", "/b", "/c"]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http10_content_length() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .build_handler();
+        request(&dav, "PUT", "/file", "*/*", "0123456789").await;
+        for &(version, uri) in &[
+            (http::Version::HTTP_10, "/"),
+            (http::Version::HTTP_11, "/"),
+            (http::Version::HTTP_10, "/file"),
+        ] {
+            let req = Request::builder()
+                .uri(uri)
+                .version(version)
+                .body(hyper::Body::empty())
+                .unwrap();
+            let resp = dav.handle(req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let len = resp.headers().get("content-length").cloned();
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            if version == http::Version::HTTP_10 {
+                assert_eq!(len.unwrap(), body.len().to_string().as_str(), "{}", uri);
+            } else {
+                // the listing is streamed.
+                assert!(len.is_none());
+            }
+        }
+
+        // a body that is too big to buffer is sent until the connection closes.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .http10_buffer_size(8)
+            .build_handler();
+        let get = |version| {
+            let req = Request::builder()
+                .uri("/")
+                .version(version)
+                .body(hyper::Body::empty())
+                .unwrap();
+            dav.handle(req)
+        };
+        let resp = get(http::Version::HTTP_10).await;
+        assert!(resp.headers().get("content-length").is_none());
+        assert_eq!(resp.headers()["connection"], "close");
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let full = hyper::body::to_bytes(get(http::Version::HTTP_11).await.into_body());
+        assert_eq!(body, full.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_served_bytes() {
+        use crate::metrics::Served;
+        use http_body::Body as _;
+        use std::sync::{Arc, Mutex};
+
+        let log: Arc<Mutex<Vec<Served>>> = Arc::new(Mutex::new(Vec::new()));
+        let log2 = log.clone();
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .read_buf_size(4)
+            .served_bytes(move |s| log2.lock().unwrap().push(s.clone()))
+            .build_handler();
+        request(&dav, "PUT", "/file", "*/*", "0123456789").await;
+        let dav = &dav;
+        let get = |range: Option<&'static str>| async move {
+            let hdrs = [("Range", range.unwrap_or_default())];
+            let n = range.map_or(0, |_| 1);
+            testfs::request(dav, "GET", "/file", &hdrs[..n], "").await
+        };
+        let last = || {
+            let s = log.lock().unwrap().pop().unwrap();
+            (
+                s.path.as_url_string(),
+                s.status.as_u16(),
+                s.range,
+                s.bytes,
+                s.complete,
+            )
+        };
+
+        body_text(get(None).await).await;
+        assert_eq!(last(), ("/file".to_string(), 200, false, 10, true));
+        body_text(get(Some("bytes=2-5")).await).await;
+        assert_eq!(last(), ("/file".to_string(), 206, true, 4, true));
+
+        // client goes away after the first chunk.
+        let mut body = get(None).await.into_body();
+        body.data().await.unwrap().unwrap();
+        assert!(log.lock().unwrap().is_empty());
+        drop(body);
+        let (_, status, _, bytes, complete) = last();
+        assert_eq!(status, 200);
+        assert!(bytes < 10 && !complete);
+    }
+
+    #[tokio::test]
+    async fn test_empty_suffix_range() {
+        let dav = memfs_handler();
+        request(&dav, "PUT", "/file", "*/*", "0123456789").await;
+        let hdrs = [("Range", "bytes=-0")];
+        let resp = testfs::request(&dav, "GET", "/file", &hdrs, "").await;
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(resp.headers()["content-range"], "bytes */10");
+    }
+
+    #[tokio::test]
+    async fn test_malformed_ranges() {
+        let dav = memfs_handler();
+        request(&dav, "PUT", "/file", "*/*", "0123456789").await;
+        let full: &[u8] = b"0123456789";
+        for (range, status, body) in &[
+            // not the "bytes" unit, or not the grammar: ignored.
+            ("bytes = 0-1", 200, full),
+            ("bytes=0 - 1", 200, full),
+            ("items=0-1", 200, full),
+            ("bytes", 200, full),
+            ("bytes=", 200, full),
+            ("bytes=-", 200, full),
+            ("bytes=a-b", 200, full),
+            ("bytes=+1-2", 200, full),
+            ("bytes=1-2-3", 200, full),
+            ("bytes=0-1;2-3", 200, full),
+            ("bytes=0-1,x", 200, full),
+            ("bytes=5-4", 200, full),
+            ("bytes=99999999999999999999-", 200, full),
+            // valid, if a bit unusual.
+            ("BYTES=0-1", 206, &b"01"[..]),
+            ("bytes= 0-1", 206, &b"01"[..]),
+            ("bytes=0-1,", 206, &b"01"[..]),
+            ("bytes=,,2-3", 206, &b"23"[..]),
+            ("bytes=8-18446744073709551615", 206, &b"89"[..]),
+            ("bytes=-18446744073709551615", 206, full),
+            ("bytes=20-30,8-", 206, &b"89"[..]),
+            ("bytes=10-", 416, &b""[..]),
+            ("bytes=10-20, 30-", 416, &b""[..]),
+        ] {
+            let resp = testfs::request(&dav, "GET", "/file", &[("Range", *range)], "").await;
+            assert_eq!(resp.status(), *status, "Range: {}", range);
+            let b = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            assert_eq!(&b[..], *body, "Range: {}", range);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_full_range() {
+        for &as_ok in &[false, true] {
+            let dav = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .full_range_as_ok(as_ok)
+                .build_handler();
+            request(&dav, "PUT", "/file", "*/*", "0123456789").await;
+            for (range, full) in &[
+                ("bytes=0-", true),
+                ("bytes=0-9", true),
+                ("bytes=0-99", true),
+                ("bytes=-10", true),
+                ("bytes=0-8", false),
+                ("bytes=0-4,5-9", false),
+            ] {
+                let hdrs = [("Range", *range)];
+                let resp = testfs::request(&dav, "GET", "/file", &hdrs, "").await;
+                if as_ok && *full {
+                    assert_eq!(resp.status(), StatusCode::OK, "Range: {}", range);
+                    assert!(!resp.headers().contains_key("content-range"));
+                    assert_eq!(resp.headers()["content-length"], "10");
+                } else {
+                    assert_eq!(
+                        resp.status(),
+                        StatusCode::PARTIAL_CONTENT,
+                        "Range: {}",
+                        range
+                    );
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_head_no_open() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        // MemFs that counts the number of times a file is opened.
+        #[derive(Clone)]
+        struct CountOpens(Arc<AtomicUsize>);
+
+        impl FsHooks for CountOpens {
+            fn open<'a>(
+                &'a self,
+                fs: &'a MemFs,
+                path: &'a DavPath,
+                options: OpenOptions,
+            ) -> FsFuture<'a, Box<dyn DavFile>> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                fs.open(path, options)
+            }
+        }
+
+        let opens = Arc::new(AtomicUsize::new(0));
+        let dav = DavHandler::builder()
+            .filesystem(HookFs::new(CountOpens(opens.clone())))
+            .build_handler();
+        request(&dav, "PUT", "/file", "*/*", "0123456789").await;
+
+        let n = opens.load(Ordering::SeqCst);
+        let hdrs = [("Range", "bytes=2-5")];
+        let resp = testfs::request(&dav, "HEAD", "/file", &hdrs, "").await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers()["content-range"], "bytes 2-5/10");
+        assert_eq!(resp.headers()["content-length"], "4");
+        assert!(resp.headers().contains_key("etag"));
+        assert_eq!(opens.load(Ordering::SeqCst), n);
+    }
+
+    #[test]
+    fn test_listing_time_format() {
+        use time::macros::{datetime, offset};
+
+        let t = datetime!(2024-01-31 12:00 UTC);
+        let fmt = |format: Option<&str>, offset: UtcOffset| {
+            let f = listing_time_format(format, offset);
+            t.to_offset(offset).format(&f).unwrap()
+        };
+        assert_eq!(fmt(None, UtcOffset::UTC), "2024-01-31 12:00Z");
+        assert_eq!(fmt(None, offset!(+2)), "2024-01-31 14:00+02:00");
+        assert_eq!(fmt(None, offset!(-3:30)), "2024-01-31 08:30-03:30");
+        let format = Some("[day].[month].[year] [hour]:[minute]:[second]");
+        assert_eq!(fmt(format, UtcOffset::UTC), "31.01.2024 12:00:00");
+        assert_eq!(fmt(Some("[bogus]"), UtcOffset::UTC), "2024-01-31 12:00Z");
+    }
+
+    #[tokio::test]
+    async fn test_fixed_response() {
+        const ROBOTS: &str = "User-agent: *\nDisallow: /\n";
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_prefix("/dav")
+            .fixed_response("/robots.txt", StatusCode::OK, ROBOTS)
+            .fixed_response("/favicon.ico", StatusCode::NO_CONTENT, "")
+            .build_handler();
+        let (status, body) = request(&dav, "GET", "/robots.txt", "*/*", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], ROBOTS.as_bytes());
+        let resp = testfs::request(&dav, "HEAD", "/robots.txt", &[], "").await;
+        assert_eq!(resp.headers()["content-type"], "text/plain; charset=utf-8");
+        assert_eq!(resp.headers()["content-length"], ROBOTS.len().to_string());
+        assert!(body_text(resp).await.is_empty());
+        let (status, _) = request(&dav, "GET", "/favicon.ico", "*/*", "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        // other methods, and paths under the prefix, are not affected.
+        let (status, _) = request(&dav, "PUT", "/robots.txt", "*/*", "x").await;
+        assert!(!status.is_success());
+        request(&dav, "PUT", "/dav/robots.txt", "*/*", "x").await;
+        let (status, body) = request(&dav, "GET", "/dav/robots.txt", "*/*", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], b"x");
+    }
+
+    #[tokio::test]
+    async fn test_well_known() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_prefix("/dav")
+            .well_known("carddav", "/dav/addressbooks/")
+            .well_known("caldav", "/dav/calendars/")
+            .build_handler();
+        let req = |method, uri| testfs::request(&dav, method, uri, &[], "");
+        for method in &["GET", "PROPFIND"] {
+            let resp = req(method, "/.well-known/carddav").await;
+            assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+            assert_eq!(resp.headers()["location"], "/dav/addressbooks/");
+            let resp = req(method, "/.well-known/caldav").await;
+            assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+            assert_eq!(resp.headers()["location"], "/dav/calendars/");
+        }
+        let resp = req("GET", "/.well-known/other").await;
+        assert_ne!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_gets() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .max_concurrent_gets(1)
+            .build_handler();
+        request(&dav, "PUT", "/file", "*/*", "hello").await;
+        let get = || testfs::request(&dav, "GET", "/file", &[], "");
+
+        // the first body is not read yet, so it still holds the permit.
+        let first = get().await;
+        assert_eq!(first.status(), StatusCode::OK);
+        let second = get().await;
+        assert_eq!(second.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(second.headers()["retry-after"], "1");
+        let (status, _) = request(&dav, "HEAD", "/file", "*/*", "").await;
+        assert_eq!(status, StatusCode::OK);
+
+        // a client that goes away releases it.
+        drop(first);
+        let third = get().await;
+        assert_eq!(third.status(), StatusCode::OK);
+        assert_eq!(body_text(third).await, "hello");
+        let (status, _) = request(&dav, "GET", "/file", "*/*", "").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_write_timeout() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .max_concurrent_gets(1)
+            .write_timeout(std::time::Duration::from_millis(50))
+            .build_handler();
+        request(&dav, "PUT", "/file", "*/*", "0123456789").await;
+        let dav = &dav;
+        let get = |range| async move {
+            testfs::request(dav, "GET", "/file", &[("Range", range)], "").await
+        };
+
+        // a body that is never read gives up, and releases the permit.
+        let first = get("bytes=0-1,4-5").await;
+        assert_eq!(first.status(), StatusCode::PARTIAL_CONTENT);
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let second = get("bytes=0-1").await;
+        assert_eq!(second.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(body_text(second).await, "01");
+        assert!(hyper::body::to_bytes(first.into_body()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_content_hash() {
+        // MemFs with a stored hash for "/file".
+        #[derive(Clone)]
+        struct Hash;
+
+        impl FsHooks for Hash {
+            fn content_hash<'a>(
+                &'a self,
+                _fs: &'a MemFs,
+                path: &'a DavPath,
+            ) -> FsFuture<'a, Vec<u8>> {
+                let hash = match path.as_bytes() {
+                    b"/file" => Ok(vec![0xde, 0xad, 0xbe, 0xef]),
+                    _ => Err(FsError::NotImplemented),
+                };
+                Box::pin(futures_util::future::ready(hash))
+            }
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(HookFs::new(Hash))
+            .build_handler();
+        let dav = &dav;
+        let req = |method, uri, hdr, body: &'static str| async move {
+            testfs::request(dav, method, uri, &[hdr], body).await
+        };
+        const TAG: &str = "\"deadbeef\"";
+        let none = ("X-None", "");
+        req("PUT", "/file", none, "hello").await;
+        req("PUT", "/other", none, "hello").await;
+
+        let resp = req("GET", "/file", none, "").await;
+        assert_eq!(resp.headers()["etag"], TAG);
+        let resp = req("GET", "/other", none, "").await;
+        assert_ne!(resp.headers()["etag"], TAG);
+        let resp = req("GET", "/file", ("If-None-Match", TAG), "").await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        let resp = req("PUT", "/file", ("If-Match", TAG), "world").await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let resp = req("PUT", "/other", ("If-Match", TAG), "world").await;
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+
+        let resp = req("PROPFIND", "/", ("Depth", "1"), "").await;
+        assert_eq!(body_text(resp).await.matches("deadbeef").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_version_etag() {
+        // MemFs where files have a version.
+        #[derive(Clone)]
+        struct Versions;
+
+        #[derive(Debug, Clone)]
+        struct VersionMeta(Box<dyn DavMetaData>);
+
+        impl DavMetaData for VersionMeta {
+            fn len(&self) -> u64 {
+                self.0.len()
+            }
+            fn modified(&self) -> FsResult<SystemTime> {
+                self.0.modified()
+            }
+            fn is_dir(&self) -> bool {
+                self.0.is_dir()
+            }
+            fn version(&self) -> Option<String> {
+                match self.0.is_dir() {
+                    true => None,
+                    false => Some(format!("rev-{}", self.0.len())),
+                }
+            }
+        }
+
+        impl FsHooks for Versions {
+            fn metadata<'a>(
+                &'a self,
+                fs: &'a MemFs,
+                path: &'a DavPath,
+            ) -> FsFuture<'a, Box<dyn DavMetaData>> {
+                Box::pin(async move {
+                    let meta = fs.metadata(path).await?;
+                    Ok(Box::new(VersionMeta(meta)) as Box<dyn DavMetaData>)
+                })
+            }
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(HookFs::new(Versions))
+            .build_handler();
+        let dav = &dav;
+        let req = |hdr| async move { testfs::request(dav, "HEAD", "/file", &[hdr], "").await };
+        const TAG: &str = "\"rev-5\"";
+        request(dav, "PUT", "/file", "*/*", "hello").await;
+
+        let resp = req(("X-None", "")).await;
+        assert_eq!(resp.headers()["etag"], TAG);
+        let resp = req(("If-None-Match", TAG)).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        let resp = req(("If-Match", "\"rev-4\"")).await;
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+
+        let resp = testfs::request(dav, "PROPFIND", "/file", &[("Depth", "0")], "").await;
+        assert!(body_text(resp).await.contains("rev-5"));
+    }
+
+    #[tokio::test]
+    async fn test_file_slash() {
+        for redirect in &[false, true] {
+            let dav = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .file_slash_redirect(*redirect)
+                .build_handler();
+            request(&dav, "PUT", "/file.txt", "*/*", "hello").await;
+            let resp = testfs::request(&dav, "GET", "/file.txt/", &[], "").await;
+            if *redirect {
+                assert_eq!(resp.status(), StatusCode::FOUND);
+                assert_eq!(resp.headers()["location"], "/file.txt");
+            } else {
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            }
+            let (status, _) = request(&dav, "GET", "/nothing/", "*/*", "").await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_content_location() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_prefix("/dav")
+            .indexfile("index.html")
+            .autoindex(true)
+            .build_handler();
+        let get = |method, uri| testfs::request(&dav, method, uri, &[], "");
+        request(&dav, "MKCOL", "/dav/dir/", "*/*", "").await;
+        request(&dav, "PUT", "/dav/dir/index.html", "*/*", "index").await;
+        for method in &["GET", "HEAD"] {
+            let resp = get(method, "/dav/dir/").await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers()["content-location"], "/dav/dir/index.html");
+        }
+
+        // a file, or a generated index, is what was asked for.
+        let resp = get("GET", "/dav/dir/index.html").await;
+        assert!(resp.headers().get("content-location").is_none());
+        let resp = get("GET", "/dav/").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get("content-location").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_root_redirect() {
+        for permanent in [false, true] {
+            let dav = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .strip_prefix("/dav")
+                .autoindex(true)
+                .root_redirect("/files/", permanent)
+                .build_handler();
+            let req = |method, uri| testfs::request(&dav, method, uri, &[], "");
+            let expected = match permanent {
+                true => StatusCode::MOVED_PERMANENTLY,
+                false => StatusCode::FOUND,
+            };
+            for method in &["GET", "HEAD"] {
+                let resp = req(method, "/dav/").await;
+                assert_eq!(resp.status(), expected);
+                assert_eq!(resp.headers()["location"], "/dav/files/");
+            }
+
+            // the rest of the tree, and WebDAV methods, are left alone.
+            request(&dav, "MKCOL", "/dav/files/", "*/*", "").await;
+            assert_eq!(req("GET", "/dav/files/").await.status(), StatusCode::OK);
+            assert_eq!(req("OPTIONS", "/dav/").await.status(), StatusCode::OK);
+            let resp = testfs::request(&dav, "PROPFIND", "/dav/", &[("Depth", "1")], "").await;
+            assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multiple_range_headers() {
+        for strict in [false, true] {
+            let dav = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .strict(strict)
+                .build_handler();
+            request(&dav, "PUT", "/file", "*/*", "0123456789").await;
+            let hdrs = [("Range", "bytes=2-3"), ("Range", "bytes=0-9")];
+            let resp = testfs::request(&dav, "GET", "/file", &hdrs, "").await;
+            if strict {
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+                continue;
+            }
+            assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(resp.headers()["content-range"], "bytes 2-3/10");
+            assert_eq!(body_text(resp).await, "23");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_immutable_paths() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .immutable_paths(r"^/assets/.*\.[0-9a-f]{6,}\.js$")
+            .build_handler();
+        let get = |method, uri| testfs::request(&dav, method, uri, &[], "");
+        request(&dav, "MKCOL", "/assets/", "*/*", "").await;
+        request(&dav, "PUT", "/assets/app.3f2a9c.js", "*/*", "app").await;
+        request(&dav, "PUT", "/assets/app.js", "*/*", "app").await;
+        for method in &["GET", "HEAD"] {
+            let resp = get(method, "/assets/app.3f2a9c.js").await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let cc = &resp.headers()["cache-control"];
+            assert_eq!(cc, "public, max-age=31536000, immutable");
+            let resp = get(method, "/assets/app.js").await;
+            assert!(resp.headers().get("cache-control").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_if_range() {
+        let dav = &memfs_handler();
+        request(dav, "PUT", "/file", "*/*", "0123456789").await;
+        let get = |if_range| async move {
+            let hdrs = [("Range", "bytes=2-3"), ("If-Range", if_range)];
+            testfs::request(dav, "GET", "/file", &hdrs, "").await
+        };
+        let resp = get("\"x\"").await;
+        let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+        let modified = resp.headers()["last-modified"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        assert_eq!(get(&etag).await.status(), StatusCode::PARTIAL_CONTENT);
+        let weak = format!("W/{}", etag);
+        assert_eq!(get(&weak).await.status(), StatusCode::OK);
+        // the file was just written, so its date is not a strong validator.
+        assert_eq!(get(&modified).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_indexfile_fallback() {
+        for autoindex in &[false, true] {
+            let dav = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .indexfile("index.html")
+                .autoindex(*autoindex)
+                .build_handler();
+            request(&dav, "PUT", "/a.txt", "*/*", "a").await;
+            let (status, body) = request(&dav, "GET", "/", "text/plain", "").await;
+            if *autoindex {
+                assert_eq!(status, StatusCode::OK);
+                assert_eq!(&body[..], b"/a.txt\n");
+            } else {
+                assert_eq!(status, StatusCode::NOT_FOUND);
+            }
+            request(&dav, "PUT", "/index.html", "*/*", "index").await;
+            let (status, body) = request(&dav, "GET", "/", "text/plain", "").await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(&body[..], b"index");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_text_charset() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .mime_type("txt2", "text/plain; charset=iso-8859-1")
+            .build_handler();
+        for (name, ct) in &[
+            ("/a.txt", "text/plain; charset=utf-8"),
+            ("/a.vcf", "text/vcard; charset=utf-8"),
+            ("/a.vtt", "text/vtt; charset=utf-8"),
+            ("/a.json", "application/json; charset=utf-8"),
+            ("/a.png", "image/png"),
+            ("/a.bin", "application/octet-stream"),
+            ("/a.txt2", "text/plain; charset=iso-8859-1"),
+        ] {
+            request(&dav, "PUT", name, "*/*", "x").await;
+            let resp = testfs::request(&dav, "GET", name, &[], "").await;
+            assert_eq!(resp.headers()["content-type"], *ct, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_html_injection() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .build_handler();
+        let url = "/%22%3E%3Cscript%3Ealert(1)";
+        let (status, _) = request(&dav, "MKCOL", url, "*/*", "").await;
+        assert_eq!(status, StatusCode::CREATED);
+        let file = format!("{}/%27%20onclick%3D%22x%22%20%26.txt", url);
+        let (status, _) = request(&dav, "PUT", &file, "*/*", "hello").await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        for uri in &["/", &format!("{}/", url)] {
+            let (status, body) = request(&dav, "GET", uri, "text/html", "").await;
+            assert_eq!(status, StatusCode::OK);
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            assert!(!body.contains("<script>"));
+            assert!(!body.contains("' onclick"));
+            assert!(!body.contains("\"x\""));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_location() {
+        for trust in &[false, true] {
+            let dav = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .autoindex(true)
+                .trust_forwarded(*trust)
+                .build_handler();
+            request(&dav, "MKCOL", "/dir", "*/*", "").await;
+            let hdrs = [
+                ("Host", "internal:8080"),
+                ("X-Forwarded-Proto", "https"),
+                ("X-Forwarded-Host", "dav.example.com"),
+            ];
+            let resp = testfs::request(&dav, "GET", "/dir", &hdrs, "").await;
+            assert_eq!(resp.status(), StatusCode::FOUND);
+            let location = match trust {
+                true => "https://dav.example.com/dir/",
+                false => "/dir/",
+            };
+            assert_eq!(resp.headers()["location"], location);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_ranges() {
+        let range_get = |dav: DavHandler, range: &'static str| async move {
+            let resp = testfs::request(&dav, "GET", "/file", &[("Range", range)], "").await;
+            let status = resp.status();
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            (status, body)
+        };
+        let fs = MemFs::new();
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .max_ranges(2)
+            .build_handler();
+        let (status, _) = request(&dav, "PUT", "/file", "*/*", "0123456789").await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        // within the limit after merging.
+        let (status, body) = range_get(dav.clone(), "bytes=0-1,2-3,3-4").await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(&body[..], b"01234");
+
+        // over the limit: the whole file.
+        let (status, body) = range_get(dav.clone(), "bytes=0-0,2-2,4-4").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], b"0123456789");
+
+        // over the limit: refused.
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .max_ranges(2)
+            .max_ranges_reject(true)
+            .build_handler();
+        let (status, _) = range_get(dav, "bytes=0-0,2-2,4-4").await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+    }
+
+    #[tokio::test]
+    async fn test_unsatisfiable_ranges() {
+        let dav = &memfs_handler();
+        request(dav, "PUT", "/file", "*/*", "0123456789").await;
+        let get = |range| async move {
+            testfs::request(dav, "GET", "/file", &[("Range", range)], "").await
+        };
+
+        // some satisfiable: multipart with only those.
+        let resp = get("bytes=0-1,20-30,-0,4-5,10-").await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(resp.headers().get("content-range").is_none());
+        let body = body_text(resp).await;
+        assert_eq!(body.matches(BOUNDARY_START).count(), 2);
+        assert!(body.contains("Content-Range: bytes 0-1/10\n"));
+        assert!(body.contains("Content-Range: bytes 4-5/10\n"));
+        assert!(!body.contains("bytes 10-"));
+
+        // none satisfiable.
+        let resp = get("bytes=20-30,-0,10-").await;
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(resp.headers()["content-range"], "bytes */10");
+        assert!(body_text(resp).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stored_content_type() {
+        let dav = &memfs_handler();
+        let proppatch = |action: &str, value: &str| {
+            format!(
+                r#"<D:propertyupdate xmlns:D="DAV:"><D:{0}><D:prop>
+                  <D:getcontenttype>{1}</D:getcontenttype>
+                </D:prop></D:{0}></D:propertyupdate>"#,
+                action, value
+            )
+        };
+        let send = |method, body: String| async move {
+            let resp = testfs::request(dav, method, "/doc.bin", &[("Depth", "0")], body).await;
+            let status = resp.status();
+            let ct = resp.headers().get("content-type").cloned();
+            (status, ct, body_text(resp).await)
+        };
+        let content_type = || async {
+            let (_, ct, _) = send("GET", String::new()).await;
+            ct.unwrap()
+        };
+        let propfind = r#"<D:propfind xmlns:D="DAV:"><D:prop>
+            <D:getcontenttype/></D:prop></D:propfind>"#;
+
+        send("PUT", "%PDF-1.4".to_string()).await;
+        assert_eq!(content_type().await, "application/octet-stream");
+
+        let (status, _, body) = send("PROPPATCH", proppatch("set", "application/pdf")).await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        assert!(body.contains("200 OK"), "{}", body);
+        assert_eq!(content_type().await, "application/pdf");
+        let (_, _, body) = send("PROPFIND", propfind.to_string()).await;
+        assert!(body.contains(">application/pdf<"), "{}", body);
+
+        // not a media type.
+        let (_, _, body) = send("PROPPATCH", proppatch("set", "pdf")).await;
+        assert!(body.contains("409 Conflict"), "{}", body);
+        assert_eq!(content_type().await, "application/pdf");
+
+        // removing the override brings back the default.
+        let (_, _, body) = send("PROPPATCH", proppatch("remove", "")).await;
+        assert!(body.contains("200 OK"), "{}", body);
+        assert_eq!(content_type().await, "application/octet-stream");
+        let (_, _, body) = send("PROPFIND", propfind.to_string()).await;
+        assert!(body.contains(">application/octet-stream<"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_accept_ranges_min_size() {
+        let memfs = MemFs::new();
+        let get = |dav, uri| async move {
+            let resp = testfs::request(dav, "GET", uri, &[("Range", "bytes=0-1")], "").await;
+            let accept_ranges = resp.headers().contains_key("accept-ranges");
+            (resp.status(), accept_ranges)
+        };
+
+        let dav = DavHandler::builder()
+            .filesystem(memfs.clone())
+            .build_handler();
+        request(&dav, "PUT", "/small", "*/*", "tiny").await;
+        request(&dav, "PUT", "/large", "*/*", "not so tiny at all").await;
+        assert_eq!(
+            get(&dav, "/small").await,
+            (StatusCode::PARTIAL_CONTENT, true)
+        );
+
+        let dav = DavHandler::builder()
+            .filesystem(memfs)
+            .accept_ranges_min_size(16)
+            .build_handler();
+        assert_eq!(get(&dav, "/small").await, (StatusCode::OK, false));
+        assert_eq!(
+            get(&dav, "/large").await,
+            (StatusCode::PARTIAL_CONTENT, true)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dir_redirect_query() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .build_handler();
+        request(&dav, "MKCOL", "/dir", "*/*", "").await;
+        for (uri, location) in &[
+            ("/dir", "/dir/"),
+            ("/dir?sort=size", "/dir/?sort=size"),
+            ("/dir?sort=size&order=desc", "/dir/?sort=size&order=desc"),
+        ] {
+            let resp = testfs::request(&dav, "GET", uri, &[], "").await;
+            assert_eq!(resp.status(), StatusCode::FOUND);
+            assert_eq!(resp.headers()["location"], *location);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_redirect_rules() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .redirect_rules(|path| {
+                let path = path.as_url_string();
+                let rest = path.strip_prefix("/legacy/")?;
+                Some((StatusCode::PERMANENT_REDIRECT, format!("/files/{}", rest)))
+            })
+            .build_handler();
+        request(&dav, "PUT", "/legacy", "*/*", "not redirected").await;
+
+        // matching rule, for any method.
+        for method in &["GET", "PROPFIND", "PUT"] {
+            let resp = testfs::request(&dav, method, "/legacy/report.pdf", &[], "").await;
+            assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+            assert_eq!(resp.headers()["location"], "/files/report.pdf");
+        }
+
+        // no matching rule.
+        let (status, body) = request(&dav, "GET", "/legacy", "*/*", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "not redirected");
+    }
+}
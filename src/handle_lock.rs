@@ -1,6 +1,6 @@
 use std::cmp;
 use std::io::Cursor;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use headers::HeaderMapExt;
 use http::StatusCode as SC;
@@ -14,7 +14,7 @@ use crate::davpath::DavPath;
 use crate::errors::*;
 use crate::fs::{FsError, OpenOptions};
 use crate::ls::*;
-use crate::util::MemBuffer;
+use crate::util::{get_depth, lock_condition, MemBuffer};
 use crate::xmltree_ext::{self, ElementExt};
 use crate::DavResult;
 
@@ -49,7 +49,7 @@ impl crate::DavInner {
 
             // try refresh
             // FIXME: you can refresh a lock owned by someone else. is that OK?
-            let timeout = get_timeout(req, true, false);
+            let timeout = get_timeout(req, false);
             let lock = match locksystem.refresh(&path, &tokens[0], timeout) {
                 Ok(lock) => lock,
                 Err(_) => return Err(SC::PRECONDITION_FAILED.into()),
@@ -61,14 +61,14 @@ impl crate::DavInner {
             prop.write_ev(&mut emitter)?;
             let buffer = emitter.into_inner().take();
 
-            let ct = "application/xml; charset=utf-8".to_owned();
+            let ct = self.xml_type(req);
             res.headers_mut().typed_insert(davheaders::ContentType(ct));
             *res.body_mut() = Body::from(buffer);
             return Ok(res);
         }
 
         // handle Depth:
-        let deep = match req.headers().typed_get::<davheaders::Depth>() {
+        let deep = match get_depth(req.headers(), self.lenient_depth.unwrap_or(false))? {
             Some(davheaders::Depth::Infinity) | None => true,
             Some(davheaders::Depth::Zero) => false,
             _ => return Err(SC::BAD_REQUEST.into()),
@@ -141,11 +141,14 @@ impl crate::DavInner {
         let shared = shared.unwrap();
 
         // create lock
-        let timeout = get_timeout(req, false, shared);
+        let timeout = get_timeout(req, shared);
         let principal = self.principal.as_deref();
         let lock = match locksystem.lock(&path, principal, owner.as_ref(), timeout, shared, deep) {
             Ok(lock) => lock,
-            Err(_) => return Err(SC::LOCKED.into()),
+            Err(l) => {
+                let body = lock_condition("no-conflicting-lock", &l);
+                return Err(DavError::Condition(SC::LOCKED, body));
+            }
         };
 
         // try to create file if it doesn't exist.
@@ -172,7 +175,7 @@ impl crate::DavInner {
 
         // output result
         let lt = format!("<{}>", lock.token);
-        let ct = "application/xml; charset=utf-8".to_owned();
+        let ct = self.xml_type(req);
         res.headers_mut().typed_insert(davheaders::LockToken(lt));
         res.headers_mut().typed_insert(davheaders::ContentType(ct));
         if meta.is_none() {
@@ -216,7 +219,10 @@ impl crate::DavInner {
                 *res.status_mut() = SC::NO_CONTENT;
                 Ok(res)
             }
-            Err(_) => Err(SC::CONFLICT.into()),
+            Err(_) => {
+                let body = "<D:lock-token-matches-request-uri/>".to_string();
+                Err(DavError::Condition(SC::CONFLICT, body))
+            }
         }
     }
 }
@@ -267,8 +273,9 @@ pub(crate) fn list_supportedlock(ls: Option<&Box<dyn DavLockSystem>>) -> Element
     elem
 }
 
-// process timeout header
-fn get_timeout(req: &Request<()>, refresh: bool, shared: bool) -> Option<Duration> {
+// process timeout header. Locks always expire, if the client asks
+// for an "Infinite" timeout or sends no Timeout header it gets the maximum.
+fn get_timeout(req: &Request<()>, shared: bool) -> Option<Duration> {
     let max_timeout = if shared {
         Duration::new(86400, 0)
     } else {
@@ -276,16 +283,10 @@ fn get_timeout(req: &Request<()>, refresh: bool, shared: bool) -> Option<Duratio
     };
     match req.headers().typed_get::<davheaders::Timeout>() {
         Some(davheaders::Timeout(ref vec)) if !vec.is_empty() => match vec[0] {
-            DavTimeout::Infinite => {
-                if refresh {
-                    None
-                } else {
-                    Some(max_timeout)
-                }
-            }
+            DavTimeout::Infinite => Some(max_timeout),
             DavTimeout::Seconds(n) => Some(cmp::min(max_timeout, Duration::new(n as u64, 0))),
         },
-        _ => None,
+        _ => Some(max_timeout),
     }
 }
 
@@ -313,7 +314,12 @@ fn build_lock_prop(lock: &DavLock, full: bool) -> Element {
         ),
     );
 
-    actlock.push_element(Element::new2("D:timeout").text(match lock.timeout {
+    // report the time that is left, not the original timeout.
+    let remaining = lock.timeout_at.map(|t| {
+        t.duration_since(SystemTime::now())
+            .unwrap_or_else(|_| Duration::new(0, 0))
+    });
+    actlock.push_element(Element::new2("D:timeout").text(match remaining {
         None => "Infinite".to_string(),
         Some(d) => format!("Second-{}", d.as_secs()),
     }));
@@ -5,6 +5,7 @@ use crate::body::Body;
 use crate::conditional::*;
 use crate::davheaders;
 use crate::fs::*;
+use crate::util::{locked, write_status};
 use crate::{DavError, DavResult};
 
 impl crate::DavInner {
@@ -23,14 +24,14 @@ impl crate::DavInner {
         if let Some(ref locksystem) = self.ls {
             let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
             let principal = self.principal.as_deref();
-            if let Err(_l) = locksystem.check(&path, principal, false, false, t) {
-                return Err(DavError::Status(StatusCode::LOCKED));
+            if let Err(l) = locksystem.check(&path, principal, false, false, t) {
+                return Err(locked(&l));
             }
         }
 
         let mut res = Response::new(Body::empty());
 
-        match self.fs.create_dir(&path).await {
+        match self.fs.create_dir_mode(&path, self.dir_mode).await {
             // RFC 4918 9.3.1 MKCOL Status Codes.
             Err(FsError::Exists) => return Err(DavError::Status(StatusCode::METHOD_NOT_ALLOWED)),
             Err(FsError::NotFound) => return Err(DavError::Status(StatusCode::CONFLICT)),
@@ -42,7 +43,7 @@ impl crate::DavInner {
                         path.with_prefix().as_url_string(),
                     ));
                 }
-                *res.status_mut() = StatusCode::CREATED;
+                *res.status_mut() = write_status(false);
             }
         }
 
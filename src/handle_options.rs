@@ -14,9 +14,17 @@ impl crate::DavInner {
         // We could simply not report webdav level 2 support if self.allow doesn't
         // contain LOCK/UNLOCK. However we do advertise support, since there might
         // be LOCK/UNLOCK support in another part of the URL space.
-        let dav = "1,2,3,sabredav-partialupdate";
+        let path = self.path(req);
+        let have_bind = self.fs.have_bind(&path).await;
+        let mut dav = "1,2,3,sabredav-partialupdate".to_string();
+        if have_bind {
+            dav.push_str(",bind");
+        }
         h.insert("DAV", dav.parse().unwrap());
         h.insert("MS-Author-Via", "DAV".parse().unwrap());
+        if self.method_allowed(DavMethod::Search) {
+            h.insert("DASL", "<DAV:basicsearch>".parse().unwrap());
+        }
         h.typed_insert(headers::ContentLength(0));
 
         // Helper to add method to array if method is in fact
@@ -28,17 +36,21 @@ impl crate::DavInner {
         let mm = |v: &mut Vec<String>, m: &str, y: DavMethod| {
             if (y == DavMethod::Options || (y != method || islock(y) != islock(method)))
                 && (!islock(y) || self.ls.is_some())
-                && self.allow.map(|x| x.contains(y)).unwrap_or(true)
+                && self.method_allowed(y)
             {
                 v.push(m.to_string());
             }
         };
 
-        let path = self.path(req);
-        let meta = self.fs.metadata(&path).await;
-        let is_unmapped = meta.is_err();
-        let is_file = meta.map(|m| m.is_file()).unwrap_or_default();
+        // "OPTIONS *" is about the server, not about a resource.
         let is_star = path.is_star() && method == DavMethod::Options;
+        let (is_unmapped, is_file) = match is_star {
+            true => (false, false),
+            false => match self.fs.metadata(&path).await {
+                Ok(meta) => (false, meta.is_file()),
+                Err(_) => (true, false),
+            },
+        };
 
         let mut v = Vec::new();
         if is_unmapped && !is_star {
@@ -55,6 +67,7 @@ impl crate::DavInner {
             }
             mm(&mut v, "OPTIONS", DavMethod::Options);
             mm(&mut v, "PROPFIND", DavMethod::PropFind);
+            mm(&mut v, "SEARCH", DavMethod::Search);
             mm(&mut v, "COPY", DavMethod::Copy);
             if path.as_url_string() != "/" {
                 mm(&mut v, "MOVE", DavMethod::Move);
@@ -62,6 +75,14 @@ impl crate::DavInner {
             }
             mm(&mut v, "LOCK", DavMethod::Lock);
             mm(&mut v, "UNLOCK", DavMethod::Unlock);
+            if have_bind && !is_file {
+                mm(&mut v, "BIND", DavMethod::Bind);
+                mm(&mut v, "UNBIND", DavMethod::Unbind);
+                mm(&mut v, "REBIND", DavMethod::Rebind);
+            }
+            if self.add_member.unwrap_or(false) && !is_file && !is_star {
+                mm(&mut v, "POST", DavMethod::Post);
+            }
         }
 
         let a = v.join(",").parse().unwrap();
@@ -70,3 +91,63 @@ impl crate::DavInner {
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::memfs::MemFs;
+    use crate::testfs::request;
+    use crate::{DavHandler, DavMethod, DavMethodSet};
+
+    #[tokio::test]
+    async fn test_disabled_methods() {
+        let mut disabled = DavMethodSet::none();
+        disabled.add(DavMethod::Delete);
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .disabled_methods(disabled)
+            .build_handler();
+        let request = |method, body| request(&dav, method, "/file", &[], body);
+        assert_eq!(request("PUT", "data").await.status(), 201);
+
+        let resp = request("DELETE", "").await;
+        assert_eq!(resp.status(), 405);
+        let allow = resp.headers()["allow"].to_str().unwrap();
+        assert!(
+            allow.contains("GET") && !allow.contains("DELETE"),
+            "{}",
+            allow
+        );
+
+        let resp = request("OPTIONS", "").await;
+        let allow = resp.headers()["allow"].to_str().unwrap();
+        assert!(
+            allow.contains("MOVE") && !allow.contains("DELETE"),
+            "{}",
+            allow
+        );
+        assert_eq!(request("GET", "").await.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_options_star() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_prefix("/dav")
+            .build_handler();
+        let request = |method| request(&dav, method, "*", &[], "");
+        let resp = request("OPTIONS").await;
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers()["dav"].to_str().unwrap().starts_with("1,2,3"));
+        assert_eq!(resp.headers()["ms-author-via"], "DAV");
+        let allow = resp.headers()["allow"].to_str().unwrap();
+        assert!(
+            allow.contains("PROPFIND") && allow.contains("DELETE"),
+            "{}",
+            allow
+        );
+
+        // "*" is not a resource.
+        assert_eq!(request("GET").await.status(), 400);
+        assert_eq!(request("PROPFIND").await.status(), 400);
+    }
+}
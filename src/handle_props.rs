@@ -2,6 +2,7 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::io::{self, Cursor};
+use std::sync::Arc;
 
 use futures_util::{future::BoxFuture, FutureExt, StreamExt};
 use headers::HeaderMapExt;
@@ -17,6 +18,7 @@ use xmltree::{Element, XMLNode};
 use crate::async_stream::AsyncStream;
 use crate::body::Body;
 use crate::conditional::if_match_get_tokens;
+use crate::davhandler::{PrincipalQuota, ResourceTypes};
 use crate::davheaders;
 use crate::davpath::*;
 use crate::errors::*;
@@ -24,14 +26,20 @@ use crate::fs::*;
 use crate::handle_lock::{list_lockdiscovery, list_supportedlock};
 use crate::ls::*;
 use crate::util::MemBuffer;
-use crate::util::{dav_xml_error, systemtime_to_httpdate, systemtime_to_rfc3339};
+use crate::util::{
+    dav_xml_error, get_depth, is_empty_body, locked, systemtime_to_httpdate, systemtime_to_rfc3339,
+};
 use crate::{DavInner, DavResult};
 
 const NS_APACHE_URI: &str = "http://apache.org/dav/props/";
 const NS_DAV_URI: &str = "DAV:";
 #[cfg(feature = "carddav")]
 const NS_CARDDAV_URI: &str = "urn:ietf:params:xml:ns:carddav";
+#[cfg(feature = "caldav")]
+const NS_CALDAV_URI: &str = "urn:ietf:params:xml:ns:caldav";
 const NS_MS_URI: &str = "urn:schemas-microsoft-com:";
+// POSIX owner, group and mode. Only sent when asked for by name.
+const NS_POSIX_URI: &str = "urn:dav-server:posix";
 
 // list returned by PROPFIND <propname/>.
 const PROPNAME_STR: &[&str] = &[
@@ -83,6 +91,10 @@ const MS_ALLPROP_STR: &[&str] = &[
     "Z:Win32LastModifiedTime",
 ];
 
+// CARDDAV:supported-address-data, unless configured otherwise.
+#[cfg(feature = "carddav")]
+const ADDRESS_DATA_TYPES: &[(&str, &str)] = &[("text/vcard", "3.0"), ("text/vcard", "4.0")];
+
 #[cfg(feature = "carddav")]
 const UNIMPLEMENTED_CARDDAV_PROP: &[&str] = &[
     // "current-user-principal",
@@ -112,6 +124,13 @@ pub (crate) struct PropWriter {
     ls: Option<Box<dyn DavLockSystem>>,
     useragent: String,
     q_cache: QuotaCache,
+    mime_types: Option<Arc<HashMap<String, String>>>,
+    brief: bool,
+    add_member: bool,
+    principal_quota: Option<(Arc<PrincipalQuota>, Option<String>)>,
+    #[cfg(feature = "carddav")]
+    address_data_types: Option<Arc<Vec<(String, String)>>>,
+    resourcetypes: Option<Arc<ResourceTypes>>,
 }
 
 #[derive(Default, Clone, Copy)]
@@ -129,8 +148,11 @@ fn init_staticprop(p: &[&str]) -> Vec<Element> {
             Some("D") => Some(NS_DAV_URI.to_string()),
             Some("A") => Some(NS_APACHE_URI.to_string()),
             Some("Z") => Some(NS_MS_URI.to_string()),
+            Some("P") => Some(NS_POSIX_URI.to_string()),
             #[cfg(feature = "carddav")]
             Some("card") => Some(NS_CARDDAV_URI.to_string()),
+            #[cfg(feature = "caldav")]
+            Some("cal") => Some(NS_CALDAV_URI.to_string()),
             _ => None,
         };
         v.push(e);
@@ -153,10 +175,10 @@ impl DavInner {
             .typed_insert(headers::CacheControl::new().with_no_cache());
         res.headers_mut().typed_insert(headers::Pragma::no_cache());
 
-        let depth = match req.headers().typed_get::<davheaders::Depth>() {
+        let depth = match get_depth(req.headers(), self.lenient_depth.unwrap_or(false))? {
             Some(davheaders::Depth::Infinity) | None => {
                 if req.headers().typed_get::<davheaders::XLitmus>().is_none() {
-                    let ct = "application/xml; charset=utf-8".to_owned();
+                    let ct = self.xml_type(req);
                     res.headers_mut().typed_insert(davheaders::ContentType(ct));
                     *res.status_mut() = StatusCode::FORBIDDEN;
                     *res.body_mut() = dav_xml_error("<D:propfind-finite-depth/>");
@@ -172,9 +194,10 @@ impl DavInner {
         let meta = self.fs.metadata(&path).await?;
         let meta = self.fixpath(&mut res, &mut path, meta);
 
+        // An empty body means allprop (RFC 4918, 9.1).
         let mut root = None;
-        if !xmldata.is_empty() {
-            trace!("{}", String::from_utf8(xmldata.to_vec()).unwrap());
+        if !is_empty_body(xmldata) {
+            trace!("{}", String::from_utf8_lossy(xmldata));
             root = match Element::parse(Cursor::new(xmldata)) {
                 Ok(t) => {
                     if t.name == "propfind" && t.namespace.as_deref() == Some("DAV:") {
@@ -210,16 +233,49 @@ impl DavInner {
 
         trace!("propfind: type request: {}", name);
 
-        let mut pw = PropWriter::new(req, &mut res, name, props, &self.fs, self.ls.as_ref())?;
+        let ct = self.xml_type(req);
+        res.headers_mut().typed_insert(davheaders::ContentType(ct));
+        let mut pw = PropWriter::new(
+            req,
+            &mut res,
+            name,
+            props,
+            &self.fs,
+            self.ls.as_ref(),
+            self.mime_types.as_ref(),
+        )?;
+
+        // Microsoft "Brief: t" header.
+        if self.microsoft_compat.unwrap_or(false) {
+            let brief = req
+                .headers()
+                .get("brief")
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.trim().eq_ignore_ascii_case("t"))
+                .unwrap_or(false);
+            pw.set_brief(brief);
+        }
+        pw.set_add_member(self.add_member.unwrap_or(false));
+        pw.set_principal_quota(self.principal_quota.clone(), self.principal.clone());
+        #[cfg(feature = "carddav")]
+        pw.set_address_data_types(self.address_data_types.clone());
+        pw.set_resourcetypes(self.resourcetypes.clone());
 
         *res.body_mut() = Body::from(AsyncStream::new(|tx| async move {
             pw.set_tx(tx);
             let is_dir = meta.is_dir();
-            pw.write_props(&path, meta).await?;
-            pw.flush().await?;
+            let (depth, noroot) = match depth {
+                davheaders::Depth::OneNoRoot => (davheaders::Depth::One, is_dir),
+                d => (d, false),
+            };
+            if !noroot {
+                pw.write_props(&path, meta).await?;
+                pw.flush().await?;
+            }
 
             if is_dir && depth != davheaders::Depth::Zero {
                 let _ = self.propfind_directory(&path, depth, &mut pw).await;
+                self.check_cancel()?;
             }
             pw.close().await?;
 
@@ -303,8 +359,14 @@ impl DavInner {
                 Some(true) | None => ReadDirMeta::DataSymlink,
                 Some(false) => ReadDirMeta::Data,
             };
+            self.check_cancel()?;
             let mut entries = match self.fs.read_dir(path, readdir_meta).await {
                 Ok(entries) => entries,
+                // a subdirectory that was removed while we were busy.
+                Err(FsError::NotFound) => {
+                    trace!("read_dir {}: not found. Skipping", path);
+                    return Ok(());
+                }
                 Err(e) => {
                     // if we cannot read_dir, just skip it.
                     error!("read_dir error {:?}", e);
@@ -313,11 +375,27 @@ impl DavInner {
             };
 
             while let Some(dirent) = entries.next().await {
+                self.check_cancel()?;
                 let mut npath = path.clone();
                 npath.push_segment(&dirent.name());
+                if !self.can_list(&npath) {
+                    continue;
+                }
                 let meta = match dirent.metadata().await {
                     Ok(meta) => meta,
                     Err(e) => {
+                        if self.show_broken_symlinks.unwrap_or(false) {
+                            let link = self.fs.symlink_metadata(&npath).await;
+                            if link.map(|m| m.is_symlink()).unwrap_or(false) {
+                                propwriter.write_status(&npath, StatusCode::NOT_FOUND)?;
+                                propwriter.flush().await?;
+                                continue;
+                            }
+                        }
+                        // the member was removed after read_dir returned it.
+                        // It is left out rather than reported as 404, just
+                        // like in the autoindex: the listing is then the
+                        // same as that of a read_dir a moment later.
                         trace!("metadata error on {}. Skipping {:?}", npath, e);
                         continue;
                     }
@@ -373,6 +451,19 @@ impl DavInner {
                             StatusCode::FORBIDDEN
                         }
                     }
+                    "getcontenttype" => {
+                        // stored as a dead property, it overrides the
+                        // type that is derived from the extension.
+                        match prop.get_text() {
+                            Some(t) if !prop.has_child_elems() && is_media_type(&t) => {}
+                            _ => return StatusCode::CONFLICT,
+                        }
+                        if can_deadprop {
+                            StatusCode::CONTINUE
+                        } else {
+                            StatusCode::FORBIDDEN
+                        }
+                    }
                     "getlastmodified" => {
                         // we might allow setting modified time
                         // by using utimes() on unix. Not yet though.
@@ -430,15 +521,18 @@ impl DavInner {
                     _ => StatusCode::FORBIDDEN,
                 }
             }
+            Some(NS_POSIX_URI) => StatusCode::FORBIDDEN,
             _ => StatusCode::CONTINUE,
         }
     }
 
     // In general, live properties cannot be removed, with the
-    // exception of getcontentlanguage and displayname.
+    // exception of getcontentlanguage and displayname. Removing
+    // getcontenttype removes the override.
     fn liveprop_remove(&self, prop: &Element, can_deadprop: bool) -> StatusCode {
         match prop.namespace.as_deref() {
             Some(NS_DAV_URI) => match prop.name.as_str() {
+                "getcontenttype" if can_deadprop => StatusCode::CONTINUE,
                 "getcontentlanguage" | "displayname" => {
                     if can_deadprop {
                         StatusCode::OK
@@ -448,7 +542,7 @@ impl DavInner {
                 }
                 _ => StatusCode::FORBIDDEN,
             },
-            Some(NS_APACHE_URI) | Some(NS_MS_URI) => StatusCode::FORBIDDEN,
+            Some(NS_APACHE_URI) | Some(NS_MS_URI) | Some(NS_POSIX_URI) => StatusCode::FORBIDDEN,
             _ => StatusCode::CONTINUE,
         }
     }
@@ -475,8 +569,8 @@ impl DavInner {
         if let Some(ref locksystem) = self.ls {
             let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
             let principal = self.principal.as_deref();
-            if let Err(_l) = locksystem.check(&path, principal, false, false, t) {
-                return Err(StatusCode::LOCKED.into());
+            if let Err(l) = locksystem.check(&path, principal, false, false, t) {
+                return Err(locked(&l));
             }
         }
 
@@ -542,6 +636,15 @@ impl DavInner {
             ret.extend(deadret.into_iter());
         }
 
+        // the ETag after the update, so that a client can make the next
+        // change conditional on it. Whether properties are part of it is
+        // up to the backend.
+        if let Ok(meta) = self.fs.metadata(&path).await {
+            if let Some(etag) = davheaders::ETag::from_meta(&meta) {
+                res.headers_mut().typed_insert(etag);
+            }
+        }
+
         // group by statuscode.
         let mut hm = HashMap::new();
         for (code, prop) in ret.into_iter() {
@@ -551,7 +654,17 @@ impl DavInner {
         }
 
         // And reply.
-        let mut pw = PropWriter::new(req, &mut res, "propertyupdate", Vec::new(), &self.fs, None)?;
+        let ct = self.xml_type(req);
+        res.headers_mut().typed_insert(davheaders::ContentType(ct));
+        let mut pw = PropWriter::new(
+            req,
+            &mut res,
+            "propertyupdate",
+            Vec::new(),
+            &self.fs,
+            None,
+            None,
+        )?;
         *res.body_mut() = Body::from(AsyncStream::new(|tx| async move {
             pw.set_tx(tx);
             pw.write_propresponse(&path, hm)?;
@@ -571,9 +684,8 @@ impl PropWriter {
         mut props: Vec<Element>,
         fs: &Box<dyn DavFileSystem>,
         ls: Option<&Box<dyn DavLockSystem>>,
+        mime_types: Option<&Arc<HashMap<String, String>>>,
     ) -> DavResult<PropWriter> {
-        let contenttype = "application/xml; charset=utf-8".parse().unwrap();
-        res.headers_mut().insert("content-type", contenttype);
         *res.status_mut() = StatusCode::MULTI_STATUS;
 
         let mut emitter = EventWriter::new_with_config(
@@ -625,14 +737,18 @@ impl PropWriter {
             .ns("D", NS_DAV_URI);
         #[cfg(feature = "carddav")]
         let mut ev = ev.ns("card", NS_CARDDAV_URI);
+        #[cfg(feature = "caldav")]
+        let mut ev = ev.ns("cal", NS_CALDAV_URI);
 
         if name != "propertyupdate" {
             let mut a = false;
             let mut m = false;
+            let mut p = false;
             for prop in &props {
                 match prop.namespace.as_deref() {
                     Some(NS_APACHE_URI) => a = true,
                     Some(NS_MS_URI) => m = true,
+                    Some(NS_POSIX_URI) => p = true,
                     _ => {}
                 }
             }
@@ -642,6 +758,9 @@ impl PropWriter {
             if m {
                 ev = ev.ns("Z", NS_MS_URI);
             }
+            if p {
+                ev = ev.ns("P", NS_POSIX_URI);
+            }
         }
         emitter.write(ev)?;
 
@@ -654,6 +773,13 @@ impl PropWriter {
             ls: ls.cloned(),
             useragent: ua.to_string(),
             q_cache: Default::default(),
+            mime_types: mime_types.cloned(),
+            brief: false,
+            add_member: false,
+            principal_quota: None,
+            #[cfg(feature = "carddav")]
+            address_data_types: None,
+            resourcetypes: None,
         })
     }
 
@@ -661,6 +787,36 @@ impl PropWriter {
         self.tx = Some(tx)
     }
 
+    // Leave out the properties that were not found.
+    pub fn set_brief(&mut self, brief: bool) {
+        self.brief = brief;
+    }
+
+    // Advertise DAV:add-member on collections.
+    pub fn set_add_member(&mut self, add_member: bool) {
+        self.add_member = add_member;
+    }
+
+    // Report the quota of `principal` rather than that of the filesystem.
+    pub fn set_principal_quota(
+        &mut self,
+        quota: Option<Arc<PrincipalQuota>>,
+        principal: Option<String>,
+    ) {
+        self.principal_quota = quota.map(|q| (q, principal));
+    }
+
+    // Extra DAV:resourcetype elements of collections.
+    pub fn set_resourcetypes(&mut self, types: Option<Arc<ResourceTypes>>) {
+        self.resourcetypes = types;
+    }
+
+    // Media types for CARDDAV:supported-address-data.
+    #[cfg(feature = "carddav")]
+    pub fn set_address_data_types(&mut self, types: Option<Arc<Vec<(String, String)>>>) {
+        self.address_data_types = types;
+    }
+
     fn build_elem<T>(
         &self,
         content: bool,
@@ -697,6 +853,15 @@ impl PropWriter {
         path: &'a DavPath,
         meta: &'a dyn DavMetaData,
     ) -> FsResult<(u64, Option<u64>)> {
+        // the quota of the principal, if there is one.
+        if let (0, Some((quota, principal))) = (qc.q_state, self.principal_quota.as_ref()) {
+            if let Some((u, t)) = quota(principal.as_deref()) {
+                qc.q_used = u;
+                qc.q_total = t;
+                qc.q_state = 2;
+            }
+        }
+
         // do lookup only once.
         match qc.q_state {
             0 => match self.fs.get_quota().await {
@@ -832,44 +997,67 @@ impl PropWriter {
                             let tm = systemtime_to_rfc3339(time);
                             return self.build_elem(docontent, pfx, prop, tm);
                         }
+                        // and if there is no ctime either, mtime.
+                        if let Ok(mtime) = meta.modified() {
+                            let tm = systemtime_to_rfc3339(mtime);
+                            return self.build_elem(docontent, pfx, prop, tm);
+                        }
                     }
                     "getcontentlanguage" => {
                         try_deadprop = true;
                     }
-                    #[cfg(not(feature = "carddav"))]
                     "displayname" => {
-                        try_deadprop = true;
+                        // one set by PROPPATCH, then one from the filesystem,
+                        // then default to the name of the resource.
+                        if docontent && self.fs.have_props(path).await {
+                            let dprop = element_to_davprop(prop);
+                            if let Ok(xml) = self.fs.get_prop(path, dprop).await {
+                                if let Ok(e) = Element::parse(Cursor::new(xml)) {
+                                    return Ok(StatusElement {
+                                        status: StatusCode::OK,
+                                        element: e,
+                                    });
+                                }
+                            }
+                        }
+                        #[cfg(feature = "carddav")]
+                        if let Ok(name) = meta.displayname() {
+                            return self.build_elem(docontent, pfx, prop, name);
+                        }
+                        if let Some(name) = default_displayname(path) {
+                            return self.build_elem(docontent, pfx, prop, name);
+                        }
                     }
-                    #[cfg(feature = "carddav")]
-                    "displayname" => {
-                        // if meta.is_addrbook().is_ok() && meta.is_addrbook()? {
-                        //     return self.build_elem(docontent, pfx, prop,  meta.displayname()?);
-                        // } else {
-                        //     try_deadprop = true;z
-                        // }
-                        return self.build_elem(docontent, pfx, prop,  meta.displayname()?);
-                    }                    
                     "getetag" => {
                         if let Some(etag) = meta.etag() {
                             return self.build_elem(docontent, pfx, prop, etag);
                         }
                     }
                     "getcontentlength" => {
-                        if !meta.is_dir() {
+                        if !meta.is_dir() && !is_special(meta) {
                             return self.build_elem(docontent, pfx, prop, meta.len().to_string());
                         }
                     }
                     "getcontenttype" => {
                         #[cfg(feature = "carddav")]
-                        if meta.is_addrbook()? {
+                        if meta.is_addrbook().unwrap_or(false) {
                             return self.build_elem(docontent, pfx, prop, "application/octet-stream")
                         }
 
                         if meta.is_dir() {
                             return self.build_elem(docontent, pfx, prop, "httpd/unix-directory")
-                        } 
-                        
-                        return self.build_elem(docontent, pfx, prop, path.get_mime_type_str())
+                        }
+
+                        if !is_special(meta) {
+                            if docontent {
+                                if let Some(ct) = stored_content_type(&*self.fs, path).await {
+                                    return self.build_elem(docontent, pfx, prop, ct);
+                                }
+                            }
+                            let types = self.mime_types.as_deref();
+                            let ct = path.get_content_type(types);
+                            return self.build_elem(docontent, pfx, prop, ct);
+                        }
                     }
                     "getlastmodified" => {
                         if let Ok(time) = meta.modified() {
@@ -881,24 +1069,43 @@ impl PropWriter {
                         let mut elem = prop.clone();
 
                         #[cfg(feature = "carddav")]
-                        if meta.is_addrbook()? && docontent {
+                        let addr_book = meta.is_addrbook().unwrap_or(false);
+                        #[cfg(not(feature = "carddav"))]
+                        let addr_book = false;
+
+                        if (meta.is_dir() || addr_book) && docontent {
                             let dir = Element::new2("D:collection");
                             elem.children.push(XMLNode::Element(dir));
 
-                            let addr_book = Element::new2("card:addressbook");
-                            elem.children.push(XMLNode::Element(addr_book));
+                            if addr_book {
+                                let addr_book = Element::new2("card:addressbook");
+                                elem.children.push(XMLNode::Element(addr_book));
+                            }
 
-                            return Ok(StatusElement {
-                                status: StatusCode::OK,
-                                element: elem,
-                            });
+                            if let Some(ref types) = self.resourcetypes {
+                                for e in types(path) {
+                                    elem.children.push(XMLNode::Element(fix_namespaces(e)));
+                                }
+                            }
                         }
 
-                        if (meta.is_addrbook().is_err() || !meta.is_addrbook()?) && meta.is_dir() && docontent {
-                            let dir = Element::new2("D:collection");
-                            elem.children.push(XMLNode::Element(dir));
+                        return Ok(StatusElement {
+                            status: StatusCode::OK,
+                            element: elem,
+                        });
+                    }
+                    "sync-token" if meta.is_dir() => {
+                        if let Ok(token) = self.fs.sync_token(path).await {
+                            return self.build_elem(docontent, pfx, prop, token);
+                        }
+                    }
+                    "add-member" if self.add_member && meta.is_dir() => {
+                        let mut elem = prop.clone();
+                        if docontent {
+                            let href = path.with_prefix().as_url_string();
+                            let href = Element::new2("D:href").text(href);
+                            elem.children.push(XMLNode::Element(href));
                         }
-
                         return Ok(StatusElement {
                             status: StatusCode::OK,
                             element: elem,
@@ -941,6 +1148,31 @@ impl PropWriter {
             #[cfg(feature = "carddav")]
             Some(NS_CARDDAV_URI) => {
                 pfx = "card";
+                if prop.name.as_str() == "supported-address-data"
+                    && meta.is_addrbook().unwrap_or(false)
+                {
+                    let mut elem = prop.clone();
+                    if docontent {
+                        let types: Vec<(&str, &str)> = match self.address_data_types {
+                            Some(ref t) => {
+                                t.iter().map(|(c, v)| (c.as_str(), v.as_str())).collect()
+                            }
+                            None => ADDRESS_DATA_TYPES.to_vec(),
+                        };
+                        for (content_type, version) in types {
+                            let mut t = Element::new2("card:address-data-type");
+                            t.attributes
+                                .insert("content-type".to_string(), content_type.to_string());
+                            t.attributes
+                                .insert("version".to_string(), version.to_string());
+                            elem.children.push(XMLNode::Element(t));
+                        }
+                    }
+                    return Ok(StatusElement {
+                        status: StatusCode::OK,
+                        element: elem,
+                    });
+                }
                 if prop.name.as_str() == "address-data" {
                     let vcard = meta.vcard_data()?.to_string();
                     let mut elem = prop.clone();
@@ -951,6 +1183,23 @@ impl PropWriter {
                     });
                 }
             }
+            #[cfg(feature = "caldav")]
+            Some(NS_CALDAV_URI) => {
+                pfx = "cal";
+                if prop.name.as_str() == "calendar-data" && !meta.is_dir() {
+                    let mut elem = prop.clone();
+                    if docontent {
+                        // returned as stored, never expanded or trimmed.
+                        elem.children.clear();
+                        let data = crate::ical::read(&*self.fs, path).await?;
+                        elem.children.push(XMLNode::Text(data));
+                    }
+                    return Ok(StatusElement {
+                        status: StatusCode::OK,
+                        element: elem,
+                    });
+                }
+            }
             Some(NS_APACHE_URI) => {
                 pfx = "A";
                 if prop.name.as_str() == "executable" {
@@ -1014,6 +1263,18 @@ impl PropWriter {
                     _ => {}
                 }
             }
+            Some(NS_POSIX_URI) => {
+                pfx = "P";
+                let value = match prop.name.as_str() {
+                    "uid" => meta.uid().map(|uid| uid.to_string()),
+                    "gid" => meta.gid().map(|gid| gid.to_string()),
+                    "mode" => meta.mode().map(|mode| format!("{:04o}", mode)),
+                    _ => None,
+                };
+                if let Some(value) = value {
+                    return self.build_elem(docontent, pfx, prop, value);
+                }
+            }
             _ => {
                 try_deadprop = true;
             }
@@ -1026,7 +1287,7 @@ impl PropWriter {
                 if let Ok(e) = Element::parse(Cursor::new(xml)) {
                     return Ok(StatusElement {
                         status: StatusCode::OK,
-                        element: e,
+                        element: fix_namespaces(e),
                     });
                 }
             }
@@ -1054,6 +1315,11 @@ impl PropWriter {
 
         // Get properties one-by-one
         let do_content = self.name != "propname";
+        let meta = if do_content && self.props.iter().any(|p| p.name == "getetag") {
+            with_content_hash(&*self.fs, path, meta).await
+        } else {
+            meta
+        };
         let mut qc = self.q_cache;
         for p in &self.props {
             #[cfg(feature = "carddav")]
@@ -1064,6 +1330,9 @@ impl PropWriter {
             let res = self
                 .build_prop(p, path, &*meta, &mut qc, do_content)
                 .await?;
+            if self.brief && res.status == StatusCode::NOT_FOUND {
+                continue;
+            }
             if res.status == StatusCode::OK || (self.name != "propname" && self.name != "allprop") {
                 add_sc_elem(&mut props, res.status, res.element);
             }
@@ -1073,15 +1342,48 @@ impl PropWriter {
         // and list the dead properties as well.
         if (self.name == "propname" || self.name == "allprop") && self.fs.have_props(path).await {
             if let Ok(v) = self.fs.get_props(path, do_content).await {
-                v.into_iter()
-                    .map(davprop_to_element)
-                    .for_each(|e| add_sc_elem(&mut props, StatusCode::OK, e));
+                // skip the ones we already have, like DAV:displayname.
+                for e in v.into_iter().map(davprop_to_element) {
+                    let have = props
+                        .get(&StatusCode::OK)
+                        .into_iter()
+                        .flatten()
+                        .any(|p| p.name == e.name && p.namespace == e.namespace);
+                    if !have {
+                        add_sc_elem(&mut props, StatusCode::OK, e);
+                    }
+                }
             }
         }
 
         Ok::<(), DavError>(self.write_propresponse(path, props)?)
     }
 
+    // The value of a property as text, for evaluating a SEARCH
+    // condition. None if the resource does not have the property.
+    pub async fn prop_value<'a>(
+        &'a mut self,
+        prop: &'a Element,
+        path: &'a DavPath,
+        meta: &'a dyn DavMetaData,
+    ) -> DavResult<Option<String>> {
+        let mut qc = self.q_cache;
+        let res = self.build_prop(prop, path, meta, &mut qc, true).await?;
+        self.q_cache = qc;
+        let text = |e: &Element| e.get_text().map(|t| t.into_owned()).unwrap_or_default();
+        if res.status == StatusCode::OK {
+            return Ok(Some(text(&res.element)));
+        }
+        if self.fs.have_props(path).await {
+            if let Ok(xml) = self.fs.get_prop(path, element_to_davprop(prop)).await {
+                if let Ok(e) = Element::parse(Cursor::new(xml)) {
+                    return Ok(Some(text(&e)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
     pub fn write_propresponse(
         &mut self,
         path: &DavPath,
@@ -1114,6 +1416,21 @@ impl PropWriter {
         Ok(())
     }
 
+    // A response with just a status, for a member that has no properties
+    // at all, like a symbolic link that points to nothing.
+    pub fn write_status(&mut self, path: &DavPath, status: StatusCode) -> DavResult<()> {
+        self.emitter.write(XmlWEvent::start_element("D:response"))?;
+        let p = path.with_prefix().as_url_string();
+        Element::new2("D:href")
+            .text(p)
+            .write_ev(&mut self.emitter)?;
+        Element::new2("D:status")
+            .text("HTTP/1.1 ".to_string() + &status.to_string())
+            .write_ev(&mut self.emitter)?;
+        self.emitter.write(XmlWEvent::end_element())?;
+        Ok(())
+    }
+
     pub async fn flush(&mut self) -> DavResult<()> {
         let buffer = self.emitter.inner_mut().take();
         self.tx.as_mut().unwrap().send(buffer).await;
@@ -1126,6 +1443,47 @@ impl PropWriter {
     }
 }
 
+// The default DAV:displayname is the last segment of the path.
+fn default_displayname(path: &DavPath) -> Option<String> {
+    let name = path.file_name_bytes();
+    if name.is_empty() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(name).to_string())
+}
+
+// A DAV:getcontenttype that was stored as a dead property, by PROPPATCH
+// or by the backend. It takes precedence over the extension.
+pub(crate) async fn stored_content_type(fs: &dyn DavFileSystem, path: &DavPath) -> Option<String> {
+    if !fs.have_props(path).await {
+        return None;
+    }
+    let prop = DavProp {
+        name: "getcontenttype".to_string(),
+        prefix: None,
+        namespace: Some(NS_DAV_URI.to_string()),
+        xml: None,
+    };
+    let xml = fs.get_prop(path, prop).await.ok()?;
+    let elem = Element::parse(Cursor::new(xml)).ok()?;
+    let ct = elem.get_text()?.trim().to_string();
+    if is_media_type(&ct) {
+        Some(ct)
+    } else {
+        None
+    }
+}
+
+// Roughly "type/subtype", optionally followed by parameters.
+fn is_media_type(s: &str) -> bool {
+    let mime = s.split(';').next().unwrap_or("").trim();
+    let token = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_graphic() && b != b'/');
+    match mime.split_once('/') {
+        Some((t, st)) => token(t) && token(st) && http::HeaderValue::from_str(s).is_ok(),
+        None => false,
+    }
+}
+
 fn add_sc_elem(hm: &mut HashMap<StatusCode, Vec<Element>>, sc: StatusCode, e: Element) {
     hm.entry(sc).or_insert_with(Vec::new);
     hm.get_mut(&sc).unwrap().push(e)
@@ -1143,7 +1501,7 @@ fn element_to_davprop_full(elem: &Element) -> DavProp {
     }
 }
 
-fn element_to_davprop(elem: &Element) -> DavProp {
+pub(crate) fn element_to_davprop(elem: &Element) -> DavProp {
     DavProp {
         name: elem.name.clone(),
         prefix: elem.prefix.clone(),
@@ -1154,7 +1512,7 @@ fn element_to_davprop(elem: &Element) -> DavProp {
 
 fn davprop_to_element(prop: DavProp) -> Element {
     if let Some(xml) = prop.xml {
-        return Element::parse2(Cursor::new(xml)).unwrap();
+        return fix_namespaces(Element::parse2(Cursor::new(xml)).unwrap());
     }
     let mut elem = Element::new(&prop.name);
     if let Some(ref ns) = prop.namespace {
@@ -1165,3 +1523,930 @@ fn davprop_to_element(prop: DavProp) -> Element {
     elem.namespace = prop.namespace.clone();
     elem
 }
+
+// A stored dead property carries all the namespace declarations that were
+// in scope when it was set, and its prefixes might clash with the ones that
+// the multistatus preamble declares (e.g. "D" bound to something else than
+// "DAV:"). Give every element a prefix that is unique for its namespace,
+// and declare just the namespaces that are used, on the property element.
+fn fix_namespaces(mut elem: Element) -> Element {
+    let mut prefixes = vec![
+        ("D".to_string(), NS_DAV_URI.to_string()),
+        ("A".to_string(), NS_APACHE_URI.to_string()),
+        ("Z".to_string(), NS_MS_URI.to_string()),
+        ("P".to_string(), NS_POSIX_URI.to_string()),
+    ];
+    #[cfg(feature = "carddav")]
+    prefixes.push(("card".to_string(), NS_CARDDAV_URI.to_string()));
+    #[cfg(feature = "caldav")]
+    prefixes.push(("cal".to_string(), NS_CALDAV_URI.to_string()));
+    let mut used = Vec::new();
+    fix_prefixes(&mut elem, &mut prefixes, &mut used);
+
+    // "D", "card" and "cal" are always declared in the preamble.
+    let mut ns = xmltree::Namespace::empty();
+    for (pfx, uri) in prefixes {
+        if used.contains(&pfx) && pfx != "D" && pfx != "card" && pfx != "cal" {
+            ns.force_put(pfx, uri);
+        }
+    }
+    elem.namespaces = Some(ns);
+    elem
+}
+
+fn fix_prefixes(elem: &mut Element, prefixes: &mut Vec<(String, String)>, used: &mut Vec<String>) {
+    elem.namespaces = None;
+    if let Some(ref uri) = elem.namespace {
+        let pfx = match prefixes.iter().find(|(_, u)| u == uri) {
+            Some((pfx, _)) => pfx.clone(),
+            None => {
+                let taken = |p: &str| prefixes.iter().any(|(q, _)| q == p);
+                let pfx = match elem.prefix {
+                    Some(ref p) if !taken(p) => p.clone(),
+                    _ => (0..)
+                        .map(|n| format!("ns{}", n))
+                        .find(|p| !taken(p))
+                        .unwrap(),
+                };
+                prefixes.push((pfx.clone(), uri.clone()));
+                pfx
+            }
+        };
+        if !used.contains(&pfx) {
+            used.push(pfx.clone());
+        }
+        elem.prefix = Some(pfx);
+    } else {
+        elem.prefix = None;
+    }
+    for node in elem.children.iter_mut() {
+        if let XMLNode::Element(ref mut child) = node {
+            fix_prefixes(child, prefixes, used);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_displayname() {
+        let path = DavPath::new("/dir/hello%20w%C3%B6rld%25.txt").unwrap();
+        assert_eq!(default_displayname(&path).unwrap(), "hello wörld%.txt");
+        let path = DavPath::new("/dir/sub%20dir/").unwrap();
+        assert_eq!(default_displayname(&path).unwrap(), "sub dir");
+        let path = DavPath::new("/").unwrap();
+        assert!(default_displayname(&path).is_none());
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_dead_props() {
+        use crate::testfs::{body_text, memfs_handler, request};
+
+        let dav = &memfs_handler();
+        let request =
+            |method, body: &'static str| request(dav, method, "/file", &[("Depth", "0")], body);
+        let resp = request("PUT", "hello").await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        // "D" is bound to another namespace, "x" is declared on an ancestor.
+        let resp = request(
+            "PROPPATCH",
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:propertyupdate xmlns:D="DAV:" xmlns:x="urn:example:x">
+              <D:set><D:prop>
+                <x:color>blue &amp; <x:b>green</x:b></x:color>
+                <D:shade xmlns:D="urn:example:d">dark <x:c/></D:shade>
+                <plain xmlns="urn:example:plain">text</plain>
+              </D:prop></D:set>
+            </D:propertyupdate>"#,
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+
+        let find_prop = |body: &str, ns: &str, name: &str| {
+            let tree = Element::parse(body.as_bytes()).unwrap();
+            let response = tree.get_child("response").unwrap();
+            let propstat = response.get_child("propstat").unwrap();
+            let prop = propstat.get_child("prop").unwrap();
+            let e = prop.get_child((name, ns)).cloned();
+            e.unwrap_or_else(|| panic!("{} {}", ns, name))
+        };
+        let check = |body: &str| {
+            let color = find_prop(body, "urn:example:x", "color");
+            assert_eq!(color.get_text().unwrap(), "blue & ");
+            let b = color.get_child(("b", "urn:example:x")).unwrap();
+            assert_eq!(b.get_text().unwrap(), "green");
+            let shade = find_prop(body, "urn:example:d", "shade");
+            assert_eq!(shade.get_text().unwrap(), "dark ");
+            assert!(shade.get_child(("c", "urn:example:x")).is_some());
+            let plain = find_prop(body, "urn:example:plain", "plain");
+            assert_eq!(plain.get_text().unwrap(), "text");
+            find_prop(body, "DAV:", "getcontentlength");
+        };
+
+        for body in &[
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:propfind xmlns:D="DAV:"><D:prop>
+              <D:getcontentlength/>
+              <a:color xmlns:a="urn:example:x"/>
+              <D:shade xmlns:D="urn:example:d"/>
+              <b:plain xmlns:b="urn:example:plain"/>
+            </D:prop></D:propfind>"#,
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:propfind xmlns:D="DAV:"><D:allprop/></D:propfind>"#,
+        ] {
+            let resp = request("PROPFIND", body).await;
+            assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+            check(&body_text(resp).await);
+        }
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_depth_noroot() {
+        use crate::testfs::{body_text, memfs_handler, request};
+
+        let dav = &memfs_handler();
+        let request = |method, uri, depth, body: &'static str| async move {
+            request(dav, method, uri, &[("Depth", depth)], body).await
+        };
+        assert_eq!(request("MKCOL", "/dir", "0", "").await.status(), 201);
+        assert_eq!(request("PUT", "/dir/a", "0", "a").await.status(), 201);
+        assert_eq!(request("PUT", "/dir/b", "0", "b").await.status(), 201);
+
+        let hrefs = |body: String| {
+            let tree = Element::parse(body.as_bytes()).unwrap();
+            let mut hrefs = tree
+                .child_elems_iter()
+                .map(|r| r.get_child("href").unwrap().get_text().unwrap().to_string())
+                .collect::<Vec<_>>();
+            hrefs.sort();
+            hrefs
+        };
+        for (depth, expected) in &[
+            ("1", &["/dir/", "/dir/a", "/dir/b"][..]),
+            ("1,noroot", &["/dir/a", "/dir/b"][..]),
+        ] {
+            let resp = request("PROPFIND", "/dir/", depth, "").await;
+            assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+            assert_eq!(hrefs(body_text(resp).await), *expected, "Depth: {}", depth);
+        }
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_can_read() {
+        use crate::memfs::MemFs;
+        use crate::testfs::{body_text, request};
+        use crate::{DavConfig, DavHandler};
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .principal("alice")
+            .autoindex(true)
+            .can_read(|principal, path| {
+                principal == Some("root") || !path.as_url_string().contains("secret")
+            })
+            .build_handler();
+        let hdrs = [("Depth", "1"), ("Accept", "text/plain")];
+        let request = |method, uri, body: &'static str| request(&dav, method, uri, &hdrs, body);
+        assert_eq!(request("MKCOL", "/dir", "").await.status(), 201);
+        assert_eq!(request("PUT", "/dir/a", "a").await.status(), 201);
+        assert_eq!(request("PUT", "/dir/secret", "s").await.status(), 201);
+
+        let resp = request("PROPFIND", "/dir/", "").await;
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+        let body = body_text(resp).await;
+        let tree = Element::parse(body.as_bytes()).unwrap();
+        let mut hrefs = tree
+            .child_elems_iter()
+            .map(|r| r.get_child("href").unwrap().get_text().unwrap().to_string())
+            .collect::<Vec<_>>();
+        hrefs.sort();
+        assert_eq!(hrefs, ["/dir/", "/dir/a"]);
+
+        let resp = request("GET", "/dir/", "").await;
+        assert_eq!(body_text(resp).await, "/dir/a\n");
+
+        // the callback gets the principal of the request.
+        let config = DavConfig::new().principal("root");
+        let req = http::Request::builder()
+            .uri("/dir/")
+            .header("Accept", "text/plain")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let resp = dav.handle_with(config, req).await;
+        assert_eq!(body_text(resp).await, "/dir/a\n/dir/secret\n");
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_collection_etag() {
+        use crate::memfs::MemFs;
+        use crate::testfs::{body_text, request};
+        use crate::DavHandler;
+
+        let fs = MemFs::new();
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        let request =
+            |method, uri, body: &'static str| request(&dav, method, uri, &[("Depth", "0")], body);
+        let propfind = |body: &'static str| {
+            let resp = request("PROPFIND", "/dir/", body);
+            async move {
+                let body = body_text(resp.await).await;
+                let tree = Element::parse(body.as_bytes()).unwrap();
+                let prop = tree
+                    .get_child("response")
+                    .and_then(|r| r.get_child("propstat"))
+                    .and_then(|p| p.get_child("prop"))
+                    .unwrap();
+                let etag = prop.get_child("getetag").unwrap();
+                etag.get_text().unwrap().to_string()
+            }
+        };
+        // allprop reports the same etag as asking for it by name.
+        let etag = || async {
+            let named = propfind(
+                r#"<D:propfind xmlns:D="DAV:"><D:prop><D:getetag/></D:prop></D:propfind>"#,
+            )
+            .await;
+            let all = propfind(r#"<D:propfind xmlns:D="DAV:"><D:allprop/></D:propfind>"#).await;
+            assert_eq!(all, named);
+            named
+        };
+        assert_eq!(request("MKCOL", "/dir", "").await.status(), 201);
+        let empty = etag().await;
+        assert_eq!(request("PUT", "/dir/a", "a").await.status(), 201);
+        let added = etag().await;
+        assert_ne!(added, empty);
+        assert_eq!(etag().await, added);
+        assert_eq!(request("PUT", "/dir/a", "aa").await.status(), 204);
+        let modified = etag().await;
+        assert_ne!(modified, added);
+        assert_eq!(request("DELETE", "/dir/a", "").await.status(), 204);
+        assert_ne!(etag().await, modified);
+
+        // If-Match and the autoindex listing use the same etag.
+        let current = format!("\"{}\"", etag().await);
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .autoindex(true)
+            .build_handler();
+        for (tag, status) in [(current.as_str(), 200), ("\"other\"", 412)] {
+            let hdrs = [("If-Match", tag)];
+            let resp = crate::testfs::request(&dav, "GET", "/dir/", &hdrs, "").await;
+            assert_eq!(resp.status(), status);
+            if status == 200 {
+                assert_eq!(resp.headers()["etag"], current.as_str());
+            }
+        }
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_vanished_member() {
+        use crate::memfs::MemFs;
+        use crate::testfs::{body_text, request, FsHooks, HookFs};
+        use crate::DavHandler;
+        use futures_util::stream;
+
+        // MemFs whose read_dir also returns "gone", as if it was
+        // removed right after the directory was read.
+        #[derive(Clone)]
+        struct Racy;
+
+        struct Gone;
+
+        impl DavDirEntry for Gone {
+            fn name(&self) -> Vec<u8> {
+                b"gone".to_vec()
+            }
+            fn metadata(&self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+                Box::pin(futures_util::future::err(FsError::NotFound))
+            }
+        }
+
+        impl FsHooks for Racy {
+            fn read_dir<'a>(
+                &'a self,
+                fs: &'a MemFs,
+                path: &'a DavPath,
+                meta: ReadDirMeta,
+            ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+                Box::pin(async move {
+                    let entries = fs.read_dir(path, meta).await?;
+                    let gone: Box<dyn DavDirEntry> = Box::new(Gone);
+                    Ok(Box::pin(entries.chain(stream::once(async { gone })))
+                        as FsStream<Box<dyn DavDirEntry>>)
+                })
+            }
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(HookFs::new(Racy))
+            .autoindex(true)
+            .build_handler();
+        let hdrs = [("Depth", "1"), ("Accept", "text/plain")];
+        let request = |method, uri, body: &'static str| request(&dav, method, uri, &hdrs, body);
+        assert_eq!(request("PUT", "/a", "a").await.status(), 201);
+
+        let resp = request("PROPFIND", "/", "").await;
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+        let body = body_text(resp).await;
+        let tree = Element::parse(body.as_bytes()).unwrap();
+        let hrefs = tree
+            .child_elems_iter()
+            .map(|r| r.get_child("href").unwrap().get_text().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(hrefs, ["/", "/a"]);
+
+        let resp = request("GET", "/", "").await;
+        assert_eq!(body_text(resp).await, "/a\n");
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_xml_content_type() {
+        use crate::memfs::MemFs;
+        use crate::testfs::{memfs_handler, request};
+        use crate::DavHandler;
+
+        let propfind = |dav: DavHandler, accept: &'static str| async move {
+            let hdrs = [("Depth", "0"), ("Accept", accept)];
+            let resp = request(&dav, "PROPFIND", "/", &hdrs, "").await;
+            assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+            resp.headers()["content-type"].to_str().unwrap().to_string()
+        };
+        let dav = memfs_handler();
+        let ct = propfind(dav.clone(), "*/*").await;
+        assert_eq!(ct, "application/xml; charset=utf-8");
+        let ct = propfind(dav, "text/xml").await;
+        assert_eq!(ct, "text/xml; charset=utf-8");
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .xml_content_type("text/xml")
+            .build_handler();
+        assert_eq!(propfind(dav, "application/xml").await, "text/xml");
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_add_member() {
+        use crate::memfs::MemFs;
+        use crate::testfs::{body_text, request};
+        use crate::DavHandler;
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .add_member(true)
+            .build_handler();
+        let dav = &dav;
+        let request = |method, uri, ct, body: &'static str| async move {
+            let hdrs = [("Depth", "0"), ("Content-Type", ct)];
+            request(dav, method, uri, &hdrs, body).await
+        };
+        assert_eq!(request("MKCOL", "/dir", "", "").await.status(), 201);
+        assert_eq!(request("PUT", "/dir/a", "", "a").await.status(), 201);
+
+        let body = r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:propfind xmlns:D="DAV:"><D:prop><D:add-member/></D:prop></D:propfind>"#;
+        let resp = request("PROPFIND", "/dir/", "application/xml", body).await;
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+        let body = body_text(resp).await;
+        let tree = Element::parse(body.as_bytes()).unwrap();
+        let prop = tree
+            .get_child("response")
+            .and_then(|r| r.get_child("propstat"))
+            .and_then(|p| p.get_child("prop"))
+            .unwrap();
+        let href = prop.get_child("add-member").unwrap().get_child("href");
+        assert_eq!(href.unwrap().get_text().unwrap(), "/dir/");
+
+        let resp = request("POST", "/dir/", "text/plain", "hello").await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert!(resp.headers().contains_key("etag"));
+        let location = resp.headers()["location"].to_str().unwrap().to_string();
+        assert!(location.starts_with("/dir/") && location.ends_with(".txt"));
+        let resp = request("GET", &location, "", "").await;
+        assert_eq!(body_text(resp).await, "hello");
+
+        let resp = request("POST", "/dir/a", "text/plain", "hello").await;
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_sync_token() {
+        use crate::memfs::MemFs;
+        use crate::testfs::{body_text, request, FsHooks, HookFs};
+        use crate::DavHandler;
+
+        // MemFs, with a sync token on the root collection.
+        #[derive(Clone)]
+        struct RootToken;
+
+        impl FsHooks for RootToken {
+            fn sync_token<'a>(&'a self, _fs: &'a MemFs, path: &'a DavPath) -> FsFuture<'a, String> {
+                let res = match path.as_url_string().as_str() {
+                    "/" => Ok("http://example.com/ns/sync/1234".to_string()),
+                    _ => Err(FsError::NotImplemented),
+                };
+                Box::pin(futures_util::future::ready(res))
+            }
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(HookFs::new(RootToken))
+            .build_handler();
+        let propfind = |uri| {
+            let body = r#"<?xml version="1.0" encoding="utf-8" ?>
+                <D:propfind xmlns:D="DAV:"><D:prop><D:sync-token/></D:prop></D:propfind>"#;
+            let resp = request(&dav, "PROPFIND", uri, &[("Depth", "0")], body);
+            async move {
+                let body = body_text(resp.await).await;
+                let tree = Element::parse(body.as_bytes()).unwrap();
+                let propstat = tree
+                    .get_child("response")
+                    .and_then(|r| r.get_child("propstat"))
+                    .unwrap();
+                let status = propstat.get_child("status").unwrap().get_text().unwrap();
+                let prop = propstat.get_child("prop").unwrap();
+                let token = prop.get_child("sync-token").unwrap().get_text();
+                (status.to_string(), token.map(|t| t.to_string()))
+            }
+        };
+        let resp = request(&dav, "PUT", "/file", &[], "hello").await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let (status, token) = propfind("/").await;
+        assert!(status.contains("200"));
+        assert_eq!(token.unwrap(), "http://example.com/ns/sync/1234");
+        let (status, _) = propfind("/file").await;
+        assert!(status.contains("404"));
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_propfind_empty_body() {
+        use crate::testfs::{body_text, memfs_handler, request};
+
+        let dav = memfs_handler();
+        for body in &["", "\r\n"] {
+            let resp = request(&dav, "PROPFIND", "/", &[("Depth", "0")], *body).await;
+            assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+            let body = body_text(resp).await;
+            assert!(body.contains("<D:resourcetype>"));
+            assert!(body.contains("<D:getlastmodified>"));
+        }
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_propfind_depth() {
+        use crate::testfs::{memfs_handler, request};
+
+        let dav = memfs_handler();
+        for (depth, status) in &[("1.0", 207), ("INFINITY", 403), ("2", 400), ("one", 400)] {
+            let resp = request(&dav, "PROPFIND", "/", &[("Depth", *depth)], "").await;
+            assert_eq!(resp.status(), *status, "{}", depth);
+        }
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_propfind_partly_found() {
+        use crate::testfs::{body_text, memfs_handler, request};
+
+        let dav = memfs_handler();
+        request(&dav, "PUT", "/file", &[], "hello").await;
+        let body = r#"<?xml version="1.0" encoding="utf-8"?>
+            <D:propfind xmlns:D="DAV:" xmlns:X="urn:x">
+              <D:prop><D:getcontentlength/><X:bogus/></D:prop>
+            </D:propfind>"#;
+        let resp = request(&dav, "PROPFIND", "/file", &[("Depth", "0")], body).await;
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+        let body = body_text(resp).await;
+        let tree = Element::parse(body.as_bytes()).unwrap();
+
+        // one response, with a propstat per status.
+        let responses: Vec<_> = tree
+            .children
+            .iter()
+            .filter_map(|n| n.as_element())
+            .collect();
+        assert_eq!(responses.len(), 1);
+        let propstats: Vec<_> = responses[0]
+            .children
+            .iter()
+            .filter_map(|n| n.as_element())
+            .filter(|e| e.name == "propstat")
+            .collect();
+        assert_eq!(propstats.len(), 2);
+        for propstat in propstats {
+            let status = propstat.get_child("status").unwrap().get_text().unwrap();
+            let prop = propstat.get_child("prop").unwrap();
+            let names: Vec<_> = prop
+                .children
+                .iter()
+                .filter_map(|n| n.as_element())
+                .map(|e| e.name.as_str())
+                .collect();
+            if status.contains("200") {
+                assert_eq!(names, ["getcontentlength"]);
+            } else {
+                assert!(status.contains("404"));
+                assert_eq!(names, ["bogus"]);
+            }
+        }
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_principal_quota() {
+        use crate::memfs::MemFs;
+        use crate::testfs::body_text;
+        use crate::{DavConfig, DavHandler};
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .principal_quota(|principal| match principal {
+                Some("alice") => Some((100, Some(1000))),
+                Some("bob") => Some((5, Some(50))),
+                _ => None,
+            })
+            .build_handler();
+        let propfind = |principal: &str| {
+            let body = r#"<?xml version="1.0" encoding="utf-8" ?>
+                <D:propfind xmlns:D="DAV:"><D:prop>
+                <D:quota-available-bytes/><D:quota-used-bytes/>
+                </D:prop></D:propfind>"#;
+            let req = http::Request::builder()
+                .method("PROPFIND")
+                .uri("/")
+                .header("Depth", "0")
+                .body(hyper::Body::from(body))
+                .unwrap();
+            let config = DavConfig::new().principal(principal);
+            let resp = dav.handle_with(config, req);
+            async move {
+                let body = body_text(resp.await).await;
+                let tree = Element::parse(body.as_bytes()).unwrap();
+                let propstat = tree
+                    .get_child("response")
+                    .and_then(|r| r.get_child("propstat"))
+                    .unwrap();
+                let status = propstat.get_child("status").unwrap().get_text().unwrap();
+                let prop = propstat.get_child("prop").unwrap();
+                let value = |name: &str| {
+                    let text = prop.get_child(name).and_then(|e| e.get_text());
+                    text.map(|t| t.to_string())
+                };
+                let avail = value("quota-available-bytes");
+                let used = value("quota-used-bytes");
+                (status.to_string(), avail, used)
+            }
+        };
+
+        let (status, avail, used) = propfind("alice").await;
+        assert!(status.contains("200"));
+        assert_eq!(avail.unwrap(), "900");
+        assert_eq!(used.unwrap(), "100");
+        let (_, avail, used) = propfind("bob").await;
+        assert_eq!(avail.unwrap(), "45");
+        assert_eq!(used.unwrap(), "5");
+        // MemFs has no quota of its own.
+        let (status, _, _) = propfind("carol").await;
+        assert!(status.contains("404"));
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_proppatch_if_match() {
+        use crate::testfs::{body_text, memfs_handler, request};
+
+        const PATCH: &str = r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:propertyupdate xmlns:D="DAV:" xmlns:a="urn:example:x">
+              <D:set><D:prop><a:color>blue</a:color></D:prop></D:set>
+            </D:propertyupdate>"#;
+
+        let dav = &memfs_handler();
+        let put = |body: &'static str| request(dav, "PUT", "/file", &[], body);
+        let proppatch = |etag| async move {
+            request(dav, "PROPPATCH", "/file", &[("If-Match", etag)], PATCH).await
+        };
+        let etag = |resp: &http::Response<_>| resp.headers()["etag"].to_str().unwrap().to_string();
+
+        let stale = etag(&put("hello").await);
+        let current = etag(&put("hello, world").await);
+        assert_ne!(stale, current);
+
+        let resp = proppatch(&stale).await;
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+        let resp = proppatch(&current).await;
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+        assert_eq!(etag(&resp), current);
+        assert!(body_text(resp).await.contains("200 OK"));
+    }
+
+    #[cfg(all(feature = "carddav", feature = "memfs"))]
+    #[tokio::test]
+    async fn test_supported_address_data() {
+        use crate::testfs::{body_text, request, AddressBook, HookFs};
+        use crate::DavHandler;
+
+        async fn propfind(dav: &DavHandler, uri: &str) -> String {
+            let body = r#"<?xml version="1.0" encoding="utf-8"?>
+                <D:propfind xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:carddav">
+                  <D:prop><C:supported-address-data/></D:prop>
+                </D:propfind>"#;
+            let resp = request(dav, "PROPFIND", uri, &[("Depth", "0")], body).await;
+            assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+            body_text(resp).await
+        }
+
+        let fs = HookFs::new(AddressBook);
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        for dir in &["/book", "/dir"] {
+            request(&dav, "MKCOL", dir, &[], "").await;
+        }
+
+        let body = propfind(&dav, "/book").await;
+        assert!(body.contains("200 OK"));
+        assert!(body.contains(r#"content-type="text/vcard""#));
+        assert!(body.contains(r#"version="3.0""#));
+        assert!(body.contains(r#"version="4.0""#));
+
+        // not an address book.
+        let body = propfind(&dav, "/dir").await;
+        assert!(body.contains("404 Not Found"));
+        assert!(!body.contains("address-data-type"));
+
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .address_data_type("text/vcard", "4.0")
+            .build_handler();
+        let body = propfind(&dav, "/book").await;
+        assert!(body.contains(r#"version="4.0""#));
+        assert!(!body.contains(r#"version="3.0""#));
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_resourcetypes() {
+        use crate::memfs::MemFs;
+        use crate::testfs::{body_text, request};
+        use crate::DavHandler;
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .resourcetypes(|path| {
+                if path.as_url_string().trim_end_matches('/') != "/dir" {
+                    return Vec::new();
+                }
+                let mut e = Element::new("mytype");
+                e.prefix = Some("X".to_string());
+                e.namespace = Some("urn:example:x".to_string());
+                vec![e]
+            })
+            .build_handler();
+        for dir in &["/dir", "/other"] {
+            request(&dav, "MKCOL", dir, &[], "").await;
+        }
+        let resourcetype = |uri| {
+            let body = r#"<?xml version="1.0" encoding="utf-8"?>
+                <D:propfind xmlns:D="DAV:"><D:prop><D:resourcetype/></D:prop></D:propfind>"#;
+            let resp = request(&dav, "PROPFIND", uri, &[("Depth", "0")], body);
+            async move {
+                let resp = resp.await;
+                assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+                let body = body_text(resp).await;
+                let tree = Element::parse(body.as_bytes()).unwrap();
+                let rt = tree.get_child("response").unwrap();
+                let rt = rt.get_child("propstat").unwrap().get_child("prop").unwrap();
+                let rt = rt.get_child("resourcetype").unwrap();
+                rt.children
+                    .iter()
+                    .filter_map(|n| n.as_element())
+                    .map(|e| (e.namespace.clone().unwrap(), e.name.clone()))
+                    .collect::<Vec<_>>()
+            }
+        };
+        let dav = |name: &str| ("DAV:".to_string(), name.to_string());
+        let x = |name: &str| ("urn:example:x".to_string(), name.to_string());
+        assert_eq!(
+            resourcetype("/dir/").await,
+            [dav("collection"), x("mytype")]
+        );
+        assert_eq!(resourcetype("/other/").await, [dav("collection")]);
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_cancel() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use crate::cancel::CancelToken;
+        use crate::memfs::MemFs;
+        use crate::testfs::{body_text, request, FsHooks, HookFs};
+        use crate::{DavConfig, DavHandler};
+
+        // MemFs that counts read_dir calls.
+        #[derive(Clone)]
+        struct CountReadDirs(Arc<AtomicUsize>);
+
+        impl FsHooks for CountReadDirs {
+            fn read_dir<'a>(
+                &'a self,
+                fs: &'a MemFs,
+                path: &'a DavPath,
+                meta: ReadDirMeta,
+            ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                fs.read_dir(path, meta)
+            }
+        }
+
+        let memfs = MemFs::new();
+        let setup = DavHandler::builder()
+            .filesystem(memfs.clone())
+            .build_handler();
+        for n in 0..10 {
+            request(&setup, "MKCOL", &format!("/d{}", n), &[], "").await;
+        }
+        let read_dirs = Arc::new(AtomicUsize::new(0));
+        let dav = DavHandler::builder()
+            .filesystem(HookFs::with_fs(memfs, CountReadDirs(read_dirs.clone())))
+            .build_handler();
+        let propfind = || {
+            http::Request::builder()
+                .method("PROPFIND")
+                .uri("/")
+                .header("Depth", "infinity")
+                // otherwise Depth: infinity is refused.
+                .header("X-Litmus", "test_cancel")
+                .body(hyper::Body::from(
+                    r#"<D:propfind xmlns:D="DAV:"><D:prop><D:displayname/></D:prop></D:propfind>"#,
+                ))
+                .unwrap()
+        };
+
+        // the root, then the first member after reading the root.
+        let mut body = dav.handle(propfind()).await.into_body();
+        body.next().await.unwrap().unwrap();
+        body.next().await.unwrap().unwrap();
+        assert_eq!(read_dirs.load(Ordering::SeqCst), 1);
+
+        // a client that goes away drops the body, and the rest is not read.
+        drop(body);
+        tokio::task::yield_now().await;
+        assert_eq!(read_dirs.load(Ordering::SeqCst), 1);
+
+        // a cancelled request ends the body with an error.
+        read_dirs.store(0, Ordering::SeqCst);
+        let token = CancelToken::new();
+        let config = DavConfig::new().cancel(token.clone());
+        let mut body = dav.handle_with(config, propfind()).await.into_body();
+        body.next().await.unwrap().unwrap();
+        body.next().await.unwrap().unwrap();
+        token.cancel();
+        let err = body.next().await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(body.next().await.is_none());
+        assert_eq!(read_dirs.load(Ordering::SeqCst), 1);
+
+        // without cancelling, all ten collections are read.
+        read_dirs.store(0, Ordering::SeqCst);
+        body_text(dav.handle(propfind()).await).await;
+        assert_eq!(read_dirs.load(Ordering::SeqCst), 11);
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_posix_props() {
+        use crate::memfs::MemFs;
+        use crate::testfs::{body_text, request, FsHooks, HookFs};
+        use crate::DavHandler;
+
+        // MemFs that reports an owner, group and mode.
+        #[derive(Clone)]
+        struct Posix;
+
+        #[derive(Debug, Clone)]
+        struct PosixMeta(Box<dyn DavMetaData>);
+
+        impl DavMetaData for PosixMeta {
+            fn len(&self) -> u64 {
+                self.0.len()
+            }
+            fn modified(&self) -> FsResult<std::time::SystemTime> {
+                self.0.modified()
+            }
+            fn is_dir(&self) -> bool {
+                self.0.is_dir()
+            }
+            fn uid(&self) -> Option<u32> {
+                Some(1000)
+            }
+            fn gid(&self) -> Option<u32> {
+                Some(100)
+            }
+            fn mode(&self) -> Option<u32> {
+                Some(0o640)
+            }
+        }
+
+        impl FsHooks for Posix {
+            fn metadata<'a>(
+                &'a self,
+                fs: &'a MemFs,
+                path: &'a DavPath,
+            ) -> FsFuture<'a, Box<dyn DavMetaData>> {
+                Box::pin(async move {
+                    let meta = fs.metadata(path).await?;
+                    Ok(Box::new(PosixMeta(meta)) as Box<dyn DavMetaData>)
+                })
+            }
+        }
+
+        let request = |dav, method, body: &'static str| async move {
+            let resp = request(dav, method, "/file", &[("Depth", "0")], body).await;
+            body_text(resp).await
+        };
+        // status and value of each property in the response.
+        let props = |body: String| {
+            let tree = Element::parse(body.as_bytes()).unwrap();
+            let mut props = Vec::new();
+            let response = tree.get_child("response").unwrap();
+            for propstat in response.child_elems_iter() {
+                if propstat.name != "propstat" {
+                    continue;
+                }
+                let status = propstat.get_child("status").unwrap().get_text().unwrap();
+                let status = status.split(' ').nth(1).unwrap().to_string();
+                for p in propstat.get_child("prop").unwrap().child_elems_iter() {
+                    if p.namespace.as_deref() == Some(NS_POSIX_URI) {
+                        let value = p.get_text().map(|t| t.to_string()).unwrap_or_default();
+                        props.push((p.name.clone(), status.clone(), value));
+                    }
+                }
+            }
+            props.sort();
+            props
+        };
+        let prop = |name: &str, status: &str, value: &str| {
+            (name.to_string(), status.to_string(), value.to_string())
+        };
+        const PROPFIND: &str = r#"<D:propfind xmlns:D="DAV:" xmlns:P="urn:dav-server:posix">
+            <D:prop><P:uid/><P:gid/><P:mode/></D:prop></D:propfind>"#;
+        const ALLPROP: &str = r#"<D:propfind xmlns:D="DAV:"><D:allprop/></D:propfind>"#;
+
+        let memfs = MemFs::new();
+        let plain = DavHandler::builder()
+            .filesystem(memfs.clone())
+            .build_handler();
+        let posix = DavHandler::builder()
+            .filesystem(HookFs::with_fs(memfs, Posix))
+            .build_handler();
+        request(&plain, "PUT", "data").await;
+
+        let body = request(&posix, "PROPFIND", PROPFIND).await;
+        assert_eq!(
+            props(body),
+            [
+                prop("gid", "200", "100"),
+                prop("mode", "200", "0640"),
+                prop("uid", "200", "1000"),
+            ]
+        );
+
+        // not with allprop.
+        let body = request(&posix, "PROPFIND", ALLPROP).await;
+        assert!(props(body).is_empty());
+
+        // a backend that does not have them.
+        let body = request(&plain, "PROPFIND", PROPFIND).await;
+        assert_eq!(
+            props(body),
+            [
+                prop("gid", "404", ""),
+                prop("mode", "404", ""),
+                prop("uid", "404", ""),
+            ]
+        );
+
+        // they are read-only.
+        let body = request(
+            &posix,
+            "PROPPATCH",
+            r#"<D:propertyupdate xmlns:D="DAV:" xmlns:P="urn:dav-server:posix">
+              <D:set><D:prop><P:mode>0777</P:mode></D:prop></D:set>
+            </D:propertyupdate>"#,
+        )
+        .await;
+        assert_eq!(props(body), [prop("mode", "403", "")]);
+    }
+}
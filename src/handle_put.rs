@@ -1,6 +1,7 @@
 use std::any::Any;
 use std::error::Error as StdError;
 use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use bytes::{Buf, Bytes};
 use headers::HeaderMapExt;
@@ -11,11 +12,25 @@ use http_body::Body as HttpBody;
 use crate::body::Body;
 use crate::conditional::if_match_get_tokens;
 use crate::davheaders;
+use crate::davpath::DavPath;
 use crate::fs::*;
+use crate::ls::DavLock;
+use crate::util::{dav_xml_error, lock_condition, write_status};
 use crate::{DavError, DavResult};
 
 const SABRE: &str = "application/x-sabredav-partialupdate";
 
+// RFC 6352, 6.3.2.1 precondition, and the largest vCard that is checked.
+const VALID_ADDRESS_DATA: &str =
+    r#"<C:valid-address-data xmlns:C="urn:ietf:params:xml:ns:carddav"/>"#;
+const MAX_ADDRESS_DATA: usize = 1024 * 1024;
+
+// Largest body that is written with DavFileSystem::write_if_etag.
+const MAX_WRITE_IF_ETAG: usize = 1024 * 1024;
+
+// Warn only once that write_if_etag is not there.
+static NO_WRITE_IF_ETAG: AtomicBool = AtomicBool::new(false);
+
 // This is a nice hack. If the type 'E' is actually an io::Error or a Box<io::Error>,
 // convert it back into a real io::Error. If it is a DavError or a Box<DavError>,
 // use its Into<io::Error> impl. Otherwise just wrap the error in io::Error::new.
@@ -52,7 +67,44 @@ where
     }
 }
 
+// file extension for a new member created by an Add-Member POST.
+fn member_extension(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+    let ext = match mime.to_ascii_lowercase().as_str() {
+        "text/vcard" | "text/x-vcard" => "vcf",
+        "text/calendar" => "ics",
+        "text/plain" => "txt",
+        "text/html" => "html",
+        "application/json" => "json",
+        "application/xml" | "text/xml" => "xml",
+        _ => return None,
+    };
+    Some(ext)
+}
+
+// The etag of an If-Match header with a single strong etag.
+fn if_match_etag(req: &Request<()>) -> Option<String> {
+    match req.headers().typed_get::<davheaders::IfMatch>()?.0 {
+        davheaders::ETagList::Tags(ref tags) if tags.len() == 1 && !tags[0].is_weak() => {
+            Some(tags[0].value().to_string())
+        }
+        _ => None,
+    }
+}
+
 impl crate::DavInner {
+    // 423 Locked. The request body has not been read, so the
+    // connection is closed.
+    fn locked(&self, req: &Request<()>, lock: &DavLock) -> Response<Body> {
+        let body = lock_condition("lock-token-submitted", lock);
+        let mut res = Response::new(dav_xml_error(&body));
+        res.headers_mut().typed_insert(headers::Connection::close());
+        let ct = self.xml_type(req);
+        res.headers_mut().typed_insert(davheaders::ContentType(ct));
+        *res.status_mut() = SC::LOCKED;
+        res
+    }
+
     pub(crate) async fn handle_put<ReqBody, ReqData, ReqError>(
         self,
         req: &Request<()>,
@@ -101,6 +153,17 @@ impl crate::DavInner {
         let mut res = Response::new(Body::empty());
         res.headers_mut().typed_insert(headers::Connection::close());
 
+        // a collection cannot be overwritten with a file.
+        if matches!(meta, Ok(ref m) if m.is_dir()) {
+            debug!("PUT/PATCH to collection {}", path);
+            let options = self.handle_options(req).await?;
+            if let Some(allow) = options.headers().get("allow") {
+                res.headers_mut().insert("allow", allow.clone());
+            }
+            *res.status_mut() = SC::METHOD_NOT_ALLOWED;
+            return Ok(res);
+        }
+
         // SabreDAV style PATCH?
         if req.method() == http::Method::PATCH {
             if !req
@@ -179,8 +242,8 @@ impl crate::DavInner {
         if let Some(ref locksystem) = self.ls {
             let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
             let principal = self.principal.as_deref();
-            if let Err(_l) = locksystem.check(&path, principal, false, false, t) {
-                return Err(DavError::StatusClose(SC::LOCKED));
+            if let Err(l) = locksystem.check(&path, principal, false, false, t) {
+                return Ok(self.locked(req, &l));
             }
         }
 
@@ -200,9 +263,91 @@ impl crate::DavInner {
             oo.create_new = true;
         }
 
+        // An address book only takes valid vCards. The body has to be
+        // checked before anything is written, so it is read into memory.
+        pin_utils::pin_mut!(body);
+        let mut address_data = None;
+        if let Some(ref validator) = self.address_data_validator {
+            if self.in_address_book(&path).await {
+                let ct = req.headers().typed_get::<davheaders::ContentType>();
+                if matches!(ct, Some(ref ct) if member_extension(&ct.0) != Some("vcf")) {
+                    return Err(DavError::StatusClose(SC::UNSUPPORTED_MEDIA_TYPE));
+                }
+                if have_count && count > MAX_ADDRESS_DATA as u64 {
+                    return Err(DavError::StatusClose(SC::PAYLOAD_TOO_LARGE));
+                }
+                // a partial update cannot be checked.
+                let data = if do_range {
+                    None
+                } else {
+                    Some(self.read_request(body.as_mut(), MAX_ADDRESS_DATA).await?)
+                };
+                match data {
+                    Some(data) if validator(&data) => address_data = Some(Bytes::from(data)),
+                    _ => {
+                        debug!("PUT {}: invalid address data", path);
+                        let ct = self.xml_type(req);
+                        res.headers_mut().typed_insert(davheaders::ContentType(ct));
+                        *res.status_mut() = SC::FORBIDDEN;
+                        *res.body_mut() = dav_xml_error(VALID_ADDRESS_DATA);
+                        return Ok(res);
+                    }
+                }
+            }
+        }
+
+        // If-Match with one etag: the filesystem can check it and write
+        // in one atomic step, so that nothing can change in between.
+        let small = have_count && count <= MAX_WRITE_IF_ETAG as u64;
+        if let (false, Some(etag)) = (do_range, if_match_etag(req)) {
+            let data = match address_data.take() {
+                Some(data) => Some(data),
+                None if small => {
+                    let data = self.read_request(body.as_mut(), MAX_WRITE_IF_ETAG).await?;
+                    if data.len() as u64 != count {
+                        error!("PUT file: body length does not match Content-Length");
+                        return Err(DavError::StatusClose(SC::BAD_REQUEST));
+                    }
+                    Some(Bytes::from(data))
+                }
+                None => None,
+            };
+            if let Some(data) = data {
+                match self.fs.write_if_etag(&path, &etag, data.clone()).await {
+                    Ok(etag) => {
+                        res.headers_mut().remove(http::header::CONNECTION);
+                        res.headers_mut()
+                            .typed_insert(headers::AcceptRanges::bytes());
+                        if let Ok(etag) = davheaders::ETag::new(false, etag) {
+                            res.headers_mut().typed_insert(etag);
+                        }
+                        if let Ok(modified) =
+                            self.fs.metadata(&path).await.and_then(|m| m.modified())
+                        {
+                            res.headers_mut()
+                                .typed_insert(headers::LastModified::from(modified));
+                        }
+                        *res.status_mut() = write_status(true);
+                        return Ok(res);
+                    }
+                    Err(FsError::PreconditionFailed) | Err(FsError::NotFound) => {
+                        debug!("PUT {}: etag changed", path);
+                        return Err(DavError::StatusClose(SC::PRECONDITION_FAILED));
+                    }
+                    Err(FsError::NotImplemented) => {
+                        if !NO_WRITE_IF_ETAG.swap(true, Ordering::Relaxed) {
+                            warn!("PUT with If-Match: no DavFileSystem::write_if_etag, the write is not atomic");
+                        }
+                        address_data = Some(data);
+                    }
+                    Err(e) => return Err(DavError::FsError(e)),
+                }
+            }
+        }
+
         let create = oo.create;
         let create_new = oo.create_new;
-        let mut file = match self.fs.open(&path, oo).await {
+        let mut file = match self.fs.open_mode(&path, oo, self.file_mode).await {
             Ok(f) => f,
             Err(FsError::NotFound) | Err(FsError::Exists) => {
                 let s = if !create || create_new {
@@ -225,17 +370,171 @@ impl crate::DavInner {
         res.headers_mut()
             .typed_insert(headers::AcceptRanges::bytes());
 
+        let limit = if have_count { Some(count) } else { None };
+        let total = match address_data {
+            Some(data) => {
+                let mut data = http_body::Full::new(data);
+                self.write_body(&mut file, &mut data, limit).await?
+            }
+            None => self.write_body(&mut file, &mut body, limit).await?,
+        };
+
+        if have_count && total > count {
+            error!("PUT file: sender is sending more bytes than expected");
+            return Err(DavError::StatusClose(SC::BAD_REQUEST));
+        }
+
+        if have_count && total < count {
+            error!("PUT file: premature EOF on input");
+            return Err(DavError::StatusClose(SC::BAD_REQUEST));
+        }
+
+        // Report whether we created or updated the file.
+        if meta.is_err() {
+            res.headers_mut().typed_insert(headers::ContentLength(0));
+        }
+        *res.status_mut() = write_status(meta.is_ok());
+
+        // no errors, connection may be kept open.
+        res.headers_mut().remove(http::header::CONNECTION);
+
+        if let Ok(m) = file.metadata().await {
+            if let Some(etag) = davheaders::ETag::from_meta(&m) {
+                res.headers_mut().typed_insert(etag);
+            }
+            if let Ok(modified) = m.modified() {
+                res.headers_mut()
+                    .typed_insert(headers::LastModified::from(modified));
+            }
+        }
+        Ok(res)
+    }
+
+    pub(crate) async fn handle_post<ReqBody, ReqData, ReqError>(
+        self,
+        req: &Request<()>,
+        body: ReqBody,
+    ) -> DavResult<Response<Body>>
+    where
+        ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+    {
+        // The only POST we know about is the RFC 5995 Add-Member POST
+        // to a collection.
+        let path = self.path(req);
+        let meta = self.fs.metadata(&path).await;
+        if !self.add_member.unwrap_or(false) || !matches!(meta, Ok(ref m) if m.is_dir()) {
+            return Err(DavError::StatusClose(SC::METHOD_NOT_ALLOWED));
+        }
+
+        let count = req
+            .headers()
+            .typed_get::<headers::ContentLength>()
+            .map(|n| n.0);
+
+        // check the If and If-* headers.
+        let tokens = if_match_get_tokens(req, meta.as_ref().ok(), &self.fs, &self.ls, &path);
+        let tokens = match tokens.await {
+            Ok(t) => t,
+            Err(s) => return Err(DavError::StatusClose(s)),
+        };
+
+        // choose a name for the new member. Add an extension that
+        // matches the Content-Type, if we know one.
+        let mut name = uuid::Uuid::new_v4().hyphenated().to_string();
+        if let Some(ext) = req
+            .headers()
+            .typed_get::<davheaders::ContentType>()
+            .and_then(|ct| member_extension(&ct.0))
+        {
+            name.push('.');
+            name.push_str(ext);
+        }
+        let mut newpath = path.clone();
+        newpath.push_segment(name.as_bytes());
+
+        // if locked check if we hold that lock.
+        if let Some(ref locksystem) = self.ls {
+            let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+            let principal = self.principal.as_deref();
+            if let Err(l) = locksystem.check(&newpath, principal, false, false, t) {
+                return Ok(self.locked(req, &l));
+            }
+        }
+
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        oo.create_new = true;
+        oo.size = count;
+        let mut file = match self.fs.open_mode(&newpath, oo, self.file_mode).await {
+            Ok(f) => f,
+            Err(FsError::Exists) => return Err(DavError::StatusClose(SC::CONFLICT)),
+            Err(e) => return Err(DavError::FsError(e)),
+        };
+
         pin_utils::pin_mut!(body);
+        let total = self.write_body(&mut file, &mut body, count).await?;
+        if matches!(count, Some(c) if total != c) {
+            error!("POST add-member: body length does not match Content-Length");
+            let _ = self.fs.remove_file(&newpath).await;
+            return Err(DavError::StatusClose(SC::BAD_REQUEST));
+        }
 
-        // loop, read body, write to file.
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = SC::CREATED;
+        res.headers_mut().typed_insert(headers::ContentLength(0));
+        let location = self.location(req, &newpath);
+        let location = http::HeaderValue::from_str(&location)
+            .map_err(|_| DavError::Status(SC::INTERNAL_SERVER_ERROR))?;
+        res.headers_mut().insert(http::header::LOCATION, location);
+        if let Ok(m) = file.metadata().await {
+            if let Some(etag) = davheaders::ETag::from_meta(&m) {
+                res.headers_mut().typed_insert(etag);
+            }
+            if let Ok(modified) = m.modified() {
+                res.headers_mut()
+                    .typed_insert(headers::LastModified::from(modified));
+            }
+        }
+        Ok(res)
+    }
+
+    // Is `path` a member of an address book.
+    #[cfg(feature = "carddav")]
+    async fn in_address_book(&self, path: &DavPath) -> bool {
+        match self.fs.metadata(&path.parent()).await {
+            Ok(meta) => meta.is_addrbook().unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    #[cfg(not(feature = "carddav"))]
+    async fn in_address_book(&self, _path: &DavPath) -> bool {
+        false
+    }
+
+    // loop, read body, write to file. Stops as soon as more than
+    // `limit` bytes have been read. Returns the number of bytes read.
+    async fn write_body<B, ReqData, ReqError>(
+        &self,
+        file: &mut Box<dyn DavFile>,
+        body: &mut B,
+        limit: Option<u64>,
+    ) -> DavResult<u64>
+    where
+        B: HttpBody<Data = ReqData, Error = ReqError> + Unpin,
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+    {
         let mut total = 0u64;
 
-        while let Some(data) = body.data().await {
+        while let Some(data) = self.read_chunk(body).await? {
             let mut buf = data.map_err(|e| to_ioerror(e))?;
             let buflen = buf.remaining();
             total += buflen as u64;
             // consistency check.
-            if have_count && total > count {
+            if matches!(limit, Some(l) if total > l) {
                 break;
             }
             // The `Buf` might actually be a `Bytes`.
@@ -251,38 +550,213 @@ impl crate::DavInner {
             }
         }
         file.flush().await?;
+        if self.fsync_on_put.unwrap_or(false) {
+            file.sync_all().await?;
+        }
+        Ok(total)
+    }
+}
 
-        if have_count && total > count {
-            error!("PUT file: sender is sending more bytes than expected");
-            return Err(DavError::StatusClose(SC::BAD_REQUEST));
+#[cfg(all(test, feature = "memfs"))]
+mod tests {
+    use crate::memfs::MemFs;
+    use crate::testfs::{body_text, memfs_handler, request};
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn test_put_etag() {
+        let dav = memfs_handler();
+        for (body, status) in &[("hello", 201), ("hello, world", 204)] {
+            let put = request(&dav, "PUT", "/file", &[], *body).await;
+            assert_eq!(put.status(), *status);
+            let head = request(&dav, "HEAD", "/file", &[], "").await;
+            for name in &["etag", "last-modified"] {
+                assert!(put.headers().contains_key(*name), "{}", name);
+                assert_eq!(put.headers()[*name], head.headers()[*name], "{}", name);
+            }
         }
+    }
 
-        if have_count && total < count {
-            error!("PUT file: premature EOF on input");
-            return Err(DavError::StatusClose(SC::BAD_REQUEST));
+    #[tokio::test]
+    async fn test_put_if_unmodified_since() {
+        // a resource that does not exist has no modification date, so
+        // If-Unmodified-Since is ignored and the PUT creates it.
+        let dav = memfs_handler();
+        let date = &[("If-Unmodified-Since", "Mon, 12 Jan 1970 13:46:40 GMT")];
+        let put = request(&dav, "PUT", "/file", date, "hello").await;
+        assert_eq!(put.status(), 201);
+        // once it exists, the date is compared.
+        let put = request(&dav, "PUT", "/file", date, "hello").await;
+        assert_eq!(put.status(), 412);
+    }
+
+    #[tokio::test]
+    async fn test_put_collection() {
+        let dav = memfs_handler();
+        assert_eq!(request(&dav, "MKCOL", "/dir", &[], "").await.status(), 201);
+        for uri in &["/dir", "/dir/"] {
+            let resp = request(&dav, "PUT", uri, &[], "hello").await;
+            assert_eq!(resp.status(), 405, "{}", uri);
+            let allow = resp.headers()["allow"].to_str().unwrap();
+            assert!(
+                allow.contains("PROPFIND") && !allow.contains("PUT"),
+                "{}",
+                allow
+            );
         }
+    }
 
-        // Report whether we created or updated the file.
-        *res.status_mut() = match meta {
-            Ok(_) => SC::NO_CONTENT,
-            Err(_) => {
-                res.headers_mut().typed_insert(headers::ContentLength(0));
-                SC::CREATED
-            }
+    #[cfg(all(unix, feature = "localfs"))]
+    #[tokio::test]
+    async fn test_create_mode() {
+        use crate::localfs::LocalFs;
+        use crate::testfs::TempDir;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("mode");
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&*dir, true, false, false))
+            .file_mode(0o600)
+            .dir_mode(0o700)
+            .build_handler();
+        for (method, uri) in &[("PUT", "/file"), ("MKCOL", "/dir")] {
+            let resp = request(&dav, method, uri, &[], "").await;
+            assert_eq!(resp.status(), 201);
+        }
+        let mode = |name: &str| {
+            let meta = std::fs::metadata(dir.join(name)).unwrap();
+            meta.permissions().mode() & 0o777
         };
+        assert_eq!(mode("file"), 0o600);
+        assert_eq!(mode("dir"), 0o700);
+    }
 
-        // no errors, connection may be kept open.
-        res.headers_mut().remove(http::header::CONNECTION);
+    #[cfg(feature = "localfs")]
+    #[tokio::test]
+    async fn test_fsync_on_put() {
+        use crate::localfs::LocalFs;
+        use crate::testfs::TempDir;
 
-        if let Ok(m) = file.metadata().await {
-            if let Some(etag) = davheaders::ETag::from_meta(&m) {
-                res.headers_mut().typed_insert(etag);
+        let dir = TempDir::new("fsync");
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&*dir, true, false, false))
+            .fsync_on_put(true)
+            .build_handler();
+        let resp = request(&dav, "PUT", "/file", &[], "hello").await;
+        assert_eq!(resp.status(), 201);
+        assert_eq!(std::fs::read(dir.join("file")).unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_strict() {
+        for strict in &[false, true] {
+            let dav = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .strict(*strict)
+                .build_handler();
+            let hdrs = [("If-Match", "\"abc\""), ("If-None-Match", "*")];
+            let status = request(&dav, "PUT", "/file", &hdrs, "hello").await.status();
+            assert_eq!(status, if *strict { 400 } else { 412 });
+        }
+    }
+
+    #[cfg(feature = "carddav")]
+    #[tokio::test]
+    async fn test_address_data_validator() {
+        use crate::testfs::{AddressBook, HookFs};
+
+        let dav = DavHandler::builder()
+            .filesystem(HookFs::new(AddressBook))
+            .address_data_validator(|data| {
+                let data = String::from_utf8_lossy(data);
+                let data = data.trim();
+                data.starts_with("BEGIN:VCARD") && data.ends_with("END:VCARD")
+            })
+            .build_handler();
+        let dav = &dav;
+        let request = |method, uri, ct: &'static str, body: &'static str| async move {
+            let hdrs = [("Content-Type", ct)];
+            let n = if ct.is_empty() { 0 } else { 1 };
+            request(dav, method, uri, &hdrs[..n], body).await
+        };
+        const VCARD: &str = "BEGIN:VCARD\r\nVERSION:4.0\r\nFN:Alice\r\nEND:VCARD\r\n";
+
+        assert_eq!(request("MKCOL", "/book", "", "").await.status(), 201);
+        assert_eq!(request("MKCOL", "/other", "", "").await.status(), 201);
+        let resp = request("PUT", "/book/a.vcf", "text/vcard", VCARD).await;
+        assert_eq!(resp.status(), 201);
+        assert_eq!(request("PUT", "/book/b.vcf", "", VCARD).await.status(), 201);
+        let resp = request("GET", "/book/a.vcf", "", "").await;
+        assert_eq!(body_text(resp).await, VCARD);
+
+        let resp = request("PUT", "/book/c.vcf", "text/vcard", "garbage").await;
+        assert_eq!(resp.status(), 403);
+        assert!(body_text(resp).await.contains("valid-address-data"));
+        assert_eq!(request("GET", "/book/c.vcf", "", "").await.status(), 404);
+        let resp = request("PUT", "/book/d.vcf", "text/plain", VCARD).await;
+        assert_eq!(resp.status(), 415);
+        assert_eq!(
+            request("PUT", "/other/e", "", "garbage").await.status(),
+            201
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_if_etag() {
+        use bytes::Bytes;
+
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::testfs::{FsHooks, HookFs};
+
+        // MemFs where someone else writes the file right before
+        // write_if_etag, after the If-Match header was checked.
+        #[derive(Clone)]
+        struct Racy;
+
+        impl FsHooks for Racy {
+            fn write_if_etag<'a>(
+                &'a self,
+                fs: &'a MemFs,
+                path: &'a DavPath,
+                etag: &'a str,
+                data: Bytes,
+            ) -> FsFuture<'a, String> {
+                Box::pin(async move {
+                    let meta = fs.metadata(path).await?;
+                    let theirs = Bytes::from("theirs, and longer");
+                    let current = meta.etag().unwrap();
+                    fs.write_if_etag(path, &current, theirs).await?;
+                    fs.write_if_etag(path, etag, data).await
+                })
             }
-            if let Ok(modified) = m.modified() {
-                res.headers_mut()
-                    .typed_insert(headers::LastModified::from(modified));
+        }
+
+        let mem = MemFs::new();
+        for racy in &[false, true] {
+            let dav = DavHandler::builder();
+            let dav = match racy {
+                true => dav.filesystem(HookFs::with_fs(mem.clone(), Racy)),
+                false => dav.filesystem(mem.clone()),
+            };
+            let dav = dav.build_handler();
+            let resp = request(&dav, "PUT", "/file", &[], "hello").await;
+            let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+            let hdrs = [("If-Match", etag.as_str()), ("Content-Length", "12")];
+            let resp = request(&dav, "PUT", "/file", &hdrs, "hello, world").await;
+            let get = request(&dav, "GET", "/file", &[], "").await;
+            let get_etag = get.headers()["etag"].clone();
+            let body = body_text(get).await;
+            if *racy {
+                // the other write wins.
+                assert_eq!(resp.status(), 412);
+                assert_eq!(body, "theirs, and longer");
+            } else {
+                assert_eq!(resp.status(), 204);
+                assert_eq!(resp.headers()["etag"], get_etag);
+                assert_ne!(resp.headers()["etag"], etag.as_str());
+                assert_eq!(body, "hello, world");
             }
         }
-        Ok(res)
     }
 }
@@ -1,10 +1,17 @@
 use std::io::Cursor;
 
+#[cfg(feature = "caldav")]
+use futures_util::{future::BoxFuture, FutureExt, StreamExt};
 use headers::HeaderMapExt;
 use http::{Request, Response, StatusCode};
 
 use crate::davpath::DavPath;
-use crate::handle_props::PropWriter;
+use crate::fs::PropertySearch;
+#[cfg(feature = "caldav")]
+use crate::fs::{DavMetaData, ReadDirMeta};
+use crate::handle_props::{element_to_davprop, PropWriter};
+#[cfg(feature = "caldav")]
+use crate::ical::{self, CompFilter, PropFilter, TextMatch};
 use crate::xmltree_ext::*;
 use xmltree::Element;
 
@@ -12,7 +19,7 @@ use crate::async_stream::AsyncStream;
 use crate::body::Body;
 use crate::davheaders;
 use crate::errors::*;
-use crate::util::dav_xml_error;
+use crate::util::{dav_xml_error, get_depth, is_empty_body};
 use crate::{DavInner, DavResult};
 
 impl DavInner {
@@ -30,10 +37,47 @@ impl DavInner {
             .typed_insert(headers::CacheControl::new().with_no_cache());
         res.headers_mut().typed_insert(headers::Pragma::no_cache());
 
-        let depth = match req.headers().typed_get::<davheaders::Depth>() {
+        // path and meta
+        let mut path = self.path(req);
+
+        // Unlike PROPFIND, a REPORT without a body is meaningless: the
+        // root element is what says which report is asked for.
+        if is_empty_body(xmldata) {
+            debug!("report: empty body");
+            return Err(DavError::XmlParseError);
+        }
+        trace!("{}", String::from_utf8_lossy(xmldata));
+        let root = match Element::parse(Cursor::new(xmldata)) {
+            Ok(t) => {
+                if t.name == "principal-property-search" && t.namespace.as_deref() == Some("DAV:") {
+                    return self.principal_property_search(req, res, t).await;
+                }
+                #[cfg(feature = "caldav")]
+                if t.name == "calendar-query" && t.namespace.as_deref() == Some(NS_CALDAV_URI) {
+                    return self.calendar_query(req, res, t).await;
+                }
+                // For now, Just supporting addressbook-multiget
+                if t.name == "addressbook-multiget"
+                    && t.namespace.as_deref() == Some("urn:ietf:params:xml:ns:carddav")
+                {
+                    t
+                } else {
+                    // a report we do not know, like CalDAV's free-busy-query.
+                    debug!("report: unsupported report {}", t.name);
+                    let ct = self.xml_type(req);
+                    res.headers_mut().typed_insert(davheaders::ContentType(ct));
+                    *res.status_mut() = StatusCode::FORBIDDEN;
+                    *res.body_mut() = dav_xml_error("<D:supported-report/>");
+                    return Ok(res);
+                }
+            }
+            Err(_) => return Err(DavError::XmlParseError),
+        };
+
+        let depth = match get_depth(req.headers(), self.lenient_depth.unwrap_or(false))? {
             Some(davheaders::Depth::Infinity) | None => {
                 if req.headers().typed_get::<davheaders::XLitmus>().is_none() {
-                    let ct = "application/xml; charset=utf-8".to_owned();
+                    let ct = self.xml_type(req);
                     res.headers_mut().typed_insert(davheaders::ContentType(ct));
                     *res.status_mut() = StatusCode::FORBIDDEN;
                     *res.body_mut() = dav_xml_error("<D:propfind-finite-depth/>");
@@ -44,58 +88,37 @@ impl DavInner {
             Some(d) => d,
         };
 
-        // path and meta
-        let mut path = self.path(req);
-
-        let mut root = None;
-        if !xmldata.is_empty() {
-            trace!("{}", String::from_utf8(xmldata.to_vec()).unwrap());
-            root = match Element::parse(Cursor::new(xmldata)) {
-                Ok(t) => {
-                    // For now, Just supporting addressbook-multiget 
-                    if t.name == "addressbook-multiget" && t.namespace.as_deref() == Some("urn:ietf:params:xml:ns:carddav") {
-                        Some(t)
-                    } else {
-                        return Err(DavError::XmlParseError);
-                    }
-                }
-                Err(_) => return Err(DavError::XmlParseError),
-            };
-        }
-
-        let (name, props) = match root.clone() {
-            None => ("allprop", Vec::new()),
-            Some(elem) => {
-                match elem
-                    .child_elems_into_iter()
-                    .find(|e| e.name == "prop")
-                {
-                    Some(elem) => match elem.name.as_str() {
-                        "prop" => ("prop", elem.take_child_elems()),
-                        _ => return Err(DavError::XmlParseError),
-                    },
-                    None => return Err(DavError::XmlParseError),
-                }
-            }
+        let props = match root.get_child("prop") {
+            Some(elem) => elem.clone().take_child_elems(),
+            None => return Err(DavError::XmlParseError),
         };
+        let name = "prop";
 
-        let list_href = match root {
-            None => Vec::new(),
-            Some(elem) => elem
-                .take_child_elems()
-                .into_iter()
-                .filter(|e| e.name == "href")
-                .collect()
-        };
+        let list_href: Vec<_> = root
+            .take_child_elems()
+            .into_iter()
+            .filter(|e| e.name == "href")
+            .collect();
 
         trace!("report: type request: {}", name);
 
-        let mut pw = PropWriter::new(req, &mut res, name, props, &self.fs, self.ls.as_ref())?;
+        let ct = self.xml_type(req);
+        res.headers_mut().typed_insert(davheaders::ContentType(ct));
+        let mut pw = PropWriter::new(
+            req,
+            &mut res,
+            name,
+            props,
+            &self.fs,
+            self.ls.as_ref(),
+            self.mime_types.as_ref(),
+        )?;
 
         *res.body_mut() = Body::from(AsyncStream::new(|tx| async move {
             pw.set_tx(tx);
             if depth != davheaders::Depth::Zero {
                 for e in list_href.iter() {
+                    self.check_cancel()?;
                     let url = e.get_text().unwrap().into_owned();
                     let url = DavPath::from_str_and_prefix(&url, "")
                         .map_err(|_| DavError::InvalidPath)?;
@@ -117,4 +140,486 @@ impl DavInner {
         
         Ok(res)
     }    
-}
\ No newline at end of file
+
+    // RFC3744 9.4, search for principals by property value. The search
+    // itself is done by the principal_search callback.
+    async fn principal_property_search(
+        self,
+        req: &Request<()>,
+        mut res: Response<Body>,
+        root: Element,
+    ) -> DavResult<Response<Body>> {
+        let principal_search = match self.principal_search.clone() {
+            Some(f) => f,
+            None => {
+                let ct = self.xml_type(req);
+                res.headers_mut().typed_insert(davheaders::ContentType(ct));
+                *res.status_mut() = StatusCode::FORBIDDEN;
+                *res.body_mut() = dav_xml_error("<D:supported-report/>");
+                return Ok(res);
+            }
+        };
+
+        let any_of = root.attributes.get("test").map(|s| s.as_str()) == Some("anyof");
+        let mut terms = Vec::new();
+        let mut props = Vec::new();
+        for elem in root.child_elems_into_iter() {
+            match elem.name.as_str() {
+                "property-search" => {
+                    let mut term = PropertySearch {
+                        props: Vec::new(),
+                        text: String::new(),
+                    };
+                    for e in elem.child_elems_into_iter() {
+                        match e.name.as_str() {
+                            "prop" => {
+                                term.props = e.child_elems_iter().map(element_to_davprop).collect()
+                            }
+                            "match" => term.text = e.get_text().unwrap_or_default().into_owned(),
+                            _ => {}
+                        }
+                    }
+                    if term.props.is_empty() {
+                        return Err(DavError::XmlParseError);
+                    }
+                    terms.push(term);
+                }
+                "prop" => props = elem.take_child_elems(),
+                // apply-to-principal-collection-set: the callback knows
+                // where the principals are.
+                _ => {}
+            }
+        }
+        if terms.is_empty() {
+            return Err(DavError::XmlParseError);
+        }
+        let paths = principal_search(&terms, any_of);
+
+        let ct = self.xml_type(req);
+        res.headers_mut().typed_insert(davheaders::ContentType(ct));
+        let mut pw = PropWriter::new(
+            req,
+            &mut res,
+            "prop",
+            props,
+            &self.fs,
+            self.ls.as_ref(),
+            self.mime_types.as_ref(),
+        )?;
+
+        *res.body_mut() = Body::from(AsyncStream::new(|tx| async move {
+            pw.set_tx(tx);
+            for path in &paths {
+                self.check_cancel()?;
+                match self.fs.metadata(path).await {
+                    Ok(meta) => pw.write_props(path, meta).await?,
+                    Err(_) => pw.write_status(path, StatusCode::NOT_FOUND)?,
+                }
+                pw.flush().await?;
+            }
+            pw.close().await?;
+            Ok(())
+        }));
+
+        Ok(res)
+    }
+
+    // RFC4791 7.8, calendar-query. Every calendar object resource within
+    // Depth is read and parsed, and returned if it matches the filter.
+    #[cfg(feature = "caldav")]
+    async fn calendar_query(
+        self,
+        req: &Request<()>,
+        mut res: Response<Body>,
+        root: Element,
+    ) -> DavResult<Response<Body>> {
+        let filter = match parse_filter(root.get_child("filter")) {
+            Ok(filter) => filter,
+            Err(cond) => {
+                debug!("calendar-query: {}", cond);
+                let ct = self.xml_type(req);
+                res.headers_mut().typed_insert(davheaders::ContentType(ct));
+                *res.status_mut() = StatusCode::FORBIDDEN;
+                *res.body_mut() =
+                    dav_xml_error(&format!(r#"<C:{} xmlns:C="{}"/>"#, cond, NS_CALDAV_URI));
+                return Ok(res);
+            }
+        };
+
+        // No Depth header means Depth: 0 for this report.
+        let depth = get_depth(req.headers(), self.lenient_depth.unwrap_or(false))?
+            .unwrap_or(davheaders::Depth::Zero);
+
+        let mut path = self.path(req);
+        let meta = self.fs.metadata(&path).await?;
+        let meta = self.fixpath(&mut res, &mut path, meta);
+
+        let (name, props) = match root
+            .child_elems_into_iter()
+            .find(|e| e.name == "propname" || e.name == "prop" || e.name == "allprop")
+        {
+            Some(elem) if elem.name == "prop" => ("prop", elem.take_child_elems()),
+            Some(elem) if elem.name == "propname" => ("propname", Vec::new()),
+            _ => ("allprop", Vec::new()),
+        };
+
+        let ct = self.xml_type(req);
+        res.headers_mut().typed_insert(davheaders::ContentType(ct));
+        let mut pw = PropWriter::new(
+            req,
+            &mut res,
+            name,
+            props,
+            &self.fs,
+            self.ls.as_ref(),
+            self.mime_types.as_ref(),
+        )?;
+
+        *res.body_mut() = Body::from(AsyncStream::new(|tx| async move {
+            pw.set_tx(tx);
+            if !meta.is_dir() {
+                self.calendar_query_file(&path, meta, &filter, &mut pw)
+                    .await?;
+            } else if depth != davheaders::Depth::Zero {
+                self.calendar_query_directory(&path, depth, &filter, &mut pw)
+                    .await?;
+            }
+            pw.close().await?;
+            Ok(())
+        }));
+
+        Ok(res)
+    }
+
+    #[cfg(feature = "caldav")]
+    fn calendar_query_directory<'a>(
+        &'a self,
+        path: &'a DavPath,
+        depth: davheaders::Depth,
+        filter: &'a CompFilter,
+        pw: &'a mut PropWriter,
+    ) -> BoxFuture<'a, DavResult<()>> {
+        async move {
+            self.check_cancel()?;
+            let mut entries = match self.fs.read_dir(path, ReadDirMeta::DataSymlink).await {
+                Ok(entries) => entries,
+                Err(e) => {
+                    trace!("calendar-query: read_dir {}: {:?}. Skipping", path, e);
+                    return Ok(());
+                }
+            };
+            while let Some(dirent) = entries.next().await {
+                self.check_cancel()?;
+                let mut npath = path.clone();
+                npath.push_segment(&dirent.name());
+                if !self.can_list(&npath) {
+                    continue;
+                }
+                let meta = match dirent.metadata().await {
+                    Ok(meta) => meta,
+                    Err(_) => continue,
+                };
+                if meta.is_symlink() {
+                    continue;
+                }
+                if !meta.is_dir() {
+                    self.calendar_query_file(&npath, meta, filter, pw).await?;
+                } else if depth == davheaders::Depth::Infinity {
+                    npath.add_slash();
+                    self.calendar_query_directory(&npath, depth, filter, pw)
+                        .await?;
+                }
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    // A resource that cannot be read, or is not iCalendar, does not match.
+    #[cfg(feature = "caldav")]
+    async fn calendar_query_file(
+        &self,
+        path: &DavPath,
+        meta: Box<dyn DavMetaData>,
+        filter: &CompFilter,
+        pw: &mut PropWriter,
+    ) -> DavResult<()> {
+        let cal = match ical::read(&*self.fs, path).await {
+            Ok(data) => ical::parse(&data),
+            Err(_) => None,
+        };
+        if matches!(cal, Some(ref cal) if filter.matches_object(cal)) {
+            pw.write_props(path, meta).await?;
+            pw.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "caldav")]
+const NS_CALDAV_URI: &str = "urn:ietf:params:xml:ns:caldav";
+
+// Parse a CALDAV:filter. On error, returns the precondition that failed:
+// valid-filter if the filter is wrong, supported-filter if it uses
+// something that we cannot evaluate, like a param-filter.
+#[cfg(feature = "caldav")]
+fn parse_filter(elem: Option<&Element>) -> Result<CompFilter, &'static str> {
+    let mut comps = elem.ok_or("valid-filter")?.child_elems_iter();
+    match (comps.next(), comps.next()) {
+        (Some(e), None) if e.name == "comp-filter" => {
+            let filter = parse_comp_filter(e)?;
+            if filter.name != "VCALENDAR" || filter.time_range.is_some() {
+                return Err("valid-filter");
+            }
+            Ok(filter)
+        }
+        _ => Err("valid-filter"),
+    }
+}
+
+#[cfg(feature = "caldav")]
+fn parse_comp_filter(elem: &Element) -> Result<CompFilter, &'static str> {
+    let mut filter = CompFilter {
+        name: elem
+            .attributes
+            .get("name")
+            .ok_or("valid-filter")?
+            .to_ascii_uppercase(),
+        is_not_defined: false,
+        time_range: None,
+        prop_filters: Vec::new(),
+        comp_filters: Vec::new(),
+    };
+    for e in elem.child_elems_iter() {
+        match e.name.as_str() {
+            "is-not-defined" => filter.is_not_defined = true,
+            "time-range" => {
+                // the time-range test is only defined for these.
+                if filter.name != "VEVENT" && filter.name != "VTODO" {
+                    return Err("supported-filter");
+                }
+                filter.time_range = Some(parse_time_range(e)?);
+            }
+            "prop-filter" => filter.prop_filters.push(parse_prop_filter(e)?),
+            "comp-filter" => filter.comp_filters.push(parse_comp_filter(e)?),
+            _ => return Err("supported-filter"),
+        }
+    }
+    Ok(filter)
+}
+
+#[cfg(feature = "caldav")]
+fn parse_prop_filter(elem: &Element) -> Result<PropFilter, &'static str> {
+    let mut filter = PropFilter {
+        name: elem
+            .attributes
+            .get("name")
+            .ok_or("valid-filter")?
+            .to_ascii_uppercase(),
+        is_not_defined: false,
+        text_match: None,
+    };
+    for e in elem.child_elems_iter() {
+        match e.name.as_str() {
+            "is-not-defined" => filter.is_not_defined = true,
+            "text-match" => {
+                let octet = match e.attributes.get("collation").map(|s| s.as_str()) {
+                    None | Some("i;ascii-casemap") => false,
+                    Some("i;octet") => true,
+                    Some(_) => return Err("supported-collation"),
+                };
+                filter.text_match = Some(TextMatch {
+                    text: e.get_text().unwrap_or_default().into_owned(),
+                    octet,
+                    negate: e.attributes.get("negate-condition").map(|s| s.as_str()) == Some("yes"),
+                });
+            }
+            // time-range on a property, param-filter.
+            _ => return Err("supported-filter"),
+        }
+    }
+    Ok(filter)
+}
+
+#[cfg(feature = "caldav")]
+fn parse_time_range(elem: &Element) -> Result<(i64, i64), &'static str> {
+    let time = |name: &str| match elem.attributes.get(name) {
+        Some(t) => ical::parse_date_time(t).map(Some).ok_or("valid-filter"),
+        None => Ok(None),
+    };
+    match (time("start")?, time("end")?) {
+        (None, None) => Err("valid-filter"),
+        (start, end) => Ok((start.unwrap_or(i64::MIN), end.unwrap_or(i64::MAX))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::davpath::DavPath;
+    use crate::memfs::MemFs;
+    use crate::testfs::{body_text, memfs_handler, request};
+    use crate::DavHandler;
+
+    const SEARCH: &str = r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:principal-property-search xmlns:D="DAV:">
+  <D:property-search>
+    <D:prop><D:displayname/></D:prop>
+    <D:match>ALI</D:match>
+  </D:property-search>
+  <D:prop><D:resourcetype/></D:prop>
+</D:principal-property-search>"#;
+
+    async fn report(dav: &DavHandler) -> (u16, String) {
+        for uri in &["/principals", "/principals/alice", "/principals/bob"] {
+            request(dav, "MKCOL", uri, &[], "").await;
+        }
+        let depth = [("Depth", "0")];
+        let resp = request(dav, "REPORT", "/principals/", &depth, SEARCH).await;
+        (resp.status().as_u16(), body_text(resp).await)
+    }
+
+    #[tokio::test]
+    async fn test_principal_property_search() {
+        let (status, body) = report(&memfs_handler()).await;
+        assert_eq!(status, 403);
+        assert!(body.contains("<D:supported-report/>"));
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .principal_search(|terms, any_of| {
+                assert!(!any_of);
+                assert_eq!(terms.len(), 1);
+                assert_eq!(terms[0].props[0].name, "displayname");
+                let text = terms[0].text.to_lowercase();
+                ["alice", "bob"]
+                    .iter()
+                    .filter(|name| name.contains(&text))
+                    .map(|name| DavPath::new(&format!("/principals/{}/", name)).unwrap())
+                    .collect()
+            })
+            .build_handler();
+        let (status, body) = report(&dav).await;
+        assert_eq!(status, 207);
+        assert!(body.contains("<D:href>/principals/alice/</D:href>"));
+        assert!(!body.contains("bob"));
+        assert!(body.contains("<D:resourcetype><D:collection>"));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_report() {
+        let dav = memfs_handler();
+        let body = r#"<?xml version="1.0" encoding="utf-8" ?>
+<C:free-busy-query xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <C:time-range start="20060104T140000Z" end="20060105T220000Z"/>
+</C:free-busy-query>"#;
+        let resp = request(&dav, "REPORT", "/", &[("Depth", "1")], body).await;
+        assert_eq!(resp.status(), 403);
+        assert!(body_text(resp).await.contains("<D:supported-report/>"));
+    }
+
+    #[cfg(feature = "caldav")]
+    #[tokio::test]
+    async fn test_calendar_query() {
+        let dav = memfs_handler();
+        request(&dav, "MKCOL", "/cal", &[], "").await;
+        let objects = [
+            (
+                "meeting",
+                "VEVENT",
+                "SUMMARY:Meeting\r\nDTSTART:20240110T100000Z\r\nDTEND:20240110T110000Z",
+            ),
+            (
+                "lunch",
+                "VEVENT",
+                "SUMMARY:Lunch\r\nDTSTART;VALUE=DATE:20240112",
+            ),
+            ("taxes", "VTODO", "SUMMARY:Taxes\r\nDUE:20240110T120000Z"),
+        ];
+        for (name, comp, props) in &objects {
+            let ical = format!(
+                "BEGIN:VCALENDAR\r\nBEGIN:{0}\r\nUID:{1}\r\n{2}\r\nEND:{0}\r\nEND:VCALENDAR\r\n",
+                comp, name, props
+            );
+            request(&dav, "PUT", &format!("/cal/{}.ics", name), &[], ical).await;
+        }
+        request(&dav, "PUT", "/cal/notes.txt", &[], "BEGIN:VEVENT").await;
+
+        let query = |filter: &str| {
+            let body = format!(
+                r#"<?xml version="1.0" encoding="utf-8" ?>
+<C:calendar-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:prop><D:getetag/><C:calendar-data/></D:prop>
+  <C:filter><C:comp-filter name="VCALENDAR">{}</C:comp-filter></C:filter>
+</C:calendar-query>"#,
+                filter
+            );
+            async {
+                let resp = request(&dav, "REPORT", "/cal/", &[("Depth", "1")], body).await;
+                let status = resp.status().as_u16();
+                let body = body_text(resp).await;
+                let mut found: Vec<_> = ["meeting", "lunch", "taxes", "notes"]
+                    .iter()
+                    .filter(|name| body.contains(&format!("/cal/{}.", name)))
+                    .map(|name| name.to_string())
+                    .collect();
+                found.sort();
+                (status, found, body)
+            }
+        };
+        let jan10 = r#"<C:time-range start="20240110T000000Z" end="20240111T000000Z"/>"#;
+
+        let (status, found, _) = query(r#"<C:comp-filter name="VEVENT"/>"#).await;
+        assert_eq!(status, 207);
+        assert_eq!(found, ["lunch", "meeting"]);
+
+        let filter = format!(r#"<C:comp-filter name="VEVENT">{}</C:comp-filter>"#, jan10);
+        let (_, found, body) = query(&filter).await;
+        assert_eq!(found, ["meeting"]);
+        assert!(body.contains("SUMMARY:Meeting"));
+        assert!(body.contains("<D:getetag>"));
+
+        let filter = r#"<C:comp-filter name="VEVENT">
+            <C:time-range start="20240111T000000Z" end="20240113T000000Z"/>
+        </C:comp-filter>"#;
+        assert_eq!(query(filter).await.1, ["lunch"]);
+
+        let filter = format!(r#"<C:comp-filter name="VTODO">{}</C:comp-filter>"#, jan10);
+        assert_eq!(query(&filter).await.1, ["taxes"]);
+
+        let filter = r#"<C:comp-filter name="VEVENT">
+            <C:prop-filter name="SUMMARY"><C:text-match>LUNCH</C:text-match></C:prop-filter>
+        </C:comp-filter>"#;
+        assert_eq!(query(filter).await.1, ["lunch"]);
+
+        let filter = r#"<C:comp-filter name="VEVENT">
+            <C:prop-filter name="DTEND"><C:is-not-defined/></C:prop-filter>
+        </C:comp-filter>"#;
+        assert_eq!(query(filter).await.1, ["lunch"]);
+
+        let filter = r#"<C:comp-filter name="VTODO"><C:is-not-defined/></C:comp-filter>"#;
+        assert_eq!(query(filter).await.1, ["lunch", "meeting"]);
+
+        // filters that we cannot evaluate.
+        for filter in &[
+            r#"<C:comp-filter name="VEVENT"><C:prop-filter name="DTSTART">
+                <C:param-filter name="TZID"/>
+            </C:prop-filter></C:comp-filter>"#,
+            r#"<C:comp-filter name="VJOURNAL">
+                <C:time-range start="20240110T000000Z"/>
+            </C:comp-filter>"#,
+        ] {
+            let (status, _, body) = query(filter).await;
+            assert_eq!(status, 403);
+            assert!(body.contains("<C:supported-filter"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_report_empty_body() {
+        let dav = memfs_handler();
+        for body in &["", "\n"] {
+            let resp = request(&dav, "REPORT", "/", &[("Depth", "0")], *body).await;
+            assert_eq!(resp.status(), 400);
+        }
+    }
+}
@@ -0,0 +1,715 @@
+use std::cmp::Ordering;
+use std::io::Cursor;
+
+use futures_util::{future::BoxFuture, FutureExt, StreamExt};
+use headers::HeaderMapExt;
+use http::{Request, Response, StatusCode};
+use xmltree::Element;
+
+use crate::async_stream::AsyncStream;
+use crate::body::Body;
+use crate::davheaders::{self, Depth};
+use crate::davpath::DavPath;
+use crate::errors::*;
+use crate::fs::*;
+use crate::handle_props::PropWriter;
+use crate::xmltree_ext::*;
+use crate::{DavInner, DavResult};
+
+// A DAV:basicsearch condition (RFC 5323, 5.5).
+enum Condition {
+    True,
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+    Not(Box<Condition>),
+    // operator, property, literal, caseless.
+    Compare(Ordering, bool, Element, String, bool),
+    Like(Element, String, bool),
+    IsDefined(Element),
+    IsCollection,
+}
+
+// A DAV:scope from the DAV:from clause.
+struct Scope {
+    path: DavPath,
+    depth: Depth,
+}
+
+// A DAV:order from the DAV:orderby clause (RFC 5323, 5.6).
+struct Order {
+    prop: Element,
+    descending: bool,
+    caseless: bool,
+}
+
+// The DAV:where and DAV:orderby clauses.
+struct Query {
+    condition: Condition,
+    orderby: Vec<Order>,
+}
+
+// A result that still has to be sorted: the resource, and the values
+// of the properties it is sorted on.
+type Found = (DavPath, Box<dyn DavMetaData>, Vec<Option<String>>);
+
+// How many more results to return, and resources to look at. With a
+// DAV:orderby, the results are collected in `sorted` first.
+struct Budget {
+    results: usize,
+    nodes: usize,
+    truncated: bool,
+    sorted: Option<Vec<Found>>,
+}
+
+impl DavInner {
+    pub(crate) async fn handle_search(
+        self,
+        req: &Request<()>,
+        xmldata: &[u8],
+    ) -> DavResult<Response<Body>> {
+        let mut res = Response::new(Body::empty());
+
+        res.headers_mut()
+            .typed_insert(headers::CacheControl::new().with_no_cache());
+        res.headers_mut().typed_insert(headers::Pragma::no_cache());
+
+        // only DAV:basicsearch is supported.
+        let root = Element::parse(Cursor::new(xmldata)).map_err(|_| DavError::XmlParseError)?;
+        if root.name != "searchrequest" || root.namespace.as_deref() != Some("DAV:") {
+            return Err(DavError::XmlParseError);
+        }
+        let search = root
+            .child_elems_into_iter()
+            .find(|e| e.name == "basicsearch" && e.namespace.as_deref() == Some("DAV:"))
+            .ok_or(DavError::XmlParseError)?;
+
+        let mut select = None;
+        let mut scopes = Vec::new();
+        let mut condition = Condition::True;
+        let mut limit = None;
+        let mut orderby = Vec::new();
+        for elem in search.child_elems_into_iter() {
+            match elem.name.as_str() {
+                "select" => select = elem.child_elems_into_iter().next(),
+                "from" => {
+                    for scope in elem.child_elems_into_iter() {
+                        scopes.push(self.parse_scope(req, scope)?);
+                    }
+                }
+                "where" => {
+                    let expr = elem.child_elems_into_iter().next();
+                    condition = parse_condition(expr.ok_or(DavError::XmlParseError)?)?;
+                }
+                "limit" => {
+                    let n = elem.get_child("nresults").and_then(|n| n.get_text());
+                    let n = n.and_then(|n| n.trim().parse::<usize>().ok());
+                    limit = Some(n.ok_or(DavError::XmlParseError)?);
+                }
+                "orderby" => {
+                    for order in elem.child_elems_into_iter() {
+                        orderby.push(parse_order(order)?);
+                    }
+                }
+                _ => return Err(DavError::XmlParseError),
+            }
+        }
+
+        let (name, props) = match select {
+            Some(e) if e.name == "prop" => ("prop", e.take_child_elems()),
+            Some(e) if e.name == "allprop" => ("allprop", Vec::new()),
+            _ => return Err(DavError::XmlParseError),
+        };
+        if scopes.is_empty() {
+            return Err(DavError::XmlParseError);
+        }
+        let req_path = self.path(req);
+        let trim = |p: &DavPath| {
+            let b = p.as_bytes();
+            b.strip_suffix(b"/").unwrap_or(b).len()
+        };
+        for scope in &scopes {
+            self.fs.metadata(&scope.path).await?;
+            self.check_privilege("read", &scope.path).await?;
+            // like a member, a scope below the request URI can be hidden.
+            let mut path = scope.path.clone();
+            while trim(&path) > trim(&req_path) {
+                if !self.can_list(&path) {
+                    return Err(StatusCode::NOT_FOUND.into());
+                }
+                path = path.parent();
+            }
+        }
+
+        let query = Query { condition, orderby };
+
+        let ct = self.xml_type(req);
+        res.headers_mut().typed_insert(davheaders::ContentType(ct));
+        let mut pw = PropWriter::new(
+            req,
+            &mut res,
+            name,
+            props,
+            &self.fs,
+            self.ls.as_ref(),
+            self.mime_types.as_ref(),
+        )?;
+        pw.set_add_member(self.add_member.unwrap_or(false));
+        pw.set_principal_quota(self.principal_quota.clone(), self.principal.clone());
+        pw.set_resourcetypes(self.resourcetypes.clone());
+
+        *res.body_mut() = Body::from(AsyncStream::new(|tx| async move {
+            pw.set_tx(tx);
+            // without a DAV:orderby, results are returned in directory
+            // order, as they are found.
+            let (results, sorted) = match query.orderby.is_empty() {
+                true => (limit.unwrap_or(usize::MAX), None),
+                false => (usize::MAX, Some(Vec::new())),
+            };
+            let mut budget = Budget {
+                results,
+                nodes: self.max_search_nodes.unwrap_or(usize::MAX),
+                truncated: false,
+                sorted,
+            };
+            for scope in &scopes {
+                let meta = self.fs.metadata(&scope.path).await?;
+                self.search_path(&scope.path, meta, scope.depth, &query, &mut budget, &mut pw)
+                    .await?;
+            }
+            if let Some(mut found) = budget.sorted.take() {
+                found.sort_by(|a, b| order_cmp(&query.orderby, &a.2, &b.2));
+                for (path, meta, _) in found.into_iter().take(limit.unwrap_or(usize::MAX)) {
+                    pw.write_props(&path, meta).await?;
+                    pw.flush().await?;
+                }
+            }
+            if budget.truncated {
+                pw.write_status(&req_path, StatusCode::INSUFFICIENT_STORAGE)?;
+            }
+            pw.close().await?;
+            Ok(())
+        }));
+
+        Ok(res)
+    }
+
+    fn parse_scope(&self, req: &Request<()>, scope: Element) -> DavResult<Scope> {
+        if scope.name != "scope" {
+            return Err(DavError::XmlParseError);
+        }
+        let href = scope.get_child("href").and_then(|h| h.get_text());
+        let href = href.ok_or(DavError::XmlParseError)?;
+        let href = href.trim();
+
+        // an absolute URL, an absolute path, or relative to the request URL.
+        let href = match href.find("://") {
+            Some(idx) => match href[idx + 3..].find('/') {
+                Some(p) => href[idx + 3 + p..].to_string(),
+                None => "/".to_string(),
+            },
+            None if href.starts_with('/') => href.to_string(),
+            None => {
+                let mut base = self.path(req);
+                base.add_slash();
+                format!("{}{}", base.with_prefix().as_url_string(), href)
+            }
+        };
+        let path = DavPath::from_str_and_prefix(&href, &self.prefix)?;
+
+        // only the request URI and what is below it can be searched.
+        let mut base = self.path(req);
+        base.add_slash();
+        let mut dir = path.clone();
+        dir.add_slash();
+        if !dir.as_bytes().starts_with(base.as_bytes()) {
+            let body = "<D:search-scope-valid/>".to_string();
+            return Err(DavError::Condition(StatusCode::BAD_REQUEST, body));
+        }
+
+        let depth = match scope.get_child("depth").and_then(|d| d.get_text()) {
+            None => Depth::Infinity,
+            Some(d) => match d.trim() {
+                "0" => Depth::Zero,
+                "1" => Depth::One,
+                "infinity" => Depth::Infinity,
+                _ => return Err(DavError::XmlParseError),
+            },
+        };
+        Ok(Scope { path, depth })
+    }
+
+    // Evaluate the condition for this resource, and if it is a
+    // collection, for its members as far as depth allows.
+    fn search_path<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: Box<dyn DavMetaData>,
+        depth: Depth,
+        query: &'a Query,
+        budget: &'a mut Budget,
+        pw: &'a mut PropWriter,
+    ) -> BoxFuture<'a, DavResult<()>> {
+        async move {
+            if budget.results == 0 {
+                return Ok(());
+            }
+            if budget.nodes == 0 {
+                budget.truncated = true;
+                return Ok(());
+            }
+            budget.nodes -= 1;
+            self.check_cancel()?;
+            let is_dir = meta.is_dir();
+            if query.condition.eval(path, &*meta, pw).await? {
+                match budget.sorted.as_mut() {
+                    Some(sorted) => {
+                        let mut values = Vec::new();
+                        for order in &query.orderby {
+                            values.push(pw.prop_value(&order.prop, path, &*meta).await?);
+                        }
+                        sorted.push((path.clone(), meta, values));
+                    }
+                    None => {
+                        pw.write_props(path, meta).await?;
+                        pw.flush().await?;
+                    }
+                }
+                budget.results -= 1;
+            }
+            if !is_dir || depth == Depth::Zero {
+                return Ok(());
+            }
+            let depth = match depth {
+                Depth::Infinity => Depth::Infinity,
+                _ => Depth::Zero,
+            };
+
+            let readdir_meta = match self.hide_symlinks {
+                Some(true) | None => ReadDirMeta::DataSymlink,
+                Some(false) => ReadDirMeta::Data,
+            };
+            let mut entries = match self.fs.read_dir(path, readdir_meta).await {
+                Ok(entries) => entries,
+                Err(e) => {
+                    debug!("search: read_dir error {:?}", e);
+                    return Ok(());
+                }
+            };
+            while let Some(dirent) = entries.next().await {
+                let mut npath = path.clone();
+                npath.push_segment(&dirent.name());
+                if !self.can_list(&npath) {
+                    continue;
+                }
+                let meta = match dirent.metadata().await {
+                    Ok(meta) if !meta.is_symlink() => meta,
+                    _ => continue,
+                };
+                if meta.is_dir() {
+                    npath.add_slash();
+                }
+                self.search_path(&npath, meta, depth, query, budget, pw)
+                    .await?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+fn parse_condition(elem: Element) -> DavResult<Condition> {
+    if elem.namespace.as_deref() != Some("DAV:") {
+        return Err(DavError::XmlParseError);
+    }
+    let caseless = elem.attributes.get("caseless").map(|s| s.as_str()) == Some("yes");
+    let op = elem.name.clone();
+    let mut args = elem.take_child_elems();
+    let cond = match op.as_str() {
+        "and" | "or" => {
+            let v = args
+                .into_iter()
+                .map(parse_condition)
+                .collect::<DavResult<Vec<_>>>()?;
+            match op.as_str() {
+                "and" => Condition::And(v),
+                _ => Condition::Or(v),
+            }
+        }
+        "not" if args.len() == 1 => Condition::Not(Box::new(parse_condition(args.remove(0))?)),
+        "is-collection" => Condition::IsCollection,
+        "is-defined" if args.len() == 1 => Condition::IsDefined(prop_operand(args.remove(0))?),
+        "eq" | "lt" | "lte" | "gt" | "gte" | "like" if args.len() == 2 => {
+            let literal = args.remove(1);
+            if literal.name != "literal" {
+                return Err(DavError::XmlParseError);
+            }
+            let literal = literal
+                .get_text()
+                .map(|t| t.into_owned())
+                .unwrap_or_default();
+            let prop = prop_operand(args.remove(0))?;
+            match op.as_str() {
+                "eq" => Condition::Compare(Ordering::Equal, true, prop, literal, caseless),
+                "lt" => Condition::Compare(Ordering::Less, false, prop, literal, caseless),
+                "lte" => Condition::Compare(Ordering::Less, true, prop, literal, caseless),
+                "gt" => Condition::Compare(Ordering::Greater, false, prop, literal, caseless),
+                "gte" => Condition::Compare(Ordering::Greater, true, prop, literal, caseless),
+                _ => Condition::Like(prop, literal, caseless),
+            }
+        }
+        _ => return Err(DavError::XmlParseError),
+    };
+    Ok(cond)
+}
+
+// <D:order caseless="yes"><D:prop><D:displayname/></D:prop><D:descending/></D:order>
+fn parse_order(elem: Element) -> DavResult<Order> {
+    if elem.name != "order" {
+        return Err(DavError::XmlParseError);
+    }
+    let caseless = elem.attributes.get("caseless").map(|s| s.as_str()) == Some("yes");
+    let mut args = elem.take_child_elems();
+    let descending = match args.len() {
+        1 => false,
+        2 => match args.remove(1).name.as_str() {
+            "ascending" => false,
+            "descending" => true,
+            _ => return Err(DavError::XmlParseError),
+        },
+        _ => return Err(DavError::XmlParseError),
+    };
+    // ordering on DAV:score is not supported, there is no DAV:contains.
+    let prop = prop_operand(args.remove(0))?;
+    Ok(Order {
+        prop,
+        descending,
+        caseless,
+    })
+}
+
+// <D:prop><D:displayname/></D:prop> -> <D:displayname/>
+fn prop_operand(elem: Element) -> DavResult<Element> {
+    if elem.name != "prop" {
+        return Err(DavError::XmlParseError);
+    }
+    let mut props = elem.take_child_elems();
+    if props.len() != 1 {
+        return Err(DavError::XmlParseError);
+    }
+    Ok(props.remove(0))
+}
+
+impl Condition {
+    fn eval<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: &'a dyn DavMetaData,
+        pw: &'a mut PropWriter,
+    ) -> BoxFuture<'a, DavResult<bool>> {
+        async move {
+            let res = match self {
+                Condition::True => true,
+                Condition::And(v) => {
+                    for c in v {
+                        if !c.eval(path, meta, pw).await? {
+                            return Ok(false);
+                        }
+                    }
+                    true
+                }
+                Condition::Or(v) => {
+                    for c in v {
+                        if c.eval(path, meta, pw).await? {
+                            return Ok(true);
+                        }
+                    }
+                    false
+                }
+                Condition::Not(c) => !c.eval(path, meta, pw).await?,
+                Condition::IsCollection => meta.is_dir(),
+                Condition::IsDefined(prop) => pw.prop_value(prop, path, meta).await?.is_some(),
+                Condition::Compare(ord, or_equal, prop, literal, caseless) => {
+                    match pw.prop_value(prop, path, meta).await? {
+                        Some(value) => {
+                            let o = compare(&value, literal, *caseless);
+                            o == *ord || (*or_equal && o == Ordering::Equal)
+                        }
+                        None => false,
+                    }
+                }
+                Condition::Like(prop, pattern, caseless) => {
+                    match pw.prop_value(prop, path, meta).await? {
+                        Some(value) if *caseless => {
+                            like(&value.to_lowercase(), &pattern.to_lowercase())
+                        }
+                        Some(value) => like(&value, pattern),
+                        None => false,
+                    }
+                }
+            };
+            Ok(res)
+        }
+        .boxed()
+    }
+}
+
+// Numbers compare as numbers, everything else as strings.
+fn compare(value: &str, literal: &str, caseless: bool) -> Ordering {
+    if let (Ok(a), Ok(b)) = (value.trim().parse::<f64>(), literal.trim().parse::<f64>()) {
+        return a.partial_cmp(&b).unwrap_or(Ordering::Less);
+    }
+    if caseless {
+        value.to_lowercase().cmp(&literal.to_lowercase())
+    } else {
+        value.cmp(literal)
+    }
+}
+
+// The order of two results. Resources that do not have the property
+// come first, then numbers, then everything else as strings.
+fn order_cmp(orderby: &[Order], a: &[Option<String>], b: &[Option<String>]) -> Ordering {
+    for (order, (a, b)) in orderby.iter().zip(a.iter().zip(b.iter())) {
+        let o = match (a, b) {
+            (Some(a), Some(b)) => match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+                (Ok(x), Ok(y)) => x.total_cmp(&y),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                _ if order.caseless => a.to_lowercase().cmp(&b.to_lowercase()),
+                _ => a.cmp(b),
+            },
+            (a, b) => a.is_some().cmp(&b.is_some()),
+        };
+        let o = if order.descending { o.reverse() } else { o };
+        if o != Ordering::Equal {
+            return o;
+        }
+    }
+    Ordering::Equal
+}
+
+// DAV:like pattern matching: "%" matches any sequence of characters,
+// "_" any single character, and "\" escapes the next one.
+enum Like {
+    Any,
+    One,
+    Char(char),
+}
+
+fn like(value: &str, pattern: &str) -> bool {
+    let value: Vec<char> = value.chars().collect();
+    let mut pat = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        pat.push(match c {
+            '\\' => Like::Char(chars.next().unwrap_or('\\')),
+            '%' => Like::Any,
+            '_' => Like::One,
+            c => Like::Char(c),
+        });
+    }
+    like_match(&value, &pat)
+}
+
+fn like_match(value: &[char], pat: &[Like]) -> bool {
+    match pat.split_first() {
+        None => value.is_empty(),
+        Some((Like::Any, rest)) => (0..=value.len()).any(|i| like_match(&value[i..], rest)),
+        Some((p, rest)) => match value.split_first() {
+            Some((v, vrest)) => {
+                let ok = match p {
+                    Like::Char(c) => c == v,
+                    _ => true,
+                };
+                ok && like_match(vrest, rest)
+            }
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_like() {
+        assert!(like("hello.txt", "%.txt"));
+        assert!(like("hello.txt", "h_llo%"));
+        assert!(like("100%", "100\\%"));
+        assert!(!like("1000", "100\\%"));
+        assert!(!like("hello.txt", "%.vcf"));
+        assert!(like("", "%"));
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_search() {
+        use crate::testfs::{body_text, memfs_handler, request};
+
+        let dav = memfs_handler();
+        assert_eq!(request(&dav, "MKCOL", "/dir", &[], "").await.status(), 201);
+        for (name, body) in &[("/a.txt", "a"), ("/dir/b.txt", "bbb"), ("/dir/c.vcf", "c")] {
+            let resp = request(&dav, "PUT", name, &[], *body).await;
+            assert_eq!(resp.status(), 201);
+        }
+
+        let search = |scope: &str, cond: &str| {
+            let body = format!(
+                r#"<?xml version="1.0" encoding="utf-8" ?>
+                <D:searchrequest xmlns:D="DAV:"><D:basicsearch>
+                  <D:select><D:prop><D:getcontentlength/></D:prop></D:select>
+                  <D:from><D:scope><D:href>{}</D:href><D:depth>infinity</D:depth></D:scope></D:from>
+                  <D:where>{}</D:where>
+                </D:basicsearch></D:searchrequest>"#,
+                scope, cond
+            );
+            request(&dav, "SEARCH", "/", &[], body)
+        };
+        let hrefs = |body: &str| {
+            let tree = Element::parse(Cursor::new(body)).unwrap();
+            let mut hrefs = tree
+                .child_elems_iter()
+                .map(|r| r.get_child("href").unwrap().get_text().unwrap().to_string())
+                .collect::<Vec<_>>();
+            hrefs.sort();
+            hrefs
+        };
+
+        let name_like = r#"<D:like caseless="yes">
+            <D:prop><D:displayname/></D:prop><D:literal>%.TXT</D:literal></D:like>"#;
+        let size_gt = r#"<D:and><D:not><D:is-collection/></D:not>
+            <D:gt><D:prop><D:getcontentlength/></D:prop><D:literal>1</D:literal></D:gt></D:and>"#;
+        for (scope, cond, expected) in &[
+            ("/", name_like, &["/a.txt", "/dir/b.txt"][..]),
+            ("/dir/", name_like, &["/dir/b.txt"][..]),
+            ("dir", name_like, &["/dir/b.txt"][..]),
+            ("/", size_gt, &["/dir/b.txt"][..]),
+        ] {
+            let resp = search(scope, cond).await;
+            assert_eq!(resp.status(), 207);
+            let body = body_text(resp).await;
+            assert_eq!(hrefs(&body), *expected, "{} {}", scope, cond);
+        }
+
+        let resp = search("/", "<D:contains>hello</D:contains>").await;
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_search_orderby() {
+        use crate::testfs::{body_text, memfs_handler, request};
+
+        let dav = memfs_handler();
+        request(&dav, "MKCOL", "/dir", &[], "").await;
+        for (name, body) in &[("/B", "bb"), ("/a", "a"), ("/c", "ccc"), ("/dir/d", "bb")] {
+            request(&dav, "PUT", name, &[], *body).await;
+        }
+        let search = |orderby: &str, limit: usize| {
+            let body = format!(
+                r#"<?xml version="1.0" encoding="utf-8" ?>
+                <D:searchrequest xmlns:D="DAV:"><D:basicsearch>
+                  <D:select><D:prop><D:getcontentlength/></D:prop></D:select>
+                  <D:from><D:scope><D:href>/</D:href><D:depth>infinity</D:depth></D:scope></D:from>
+                  <D:where><D:not><D:is-collection/></D:not></D:where>
+                  <D:orderby>{}</D:orderby>
+                  <D:limit><D:nresults>{}</D:nresults></D:limit>
+                </D:basicsearch></D:searchrequest>"#,
+                orderby, limit
+            );
+            let resp = request(&dav, "SEARCH", "/", &[], body);
+            async move {
+                let resp = resp.await;
+                if resp.status() != 207 {
+                    return Err(resp.status());
+                }
+                let body = body_text(resp).await;
+                let tree = Element::parse(body.as_bytes()).unwrap();
+                let hrefs = tree.child_elems_iter().map(|r| {
+                    let href = r.get_child("href").unwrap().get_text().unwrap();
+                    href.to_string()
+                });
+                Ok(hrefs.collect::<Vec<_>>())
+            }
+        };
+
+        let size = "<D:order><D:prop><D:getcontentlength/></D:prop></D:order>";
+        let size_desc = r#"<D:order><D:prop><D:getcontentlength/></D:prop>
+            <D:descending/></D:order>"#;
+        let name = r#"<D:order caseless="yes"><D:prop><D:displayname/></D:prop>
+            <D:ascending/></D:order>"#;
+        let name_desc = r#"<D:order><D:prop><D:displayname/></D:prop>
+            <D:descending/></D:order>"#;
+        assert_eq!(search(name, 9).await.unwrap(), ["/a", "/B", "/c", "/dir/d"]);
+        assert_eq!(
+            search(name_desc, 9).await.unwrap(),
+            ["/dir/d", "/c", "/a", "/B"]
+        );
+        let by_size = format!("{}{}", size, name_desc);
+        assert_eq!(
+            search(&by_size, 9).await.unwrap(),
+            ["/a", "/dir/d", "/B", "/c"]
+        );
+
+        // the limit is applied after sorting.
+        let by_size = format!("{}{}", size_desc, name);
+        assert_eq!(search(&by_size, 2).await.unwrap(), ["/c", "/B"]);
+
+        // there is no DAV:score to order on.
+        let score = "<D:order><D:score/></D:order>";
+        assert_eq!(search(score, 9).await.unwrap_err(), 400);
+    }
+
+    #[cfg(feature = "memfs")]
+    #[tokio::test]
+    async fn test_search_scopes() {
+        use crate::memfs::MemFs;
+        use crate::testfs::{body_text, request};
+        use crate::DavHandler;
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .can_read(|_, path| !path.as_url_string().starts_with("/dir/secret"))
+            .max_search_nodes(3)
+            .build_handler();
+        let dav = &dav;
+        let request = |method, uri, body: String| async move {
+            let resp = request(dav, method, uri, &[], body).await;
+            (resp.status().as_u16(), body_text(resp).await)
+        };
+        request("MKCOL", "/dir", "".into()).await;
+        request("MKCOL", "/dir/secret", "".into()).await;
+        for name in &["/a.txt", "/dir/b.txt", "/dir/secret/c.txt"] {
+            request("PUT", name, "data".into()).await;
+        }
+        let search = |uri, scope: &str| {
+            let body = format!(
+                r#"<?xml version="1.0" encoding="utf-8" ?>
+                <D:searchrequest xmlns:D="DAV:"><D:basicsearch>
+                  <D:select><D:prop><D:getcontentlength/></D:prop></D:select>
+                  <D:from><D:scope><D:href>{}</D:href><D:depth>infinity</D:depth></D:scope></D:from>
+                </D:basicsearch></D:searchrequest>"#,
+                scope
+            );
+            request("SEARCH", uri, body)
+        };
+
+        // only below the request URI.
+        let (status, body) = search("/dir/", "/").await;
+        assert_eq!(status, 400);
+        assert!(body.contains("search-scope-valid"));
+        assert_eq!(search("/dir/", "/dir").await.0, 207);
+
+        // a hidden scope is not there.
+        assert_eq!(search("/", "/dir/secret/").await.0, 404);
+        let (status, body) = search("/dir/", "/dir/").await;
+        assert_eq!(status, 207);
+        assert!(!body.contains("secret"));
+
+        // too many resources: the results are incomplete.
+        let (status, body) = search("/", "/").await;
+        assert_eq!(status, 207);
+        assert!(body.contains("507"));
+        assert_eq!(body.matches("<D:href>").count(), 4, "{}", body);
+    }
+}
@@ -0,0 +1,486 @@
+// Just enough of iCalendar (RFC 5545) to evaluate a CalDAV calendar-query
+// filter (RFC 4791, 9.7): components, properties, DATE / DATE-TIME values
+// and durations.
+//
+// Some simplifications:
+//
+// - time zones are not looked at. A DATE-TIME with a TZID, or a floating
+//   one, is taken to be in UTC.
+// - recurrences are not expanded. A component with an RRULE or RDATE
+//   overlaps every time-range that ends after its first instance starts.
+//   That can make a recurring event match a range in which it has no
+//   instance, but it never leaves out one that does.
+
+use std::convert::TryFrom;
+
+use crate::davpath::DavPath;
+use crate::fs::{DavFileSystem, FsResult, OpenOptions};
+
+// A component, like VCALENDAR or VEVENT, with its properties and
+// subcomponents.
+#[derive(Debug, Clone)]
+pub(crate) struct Component {
+    pub name: String,
+    pub props: Vec<Property>,
+    pub comps: Vec<Component>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Property {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub value: String,
+}
+
+// Parse an iCalendar object. Returns None if the components do not nest.
+pub(crate) fn parse(data: &str) -> Option<Component> {
+    // unfold: a line that starts with a space or tab continues the last one.
+    let mut lines: Vec<String> = Vec::new();
+    for line in data.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match line.strip_prefix(|c| c == ' ' || c == '\t') {
+            Some(rest) if !lines.is_empty() => lines.last_mut().unwrap().push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut stack: Vec<Component> = Vec::new();
+    for line in lines.iter().filter(|l| !l.is_empty()) {
+        let prop = parse_line(line)?;
+        if prop.name == "BEGIN" {
+            stack.push(Component {
+                name: prop.value.to_ascii_uppercase(),
+                props: Vec::new(),
+                comps: Vec::new(),
+            });
+        } else if prop.name == "END" {
+            let comp = stack.pop()?;
+            if !comp.name.eq_ignore_ascii_case(&prop.value) {
+                return None;
+            }
+            match stack.last_mut() {
+                Some(parent) => parent.comps.push(comp),
+                None => return Some(comp),
+            }
+        } else {
+            stack.last_mut()?.props.push(prop);
+        }
+    }
+    None
+}
+
+// Read a calendar object resource.
+pub(crate) async fn read(fs: &dyn DavFileSystem, path: &DavPath) -> FsResult<String> {
+    let mut file = fs.open(path, OpenOptions::read()).await?;
+    let mut data = Vec::new();
+    loop {
+        let buf = file.read_bytes(8192).await?;
+        if buf.is_empty() {
+            break;
+        }
+        data.extend_from_slice(&buf);
+    }
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+// NAME;PARAM=VALUE;PARAM="QUOTED:VALUE":VALUE
+fn parse_line(line: &str) -> Option<Property> {
+    let mut params = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    let mut name = None;
+    let mut param = None;
+    for (idx, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' | ':' if !quoted => {
+                let part = &line[start..idx];
+                start = idx + 1;
+                if name.is_none() {
+                    name = Some(part.to_ascii_uppercase());
+                } else if let Some((n, v)) = part.split_once('=') {
+                    param = Some((n.to_ascii_uppercase(), v.trim_matches('"').to_string()));
+                }
+                if let Some(p) = param.take() {
+                    params.push(p);
+                }
+                if c == ':' {
+                    return Some(Property {
+                        name: name?,
+                        params,
+                        value: line[start..].to_string(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+impl Component {
+    pub fn prop(&self, name: &str) -> Option<&Property> {
+        self.props
+            .iter()
+            .find(|p| p.name.eq_ignore_ascii_case(name))
+    }
+
+    // Does this component overlap the time-range [start, end) ? This
+    // is the table in RFC 4791, 9.9, which only covers VEVENT and VTODO.
+    pub fn overlaps(&self, start: i64, end: i64) -> bool {
+        let time = |name: &str| self.prop(name).and_then(|p| p.date_time());
+        let dtstart = time("DTSTART");
+        let duration = self.prop("DURATION").and_then(|p| parse_duration(&p.value));
+        if self.prop("RRULE").is_some() || self.prop("RDATE").is_some() {
+            if let Some((dtstart, _)) = dtstart {
+                return end > dtstart;
+            }
+        }
+
+        match self.name.as_str() {
+            "VEVENT" => {
+                let (dtstart, is_date) = match dtstart {
+                    Some(d) => d,
+                    None => return false,
+                };
+                match (time("DTEND"), duration) {
+                    (Some((dtend, _)), _) => start < dtend && end > dtstart,
+                    (None, Some(d)) if d > 0 => start < dtstart + d && end > dtstart,
+                    (None, None) if is_date => start < dtstart + 86400 && end > dtstart,
+                    (None, _) => start <= dtstart && end > dtstart,
+                }
+            }
+            "VTODO" => {
+                let dtstart = dtstart.map(|d| d.0);
+                let due = time("DUE").map(|d| d.0);
+                match (dtstart, due, duration) {
+                    (Some(s), _, Some(d)) => start <= s + d && (end > s || end >= s + d),
+                    (Some(s), Some(due), None) => {
+                        (start < due || start <= s) && (end > s || end >= due)
+                    }
+                    (Some(s), None, None) => start <= s && end > s,
+                    (None, Some(due), _) => start < due && end >= due,
+                    (None, None, _) => {
+                        let completed = time("COMPLETED").map(|d| d.0);
+                        let created = time("CREATED").map(|d| d.0);
+                        match (completed, created) {
+                            (Some(comp), Some(cr)) => {
+                                (start <= cr || start <= comp) && (end >= cr || end >= comp)
+                            }
+                            (Some(comp), None) => start <= comp && end >= comp,
+                            (None, Some(cr)) => end > cr,
+                            (None, None) => true,
+                        }
+                    }
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Property {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    // The value of a TEXT property, with the escapes removed.
+    pub fn text(&self) -> String {
+        let mut text = String::with_capacity(self.value.len());
+        let mut chars = self.value.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                text.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') | Some('N') => text.push('\n'),
+                Some(c) => text.push(c),
+                None => {}
+            }
+        }
+        text
+    }
+
+    // A DATE or DATE-TIME value as a unix timestamp, and whether
+    // it was a DATE.
+    pub fn date_time(&self) -> Option<(i64, bool)> {
+        let t = parse_date_time(&self.value)?;
+        Some((
+            t,
+            self.param("VALUE") == Some("DATE") || self.value.len() == 8,
+        ))
+    }
+}
+
+// Parse a DATE (19970714) or DATE-TIME (19970714T173000Z) value.
+pub(crate) fn parse_date_time(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let (date, time) = match s.split_once('T') {
+        Some((d, t)) => (d, Some(t.strip_suffix('Z').unwrap_or(t))),
+        None => (s, None),
+    };
+    let num = |s: &str| -> Option<u32> {
+        if s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse().ok()
+        } else {
+            None
+        }
+    };
+    if date.len() != 8 || matches!(time, Some(t) if t.len() != 6) {
+        return None;
+    }
+    let month = time::Month::try_from(num(&date[4..6])? as u8).ok()?;
+    let date =
+        time::Date::from_calendar_date(num(&date[..4])? as i32, month, num(&date[6..])? as u8)
+            .ok()?;
+    let (h, m, sec) = match time {
+        Some(t) => (num(&t[..2])?, num(&t[2..4])?, num(&t[4..])?),
+        None => (0, 0, 0),
+    };
+    let dt = date.with_hms(h as u8, m as u8, sec as u8).ok()?;
+    Some(dt.assume_utc().unix_timestamp())
+}
+
+// Parse a DURATION value, like P1W, -PT15M or P1DT12H, into seconds.
+pub(crate) fn parse_duration(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let (neg, s) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let s = s.strip_prefix('P')?;
+    let mut secs = 0i64;
+    let mut num: Option<i64> = None;
+    let mut in_time = false;
+    for c in s.chars() {
+        if let Some(d) = c.to_digit(10) {
+            num = Some(num.unwrap_or(0).checked_mul(10)?.checked_add(d as i64)?);
+            continue;
+        }
+        let unit = match (c, in_time) {
+            ('T', false) if num.is_none() => {
+                in_time = true;
+                continue;
+            }
+            ('W', false) => 604800,
+            ('D', false) => 86400,
+            ('H', true) => 3600,
+            ('M', true) => 60,
+            ('S', true) => 1,
+            _ => return None,
+        };
+        secs = secs.checked_add(num.take()?.checked_mul(unit)?)?;
+    }
+    if num.is_some() || s.is_empty() {
+        return None;
+    }
+    Some(if neg { -secs } else { secs })
+}
+
+// A CALDAV:comp-filter.
+#[derive(Debug)]
+pub(crate) struct CompFilter {
+    pub name: String,
+    pub is_not_defined: bool,
+    pub time_range: Option<(i64, i64)>,
+    pub prop_filters: Vec<PropFilter>,
+    pub comp_filters: Vec<CompFilter>,
+}
+
+// A CALDAV:prop-filter.
+#[derive(Debug)]
+pub(crate) struct PropFilter {
+    pub name: String,
+    pub is_not_defined: bool,
+    pub text_match: Option<TextMatch>,
+}
+
+// A CALDAV:text-match. Collation is i;ascii-casemap, or i;octet
+// if `octet` is set.
+#[derive(Debug)]
+pub(crate) struct TextMatch {
+    pub text: String,
+    pub octet: bool,
+    pub negate: bool,
+}
+
+impl CompFilter {
+    // Does the top-level filter match this calendar object?
+    pub fn matches_object(&self, cal: &Component) -> bool {
+        let found = cal.name.eq_ignore_ascii_case(&self.name);
+        if self.is_not_defined {
+            return !found;
+        }
+        found && self.matches(cal)
+    }
+
+    // Apply the filter to the subcomponents of `parent`.
+    fn matches_in(&self, parent: &Component) -> bool {
+        let mut found = parent
+            .comps
+            .iter()
+            .filter(|c| c.name.eq_ignore_ascii_case(&self.name));
+        if self.is_not_defined {
+            return found.next().is_none();
+        }
+        found.any(|c| self.matches(c))
+    }
+
+    fn matches(&self, comp: &Component) -> bool {
+        if let Some((start, end)) = self.time_range {
+            if !comp.overlaps(start, end) {
+                return false;
+            }
+        }
+        self.prop_filters.iter().all(|f| f.matches_in(comp))
+            && self.comp_filters.iter().all(|f| f.matches_in(comp))
+    }
+}
+
+impl PropFilter {
+    fn matches_in(&self, comp: &Component) -> bool {
+        let mut found = comp
+            .props
+            .iter()
+            .filter(|p| p.name.eq_ignore_ascii_case(&self.name));
+        if self.is_not_defined {
+            return found.next().is_none();
+        }
+        match self.text_match {
+            Some(ref tm) => found.any(|p| tm.matches(&p.text())),
+            None => found.next().is_some(),
+        }
+    }
+}
+
+impl TextMatch {
+    fn matches(&self, value: &str) -> bool {
+        let found = if self.octet {
+            value.contains(&self.text)
+        } else {
+            value
+                .to_ascii_lowercase()
+                .contains(&self.text.to_ascii_lowercase())
+        };
+        found != self.negate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAL: &str = "BEGIN:VCALENDAR\r\n\
+VERSION:2.0\r\n\
+BEGIN:VEVENT\r\n\
+UID:1\r\n\
+SUMMARY;LANGUAGE=en:Team meeting\\, weekly\r\n\
+DESCRIPTION:a long\r\n  line\r\n\
+DTSTART;TZID=\"Europe/Amsterdam\":20240110T100000\r\n\
+DTEND:20240110T110000Z\r\n\
+END:VEVENT\r\n\
+END:VCALENDAR\r\n";
+
+    #[test]
+    fn test_parse() {
+        let cal = parse(CAL).unwrap();
+        assert_eq!(cal.name, "VCALENDAR");
+        assert_eq!(cal.comps.len(), 1);
+        let ev = &cal.comps[0];
+        assert_eq!(ev.name, "VEVENT");
+        assert_eq!(ev.prop("summary").unwrap().text(), "Team meeting, weekly");
+        assert_eq!(ev.prop("SUMMARY").unwrap().param("language"), Some("en"));
+        assert_eq!(ev.prop("DESCRIPTION").unwrap().value, "a long line");
+        let dtstart = ev.prop("DTSTART").unwrap();
+        assert_eq!(dtstart.param("TZID"), Some("Europe/Amsterdam"));
+        assert_eq!(dtstart.date_time(), Some((1704880800, false)));
+
+        assert!(parse("BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nEND:VCALENDAR\r\n").is_none());
+        assert!(parse("BEGIN:VCALENDAR\r\n").is_none());
+    }
+
+    #[test]
+    fn test_values() {
+        assert_eq!(parse_date_time("19700102"), Some(86400));
+        assert_eq!(parse_date_time("19700101T000130Z"), Some(90));
+        assert_eq!(parse_date_time("19701301"), None);
+        assert_eq!(parse_date_time("1970010"), None);
+        assert_eq!(parse_duration("P1W"), Some(604800));
+        assert_eq!(parse_duration("-PT15M"), Some(-900));
+        assert_eq!(parse_duration("P1DT12H"), Some(129600));
+        assert_eq!(parse_duration("PT"), Some(0));
+        assert_eq!(parse_duration("P"), None);
+        assert_eq!(parse_duration("P1H"), None);
+        assert_eq!(parse_duration("PT1"), None);
+    }
+
+    fn comp(name: &str, props: &[(&str, &str)]) -> Component {
+        Component {
+            name: name.to_string(),
+            props: props
+                .iter()
+                .map(|(n, v)| Property {
+                    name: n.to_string(),
+                    params: Vec::new(),
+                    value: v.to_string(),
+                })
+                .collect(),
+            comps: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let t = |s: &str| parse_date_time(s).unwrap();
+        let day = (t("20240110T000000Z"), t("20240111T000000Z"));
+        let next = (t("20240111T000000Z"), t("20240112T000000Z"));
+        let check = |c: &Component, range: (i64, i64)| c.overlaps(range.0, range.1);
+
+        let ev = comp(
+            "VEVENT",
+            &[
+                ("DTSTART", "20240110T230000Z"),
+                ("DTEND", "20240111T010000Z"),
+            ],
+        );
+        assert!(check(&ev, day) && check(&ev, next));
+
+        let ev = comp(
+            "VEVENT",
+            &[("DTSTART", "20240110T230000Z"), ("DURATION", "PT1H")],
+        );
+        assert!(check(&ev, day) && !check(&ev, next));
+
+        let ev = comp("VEVENT", &[("DTSTART", "20240110")]);
+        assert!(check(&ev, day) && !check(&ev, next));
+
+        let ev = comp("VEVENT", &[("DTSTART", "20240111T000000Z")]);
+        assert!(!check(&ev, day) && check(&ev, next));
+
+        let ev = comp(
+            "VEVENT",
+            &[("DTSTART", "20240101T100000Z"), ("RRULE", "FREQ=DAILY")],
+        );
+        assert!(check(&ev, day) && check(&ev, next));
+
+        let todo = comp("VTODO", &[("DUE", "20240111T120000Z")]);
+        assert!(!check(&todo, day) && check(&todo, next));
+
+        let todo = comp(
+            "VTODO",
+            &[("DTSTART", "20240109T000000Z"), ("DUE", "20240110T120000Z")],
+        );
+        assert!(check(&todo, day) && !check(&todo, next));
+
+        let todo = comp("VTODO", &[("CREATED", "20240110T120000Z")]);
+        assert!(check(&todo, day) && check(&todo, next));
+
+        assert!(check(&comp("VTODO", &[]), day));
+        assert!(!check(&comp("VJOURNAL", &[("DTSTART", "20240110")]), day));
+    }
+}
@@ -9,8 +9,9 @@
 //! A `handler` is a piece of code that takes a `http::Request`, processes it in some
 //! way, and then generates a `http::Response`. This library is a `handler` that maps
 //! the HTTP/Webdav protocol to the filesystem. Or actually, "a" filesystem. Included
-//! is an adapter for the local filesystem (`localfs`), and an adapter for an
-//! in-memory filesystem (`memfs`).
+//! is an adapter for the local filesystem (`localfs`), an adapter for an
+//! in-memory filesystem (`memfs`), and a read-only adapter for a tar archive (`tarfs`).
+//! `cachefs` can be put in front of any of them to cache metadata.
 //!
 //! So this library can be used as a handler with HTTP servers like [hyper],
 //! [warp], [actix-web], etc. Either as a correct and complete HTTP handler for
@@ -134,6 +135,7 @@ mod conditional;
 mod davhandler;
 mod davheaders;
 mod errors;
+mod handle_bind;
 mod handle_copymove;
 mod handle_delete;
 mod handle_gethead;
@@ -144,6 +146,9 @@ mod handle_props;
 mod handle_put;
 #[cfg(feature = "carddav")]
 mod handle_report;
+mod handle_search;
+#[cfg(feature = "caldav")]
+mod ical;
 #[cfg(any(docsrs, feature = "localfs"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "localfs")))]
 mod localfs_macos;
@@ -151,12 +156,18 @@ mod localfs_macos;
 #[cfg_attr(docsrs, doc(cfg(feature = "localfs")))]
 mod localfs_windows;
 mod multierror;
+#[cfg(test)]
+mod testfs;
 mod tree;
 mod util;
 mod voidfs;
 mod xmltree_ext;
 
 pub mod body;
+#[cfg(any(docsrs, feature = "cachefs"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "cachefs")))]
+pub mod cachefs;
+pub mod cancel;
 pub mod davpath;
 pub mod fakels;
 pub mod fs;
@@ -168,6 +179,12 @@ pub mod ls;
 #[cfg_attr(docsrs, doc(cfg(feature = "memfs")))]
 pub mod memfs;
 pub mod memls;
+pub mod metrics;
+pub mod ranges;
+pub mod shutdown;
+#[cfg(any(docsrs, feature = "tarfs"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "tarfs")))]
+pub mod tarfs;
 
 #[cfg(any(docsrs, feature = "actix-compat"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "actix-compat")))]
@@ -6,12 +6,13 @@
 
 use std::any::Any;
 use std::collections::VecDeque;
+use std::ffi::OsString;
 use std::future::Future;
 use std::io::{self, Read, Seek, SeekFrom, Write};
 #[cfg(unix)]
 use std::os::unix::{
     ffi::OsStrExt,
-    fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt},
+    fs::{DirBuilderExt, FileExt, MetadataExt, OpenOptionsExt, PermissionsExt},
 };
 #[cfg(target_os = "windows")]
 use std::os::windows::prelude::*;
@@ -82,12 +83,36 @@ where
 }
 
 #[derive(Debug, Clone)]
-struct LocalFsMetaData(std::fs::Metadata);
+struct LocalFsMetaData {
+    meta: std::fs::Metadata,
+    etag_mode: ETagMode,
+    // hash of the contents, for ETagMode::ContentHash.
+    hash: Option<u64>,
+}
+
+/// How [`LocalFs`] generates ETags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ETagMode {
+    /// Size and modification time.
+    MtimeSize,
+    /// Inode number, size and modification time, like Apache does. This
+    /// is the default. A file that is replaced gets a new ETag, even if
+    /// the contents are the same. Only available on unix, elsewhere this
+    /// is the same as `MtimeSize`.
+    #[default]
+    Inode,
+    /// A hash of the contents of the file. This stays the same over renames
+    /// and between replicas, but the entire file has to be read every time
+    /// its metadata is retrieved. Directories use `MtimeSize`.
+    ContentHash,
+}
 
 /// Local Filesystem implementation.
 #[derive(Clone)]
 pub struct LocalFs {
     pub(crate) inner: Arc<LocalFsInner>,
+    etag_mode: ETagMode,
+    file_cache: Option<Arc<FileCache>>,
 }
 
 // inner struct.
@@ -101,8 +126,34 @@ pub(crate) struct LocalFsInner {
     pub fs_access_guard: Option<Box<dyn Fn() -> Box<dyn Any> + Send + Sync + 'static>>,
 }
 
+// The last field is the directory of a file that was opened for
+// writing, see touch_dir.
 #[derive(Debug)]
-struct LocalFsFile(Option<std::fs::File>);
+struct LocalFsFile(
+    Option<std::fs::File>,
+    ETagMode,
+    FileCacheRef,
+    Option<PathBuf>,
+);
+
+// What a LocalFsFile needs for read_range, see LocalFs::set_file_cache.
+type FileCacheRef = Option<(Arc<FileCache>, PathBuf)>;
+
+// The contents of recently served small files.
+#[derive(Debug)]
+struct FileCache {
+    max_size: u64,
+    files: parking_lot::Mutex<lru::LruCache<PathBuf, Arc<Cached>>>,
+}
+
+// A file, as it was when it was read.
+#[derive(Debug)]
+struct Cached {
+    data: Bytes,
+    len: u64,
+    mtime: SystemTime,
+    id: (u64, u64),
+}
 
 struct LocalFsReadDir {
     fs: LocalFs,
@@ -116,7 +167,7 @@ struct LocalFsReadDir {
 // a DirEntry either already has the metadata available, or a handle
 // to the filesystem so it can call fs.blocking()
 enum Meta {
-    Data(io::Result<std::fs::Metadata>),
+    Data(io::Result<LocalFsMetaData>),
     Fs(LocalFs),
 }
 
@@ -152,6 +203,8 @@ impl LocalFs {
         Box::new({
             LocalFs {
                 inner: Arc::new(inner),
+                etag_mode: ETagMode::default(),
+                file_cache: None,
             }
         })
     }
@@ -172,6 +225,8 @@ impl LocalFs {
         Box::new({
             LocalFs {
                 inner: Arc::new(inner),
+                etag_mode: ETagMode::default(),
+                file_cache: None,
             }
         })
     }
@@ -196,10 +251,37 @@ impl LocalFs {
         Box::new({
             LocalFs {
                 inner: Arc::new(inner),
+                etag_mode: ETagMode::default(),
+                file_cache: None,
             }
         })
     }
 
+    /// Choose how ETags are generated, see [`ETagMode`].
+    pub fn set_etag_mode(&mut self, mode: ETagMode) {
+        self.etag_mode = mode;
+    }
+
+    /// Serve `GET` of files up to `max_size` bytes from memory instead
+    /// of with `read` calls. Meant for small files that are requested a
+    /// lot. The contents of the `max_files` most recently served files
+    /// are kept.
+    ///
+    /// A file is read again when it was replaced, or its size or
+    /// modification time has changed. Bigger files, and files that
+    /// changed while being read, are read as usual.
+    ///
+    /// The files are copied, not memory mapped: a mapped file that is
+    /// truncated by another process while it is being sent would crash
+    /// the server with `SIGBUS`.
+    pub fn set_file_cache(&mut self, max_size: u64, max_files: usize) {
+        let size = std::num::NonZeroUsize::new(max_files.max(1)).unwrap();
+        self.file_cache = Some(Arc::new(FileCache {
+            max_size,
+            files: parking_lot::Mutex::new(lru::LruCache::new(size)),
+        }));
+    }
+
     fn fspath_dbg(&self, path: &DavPath) -> PathBuf {
         let mut pathbuf = self.inner.basedir.clone();
         if !self.inner.is_file {
@@ -248,8 +330,12 @@ impl DavFileSystem for LocalFs {
             if self.is_notfound(&path) {
                 return Err(FsError::NotFound);
             }
-            self.blocking(move || match std::fs::metadata(path) {
-                Ok(meta) => Ok(Box::new(LocalFsMetaData(meta)) as Box<dyn DavMetaData>),
+            let etag_mode = self.etag_mode;
+            self.blocking(move || match std::fs::metadata(&path) {
+                Ok(meta) => {
+                    Ok(Box::new(LocalFsMetaData::new(meta, etag_mode, &path))
+                        as Box<dyn DavMetaData>)
+                }
                 Err(e) => Err(e.into()),
             })
             .await
@@ -266,8 +352,12 @@ impl DavFileSystem for LocalFs {
             if self.is_notfound(&path) {
                 return Err(FsError::NotFound);
             }
-            self.blocking(move || match std::fs::symlink_metadata(path) {
-                Ok(meta) => Ok(Box::new(LocalFsMetaData(meta)) as Box<dyn DavMetaData>),
+            let etag_mode = self.etag_mode;
+            self.blocking(move || match std::fs::symlink_metadata(&path) {
+                Ok(meta) => {
+                    Ok(Box::new(LocalFsMetaData::new(meta, etag_mode, &path))
+                        as Box<dyn DavMetaData>)
+                }
                 Err(e) => Err(e.into()),
             })
             .await
@@ -306,14 +396,30 @@ impl DavFileSystem for LocalFs {
     }
 
     fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<Box<dyn DavFile>> {
+        self.open_mode(path, options, None)
+    }
+
+    #[allow(unused_variables)]
+    fn open_mode<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+        mode: Option<u32>,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
         async move {
             trace!("FS: open {:?}", self.fspath_dbg(path));
             if self.is_forbidden(path) {
                 return Err(FsError::Forbidden);
             }
             #[cfg(unix)]
-            let mode = if self.inner.public { 0o644 } else { 0o600 };
+            let mode = mode.unwrap_or(if self.inner.public { 0o644 } else { 0o600 });
+            let etag_mode = self.etag_mode;
             let path = self.fspath(path);
+            let cache = self.file_cache.clone().map(|c| (c, path.clone()));
+            let dir = match options.write || options.append {
+                true => path.parent().map(Path::to_path_buf),
+                false => None,
+            };
             self.blocking(move || {
                 #[cfg(unix)]
                 let res = std::fs::OpenOptions::new()
@@ -335,7 +441,10 @@ impl DavFileSystem for LocalFs {
                     .create_new(options.create_new)
                     .open(path);
                 match res {
-                    Ok(file) => Ok(Box::new(LocalFsFile(Some(file))) as Box<dyn DavFile>),
+                    Ok(file) => {
+                        let file = LocalFsFile(Some(file), etag_mode, cache, dir);
+                        Ok(Box::new(file) as Box<dyn DavFile>)
+                    }
                     Err(e) => Err(e.into()),
                 }
             })
@@ -345,13 +454,18 @@ impl DavFileSystem for LocalFs {
     }
 
     fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
+        self.create_dir_mode(path, None)
+    }
+
+    #[allow(unused_variables)]
+    fn create_dir_mode<'a>(&'a self, path: &'a DavPath, mode: Option<u32>) -> FsFuture<'a, ()> {
         async move {
             trace!("FS: create_dir {:?}", self.fspath_dbg(path));
             if self.is_forbidden(path) {
                 return Err(FsError::Forbidden);
             }
             #[cfg(unix)]
-            let mode = if self.inner.public { 0o755 } else { 0o700 };
+            let mode = mode.unwrap_or(if self.inner.public { 0o755 } else { 0o700 });
             let path = self.fspath(path);
             self.blocking(move || {
                 #[cfg(unix)]
@@ -461,6 +575,82 @@ impl DavFileSystem for LocalFs {
         }
         .boxed()
     }
+
+    fn have_bind<'a>(
+        &'a self,
+        _path: &'a DavPath,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        Box::pin(future::ready(true))
+    }
+
+    // Only files can have more than one binding (hard links).
+    fn link<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            trace!(
+                "FS: link {:?} {:?}",
+                self.fspath_dbg(from),
+                self.fspath_dbg(to)
+            );
+            if self.is_forbidden(from) || self.is_forbidden(to) {
+                return Err(FsError::Forbidden);
+            }
+            let path_from = self.fspath(from);
+            let path_to = self.fspath(to);
+            self.blocking(move || {
+                // link to a temporary name next to `to` and rename that,
+                // so that an existing `to` is replaced atomically.
+                let mut name = OsString::from(".");
+                name.push(path_to.file_name().unwrap_or_default());
+                name.push(format!(".bind-{}", std::process::id()));
+                let path_tmp = path_to.with_file_name(name);
+                std::fs::hard_link(path_from, &path_tmp)?;
+                let res = std::fs::rename(&path_tmp, path_to);
+                // rename() does nothing if both are bindings of the same file.
+                let _ = std::fs::remove_file(&path_tmp);
+                res.map_err(|e| e.into())
+            })
+            .await
+        }
+        .boxed()
+    }
+
+    fn relink<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            trace!(
+                "FS: relink {:?} {:?}",
+                self.fspath_dbg(from),
+                self.fspath_dbg(to)
+            );
+            if self.is_forbidden(from) || self.is_forbidden(to) {
+                return Err(FsError::Forbidden);
+            }
+            let path_from = self.fspath(from);
+            let path_to = self.fspath(to);
+            self.blocking(move || {
+                std::fs::rename(&path_from, path_to)?;
+                // rename() does nothing if both are bindings of the same file.
+                if path_from.symlink_metadata().is_ok() {
+                    std::fs::remove_file(&path_from)?;
+                }
+                Ok(())
+            })
+            .await
+        }
+        .boxed()
+    }
+
+    fn unlink<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            trace!("FS: unlink {:?}", self.fspath_dbg(path));
+            if self.is_forbidden(path) {
+                return Err(FsError::Forbidden);
+            }
+            let path = self.fspath(path);
+            self.blocking(move || std::fs::remove_file(path).map_err(|e| e.into()))
+                .await
+        }
+        .boxed()
+    }
 }
 
 // read_batch() result.
@@ -493,9 +683,11 @@ fn read_batch(
     for _ in 0..256 {
         match iterator.next() {
             Some(Ok(entry)) => {
+                let path = entry.path();
+                let new = |meta| LocalFsMetaData::new(meta, fs.etag_mode, &path);
                 let meta = match do_meta {
-                    ReadDirMeta::Data => Meta::Data(std::fs::metadata(entry.path())),
-                    ReadDirMeta::DataSymlink => Meta::Data(entry.metadata()),
+                    ReadDirMeta::Data => Meta::Data(std::fs::metadata(&path).map(new)),
+                    ReadDirMeta::DataSymlink => Meta::Data(entry.metadata().map(new)),
                     ReadDirMeta::None => Meta::Fs(fs.clone()),
                 };
                 let d = LocalFsDirEntry { meta, entry };
@@ -594,9 +786,9 @@ impl LocalFsDirEntry {
     async fn is_a(&self, is: Is) -> FsResult<bool> {
         match self.meta {
             Meta::Data(Ok(ref meta)) => Ok(match is {
-                Is::File => meta.file_type().is_file(),
-                Is::Dir => meta.file_type().is_dir(),
-                Is::Symlink => meta.file_type().is_symlink(),
+                Is::File => meta.meta.file_type().is_file(),
+                Is::Dir => meta.meta.file_type().is_dir(),
+                Is::Symlink => meta.meta.file_type().is_symlink(),
             }),
             Meta::Data(Err(ref e)) => Err(e.into()),
             Meta::Fs(ref fs) => {
@@ -620,15 +812,17 @@ impl DavDirEntry for LocalFsDirEntry {
         match self.meta {
             Meta::Data(ref meta) => {
                 let m = match meta {
-                    Ok(meta) => Ok(Box::new(LocalFsMetaData(meta.clone())) as Box<dyn DavMetaData>),
+                    Ok(meta) => Ok(Box::new(meta.clone()) as Box<dyn DavMetaData>),
                     Err(e) => Err(e.into()),
                 };
                 Box::pin(future::ready(m))
             }
             Meta::Fs(ref fs) => {
                 let fullpath = self.entry.path();
+                let etag_mode = fs.etag_mode;
                 fs.blocking(move || match std::fs::metadata(&fullpath) {
-                    Ok(meta) => Ok(Box::new(LocalFsMetaData(meta)) as Box<dyn DavMetaData>),
+                    Ok(meta) => Ok(Box::new(LocalFsMetaData::new(meta, etag_mode, &fullpath))
+                        as Box<dyn DavMetaData>),
                     Err(e) => Err(e.into()),
                 })
                 .boxed()
@@ -659,13 +853,98 @@ impl DavDirEntry for LocalFsDirEntry {
     }
 }
 
+impl FileCache {
+    // Get a range of the file, after reading it (again) if needed. Files
+    // that are too big, or shorter than the range, are NotImplemented so
+    // that the caller reads them instead.
+    fn read_range(
+        &self,
+        file: &std::fs::File,
+        path: &Path,
+        offset: u64,
+        count: u64,
+    ) -> FsResult<Bytes> {
+        let meta = file.metadata()?;
+        let (len, mtime) = (meta.len(), meta.modified()?);
+        if !meta.is_file() || len == 0 || len > self.max_size || offset.saturating_add(count) > len
+        {
+            return Err(FsError::NotImplemented);
+        }
+        #[cfg(unix)]
+        let id = (meta.dev(), meta.ino());
+        #[cfg(not(unix))]
+        let id = (0, 0);
+        let cached = self.files.lock().get(path).cloned();
+        let cached = match cached {
+            Some(c) if c.len == len && c.mtime == mtime && c.id == id => c,
+            _ => {
+                let data = read_at_start(file, len)?;
+                // it was written to while we were reading it.
+                let meta = file.metadata()?;
+                if meta.len() != len || meta.modified()? != mtime {
+                    return Err(FsError::NotImplemented);
+                }
+                let c = Arc::new(Cached {
+                    data: Bytes::from(data),
+                    len,
+                    mtime,
+                    id,
+                });
+                self.files.lock().put(path.to_path_buf(), c.clone());
+                c
+            }
+        };
+        Ok(cached
+            .data
+            .slice(offset as usize..(offset + count) as usize))
+    }
+}
+
+// Read the first `len` bytes of a file without moving the file position,
+// which read_range must leave alone.
+fn read_at_start(file: &std::fs::File, len: u64) -> FsResult<Vec<u8>> {
+    let mut buf = vec![0; len as usize];
+    #[cfg(unix)]
+    let res = file.read_exact_at(&mut buf, 0);
+    #[cfg(windows)]
+    let res = {
+        let mut file = file;
+        let pos = file.stream_position()?;
+        file.seek(SeekFrom::Start(0))?;
+        let res = file.read_exact(&mut buf);
+        file.seek(SeekFrom::Start(pos))?;
+        res
+    };
+    match res {
+        Ok(()) => Ok(buf),
+        // it was truncated.
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(FsError::NotImplemented),
+        Err(e) => Err(e.into()),
+    }
+}
+
 impl DavFile for LocalFsFile {
     fn metadata(&mut self) -> FsFuture<Box<dyn DavMetaData>> {
         async move {
-            let file = self.0.take().unwrap();
-            let (meta, file) = blocking(move || (file.metadata(), file)).await;
+            let mut file = self.0.take().unwrap();
+            let etag_mode = self.1;
+            let (meta, file) = blocking(move || {
+                let meta = file.metadata().map(|meta| {
+                    let hash = match etag_mode {
+                        ETagMode::ContentHash if meta.is_file() => hash_file(&mut file).ok(),
+                        _ => None,
+                    };
+                    LocalFsMetaData {
+                        meta,
+                        etag_mode,
+                        hash,
+                    }
+                });
+                (meta, file)
+            })
+            .await;
             self.0 = Some(file);
-            Ok(Box::new(LocalFsMetaData(meta?)) as Box<dyn DavMetaData>)
+            Ok(Box::new(meta?) as Box<dyn DavMetaData>)
         }
         .boxed()
     }
@@ -734,52 +1013,133 @@ impl DavFile for LocalFsFile {
     fn flush(&mut self) -> FsFuture<()> {
         async move {
             let mut file = self.0.take().unwrap();
-            let (res, file) = blocking(move || (file.flush(), file)).await;
+            let dir = self.3.clone();
+            let (res, file) = blocking(move || {
+                let res = file.flush();
+                if let Some(dir) = dir {
+                    let _ = touch_dir(&dir);
+                }
+                (res, file)
+            })
+            .await;
             self.0 = Some(file);
             res.map_err(|e| e.into())
         }
         .boxed()
     }
+
+    fn sync_all(&mut self) -> FsFuture<'_, ()> {
+        async move {
+            let file = self.0.take().unwrap();
+            let (res, file) = blocking(move || (file.sync_all(), file)).await;
+            self.0 = Some(file);
+            res.map_err(|e| e.into())
+        }
+        .boxed()
+    }
+
+    fn read_range(&mut self, offset: u64, count: u64) -> FsFuture<'_, Bytes> {
+        async move {
+            let (cache, path) = match self.2 {
+                Some((ref cache, ref path)) => (cache.clone(), path.clone()),
+                None => return Err(FsError::NotImplemented),
+            };
+            let file = self.0.take().unwrap();
+            let (res, file) = blocking(move || {
+                let res = cache.read_range(&file, &path, offset, count);
+                (res, file)
+            })
+            .await;
+            self.0 = Some(file);
+            res
+        }
+        .boxed()
+    }
+}
+
+impl LocalFsMetaData {
+    // This is sync code, must be run in `blocking()`.
+    fn new(meta: std::fs::Metadata, etag_mode: ETagMode, path: &Path) -> LocalFsMetaData {
+        let hash = match etag_mode {
+            ETagMode::ContentHash if meta.is_file() => std::fs::File::open(path)
+                .and_then(|mut file| hash_file(&mut file))
+                .ok(),
+            _ => None,
+        };
+        LocalFsMetaData {
+            meta,
+            etag_mode,
+            hash,
+        }
+    }
+}
+
+// Set the mtime of a directory to now. The etag of a directory is
+// derived from its mtime, and that has to change when a member is
+// modified, not only when one is added or removed.
+// This is sync code, must be run in `blocking()`.
+fn touch_dir(dir: &Path) -> io::Result<()> {
+    std::fs::File::open(dir)?.set_modified(SystemTime::now())
+}
+
+// Hash the contents of a file, leaving the file position alone.
+// This is sync code, must be run in `blocking()`.
+fn hash_file(file: &mut std::fs::File) -> io::Result<u64> {
+    use std::hash::Hasher;
+    let pos = file.stream_position()?;
+    file.seek(SeekFrom::Start(0))?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    let mut buf = vec![0u8; 65536];
+    let res = loop {
+        match file.read(&mut buf) {
+            Ok(0) => break Ok(hasher.finish()),
+            Ok(n) => hasher.write(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => break Err(e),
+        }
+    };
+    file.seek(SeekFrom::Start(pos))?;
+    res
 }
 
 impl DavMetaData for LocalFsMetaData {
     fn len(&self) -> u64 {
-        self.0.len()
+        self.meta.len()
     }
     fn created(&self) -> FsResult<SystemTime> {
-        self.0.created().map_err(|e| e.into())
+        self.meta.created().map_err(|e| e.into())
     }
     fn modified(&self) -> FsResult<SystemTime> {
-        self.0.modified().map_err(|e| e.into())
+        self.meta.modified().map_err(|e| e.into())
     }
     fn accessed(&self) -> FsResult<SystemTime> {
-        self.0.accessed().map_err(|e| e.into())
+        self.meta.accessed().map_err(|e| e.into())
     }
 
     #[cfg(unix)]
     fn status_changed(&self) -> FsResult<SystemTime> {
-        Ok(UNIX_EPOCH + Duration::new(self.0.ctime() as u64, 0))
+        Ok(UNIX_EPOCH + Duration::new(self.meta.ctime() as u64, 0))
     }
 
     #[cfg(windows)]
     fn status_changed(&self) -> FsResult<SystemTime> {
-        Ok(UNIX_EPOCH + Duration::from_nanos(self.0.creation_time() - 116444736000000000))
+        Ok(UNIX_EPOCH + Duration::from_nanos(self.meta.creation_time() - 116444736000000000))
     }
 
     fn is_dir(&self) -> bool {
-        self.0.is_dir()
+        self.meta.is_dir()
     }
     fn is_file(&self) -> bool {
-        self.0.is_file()
+        self.meta.is_file()
     }
     fn is_symlink(&self) -> bool {
-        self.0.file_type().is_symlink()
+        self.meta.file_type().is_symlink()
     }
 
     #[cfg(unix)]
     fn executable(&self) -> FsResult<bool> {
-        if self.0.is_file() {
-            return Ok((self.0.permissions().mode() & 0o100) > 0);
+        if self.meta.is_file() {
+            return Ok((self.meta.permissions().mode() & 0o100) > 0);
         }
         Err(FsError::NotImplemented)
     }
@@ -790,29 +1150,131 @@ impl DavMetaData for LocalFsMetaData {
         Err(FsError::NotImplemented)
     }
 
-    // same as the default apache etag.
     #[cfg(unix)]
-    fn etag(&self) -> Option<String> {
-        let modified = self.0.modified().ok()?;
-        let t = modified.duration_since(UNIX_EPOCH).ok()?;
-        let t = t.as_secs() * 1000000 + t.subsec_nanos() as u64 / 1000;
-        if self.is_file() {
-            Some(format!("{:x}-{:x}-{:x}", self.0.ino(), self.0.len(), t))
-        } else {
-            Some(format!("{:x}-{:x}", self.0.ino(), t))
-        }
+    fn uid(&self) -> Option<u32> {
+        Some(self.meta.uid())
+    }
+
+    #[cfg(unix)]
+    fn gid(&self) -> Option<u32> {
+        Some(self.meta.gid())
+    }
+
+    #[cfg(unix)]
+    fn mode(&self) -> Option<u32> {
+        Some(self.meta.mode() & 0o7777)
     }
 
-    // same as the default apache etag.
-    #[cfg(windows)]
     fn etag(&self) -> Option<String> {
-        let modified = self.0.modified().ok()?;
+        if let Some(hash) = self.hash {
+            return Some(format!("{:016x}", hash));
+        }
+        let modified = self.meta.modified().ok()?;
         let t = modified.duration_since(UNIX_EPOCH).ok()?;
         let t = t.as_secs() * 1000000 + t.subsec_nanos() as u64 / 1000;
+        // same as the default apache etag.
+        #[cfg(unix)]
+        if self.etag_mode == ETagMode::Inode {
+            let ino = self.meta.ino();
+            return if self.is_file() {
+                Some(format!("{:x}-{:x}-{:x}", ino, self.meta.len(), t))
+            } else {
+                Some(format!("{:x}-{:x}", ino, t))
+            };
+        }
         if self.is_file() {
-            Some(format!("{:x}-{:x}", self.0.len(), t))
+            Some(format!("{:x}-{:x}", self.meta.len(), t))
         } else {
             Some(format!("{:x}", t))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testfs::TempDir;
+
+    #[tokio::test]
+    async fn test_etag_content_hash() {
+        let dir = TempDir::new("etag");
+        std::fs::write(dir.join("a"), "hello").unwrap();
+        std::fs::write(dir.join("b"), "hello").unwrap();
+        std::fs::write(dir.join("c"), "world").unwrap();
+
+        let mut fs = LocalFs::new(&*dir, false, false, false);
+        fs.set_etag_mode(ETagMode::ContentHash);
+        let etag = |name: &'static str| {
+            let fs = fs.clone();
+            async move {
+                let path = DavPath::new(name).unwrap();
+                let meta = fs.metadata(&path).await.unwrap();
+                let mut file = fs.open(&path, OpenOptions::read()).await.unwrap();
+                let fmeta = file.metadata().await.unwrap();
+                assert_eq!(meta.etag(), fmeta.etag());
+                meta.etag().unwrap()
+            }
+        };
+        assert_eq!(etag("/a").await, etag("/b").await);
+        assert_ne!(etag("/a").await, etag("/c").await);
+    }
+
+    #[tokio::test]
+    async fn test_dir_etag() {
+        let dir = TempDir::new("dir-etag");
+        std::fs::write(dir.join("a"), "hello").unwrap();
+
+        let fs = LocalFs::new(&*dir, false, false, false);
+        let root = DavPath::new("/").unwrap();
+        let before = fs.metadata(&root).await.unwrap().etag();
+
+        // writing a member changes the etag of the directory.
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let path = DavPath::new("/a").unwrap();
+        let options = OpenOptions {
+            write: true,
+            ..OpenOptions::default()
+        };
+        let mut file = fs.open(&path, options).await.unwrap();
+        file.write_bytes(Bytes::from("world")).await.unwrap();
+        file.flush().await.unwrap();
+        assert_ne!(fs.metadata(&root).await.unwrap().etag(), before);
+    }
+
+    #[tokio::test]
+    async fn test_file_cache() {
+        let dir = TempDir::new("file-cache");
+        std::fs::write(dir.join("small"), "0123456789").unwrap();
+        std::fs::write(dir.join("big"), "0123456789abcdef").unwrap();
+
+        let mut fs = LocalFs::new(&*dir, false, false, false);
+        fs.set_file_cache(10, 4);
+        let read_range = |name: &'static str, offset: u64, count: u64| {
+            let fs = fs.clone();
+            async move {
+                let path = DavPath::new(name).unwrap();
+                let mut file = fs.open(&path, OpenOptions::read()).await.unwrap();
+                file.read_range(offset, count).await
+            }
+        };
+        assert_eq!(read_range("/small", 2, 3).await.unwrap(), "234");
+        assert_eq!(
+            read_range("/small", 8, 3).await,
+            Err(FsError::NotImplemented)
+        );
+        assert_eq!(read_range("/big", 0, 1).await, Err(FsError::NotImplemented));
+
+        // a replaced file is read again.
+        std::fs::write(dir.join("new"), "abcde").unwrap();
+        std::fs::rename(dir.join("new"), dir.join("small")).unwrap();
+        assert_eq!(read_range("/small", 2, 3).await.unwrap(), "cde");
+
+        // and so is one that is truncated in place.
+        std::fs::write(dir.join("small"), "xyz").unwrap();
+        assert_eq!(read_range("/small", 1, 2).await.unwrap(), "yz");
+        assert_eq!(
+            read_range("/small", 2, 3).await,
+            Err(FsError::NotImplemented)
+        );
+    }
+}
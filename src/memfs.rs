@@ -64,6 +64,8 @@ struct MemFsDirEntry {
 struct MemFsFile {
     tree: Arc<Mutex<Tree>>,
     node_id: u64,
+    // the directory the file is in, see MemFsFile::touch.
+    parent_id: u64,
     pos: usize,
     append: bool,
 }
@@ -83,19 +85,23 @@ impl MemFs {
         path: &[u8],
         options: OpenOptions,
     ) -> FsResult<Box<dyn DavFile>> {
-        let node_id = match tree.lookup(path) {
+        let (node_id, parent_id) = match tree.lookup(path) {
             Ok(n) => {
                 if options.create_new {
                     return Err(FsError::Exists);
                 }
-                n
+                (n, tree.lookup_parent(path)?)
             }
             Err(FsError::NotFound) => {
                 if !options.create {
                     return Err(FsError::NotFound);
                 }
                 let parent_id = tree.lookup_parent(path)?;
-                tree.add_child(parent_id, file_name(path), MemFsNode::new_file(), true)?
+                let node_id =
+                    tree.add_child(parent_id, file_name(path), MemFsNode::new_file(), true)?;
+                tree.get_node_mut(parent_id)?
+                    .update_mtime(SystemTime::now());
+                (node_id, parent_id)
             }
             Err(e) => return Err(e),
         };
@@ -105,17 +111,32 @@ impl MemFs {
         }
         if options.truncate {
             node.as_file_mut()?.data.truncate(0);
-            node.update_mtime(SystemTime::now());
+            MemFsFile::touch(tree, node_id, parent_id);
         }
         Ok(Box::new(MemFsFile {
             tree: self.tree.clone(),
             node_id,
+            parent_id,
             pos: 0,
             append: options.append,
         }))
     }
 }
 
+impl MemFsFile {
+    // A write changes the mtime of the file, and that of the directory
+    // it is in: that is where the etag of the collection comes from,
+    // and it has to change when a member is modified.
+    fn touch(tree: &mut Tree, node_id: u64, parent_id: u64) {
+        let now = SystemTime::now();
+        for id in [node_id, parent_id] {
+            if let Ok(node) = tree.get_node_mut(id) {
+                node.update_mtime(now);
+            }
+        }
+    }
+}
+
 impl Clone for MemFs {
     fn clone(&self) -> Self {
         MemFs {
@@ -249,6 +270,28 @@ impl DavFileSystem for MemFs {
         .boxed()
     }
 
+    fn write_if_etag<'a>(
+        &'a self,
+        path: &'a DavPath,
+        etag: &'a str,
+        data: Bytes,
+    ) -> FsFuture<'a, String> {
+        async move {
+            let tree = &mut *self.tree.lock().unwrap();
+            let node_id = tree.lookup(path.as_bytes())?;
+            let parent_id = tree.lookup_parent(path.as_bytes())?;
+            let node = tree.get_node_mut(node_id)?;
+            if node.as_dirent(b"").etag().as_deref() != Some(etag) {
+                return Err(FsError::PreconditionFailed);
+            }
+            node.as_file_mut()?.data = data.to_vec();
+            MemFsFile::touch(tree, node_id, parent_id);
+            let node = tree.get_node(node_id)?;
+            node.as_dirent(b"").etag().ok_or(FsError::GeneralFailure)
+        }
+        .boxed()
+    }
+
     fn have_props<'a>(&'a self, _path: &'a DavPath) -> BoxFuture<'a, bool> {
         future::ready(true).boxed()
     }
@@ -387,6 +430,7 @@ impl DavFile for MemFsFile {
             }
             file.data[self.pos..end].copy_from_slice(&buf);
             self.pos = end;
+            MemFsFile::touch(tree, self.node_id, self.parent_id);
             Ok(())
         }
         .boxed()
@@ -411,6 +455,7 @@ impl DavFile for MemFsFile {
                 buf.advance(len);
                 self.pos += len;
             }
+            MemFsFile::touch(tree, self.node_id, self.parent_id);
             Ok(())
         }
         .boxed()
@@ -53,6 +53,7 @@ impl DavLockSystem for MemLs {
         deep: bool,
     ) -> Result<DavLock, DavLock> {
         let inner = &mut *self.0.lock().unwrap();
+        remove_expired(&mut inner.tree, tree::ROOT_ID, SystemTime::now());
 
         // any locks in the path?
         let rc = check_locks_to_path(&inner.tree, path, None, true, &Vec::new(), shared);
@@ -87,6 +88,7 @@ impl DavLockSystem for MemLs {
 
     fn unlock(&self, path: &DavPath, token: &str) -> Result<(), ()> {
         let inner = &mut *self.0.lock().unwrap();
+        remove_expired(&mut inner.tree, tree::ROOT_ID, SystemTime::now());
         let node_id = match lookup_lock(&inner.tree, path, token) {
             None => {
                 trace!("unlock: {} not found at {}", token, path);
@@ -114,6 +116,7 @@ impl DavLockSystem for MemLs {
     ) -> Result<DavLock, ()> {
         trace!("refresh lock {}", token);
         let inner = &mut *self.0.lock().unwrap();
+        remove_expired(&mut inner.tree, tree::ROOT_ID, SystemTime::now());
         let node_id = match lookup_lock(&inner.tree, path, token) {
             None => {
                 trace!("lock not found");
@@ -138,7 +141,8 @@ impl DavLockSystem for MemLs {
         deep: bool,
         submitted_tokens: Vec<&str>,
     ) -> Result<(), DavLock> {
-        let inner = &*self.0.lock().unwrap();
+        let inner = &mut *self.0.lock().unwrap();
+        remove_expired(&mut inner.tree, tree::ROOT_ID, SystemTime::now());
         let _st = submitted_tokens.clone();
         let rc = check_locks_to_path(
             &inner.tree,
@@ -168,7 +172,8 @@ impl DavLockSystem for MemLs {
     }
 
     fn discover(&self, path: &DavPath) -> Vec<DavLock> {
-        let inner = &*self.0.lock().unwrap();
+        let inner = &mut *self.0.lock().unwrap();
+        remove_expired(&mut inner.tree, tree::ROOT_ID, SystemTime::now());
         list_locks(&inner.tree, path)
     }
 
@@ -303,6 +308,29 @@ fn check_locks_from_node(
     Ok(())
 }
 
+// Remove the locks that have timed out, and the nodes that are left empty.
+fn remove_expired(tree: &mut Tree, node_id: u64, now: SystemTime) {
+    if let Ok(children) = tree.get_children(node_id) {
+        for (_, child_id) in children {
+            remove_expired(tree, child_id, now);
+        }
+    }
+    let empty = match tree.get_node_mut(node_id) {
+        Ok(node) => {
+            node.retain(|l| match l.timeout_at {
+                Some(t) => t > now,
+                None => true,
+            });
+            node.is_empty()
+        }
+        Err(_) => return,
+    };
+    if empty && node_id != tree::ROOT_ID {
+        // fails if the node still has children, that's fine.
+        tree.delete_node(node_id).ok();
+    }
+}
+
 // Find or create node.
 fn get_or_create_path_node<'a>(tree: &'a mut Tree, path: &DavPath) -> &'a mut Vec<DavLock> {
     let mut node_id = tree::ROOT_ID;
@@ -392,3 +420,28 @@ fn get_child(tree: &Tree, node_id: u64, seg: &[u8]) -> FsResult<u64> {
     }
     tree.get_child(node_id, seg)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_expiry() {
+        let ls = MemLs::new();
+        let path = DavPath::new("/a/b").unwrap();
+
+        let timeout = Some(Duration::from_secs(600));
+        let lock = ls.lock(&path, None, None, timeout, false, false).unwrap();
+        assert!(ls.check(&path, None, false, false, Vec::new()).is_err());
+        assert!(ls
+            .check(&path, None, false, false, vec![lock.token.as_str()])
+            .is_ok());
+
+        // an expired lock does not block writes anymore.
+        let timeout = Some(Duration::from_secs(0));
+        let lock = ls.refresh(&path, &lock.token, timeout).unwrap();
+        assert!(ls.check(&path, None, false, false, Vec::new()).is_ok());
+        assert!(ls.discover(&path).is_empty());
+        assert!(ls.unlock(&path, &lock.token).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+//! Contains the trait that defines a metrics collector.
+//!
+//! The handler calls the methods of a `DavMetrics` implementation at
+//! a few well-defined points: when a request comes in, when the status
+//! of the response is known, and while a `GET` response body is being sent.
+//! That is enough to count requests per method and status class, bytes
+//! served, and the number of active streaming responses.
+//!
+//! All methods have a default implementation that does nothing. If no
+//! metrics collector is configured, nothing is called at all.
+//!
+//! The methods are synchronous and are called from the request path,
+//! so they should be cheap (e.g. increment an atomic counter).
+//!
+//! For accounting per request there is also the `served_bytes` callback
+//! of `DavConfig`, which gets a `Served` record for every file body.
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use http::{Method, StatusCode};
+
+use crate::davpath::DavPath;
+
+/// The trait that defines a metrics collector.
+pub trait DavMetrics: Debug + Sync + Send + BoxCloneMetrics {
+    /// A request came in.
+    #[allow(unused_variables)]
+    fn request(&self, method: &Method) {}
+
+    /// The status of the response to a request is known.
+    #[allow(unused_variables)]
+    fn response(&self, method: &Method, status: StatusCode) {}
+
+    /// `count` bytes of file data were sent in a response body.
+    #[allow(unused_variables)]
+    fn bytes_sent(&self, count: u64) {}
+
+    /// A streaming response body started.
+    fn stream_start(&self) {}
+
+    /// A streaming response body is done, or was dropped.
+    fn stream_end(&self) {}
+}
+
+#[doc(hidden)]
+pub trait BoxCloneMetrics {
+    fn box_clone(&self) -> Box<dyn DavMetrics>;
+}
+
+// generic Clone, calls implementation-specific box_clone().
+impl Clone for Box<dyn DavMetrics> {
+    fn clone(&self) -> Box<dyn DavMetrics> {
+        self.box_clone()
+    }
+}
+
+// implementation-specific clone.
+#[doc(hidden)]
+impl<M: Clone + DavMetrics + 'static> BoxCloneMetrics for M {
+    fn box_clone(&self) -> Box<dyn DavMetrics> {
+        Box::new((*self).clone())
+    }
+}
+
+// Calls stream_start() when created and stream_end() when dropped,
+// so that a response body that is dropped halfway is counted as well.
+pub(crate) struct StreamGuard(Option<Box<dyn DavMetrics>>);
+
+impl StreamGuard {
+    pub(crate) fn new(metrics: Option<Box<dyn DavMetrics>>) -> StreamGuard {
+        if let Some(ref m) = metrics {
+            m.stream_start();
+        }
+        StreamGuard(metrics)
+    }
+
+    pub(crate) fn bytes_sent(&self, count: u64) {
+        if let Some(ref m) = self.0 {
+            m.bytes_sent(count);
+        }
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        if let Some(ref m) = self.0 {
+            m.stream_end();
+        }
+    }
+}
+
+/// What the body of a `GET` response for a file served.
+///
+/// Passed to the `served_bytes` callback of `DavConfig` when the body is
+/// done, or when it is dropped because the client went away.
+#[derive(Debug, Clone)]
+pub struct Served {
+    /// Path of the file.
+    pub path: DavPath,
+    /// Status of the response, `200 OK` or `206 Partial Content`.
+    pub status: StatusCode,
+    /// The request asked for one or more ranges of the file.
+    pub range: bool,
+    /// Number of bytes of file data handed to the response body.
+    pub bytes: u64,
+    /// The whole body was sent.
+    pub complete: bool,
+}
+
+pub(crate) type ServedBytes = dyn Fn(&Served) + Send + Sync;
+
+// Calls the served_bytes callback when dropped.
+pub(crate) struct ServedGuard {
+    callback: Option<Arc<ServedBytes>>,
+    served: Served,
+}
+
+impl ServedGuard {
+    pub(crate) fn new(
+        callback: Option<Arc<ServedBytes>>,
+        path: DavPath,
+        status: StatusCode,
+    ) -> Self {
+        let served = Served {
+            path,
+            status,
+            range: status == StatusCode::PARTIAL_CONTENT,
+            bytes: 0,
+            complete: false,
+        };
+        ServedGuard { callback, served }
+    }
+
+    pub(crate) fn bytes_sent(&mut self, count: u64) {
+        self.served.bytes += count;
+    }
+
+    pub(crate) fn complete(&mut self) {
+        self.served.complete = true;
+    }
+}
+
+impl Drop for ServedGuard {
+    fn drop(&mut self) {
+        if let Some(ref callback) = self.callback {
+            callback(&self.served);
+        }
+    }
+}
@@ -60,6 +60,7 @@ fn write_response(w: &mut XmlWriter, path: &DavPath, sc: StatusCode) -> Result<(
 
 pub(crate) async fn multi_error<S>(
     req_path: DavPath,
+    content_type: String,
     status_stream: S,
 ) -> Result<Response<Body>, DavError>
 where
@@ -148,7 +149,7 @@ where
 
     // return response.
     let resp = Response::builder()
-        .header("content-type", "application/xml; charset=utf-8")
+        .header("content-type", content_type)
         .status(StatusCode::MULTI_STATUS)
         .body(Body::from(body))
         .unwrap();
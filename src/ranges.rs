@@ -0,0 +1,161 @@
+//! Parsing of the HTTP `Range` header.
+//!
+//! `parse_ranges` is what the handler uses for `GET`. It is public so
+//! that applications that serve content themselves, like a media server
+//! that does its own seeking, can use the same rules: the header is
+//! parsed strictly (RFC 9110, 14.1), a header that does not parse is
+//! ignored, and ranges are clamped to the length of the content. If a
+//! request has more than one `Range` header, only the first one is used.
+//!
+//! ```
+//! use dav_server::ranges::{parse_ranges, Range, RangeResolution};
+//!
+//! let mut headers = http::HeaderMap::new();
+//! headers.insert("range", "bytes=0-99,-10".parse().unwrap());
+//! match parse_ranges(&headers, 50) {
+//!     RangeResolution::Ranges(r) => {
+//!         assert_eq!(r, [Range { start: 0, count: 50 }, Range { start: 40, count: 10 }]);
+//!     }
+//!     _ => unreachable!(),
+//! }
+//! ```
+use std::cmp;
+
+use headers::Header;
+
+use crate::davheaders::{self, ByteRange};
+
+/// A satisfiable byte range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    /// Offset of the first byte.
+    pub start: u64,
+    /// Number of bytes, at least 1.
+    pub count: u64,
+}
+
+/// Result of `parse_ranges`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeResolution {
+    /// There is no valid `Range` header: send the entire content.
+    Full,
+    /// The satisfiable ranges, in the order of the request. Ranges are
+    /// neither sorted nor merged.
+    Ranges(Vec<Range>),
+    /// None of the ranges is satisfiable: `416 Range Not Satisfiable`.
+    Unsatisfiable,
+}
+
+/// Resolve the `Range` header of a request for content of `len` bytes.
+///
+/// Ranges that start beyond the end are skipped, ranges that extend past
+/// the end are clamped, and a suffix range longer than the content means
+/// the whole content. Only if no range is left the result is
+/// `Unsatisfiable`.
+pub fn parse_ranges(headers: &http::HeaderMap, len: u64) -> RangeResolution {
+    let r = match first_range(headers) {
+        Some(r) => r,
+        None => return RangeResolution::Full,
+    };
+    trace!("parse_ranges: range header {:?}", r);
+    let mut ranges = Vec::new();
+    for range in &r.0 {
+        let (start, count) = match *range {
+            ByteRange::FromTo(s, e) if s < len => (s, cmp::min(e, len - 1) - s + 1),
+            ByteRange::AllFrom(s) if s < len => (s, len - s),
+            // "bytes=-0" asks for nothing, and is not satisfiable.
+            ByteRange::Last(n) if n > 0 && len > 0 => {
+                let n = cmp::min(n, len);
+                (len - n, n)
+            }
+            _ => {
+                debug!("parse_ranges: skipping unsatisfiable range {:?}", range);
+                continue;
+            }
+        };
+        ranges.push(Range { start, count });
+    }
+    if ranges.is_empty() {
+        RangeResolution::Unsatisfiable
+    } else {
+        RangeResolution::Ranges(ranges)
+    }
+}
+
+// The first Range header. Some proxies add a second one; decoding
+// them all would fail, and the request would silently become a
+// request for the whole content.
+fn first_range(headers: &http::HeaderMap) -> Option<davheaders::Range> {
+    let value = headers.get(http::header::RANGE)?;
+    davheaders::Range::decode(&mut std::iter::once(value)).ok()
+}
+
+// Does the Range header have a satisfiable suffix range.
+pub(crate) fn has_suffix_range(headers: &http::HeaderMap, len: u64) -> bool {
+    match first_range(headers) {
+        Some(r) => {
+            r.0.iter()
+                .any(|r| matches!(*r, ByteRange::Last(n) if n > 0 && len > 0))
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(range: &str, len: u64) -> RangeResolution {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("range", range.parse().unwrap());
+        parse_ranges(&headers, len)
+    }
+
+    fn ranges(r: &[(u64, u64)]) -> RangeResolution {
+        let r = r.iter().map(|&(start, count)| Range { start, count });
+        RangeResolution::Ranges(r.collect())
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        use RangeResolution::*;
+        assert_eq!(parse_ranges(&http::HeaderMap::new(), 10), Full);
+        assert_eq!(parse("items=0-1", 10), Full);
+        assert_eq!(parse("bytes=5-4", 10), Full);
+        assert_eq!(parse("bytes=0-1", 10), ranges(&[(0, 2)]));
+        assert_eq!(parse("bytes=8-", 10), ranges(&[(8, 2)]));
+        assert_eq!(parse("bytes=8-20", 10), ranges(&[(8, 2)]));
+        assert_eq!(parse("bytes=-3", 10), ranges(&[(7, 3)]));
+        assert_eq!(parse("bytes=-30", 10), ranges(&[(0, 10)]));
+        assert_eq!(parse("bytes=5-6,0-1", 10), ranges(&[(5, 2), (0, 2)]));
+        assert_eq!(parse("bytes=20-30,8-", 10), ranges(&[(8, 2)]));
+        assert_eq!(parse("bytes=10-", 10), Unsatisfiable);
+        assert_eq!(parse("bytes=-0", 10), Unsatisfiable);
+        assert_eq!(parse("bytes=-5", 0), Unsatisfiable);
+    }
+
+    #[test]
+    fn test_unsatisfiable_ranges() {
+        use RangeResolution::*;
+        // the unsatisfiable ones are dropped, the rest is kept.
+        assert_eq!(
+            parse("bytes=0-1,20-30,-0,4-5,10-", 10),
+            ranges(&[(0, 2), (4, 2)])
+        );
+        assert_eq!(parse("bytes=20-30,-0,10-", 10), Unsatisfiable);
+    }
+
+    #[test]
+    fn test_multiple_range_headers() {
+        let mut headers = http::HeaderMap::new();
+        headers.append("range", "bytes=2-3".parse().unwrap());
+        headers.append("range", "bytes=0-9".parse().unwrap());
+        assert_eq!(parse_ranges(&headers, 10), ranges(&[(2, 2)]));
+        assert!(!has_suffix_range(&headers, 10));
+
+        let mut headers = http::HeaderMap::new();
+        headers.append("range", "bytes=-2".parse().unwrap());
+        headers.append("range", "bytes=0-1".parse().unwrap());
+        assert!(has_suffix_range(&headers, 10));
+    }
+}
@@ -0,0 +1,237 @@
+//! Graceful shutdown.
+//!
+//! Give the handler a `Shutdown` with `DavConfig::shutdown`, and call
+//! `Shutdown::drain` when the server is going down. From then on new
+//! requests are answered with `503 Service Unavailable`. Requests that
+//! are already being handled can finish, including response bodies that
+//! are still being sent. A body that is not done when the timeout expires
+//! is ended with an error, so that the server closes the connection
+//! instead of leaving the client with a body that looks complete. How
+//! much of it was sent is logged.
+//!
+//! Example:
+//!
+//! ```no_run
+//! # async fn example() {
+//! use std::time::Duration;
+//! use dav_server::{memfs::MemFs, shutdown::Shutdown, DavHandler};
+//!
+//! let shutdown = Shutdown::new();
+//! let dav = DavHandler::builder()
+//!     .filesystem(MemFs::new())
+//!     .shutdown(shutdown.clone())
+//!     .build_handler();
+//!
+//! // ... serve requests with `dav` until it is time to stop, then:
+//! shutdown.drain(Duration::from_secs(30)).await;
+//! # }
+//! ```
+//!
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::{self, Either};
+use futures_util::StreamExt;
+use tokio::sync::Notify;
+
+use crate::async_stream::AsyncStream;
+use crate::body::Body;
+
+/// Coordinates the graceful shutdown of a `DavHandler`.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    draining: AtomicBool,
+    aborted: AtomicBool,
+    active: AtomicUsize,
+    // a request is done.
+    done: Notify,
+    // the drain timeout expired.
+    abort: Notify,
+}
+
+impl Shutdown {
+    /// Create a new `Shutdown`.
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    /// Has `drain` been called.
+    pub fn is_draining(&self) -> bool {
+        self.inner.draining.load(Ordering::SeqCst)
+    }
+
+    /// Number of requests in flight. A request is in flight until its
+    /// response body has been sent.
+    pub fn active(&self) -> usize {
+        self.inner.active.load(Ordering::SeqCst)
+    }
+
+    /// Refuse new requests, and wait at most `timeout` for the requests
+    /// in flight to finish.
+    ///
+    /// Response bodies that are still being sent after that are ended.
+    /// A body that is waiting for a slow client only notices at the next
+    /// chunk, or when the server drops the connection. Returns `true` if
+    /// all requests finished in time.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.inner.draining.store(true, Ordering::SeqCst);
+        let deadline = tokio::time::sleep(timeout);
+        futures_util::pin_mut!(deadline);
+        loop {
+            let done = self.inner.done.notified();
+            if self.active() == 0 {
+                return true;
+            }
+            futures_util::pin_mut!(done);
+            if let Either::Right(_) = future::select(done, &mut deadline).await {
+                break;
+            }
+        }
+        warn!(
+            "shutdown: {} requests still active after {:?}, ending them",
+            self.active(),
+            timeout
+        );
+        self.inner.aborted.store(true, Ordering::SeqCst);
+        self.inner.abort.notify_waiters();
+        false
+    }
+
+    // Count a new request, unless we are draining.
+    pub(crate) fn request(&self) -> Option<RequestGuard> {
+        if self.is_draining() {
+            return None;
+        }
+        self.inner.active.fetch_add(1, Ordering::SeqCst);
+        Some(RequestGuard(self.inner.clone()))
+    }
+}
+
+// A request in flight.
+pub(crate) struct RequestGuard(Arc<Inner>);
+
+impl RequestGuard {
+    // Keep the request in flight until the body has been sent, and end
+    // the body if the drain timeout expires.
+    pub(crate) fn body(self, body: Body) -> Body {
+        if !body.is_stream() {
+            return body;
+        }
+        Body::from(AsyncStream::new(|mut tx| async move {
+            let guard = self;
+            let mut body = body;
+            let mut sent = 0u64;
+            loop {
+                let abort = guard.0.abort.notified();
+                if guard.0.aborted.load(Ordering::SeqCst) {
+                    warn!("shutdown: response body ended after {} bytes", sent);
+                    return Err(io::Error::new(
+                        io::ErrorKind::Interrupted,
+                        "server is shutting down",
+                    ));
+                }
+                futures_util::pin_mut!(abort);
+                let next = match future::select(body.next(), abort).await {
+                    Either::Left((next, _)) => next,
+                    Either::Right(_) => continue,
+                };
+                match next {
+                    Some(Ok(data)) => {
+                        sent += data.len() as u64;
+                        tx.send(data).await;
+                    }
+                    Some(Err(e)) => return Err(e),
+                    None => return Ok(()),
+                }
+            }
+        }))
+    }
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+        self.0.done.notify_waiters();
+    }
+}
+
+#[cfg(all(test, feature = "memfs"))]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::testfs::request;
+    use crate::DavHandler;
+
+    const LEN: usize = 256 * 1024;
+
+    async fn setup(shutdown: &Shutdown) -> (DavHandler, Body) {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .read_buf_size(4096)
+            .shutdown(shutdown.clone())
+            .build_handler();
+        request(&dav, "PUT", "/file", &[], vec![b'x'; LEN]).await;
+        assert_eq!(shutdown.active(), 0);
+
+        // start a large GET, and read a bit of it.
+        let mut body = request(&dav, "GET", "/file", &[], "").await.into_body();
+        assert_eq!(shutdown.active(), 1);
+        assert_eq!(body.next().await.unwrap().unwrap().len(), 4096);
+        (dav, body)
+    }
+
+    // start drain() and wait until it is in effect.
+    async fn start_drain(shutdown: &Shutdown, timeout: Duration) -> tokio::task::JoinHandle<bool> {
+        let s = shutdown.clone();
+        let drain = tokio::spawn(async move { s.drain(timeout).await });
+        while !shutdown.is_draining() {
+            tokio::task::yield_now().await;
+        }
+        drain
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        let shutdown = Shutdown::new();
+        let (dav, mut body) = setup(&shutdown).await;
+        let drain = start_drain(&shutdown, Duration::from_secs(10)).await;
+
+        // new requests are refused.
+        let resp = request(&dav, "GET", "/file", &[], "").await;
+        assert_eq!(resp.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()["connection"], "close");
+
+        // the GET in flight finishes.
+        let mut total = 4096;
+        while let Some(data) = body.next().await {
+            total += data.unwrap().len();
+        }
+        assert_eq!(total, LEN);
+        drop(body);
+        assert!(drain.await.unwrap());
+        assert_eq!(shutdown.active(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout() {
+        let shutdown = Shutdown::new();
+        let (_dav, mut body) = setup(&shutdown).await;
+        let drain = start_drain(&shutdown, Duration::from_millis(20)).await;
+
+        // the client stalls, and the body is ended when the time is up.
+        assert!(!drain.await.unwrap());
+        let err = body.next().await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        drop(body);
+        assert_eq!(shutdown.active(), 0);
+    }
+}
@@ -0,0 +1,607 @@
+//! Read-only filesystem that serves the contents of a tar archive.
+//!
+//! The archive is indexed once, when the filesystem is created. After
+//! that files are read straight from the archive. A tar archive stores
+//! its entries uncompressed, so seeking (and thus serving `Range`
+//! requests) is as cheap as it is for a plain file.
+//!
+//! Supported are ustar archives, GNU long names and the `path`, `size`
+//! and `mtime` records of pax headers. Symbolic links, hard links and
+//! special files are left out. Compressed archives (`.tar.gz` etc) and
+//! zip archives are not supported.
+//!
+//! The archive file must not be changed while it is being served.
+//!
+//! Example:
+//!
+//! ```no_run
+//! use dav_server::{tarfs::TarFs, DavHandler};
+//!
+//! let dav = DavHandler::builder()
+//!     .filesystem(TarFs::new("/srv/site.tar").unwrap())
+//!     .build_handler();
+//! ```
+//!
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::{Buf, Bytes};
+use futures_util::{future, future::FutureExt};
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+use crate::tree;
+
+type Tree = tree::Tree<Vec<u8>, TarFsNode>;
+
+/// Read-only filesystem backed by a tar archive.
+#[derive(Debug, Clone)]
+pub struct TarFs {
+    inner: Arc<TarFsInner>,
+}
+
+#[derive(Debug)]
+struct TarFsInner {
+    archive: PathBuf,
+    tree: Tree,
+}
+
+#[derive(Debug, Clone)]
+struct TarFsNode {
+    is_dir: bool,
+    // offset of the data in the archive.
+    offset: u64,
+    size: u64,
+    mtime: SystemTime,
+}
+
+#[derive(Debug, Clone)]
+struct TarFsDirEntry {
+    name: Vec<u8>,
+    node: TarFsNode,
+}
+
+#[derive(Debug)]
+struct TarFsFile {
+    file: Option<File>,
+    node: TarFsNode,
+    pos: u64,
+}
+
+impl TarFs {
+    /// Create a new "tarfs" filesystem, serving the archive at `archive`.
+    ///
+    /// This reads the headers of all entries in the archive, and fails
+    /// if the archive cannot be read or is not a valid tar archive.
+    pub fn new(archive: impl AsRef<Path>) -> io::Result<Box<TarFs>> {
+        let archive = archive.as_ref().to_path_buf();
+        let mut file = File::open(&archive)?;
+        let mtime = file.metadata()?.modified().unwrap_or(UNIX_EPOCH);
+        let tree = read_index(&mut file, mtime)?;
+        Ok(Box::new(TarFs {
+            inner: Arc::new(TarFsInner { archive, tree }),
+        }))
+    }
+
+    fn lookup(&self, path: &DavPath) -> FsResult<&TarFsNode> {
+        let tree = &self.inner.tree;
+        let mut node_id = tree::ROOT_ID;
+        for seg in path
+            .as_bytes()
+            .split(|&c| c == b'/')
+            .filter(|s| !s.is_empty())
+        {
+            if !tree.get_node(node_id)?.is_dir {
+                return Err(FsError::NotFound);
+            }
+            node_id = tree.get_child(node_id, seg)?;
+        }
+        tree.get_node(node_id)
+    }
+}
+
+impl DavFileSystem for TarFs {
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            let node = self.lookup(path)?.clone();
+            Ok(Box::new(node) as Box<dyn DavMetaData>)
+        }
+        .boxed()
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        _meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        async move {
+            let tree = &self.inner.tree;
+            let mut node_id = tree::ROOT_ID;
+            for seg in path
+                .as_bytes()
+                .split(|&c| c == b'/')
+                .filter(|s| !s.is_empty())
+            {
+                node_id = tree.get_child(node_id, seg)?;
+            }
+            if !tree.get_node(node_id)?.is_dir {
+                return Err(FsError::Forbidden);
+            }
+            let mut v: Vec<Box<dyn DavDirEntry>> = Vec::new();
+            for (name, child_id) in tree.get_children(node_id)? {
+                let node = tree.get_node(child_id)?.clone();
+                v.push(Box::new(TarFsDirEntry { name, node }));
+            }
+            let strm = futures_util::stream::iter(v);
+            Ok(Box::pin(strm) as FsStream<Box<dyn DavDirEntry>>)
+        }
+        .boxed()
+    }
+
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        async move {
+            if options.write || options.append || options.truncate || options.create_new {
+                return Err(FsError::Forbidden);
+            }
+            let node = self.lookup(path)?.clone();
+            if node.is_dir {
+                return Err(FsError::Forbidden);
+            }
+            let file = File::open(&self.inner.archive)?;
+            Ok(Box::new(TarFsFile {
+                file: Some(file),
+                node,
+                pos: 0,
+            }) as Box<dyn DavFile>)
+        }
+        .boxed()
+    }
+
+    fn create_dir<'a>(&'a self, _path: &'a DavPath) -> FsFuture<'a, ()> {
+        future::err(FsError::Forbidden).boxed()
+    }
+
+    fn remove_dir<'a>(&'a self, _path: &'a DavPath) -> FsFuture<'a, ()> {
+        future::err(FsError::Forbidden).boxed()
+    }
+
+    fn remove_file<'a>(&'a self, _path: &'a DavPath) -> FsFuture<'a, ()> {
+        future::err(FsError::Forbidden).boxed()
+    }
+
+    fn rename<'a>(&'a self, _from: &'a DavPath, _to: &'a DavPath) -> FsFuture<'a, ()> {
+        future::err(FsError::Forbidden).boxed()
+    }
+
+    fn copy<'a>(&'a self, _from: &'a DavPath, _to: &'a DavPath) -> FsFuture<'a, ()> {
+        future::err(FsError::Forbidden).boxed()
+    }
+}
+
+impl DavDirEntry for TarFsDirEntry {
+    fn metadata(&self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        let meta = self.node.clone();
+        Box::pin(future::ok(Box::new(meta) as Box<dyn DavMetaData>))
+    }
+
+    fn name(&self) -> Vec<u8> {
+        self.name.clone()
+    }
+}
+
+impl DavFile for TarFsFile {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        let meta = self.node.clone();
+        Box::pin(future::ok(Box::new(meta) as Box<dyn DavMetaData>))
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, Bytes> {
+        async move {
+            let count = std::cmp::min(count as u64, self.node.size.saturating_sub(self.pos));
+            let offset = self.node.offset + self.pos;
+            let mut file = self.file.take().ok_or(FsError::GeneralFailure)?;
+            let (file, res) = tokio::task::spawn_blocking(move || {
+                let mut buf = vec![0; count as usize];
+                let res = file
+                    .seek(SeekFrom::Start(offset))
+                    .and_then(|_| file.read_exact(&mut buf))
+                    .map(|_| buf);
+                (file, res)
+            })
+            .await
+            .map_err(|_| FsError::GeneralFailure)?;
+            self.file = Some(file);
+            let buf = res?;
+            self.pos += buf.len() as u64;
+            Ok(Bytes::from(buf))
+        }
+        .boxed()
+    }
+
+    fn write_bytes(&mut self, _buf: Bytes) -> FsFuture<'_, ()> {
+        future::err(FsError::Forbidden).boxed()
+    }
+
+    fn write_buf(&mut self, _buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+        future::err(FsError::Forbidden).boxed()
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        future::ok(()).boxed()
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        let (start, offset) = match pos {
+            SeekFrom::Start(npos) => (npos, 0),
+            SeekFrom::Current(npos) => (self.pos, npos),
+            SeekFrom::End(npos) => (self.node.size, npos),
+        };
+        let res = if offset < 0 {
+            start.checked_sub(offset.unsigned_abs())
+        } else {
+            start.checked_add(offset as u64)
+        };
+        match res {
+            Some(pos) => {
+                self.pos = pos;
+                future::ok(pos).boxed()
+            }
+            None => {
+                let err = io::Error::new(io::ErrorKind::InvalidInput, "invalid seek");
+                future::err(err.into()).boxed()
+            }
+        }
+    }
+}
+
+impl DavMetaData for TarFsNode {
+    fn len(&self) -> u64 {
+        self.size
+    }
+
+    fn modified(&self) -> FsResult<SystemTime> {
+        Ok(self.mtime)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    // The offset in the archive identifies an entry, the modification
+    // time and size make sure it changes if the archive is replaced.
+    fn etag(&self) -> Option<String> {
+        let t = self.mtime.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        Some(format!("{:x}-{:x}-{:x}", self.offset, self.size, t))
+    }
+}
+
+// Values from a pax extended header, they override the ones
+// in the next ustar header.
+#[derive(Default)]
+struct PaxValues {
+    path: Option<Vec<u8>>,
+    size: Option<u64>,
+    mtime: Option<u64>,
+}
+
+// Read all headers from the archive and build the tree.
+// Maximum size of a GNU long name or pax extended header.
+const MAX_EXT_HEADER_SIZE: u64 = 1024 * 1024;
+
+fn read_index(file: &mut File, mtime: SystemTime) -> io::Result<Tree> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let root = TarFsNode {
+        is_dir: true,
+        offset: 0,
+        size: 0,
+        mtime,
+    };
+    let mut tree = Tree::new(root.clone());
+    let mut pos = 0u64;
+    let mut long_name = None;
+    let mut pax = PaxValues::default();
+
+    let mut header = [0u8; 512];
+    loop {
+        file.seek(SeekFrom::Start(pos))?;
+        if let Err(e) = file.read_exact(&mut header) {
+            // a missing end-of-archive marker is common enough.
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                break;
+            }
+            return Err(e);
+        }
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let chksum = parse_number(&header[148..156]).ok_or_else(|| invalid("bad checksum"))?;
+        let sum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| {
+                if (148..156).contains(&i) {
+                    32
+                } else {
+                    b as u64
+                }
+            })
+            .sum();
+        if sum != chksum {
+            return Err(invalid("bad checksum"));
+        }
+
+        let size = match pax.size.take() {
+            Some(size) => size,
+            None => parse_number(&header[124..136]).ok_or_else(|| invalid("bad size"))?,
+        };
+        let offset = pos + 512;
+        pos = size
+            .checked_add(511)
+            .and_then(|s| offset.checked_add(s & !511))
+            .ok_or_else(|| invalid("bad size"))?;
+
+        let typeflag = header[156];
+        match typeflag {
+            // GNU long name, pax extended header.
+            b'L' | b'x' => {
+                if size > MAX_EXT_HEADER_SIZE {
+                    return Err(invalid("extended header too big"));
+                }
+                let mut data = vec![0; size as usize];
+                file.read_exact(&mut data)?;
+                if typeflag == b'L' {
+                    long_name = Some(cstr(&data).to_vec());
+                } else {
+                    pax = parse_pax(&data);
+                }
+                continue;
+            }
+            // regular file, directory.
+            b'0' | b'\0' | b'7' | b'5' => {}
+            // anything else, including pax global headers.
+            _ => {
+                long_name = None;
+                pax = PaxValues::default();
+                continue;
+            }
+        }
+
+        let name = match pax.path.take().or_else(|| long_name.take()) {
+            Some(name) => name,
+            None => {
+                let name = cstr(&header[0..100]);
+                let prefix = cstr(&header[345..500]);
+                if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                    [prefix, b"/", name].concat()
+                } else {
+                    name.to_vec()
+                }
+            }
+        };
+        let secs = match pax.mtime.take() {
+            Some(secs) => secs,
+            None => parse_number(&header[136..148]).unwrap_or(0),
+        };
+        let is_dir = typeflag == b'5' || name.ends_with(b"/");
+        let node = TarFsNode {
+            is_dir,
+            offset,
+            size: if is_dir { 0 } else { size },
+            mtime: UNIX_EPOCH + Duration::from_secs(secs),
+        };
+        add_entry(&mut tree, &name, node, &root);
+    }
+    Ok(tree)
+}
+
+// Add an entry to the tree, creating its parent directories if needed.
+fn add_entry(tree: &mut Tree, name: &[u8], node: TarFsNode, dir: &TarFsNode) {
+    let segs = name
+        .split(|&c| c == b'/')
+        .filter(|s| !s.is_empty() && *s != b".")
+        .collect::<Vec<_>>();
+    if segs.is_empty() || segs.contains(&&b".."[..]) {
+        return;
+    }
+    let mut node_id = tree::ROOT_ID;
+    for seg in &segs[..segs.len() - 1] {
+        node_id = match tree.get_child(node_id, *seg) {
+            Ok(id) if tree.get_node(id).map(|n| n.is_dir).unwrap_or(false) => id,
+            Ok(_) => return,
+            Err(_) => match tree.add_child(node_id, seg.to_vec(), dir.clone(), false) {
+                Ok(id) => id,
+                Err(_) => return,
+            },
+        };
+    }
+    let name = segs[segs.len() - 1].to_vec();
+    match tree.get_child(node_id, &name[..]) {
+        // a directory that was already created as a parent.
+        Ok(id) if node.is_dir => {
+            if let Ok(existing) = tree.get_node_mut(id) {
+                if existing.is_dir {
+                    *existing = node;
+                }
+            }
+        }
+        // later entries replace earlier ones, but not directories.
+        Ok(id) => {
+            if !tree.get_node(id).map(|n| n.is_dir).unwrap_or(true) {
+                let _ = tree.add_child(node_id, name, node, true);
+            }
+        }
+        Err(_) => {
+            let _ = tree.add_child(node_id, name, node, false);
+        }
+    }
+}
+
+// The part of a header field up to the first NUL byte.
+fn cstr(field: &[u8]) -> &[u8] {
+    match field.iter().position(|&b| b == 0) {
+        Some(n) => &field[..n],
+        None => field,
+    }
+}
+
+// Numeric header field: octal, or base-256 (GNU) if the high bit is set.
+fn parse_number(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        let mut n = (field[0] & 0x7f) as u64;
+        for &b in &field[1..] {
+            n = n.checked_mul(256)?.checked_add(b as u64)?;
+        }
+        return Some(n);
+    }
+    let s = std::str::from_utf8(cstr(field)).ok()?;
+    let s = s.trim_matches(|c| c == ' ' || c == '\0');
+    if s.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(s, 8).ok()
+}
+
+// Records in a pax header look like "<len> <key>=<value>\n".
+fn parse_pax(mut data: &[u8]) -> PaxValues {
+    let mut pax = PaxValues::default();
+    while let Some(sp) = data.iter().position(|&b| b == b' ') {
+        let len = match std::str::from_utf8(&data[..sp])
+            .ok()
+            .and_then(|l| l.parse().ok())
+        {
+            Some(len) if len > sp && len <= data.len() => len,
+            _ => break,
+        };
+        let record = &data[sp + 1..len];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Some(eq) = record.iter().position(|&b| b == b'=') {
+            let (key, value) = (&record[..eq], &record[eq + 1..]);
+            let num = || std::str::from_utf8(value).ok();
+            match key {
+                b"path" => pax.path = Some(value.to_vec()),
+                b"size" => pax.size = num().and_then(|v| v.parse().ok()),
+                b"mtime" => {
+                    let secs = num().and_then(|v| v.split('.').next()?.parse().ok());
+                    pax.mtime = secs;
+                }
+                _ => {}
+            }
+        }
+        data = &data[len..];
+    }
+    pax
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testfs::TempDir;
+    use futures_util::StreamExt;
+
+    // build a ustar header + data.
+    fn entry(name: &str, typeflag: u8, data: &[u8]) -> Vec<u8> {
+        let mut h = vec![0u8; 512];
+        h[..name.len()].copy_from_slice(name.as_bytes());
+        h[100..107].copy_from_slice(b"0000644");
+        h[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        h[136..147].copy_from_slice(format!("{:011o}", 1_600_000_000).as_bytes());
+        h[156] = typeflag;
+        h[257..263].copy_from_slice(b"ustar\0");
+        h[263..265].copy_from_slice(b"00");
+        h[148..156].copy_from_slice(b"        ");
+        let sum: u32 = h.iter().map(|&b| b as u32).sum();
+        h[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        h.extend_from_slice(data);
+        h.resize((h.len() + 511) & !511, 0);
+        h
+    }
+
+    fn pax(key: &str, value: &str) -> Vec<u8> {
+        let rec = format!(" {}={}\n", key, value);
+        let mut len = rec.len() + 1;
+        while len != rec.len() + len.to_string().len() {
+            len += 1;
+        }
+        let rec = format!("{}{}", len, rec);
+        entry("PaxHeader", b'x', rec.as_bytes())
+    }
+
+    #[tokio::test]
+    async fn test_tarfs() {
+        let long = format!("dir/{}.txt", "x".repeat(120));
+        let mut tar = Vec::new();
+        tar.extend(entry("dir/", b'5', b""));
+        tar.extend(entry("dir/a.txt", b'0', b"hello world"));
+        tar.extend(entry("other/sub/b.txt", b'0', b"bbb"));
+        tar.extend(pax("path", &long));
+        tar.extend(entry("ignored", b'0', b"long name"));
+        tar.extend(entry("dir/link", b'2', b""));
+        tar.extend(vec![0u8; 1024]);
+
+        let dir = TempDir::new("tarfs");
+        let path = dir.join("test.tar");
+        std::fs::write(&path, &tar).unwrap();
+        let fs = TarFs::new(&path).unwrap();
+
+        // implicit directories are there, links are not.
+        let dir = DavPath::new("/other/sub/").unwrap();
+        assert!(fs.metadata(&dir).await.unwrap().is_dir());
+        let dir = DavPath::new("/dir/").unwrap();
+        let mut names = fs
+            .read_dir(&dir, ReadDirMeta::Data)
+            .await
+            .unwrap()
+            .map(|e| String::from_utf8(e.name()).unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["a.txt".to_string(), format!("{}.txt", "x".repeat(120))]
+        );
+
+        // read a range from a file.
+        let file = DavPath::new("/dir/a.txt").unwrap();
+        assert_eq!(fs.metadata(&file).await.unwrap().len(), 11);
+        let mut f = fs.open(&file, OpenOptions::read()).await.unwrap();
+        f.seek(SeekFrom::Start(6)).await.unwrap();
+        assert_eq!(&f.read_bytes(100).await.unwrap()[..], b"world");
+        assert!(f.read_bytes(100).await.unwrap().is_empty());
+
+        let file = DavPath::new(&format!("/{}", long)).unwrap();
+        let mut f = fs.open(&file, OpenOptions::read()).await.unwrap();
+        assert_eq!(&f.read_bytes(100).await.unwrap()[..], b"long name");
+
+        // and it is read-only.
+        assert!(fs.open(&file, OpenOptions::write()).await.is_err());
+        assert!(fs.create_dir(&DavPath::new("/new").unwrap()).await.is_err());
+    }
+
+    #[test]
+    fn test_bad_sizes() {
+        let dir = TempDir::new("tarfs-size");
+        let path = dir.join("test.tar");
+
+        // a GNU long name of 8 GiB.
+        let mut tar = entry("././@LongLink", b'L', b"");
+        tar[124..135].copy_from_slice(b"77777777777");
+        tar[148..156].copy_from_slice(b"        ");
+        let sum: u32 = tar[..512].iter().map(|&b| b as u32).sum();
+        tar[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        std::fs::write(&path, &tar).unwrap();
+        assert!(TarFs::new(&path).is_err());
+
+        // a pax size that makes the offset of the next header overflow.
+        let mut tar = pax("size", &u64::MAX.to_string());
+        tar.extend(entry("file", b'0', b""));
+        std::fs::write(&path, &tar).unwrap();
+        assert!(TarFs::new(&path).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+// Helpers for the tests: a handler on a MemFs, sending a request to
+// it, and a temporary directory for the tests that need a real one.
+//
+// And a MemFs with some of its methods replaced, for the tests that need
+// a filesystem that behaves in a particular way. A test implements
+// FsHooks for a type of its own, overriding only the methods it is
+// interested in. Everything else goes to the MemFs.
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "memfs")]
+use bytes::Bytes;
+#[cfg(feature = "memfs")]
+use futures_util::future::BoxFuture;
+use http::{Response, StatusCode};
+
+use crate::body::Body;
+#[cfg(feature = "memfs")]
+use crate::davpath::DavPath;
+#[cfg(feature = "memfs")]
+use crate::fs::*;
+#[cfg(feature = "memfs")]
+use crate::memfs::MemFs;
+use crate::DavHandler;
+
+// A handler on a new, empty MemFs.
+#[cfg(feature = "memfs")]
+pub(crate) fn memfs_handler() -> DavHandler {
+    DavHandler::builder()
+        .filesystem(MemFs::new())
+        .build_handler()
+}
+
+// Send a request to `dav`. `hdrs` are the request headers.
+pub(crate) async fn request(
+    dav: &DavHandler,
+    method: &str,
+    uri: &str,
+    hdrs: &[(&str, &str)],
+    body: impl Into<hyper::Body>,
+) -> Response<Body> {
+    let mut req = http::Request::builder().method(method).uri(uri);
+    for (name, value) in hdrs {
+        req = req.header(*name, *value);
+    }
+    dav.handle(req.body(body.into()).unwrap()).await
+}
+
+// The body of a response, as text.
+pub(crate) async fn body_text(resp: Response<Body>) -> String {
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+// A new directory in the temp dir, removed with everything in it
+// when dropped.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let dir = format!("dav-{}-{}", name, std::process::id());
+        let dir = std::env::temp_dir().join(dir);
+        std::fs::create_dir(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(feature = "memfs")]
+pub(crate) trait FsHooks: Clone + Send + Sync + 'static {
+    fn open<'a>(
+        &'a self,
+        fs: &'a MemFs,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        fs.open(path, options)
+    }
+    fn read_dir<'a>(
+        &'a self,
+        fs: &'a MemFs,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        fs.read_dir(path, meta)
+    }
+    fn metadata<'a>(
+        &'a self,
+        fs: &'a MemFs,
+        path: &'a DavPath,
+    ) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        fs.metadata(path)
+    }
+    fn write_if_etag<'a>(
+        &'a self,
+        fs: &'a MemFs,
+        path: &'a DavPath,
+        etag: &'a str,
+        data: Bytes,
+    ) -> FsFuture<'a, String> {
+        fs.write_if_etag(path, etag, data)
+    }
+    fn sync_token<'a>(&'a self, fs: &'a MemFs, path: &'a DavPath) -> FsFuture<'a, String> {
+        fs.sync_token(path)
+    }
+    fn content_hash<'a>(&'a self, fs: &'a MemFs, path: &'a DavPath) -> FsFuture<'a, Vec<u8>> {
+        fs.content_hash(path)
+    }
+    fn get_acl<'a>(&'a self, fs: &'a MemFs, path: &'a DavPath) -> FsFuture<'a, DavAccessControl> {
+        fs.get_acl(path)
+    }
+}
+
+#[cfg(feature = "memfs")]
+#[derive(Clone)]
+pub(crate) struct HookFs<H> {
+    pub fs: Box<MemFs>,
+    pub hooks: H,
+}
+
+#[cfg(feature = "memfs")]
+impl<H: FsHooks> HookFs<H> {
+    pub fn new(hooks: H) -> Box<HookFs<H>> {
+        HookFs::with_fs(MemFs::new(), hooks)
+    }
+
+    pub fn with_fs(fs: Box<MemFs>, hooks: H) -> Box<HookFs<H>> {
+        Box::new(HookFs { fs, hooks })
+    }
+}
+
+#[cfg(feature = "memfs")]
+impl<H: FsHooks> DavFileSystem for HookFs<H> {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        self.hooks.open(&self.fs, path, options)
+    }
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        self.hooks.read_dir(&self.fs, path, meta)
+    }
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.hooks.metadata(&self.fs, path)
+    }
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.create_dir(path)
+    }
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.remove_file(path)
+    }
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.remove_dir(path)
+    }
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.rename(from, to)
+    }
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.copy(from, to)
+    }
+    fn write_if_etag<'a>(
+        &'a self,
+        path: &'a DavPath,
+        etag: &'a str,
+        data: Bytes,
+    ) -> FsFuture<'a, String> {
+        self.hooks.write_if_etag(&self.fs, path, etag, data)
+    }
+    fn have_props<'a>(&'a self, path: &'a DavPath) -> BoxFuture<'a, bool> {
+        self.fs.have_props(path)
+    }
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(StatusCode, DavProp)>> {
+        self.fs.patch_props(path, patch)
+    }
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        self.fs.get_props(path, do_content)
+    }
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        self.fs.get_prop(path, prop)
+    }
+    fn sync_token<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        self.hooks.sync_token(&self.fs, path)
+    }
+    fn content_hash<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Vec<u8>> {
+        self.hooks.content_hash(&self.fs, path)
+    }
+    fn get_acl<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, DavAccessControl> {
+        self.hooks.get_acl(&self.fs, path)
+    }
+}
+
+// Hooks that make "/book" an address book.
+#[cfg(all(feature = "memfs", feature = "carddav"))]
+#[derive(Clone)]
+pub(crate) struct AddressBook;
+
+#[cfg(all(feature = "memfs", feature = "carddav"))]
+impl FsHooks for AddressBook {
+    fn metadata<'a>(
+        &'a self,
+        fs: &'a MemFs,
+        path: &'a DavPath,
+    ) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        Box::pin(async move {
+            let meta = fs.metadata(path).await?;
+            if path.as_url_string().trim_end_matches('/') == "/book" {
+                return Ok(Box::new(BookMeta(meta)) as Box<dyn DavMetaData>);
+            }
+            Ok(meta)
+        })
+    }
+}
+
+#[cfg(all(feature = "memfs", feature = "carddav"))]
+#[derive(Debug, Clone)]
+struct BookMeta(Box<dyn DavMetaData>);
+
+#[cfg(all(feature = "memfs", feature = "carddav"))]
+impl DavMetaData for BookMeta {
+    fn len(&self) -> u64 {
+        self.0.len()
+    }
+    fn modified(&self) -> FsResult<std::time::SystemTime> {
+        self.0.modified()
+    }
+    fn is_dir(&self) -> bool {
+        self.0.is_dir()
+    }
+    fn is_addrbook(&self) -> FsResult<bool> {
+        Ok(true)
+    }
+}
@@ -2,13 +2,16 @@ use std::io::{Cursor, Write};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use bytes::Bytes;
-use headers::Header;
+use headers::{Header, HeaderMapExt};
 use http::method::InvalidMethod;
+use http::StatusCode;
 use time::format_description::well_known::Rfc3339;
 use time::macros::offset;
 
 use crate::body::Body;
+use crate::davheaders::Depth;
 use crate::errors::DavError;
+use crate::ls::DavLock;
 use crate::DavResult;
 
 /// HTTP Methods supported by DavHandler.
@@ -29,7 +32,12 @@ pub enum DavMethod {
     Lock = 0x0800,
     Unlock = 0x1000,
     #[cfg(feature = "carddav")]
-    Report = 0x2000
+    Report = 0x2000,
+    Bind = 0x4000,
+    Unbind = 0x8000,
+    Rebind = 0x10000,
+    Post = 0x20000,
+    Search = 0x40000,
 }
 
 // translate method into our own enum that has webdav methods as well.
@@ -41,6 +49,7 @@ pub(crate) fn dav_method(m: &http::Method) -> DavResult<DavMethod> {
         http::Method::PATCH => DavMethod::Patch,
         http::Method::DELETE => DavMethod::Delete,
         http::Method::OPTIONS => DavMethod::Options,
+        http::Method::POST => DavMethod::Post,
         _ => match m.as_str() {
             "PROPFIND" => DavMethod::PropFind,
             "PROPPATCH" => DavMethod::PropPatch,
@@ -51,6 +60,10 @@ pub(crate) fn dav_method(m: &http::Method) -> DavResult<DavMethod> {
             "UNLOCK" => DavMethod::Unlock,
             #[cfg(feature = "carddav")]
             "REPORT" => DavMethod::Report,
+            "BIND" => DavMethod::Bind,
+            "UNBIND" => DavMethod::Unbind,
+            "REBIND" => DavMethod::Rebind,
+            "SEARCH" => DavMethod::Search,
             _ => {
                 return Err(DavError::UnknownDavMethod);
             }
@@ -131,6 +144,11 @@ impl DavMethodSet {
                 "unlock" => DavMethod::Unlock as u32,
                 #[cfg(feature = "carddav")]
                 "report" => DavMethod::Report as u32,
+                "bind" => DavMethod::Bind as u32,
+                "unbind" => DavMethod::Unbind as u32,
+                "rebind" => DavMethod::Rebind as u32,
+                "post" => DavMethod::Post as u32,
+                "search" => DavMethod::Search as u32,
                 "http-ro" => Self::HTTP_RO.0,
                 "http-rw" => Self::HTTP_RW.0,
                 "webdav-ro" => Self::WEBDAV_RO.0,
@@ -157,6 +175,84 @@ pub(crate) fn dav_xml_error(body: &str) -> Body {
     Body::from(xml)
 }
 
+// A lock precondition of RFC 4918, 16, like "lock-token-submitted",
+// with the root of the lock that is in the way.
+pub(crate) fn lock_condition(name: &str, lock: &DavLock) -> String {
+    let href = lock.path.with_prefix().as_url_string();
+    format!("<D:{0}><D:href>{1}</D:href></D:{0}>", name, href)
+}
+
+// 423 Locked, because the token of `lock` was not submitted.
+pub(crate) fn locked(lock: &DavLock) -> DavError {
+    let body = lock_condition("lock-token-submitted", lock);
+    DavError::Condition(StatusCode::LOCKED, body)
+}
+
+// A request body that has no XML in it. Some clients send a lone
+// newline instead of nothing at all.
+pub(crate) fn is_empty_body(xmldata: &[u8]) -> bool {
+    xmldata.iter().all(|b| b.is_ascii_whitespace())
+}
+
+// Parse the Depth header. Some clients send an integer larger than 1,
+// like "Depth: 2". That is a 400 Bad Request, unless `lenient` is set:
+// then it means infinity.
+pub(crate) fn get_depth(headers: &http::HeaderMap, lenient: bool) -> DavResult<Option<Depth>> {
+    headers.typed_try_get::<Depth>().or_else(|_| {
+        let value = headers.get("depth").and_then(|v| v.to_str().ok());
+        match value.and_then(|v| v.trim().parse::<u64>().ok()) {
+            Some(n) if lenient && n > 1 => {
+                debug!("Depth: {} taken as infinity", n);
+                Ok(Some(Depth::Infinity))
+            }
+            _ => {
+                debug!("invalid Depth header");
+                Err(StatusCode::BAD_REQUEST.into())
+            }
+        }
+    })
+}
+
+// Status of a successful write to a target that did or did not exist
+// before. RFC 4918 uses the same rule for all write methods:
+//
+//   PUT          201 Created / 204 No Content
+//   MKCOL        201 Created (an existing target is 405)
+//   COPY, MOVE   201 Created / 204 No Content
+//
+// BIND and REBIND (RFC 5842) answer 200 OK on replace instead.
+pub(crate) fn write_status(existed: bool) -> StatusCode {
+    if existed {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::CREATED
+    }
+}
+
+// Does the Accept header prefer text/xml over application/xml. Older
+// clients sometimes only accept the former.
+pub(crate) fn prefers_text_xml(headers: &http::HeaderMap) -> bool {
+    let accept = match headers.get("accept").and_then(|v| v.to_str().ok()) {
+        Some(accept) => accept,
+        None => return false,
+    };
+    let (mut text, mut app) = (0.0, 0.0);
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let mtype = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let qval = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|v| v.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match mtype.as_str() {
+            "text/xml" => text = qval,
+            "application/xml" => app = qval,
+            _ => {}
+        }
+    }
+    text > app
+}
+
 pub(crate) fn systemtime_to_offsetdatetime(t: SystemTime) -> time::OffsetDateTime {
     match t.duration_since(UNIX_EPOCH) {
         Ok(t) => {
@@ -179,6 +275,92 @@ pub(crate) fn systemtime_to_rfc3339(t: SystemTime) -> String {
     systemtime_to_offsetdatetime(t).format(&Rfc3339).unwrap()
 }
 
+// Scheme and host of the original request, as told by a reverse proxy
+// in the Forwarded or X-Forwarded-Proto / X-Forwarded-Host headers.
+// Falls back to the Host header for the host. Returns "scheme://host".
+pub(crate) fn forwarded_origin(headers: &http::HeaderMap) -> Option<String> {
+    let get = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+
+    // only look at the first (closest to the client) element of Forwarded.
+    let mut proto = None;
+    let mut host = None;
+    if let Some(fwd) = get("forwarded") {
+        for param in fwd.split(';') {
+            let mut kv = param.splitn(2, '=');
+            let key = kv.next().unwrap_or("").trim().to_ascii_lowercase();
+            let value = kv.next().unwrap_or("").trim().trim_matches('"');
+            match key.as_str() {
+                "proto" if !value.is_empty() => proto = Some(value.to_string()),
+                "host" if !value.is_empty() => host = Some(value.to_string()),
+                _ => {}
+            }
+        }
+    }
+    let proto = proto.or_else(|| get("x-forwarded-proto"))?;
+    let host = host
+        .or_else(|| get("x-forwarded-host"))
+        .or_else(|| get("host"))?;
+
+    // do not let a header inject anything but a scheme and a host.
+    let scheme_ok = proto
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.');
+    let host_ok = host
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-.:[]_".contains(c));
+    if !scheme_ok || !host_ok {
+        return None;
+    }
+    Some(format!("{}://{}", proto.to_ascii_lowercase(), host))
+}
+
+// Headers that contradict each other, checked in strict mode. Returns
+// a description of the first conflict found.
+pub(crate) fn conflicting_headers(
+    method: DavMethod,
+    headers: &http::HeaderMap,
+) -> Option<&'static str> {
+    let has = |name: &str| headers.contains_key(name);
+    let value = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    // the resource must exist, and must not exist.
+    if has("if-match") && value("if-none-match").map(|v| v.trim()) == Some("*") {
+        return Some("If-Match with If-None-Match: *");
+    }
+
+    match method {
+        // a range of a body whose length is not known up front.
+        DavMethod::Put | DavMethod::Patch => {
+            let chunked = value("transfer-encoding")
+                .map(|v| v.to_ascii_lowercase().contains("chunked"))
+                .unwrap_or(false);
+            if has("content-range") && chunked {
+                return Some("Content-Range with a chunked body");
+            }
+        }
+        // a request for part of a resource, that sends a body of its own.
+        DavMethod::Get | DavMethod::Head => {
+            let length = value("content-length").and_then(|v| v.trim().parse::<u64>().ok());
+            if has("range") && matches!(length, Some(n) if n > 0) {
+                return Some("Range with a request body");
+            }
+            // a proxy that added its own; which one is meant?
+            if headers.get_all("range").iter().nth(1).is_some() {
+                return Some("more than one Range header");
+            }
+        }
+        _ => {}
+    }
+    None
+}
+
 // A buffer that implements "Write".
 #[derive(Clone)]
 pub(crate) struct MemBuffer(Cursor<Vec<u8>>);
@@ -214,4 +396,112 @@ mod tests {
     fn test_rfc3339() {
         assert!(systemtime_to_rfc3339(UNIX_EPOCH) == "1970-01-01T00:00:00Z");
     }
+
+    #[test]
+    fn test_get_depth() {
+        let depth = |value: &'static str, lenient| {
+            let mut map = http::HeaderMap::new();
+            map.insert("depth", value.parse().unwrap());
+            get_depth(&map, lenient).map_err(|e| e.statuscode())
+        };
+        for &lenient in &[false, true] {
+            assert_eq!(depth("0", lenient), Ok(Some(Depth::Zero)));
+            assert_eq!(depth("1", lenient), Ok(Some(Depth::One)));
+            assert_eq!(depth("infinity", lenient), Ok(Some(Depth::Infinity)));
+            assert_eq!(depth("-1", lenient), Err(StatusCode::BAD_REQUEST));
+            assert_eq!(depth("two", lenient), Err(StatusCode::BAD_REQUEST));
+        }
+        assert_eq!(depth("2", false), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(depth("2", true), Ok(Some(Depth::Infinity)));
+        assert_eq!(depth("10", true), Ok(Some(Depth::Infinity)));
+    }
+
+    #[test]
+    fn test_prefers_text_xml() {
+        let prefers = |accept: &'static str| {
+            let mut map = http::HeaderMap::new();
+            map.insert("accept", accept.parse().unwrap());
+            prefers_text_xml(&map)
+        };
+        assert!(prefers("text/xml"));
+        assert!(prefers("text/xml, application/xml;q=0.5"));
+        assert!(!prefers("text/xml;q=0.5, application/xml"));
+        assert!(!prefers("application/xml, text/xml"));
+        assert!(!prefers("*/*"));
+        assert!(!prefers_text_xml(&http::HeaderMap::new()));
+    }
+
+    #[test]
+    fn test_forwarded_origin() {
+        let origin = |hdrs: &[(&'static str, &'static str)]| {
+            let mut map = http::HeaderMap::new();
+            for (k, v) in hdrs {
+                map.append(*k, v.parse().unwrap());
+            }
+            forwarded_origin(&map)
+        };
+        let fwd = "for=10.0.0.1;proto=https;host=\"dav.example.com\", for=10.0.0.2";
+        assert_eq!(
+            origin(&[("forwarded", fwd), ("host", "internal:8080")]).unwrap(),
+            "https://dav.example.com"
+        );
+        assert_eq!(
+            origin(&[
+                ("x-forwarded-proto", "https, http"),
+                ("host", "example.com")
+            ])
+            .unwrap(),
+            "https://example.com"
+        );
+        assert_eq!(
+            origin(&[
+                ("x-forwarded-proto", "HTTPS"),
+                ("x-forwarded-host", "example.com:8443"),
+                ("host", "internal")
+            ])
+            .unwrap(),
+            "https://example.com:8443"
+        );
+        assert!(origin(&[("host", "example.com")]).is_none());
+        assert!(origin(&[("x-forwarded-proto", "https"), ("x-forwarded-host", "a/b")]).is_none());
+    }
+
+    #[test]
+    fn test_conflicting_headers() {
+        let conflict = |method: DavMethod, hdrs: &[(&'static str, &'static str)]| {
+            let mut map = http::HeaderMap::new();
+            for (k, v) in hdrs {
+                map.append(*k, v.parse().unwrap());
+            }
+            conflicting_headers(method, &map).is_some()
+        };
+        let etag = ("if-match", "\"abc\"");
+        assert!(conflict(DavMethod::Put, &[etag, ("if-none-match", "*")]));
+        assert!(!conflict(
+            DavMethod::Put,
+            &[etag, ("if-none-match", "\"def\"")]
+        ));
+        assert!(!conflict(DavMethod::Put, &[("if-none-match", "*")]));
+
+        let range = ("content-range", "bytes 0-9/100");
+        assert!(conflict(
+            DavMethod::Put,
+            &[range, ("transfer-encoding", "chunked")]
+        ));
+        assert!(!conflict(
+            DavMethod::Put,
+            &[range, ("content-length", "10")]
+        ));
+        assert!(!conflict(
+            DavMethod::Put,
+            &[("transfer-encoding", "chunked")]
+        ));
+
+        let range = ("range", "bytes=0-9");
+        assert!(conflict(DavMethod::Get, &[range, ("content-length", "5")]));
+        assert!(!conflict(DavMethod::Get, &[range, ("content-length", "0")]));
+        assert!(!conflict(DavMethod::Get, &[range]));
+        assert!(conflict(DavMethod::Get, &[range, ("range", "bytes=5-9")]));
+        assert!(!conflict(DavMethod::Put, &[range, ("content-length", "5")]));
+    }
 }